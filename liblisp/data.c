@@ -1,25 +1,45 @@
+#include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
 #include "data.h"
 #include "err.h"
+#include "gc.h"
+
+/** The number of data that have been allocated by `new_datum` and not yet freed. */
+static size_t LiveData = 0;
+
+/** Every keyword made by `keyword`, in the order they were first asked for. */
+static struct LispDatum** Keywords = NULL;
+static size_t KeywordCount = 0;
+static size_t KeywordCapacity = 0;
+
+struct LispDatum* new_datum(enum LispDataType type) {
+  gc_before_allocation();
 
-struct LispDatum* new_integer(int32_t i) {
   struct LispDatum* x = malloc(sizeof(struct LispDatum));
-  x->type = Integer;
+  x->type = type;
+  x->refs = 1;
+  x->marked = 0;
+  ++LiveData;
+
+  gc_track(x);
+  return x;
+}
+
+struct LispDatum* new_integer(int32_t i) {
+  struct LispDatum* x = new_datum(Integer);
   x->int_val = i;
   return x;
 }
 
 struct LispDatum* new_real(double d) {
-  struct LispDatum* x = malloc(sizeof(struct LispDatum));
-  x->type = Real;
+  struct LispDatum* x = new_datum(Real);
   x->float_val = d;
   return x;
 }
 
 struct LispDatum* new_rational(int32_t a, int32_t b) {
-  struct LispDatum* x = malloc(sizeof(struct LispDatum));
-  x->type = Rational;
+  struct LispDatum* x = new_datum(Rational);
   x->num = a;
   x->den = b;
   simplify(x);
@@ -27,28 +47,32 @@ struct LispDatum* new_rational(int32_t a, int32_t b) {
 }
 
 struct LispDatum* new_complex(double r, double i) {
-  struct LispDatum* x = malloc(sizeof(struct LispDatum));
-  x->type = Complex;
+  struct LispDatum* x = new_datum(Complex);
   x->real = r;
   x->im = i;
   return x;
 }
 
 struct LispDatum* new_symbol(const char* content) {
-  struct LispDatum* x = malloc(sizeof(struct LispDatum));
-  x->type = Symbol;
-  x->content = malloc(sizeof(strlen(content)));
-  strcpy(x->content, content);
+  struct LispDatum* x = new_datum(Symbol);
+  x->label = malloc(strlen(content) + 1);
+  strcpy(x->label, content);
   return x;
 }
 
-// TODO(matthew-c21): Whenever garbage collection is implemented, this should update the reference count.
 struct LispDatum* new_cons(struct LispDatum* car, struct LispDatum* cdr) {
-  struct LispDatum* x = malloc(sizeof(struct LispDatum));
-  x->type = Cons;
+  struct LispDatum* x = new_datum(Cons);
   x->car = car;
 
-  x->cdr = car == NULL || cdr->car == NULL || datum_cmp(cdr, get_nil()) ? NULL : cdr;
+  // Empty lists and nil both terminate the list. Only the car of an actual cons cell may be inspected, since the other
+  // members of the union may overlap it.
+  x->cdr = car == NULL || cdr == NULL || cdr->type == Nil || (cdr->type == Cons && cdr->car == NULL) ? NULL : cdr;
+
+  // A terminator that isn't kept is dropped, since the reference to it was handed over.
+  if (x->cdr == NULL) {
+    release(cdr);
+  }
+
   return x;
 }
 
@@ -58,28 +82,102 @@ struct LispDatum* get_nil() {
   return &x;
 }
 
-// TODO(matthew-c21): Update for garbage collection later on.
+void free_datum(struct LispDatum* x) {
+  switch (x->type) {
+    case String:
+      free(x->content);
+      break;
+    case Symbol:
+    case Keyword:
+      free(x->label);
+      break;
+    case Lambda:
+      free(x->captures);
+      break;
+    default:
+      break;
+  }
+
+  free(x);
+  --LiveData;
+}
+
+struct LispDatum* retain(struct LispDatum* x) {
+  if (x != NULL && x->refs > 0 && !gc_enabled()) {
+    ++x->refs;
+  }
+
+  return x;
+}
+
+void release(struct LispDatum* x) {
+  if (gc_enabled()) {
+    return;
+  }
+
+  // Lists are released a cell at a time, rather than recursively, so that long lists don't overflow the stack.
+  while (x != NULL && x->refs > 0 && --x->refs == 0) {
+    struct LispDatum* next = NULL;
+
+    if (x->type == Cons) {
+      release(x->car);
+      next = x->cdr;
+    } else if (x->type == Lambda) {
+      release_all(x->captures, x->n_captures);
+    }
+
+    free_datum(x);
+    x = next;
+  }
+}
+
+void release_all(struct LispDatum** xs, uint32_t n) {
+  for (uint32_t i = 0; i < n; ++i) {
+    release(xs[i]);
+  }
+}
+
+void reassign(struct LispDatum** variable, struct LispDatum* value) {
+  struct LispDatum* old = *variable;
+  *variable = value;
+  release(old);
+}
+
+int consume_truthy(struct LispDatum* x) {
+  int t = truthy(x);
+  release(x);
+  return t;
+}
+
+size_t live_data() {
+  return LiveData;
+}
+
+void report_leaks() {
+  if (LiveData > 0) {
+    fprintf(stderr, "Leak check: %zu object(s) still live at exit.\n", LiveData);
+  }
+}
+
 void discard_datum(struct LispDatum* x) {
+  if (gc_enabled() || x->refs == 0) {
+    return;
+  }
+
   switch (x->type) {
     case Integer:
     case Rational:
     case Real:
     case Complex:
-      free(x);
-      break;
     case String:
-      free(x->content);
-      free(x);
-      break;
     case Symbol:
     case Keyword:
-      free(x->label);
-      free(x);
+      free_datum(x);
       break;
     case Cons:
       discard_datum(x->car);
       discard_datum(x->cdr);
-      free(x);
+      free_datum(x);
       break;
     case Bool:
     case Nil:
@@ -134,8 +232,7 @@ void simplify(struct LispDatum* x) {
 }
 
 struct LispDatum* new_string(const char* s) {
-  struct LispDatum* string = malloc(sizeof(struct LispDatum));
-  string->type = String;
+  struct LispDatum* string = new_datum(String);
 
   size_t len = strlen(s);
   string->length = len;
@@ -167,9 +264,33 @@ struct LispDatum* new_keyword(const char* s) {
   return keyword;
 }
 
+struct LispDatum* keyword(const char* s) {
+  for (size_t i = 0; i < KeywordCount; ++i) {
+    if (strcmp(Keywords[i]->label, s) == 0) {
+      return Keywords[i];
+    }
+  }
+
+  if (KeywordCount == KeywordCapacity) {
+    KeywordCapacity = KeywordCapacity == 0 ? 64 : KeywordCapacity * 2;
+    Keywords = realloc(Keywords, sizeof(struct LispDatum*) * KeywordCapacity);
+  }
+
+  // Interned keywords aren't made by `new_datum`, so they're neither counted as live nor tracked by the collector, and
+  // having no references, they're never freed.
+  struct LispDatum* x = malloc(sizeof(struct LispDatum));
+  x->type = Keyword;
+  x->refs = 0;
+  x->marked = 0;
+  x->label = malloc(strlen(s) + 1);
+  strcpy(x->label, s);
+
+  Keywords[KeywordCount++] = x;
+  return x;
+}
+
 struct LispDatum* new_lambda(LispFunction f, struct LispDatum** captures, uint32_t n_captures, char* name) {
-  struct LispDatum* lambda = malloc(sizeof(struct LispDatum));
-  lambda->type = Lambda;
+  struct LispDatum* lambda = new_datum(Lambda);
   lambda->f = f;
 
   // The only named lambdas are those created at compile time, meaning they have a statically stored name.
@@ -20,10 +20,22 @@ enum LispDataType {
   Integer = 0, Rational = 1, Real = 2, Complex = 3, String, Symbol, Bool, Cons, Nil, Lambda, Keyword
 };
 
-/** Since LISP is a dynamically typed language, this struct exists as a way to produce that same behavior. */
+/**
+ * Since LISP is a dynamically typed language, this struct exists as a way to produce that same behavior.
+ *
+ * Data are reference counted. Functions that return data, including every `LispFunction`, hand a new reference to the
+ * caller, and only borrow their arguments. Data that hold others, like cons cells and the captures of lambdas, own a
+ * reference to each of them.
+ */
 struct LispDatum {
   enum LispDataType type;
 
+  /** The number of references to a datum. Statically allocated data, like nil, have 0, and are never freed. */
+  uint32_t refs;
+
+  /** Set on the data found to be in use while the tracing collector is collecting. See gc.h. */
+  uint8_t marked;
+
   union {
     struct { int32_t num; int32_t den; };  // rational
     int32_t int_val; // integer
@@ -41,8 +53,9 @@ struct LispDatum {
     struct { struct LispDatum* car; struct LispDatum* cdr; };  // cons
 
     /**
-     * The "name" field is non-NULL only for static lambdas, meaning those associated with native C functions or those
-     * created using the `defun` special form.
+     * The "name" field is statically stored. Native C functions and those created using the `defun` special form are
+     * named after their Lisp names. Lambdas made by compiled code are named after the variable they're defined as, or
+     * after where they're written, in angle brackets. It's NULL for lambdas made any other way.
      *
      * The behavior regarding a mismatch between the actual size of the captures array and the n_captures count is
      * undefined.
@@ -51,8 +64,13 @@ struct LispDatum {
   };
 };
 
-// TODO(matthew-c21): Later on, these should be modified to connect to the garbage collector.
 // NOTE(matthew-c21): None of these `new` functions do any kind of validation
+
+/**
+ * Allocates a datum of the given type, with a single reference owned by the caller. Its value is left to be filled in.
+ */
+struct LispDatum* new_datum(enum LispDataType type);
+
 struct LispDatum* new_integer(int32_t i);
 struct LispDatum* new_real(double d);
 struct LispDatum* new_rational(int32_t a, int32_t b);
@@ -67,14 +85,20 @@ struct LispDatum* get_false();
  * runtime are null terminated. This should be tested within string functions, specifically ones like concat.
  */
 struct LispDatum* new_string(const char* s);
-// TODO(matthew-c21): Deprecate this function and replace it with a `keyword` function that returns interned keywords.
 struct LispDatum* new_keyword(const char* s);
 
+/**
+ * Returns the keyword named s, which is the same datum for every call with the same name, so identical keywords are
+ * pointer-equal. Keywords made this way are statically allocated, like nil, and live until the program exits.
+ */
+struct LispDatum* keyword(const char* s);
+
 /**
  * Construct an anonymous function based around a static function f.
  *
  * @param captures an array containing the values captured by the lambda expression. An empty capture array should be
- *        NULL.
+ *        NULL. Otherwise, the lambda takes over the array, which must have been allocated by `malloc`, along with the
+ *        references in it.
  * @param n_captures the size of the captures array. If captures is NULL, this value is ignored, and the resulting
  *        lambda will record having 0 captures.
  */
@@ -82,11 +106,60 @@ struct LispDatum* new_lambda(LispFunction f, struct LispDatum** captures, uint32
 
 /**
  * Constructs a new pair given car and cdr. If cdr is nil, this creates a properly terminated list only containing car.
+ * The pair takes over the caller's references to car and cdr.
  */
 struct LispDatum* new_cons(struct LispDatum* car, struct LispDatum* cdr);
 
+/**
+ * Frees x, and everything in it, regardless of any other references to them. Does nothing when the tracing collector is
+ * enabled, since it frees data itself, or to statically allocated data.
+ */
 void discard_datum(struct LispDatum* x);
 
+/**
+ * Frees x alone, leaving the data it refers to. Used by the tracing collector, which frees each datum separately.
+ */
+void free_datum(struct LispDatum* x);
+
+/**
+ * Adds a reference to x, and returns it. NULL and statically allocated data are left as they are, as is everything once
+ * the tracing collector is enabled.
+ */
+struct LispDatum* retain(struct LispDatum* x);
+
+/**
+ * Drops a reference to x. Once none are left, x is freed, and drops its references to the data it holds. Does nothing
+ * once the tracing collector is enabled.
+ */
+void release(struct LispDatum* x);
+
+/**
+ * Releases each of the first n data in xs.
+ */
+void release_all(struct LispDatum** xs, uint32_t n);
+
+/**
+ * Stores a value in a variable, taking over the caller's reference to it, then releases the value the variable held
+ * before, if it wasn't NULL.
+ */
+void reassign(struct LispDatum** variable, struct LispDatum* value);
+
+/**
+ * Determines if x is truthy, then releases the caller's reference to it.
+ */
+int consume_truthy(struct LispDatum* x);
+
+/**
+ * The number of data allocated by `new_datum` that haven't been freed.
+ */
+size_t live_data();
+
+/**
+ * Reports any data that haven't been freed to stderr. Used by programs compiled with `--leak-check`, once they've
+ * released everything they hold.
+ */
+void report_leaks();
+
 struct LispDatum* get_nil();
 
 void simplify(struct LispDatum* x);
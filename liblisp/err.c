@@ -1,3 +1,4 @@
+#include <stdint.h>
 #include <stdio.h>
 #include <stdlib.h>
 
@@ -14,6 +15,14 @@ enum Cause GlobalErrorState = None;
 // TODO(matthew-c21): This isn't a particularly reasonable default.
 static enum ErrorBehavior GlobalErrorBehavior = LogOnly;
 
+static jmp_buf* GlobalErrorRecovery = NULL;
+
+/** The innermost call to a Lisp function that hasn't returned yet. */
+static struct CallFrame* GlobalCallStack = NULL;
+
+/** Deep recursion would bury the error, so only this many of the innermost calls are printed. */
+#define BACKTRACE_LIMIT 16
+
 static void destroy_and_exit() {
   // TODO(matthew-c21): If necessary, add resource handles here to be closed before exiting.
   exit(-1);
@@ -38,16 +47,45 @@ static char* cause_string(enum Cause cause) {
   }
 }
 
+static void print_backtrace() {
+  uint32_t depth = 0;
+
+  for (struct CallFrame* frame = GlobalCallStack; frame != NULL; frame = frame->caller) {
+    if (depth < BACKTRACE_LIMIT) {
+      fprintf(stderr, "  in %s\n", frame->name);
+    }
+
+    ++depth;
+  }
+
+  if (depth > BACKTRACE_LIMIT) {
+    fprintf(stderr, "  ... and %u more\n", depth - BACKTRACE_LIMIT);
+  }
+}
+
 void* raise_err(enum Cause cause, const char* msg) {
   fprintf(stderr, "%s: %s\n", cause_string(cause), msg);
   GlobalErrorState = cause;
 
+  if (cause != None) {
+    print_backtrace();
+  }
+
   switch (GlobalErrorBehavior) {
     case LogAndQuit:
       destroy_and_exit();
       break;
     case LogOnly:
       break;
+    case LogAndRecover:
+      if (GlobalErrorRecovery != NULL) {
+        // The frames of every call that's abandoned are gone along with it.
+        GlobalCallStack = NULL;
+        longjmp(*GlobalErrorRecovery, 1);
+      }
+
+      destroy_and_exit();
+      break;
     default:
       fprintf(stderr, "Illegal error behavior detected. Exiting immediately.");
       destroy_and_exit();
@@ -60,6 +98,20 @@ void set_global_error_behavior(enum ErrorBehavior behavior) {
   GlobalErrorBehavior = behavior;
 }
 
+void set_error_recovery(jmp_buf* recovery) {
+  GlobalErrorRecovery = recovery;
+}
+
 enum Cause get_error_state() {
   return GlobalErrorState;
 }
+
+void push_frame(struct CallFrame* frame, const char* name) {
+  frame->name = name;
+  frame->caller = GlobalCallStack;
+  GlobalCallStack = frame;
+}
+
+void pop_frame(struct CallFrame* frame) {
+  GlobalCallStack = frame->caller;
+}
@@ -1,6 +1,8 @@
 #ifndef LISP_ERR_H
 #define LISP_ERR_H
 
+#include <setjmp.h>
+
 enum Cause {
   None = 0, Type, Argument, ZeroDivision, Math, Generic
 };
@@ -15,7 +17,22 @@ enum ErrorBehavior {
   /**
    * Standard behavior that logs an error to `stderr` and quits.
    */
-  LogAndQuit
+  LogAndQuit,
+
+  /**
+   * Logs an error to `stderr` and jumps back to the point given to `set_error_recovery`. Used by the REPL, so that an
+   * error only abandons the current input. Quits if no recovery point is set.
+   */
+  LogAndRecover
+};
+
+/**
+ * A record of a call to a Lisp function, kept on the C stack by the function being called. Frames are linked from the
+ * innermost call outwards, and are printed as a backtrace when an error is raised.
+ */
+struct CallFrame {
+  const char* name;
+  struct CallFrame* caller;
 };
 
 /**
@@ -31,9 +48,26 @@ void* raise_err(enum Cause cause, const char* msg);
 
 enum Cause get_error_state();
 
+/**
+ * Marks the start of a call to the named Lisp function. The frame must stay alive until it is popped, so it is usually a
+ * local variable of the function.
+ */
+void push_frame(struct CallFrame* frame, const char* name);
+
+/**
+ * Marks the end of the innermost call, which must be the one that `frame` was pushed for.
+ */
+void pop_frame(struct CallFrame* frame);
+
 /**
  * Change what happens when an error is raised. Mostly used for debugging purposes.
  */
 void set_global_error_behavior(enum ErrorBehavior behavior);
 
+/**
+ * Set the point that errors jump to when the error behavior is `LogAndRecover`. The recovery point must be set by
+ * `setjmp` in a function that is still running when an error is raised.
+ */
+void set_error_recovery(jmp_buf* recovery);
+
 #endif //LISP_ERR_H
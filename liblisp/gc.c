@@ -0,0 +1,174 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include "gc.h"
+
+/** A datum allocated while the collector is enabled, numbered in the order of allocation. */
+struct HeapEntry {
+  struct LispDatum* datum;
+  size_t serial;
+};
+
+/** A registered root, which is either a single variable or an array. */
+struct Root {
+  struct LispDatum** slots;
+  uint32_t n;
+};
+
+static int Enabled = 0;
+static int Stress = 0;
+
+static struct HeapEntry* Heap = NULL;
+static size_t HeapSize = 0;
+static size_t HeapCapacity = 0;
+
+/** The number of data allocated so far, which is the serial number of the next one. */
+static size_t Allocations = 0;
+
+/** The heap is collected once it reaches this size, which doubles what survived the last collection. */
+#define MIN_COLLECTION 1024
+static size_t NextCollection = MIN_COLLECTION;
+
+static struct Root* Roots = NULL;
+static size_t RootCount = 0;
+static size_t RootCapacity = 0;
+
+/** Data with a serial number of at least this are kept by every collection. Nothing is pinned at SIZE_MAX. */
+static size_t Pin = SIZE_MAX;
+
+void gc_enable(int stress) {
+  Enabled = 1;
+  Stress = stress;
+}
+
+int gc_enabled() {
+  return Enabled;
+}
+
+void gc_push_frame(struct GcFrame* frame) {
+  frame->roots = RootCount;
+  frame->pin = Pin;
+  Pin = SIZE_MAX;
+}
+
+void gc_pop_frame(const struct GcFrame* frame) {
+  RootCount = frame->roots;
+  Pin = frame->pin;
+}
+
+void gc_root_array(struct LispDatum** array, uint32_t n) {
+  if (!Enabled) {
+    return;
+  }
+
+  if (RootCount == RootCapacity) {
+    RootCapacity = RootCapacity == 0 ? 64 : RootCapacity * 2;
+    Roots = realloc(Roots, sizeof(struct Root) * RootCapacity);
+  }
+
+  Roots[RootCount].slots = array;
+  Roots[RootCount].n = n;
+  ++RootCount;
+}
+
+void gc_root(struct LispDatum** variable) {
+  gc_root_array(variable, 1);
+}
+
+void gc_pop_roots(uint32_t n) {
+  if (Enabled) {
+    RootCount -= n;
+  }
+}
+
+size_t gc_pin() {
+  size_t previous = Pin;
+
+  // A pin that's already in place covers everything after it.
+  if (Pin > Allocations) {
+    Pin = Allocations;
+  }
+
+  return previous;
+}
+
+void gc_unpin(size_t pin) {
+  Pin = pin;
+}
+
+struct LispDatum* call_native(LispFunction f, struct LispDatum** args, uint32_t nargs) {
+  size_t pin = gc_pin();
+  struct LispDatum* result = f(args, nargs);
+  gc_unpin(pin);
+  return result;
+}
+
+static void mark(struct LispDatum* x) {
+  // Lists are followed along their cdrs in a loop, so that long lists don't overflow the stack. Static data are never
+  // freed, so they aren't marked.
+  while (x != NULL && x->refs > 0 && !x->marked) {
+    x->marked = 1;
+
+    if (x->type == Cons) {
+      mark(x->car);
+      x = x->cdr;
+    } else {
+      if (x->type == Lambda) {
+        for (uint32_t i = 0; i < x->n_captures; ++i) {
+          mark(x->captures[i]);
+        }
+      }
+
+      x = NULL;
+    }
+  }
+}
+
+void gc_collect() {
+  for (size_t i = 0; i < RootCount; ++i) {
+    for (uint32_t j = 0; j < Roots[i].n; ++j) {
+      mark(Roots[i].slots[j]);
+    }
+  }
+
+  for (size_t i = 0; i < HeapSize; ++i) {
+    if (Heap[i].serial >= Pin) {
+      mark(Heap[i].datum);
+    }
+  }
+
+  // Survivors are moved down in place, keeping them in the order they were allocated.
+  size_t kept = 0;
+
+  for (size_t i = 0; i < HeapSize; ++i) {
+    if (Heap[i].datum->marked) {
+      Heap[i].datum->marked = 0;
+      Heap[kept++] = Heap[i];
+    } else {
+      free_datum(Heap[i].datum);
+    }
+  }
+
+  HeapSize = kept;
+  NextCollection = kept * 2 > MIN_COLLECTION ? kept * 2 : MIN_COLLECTION;
+}
+
+void gc_before_allocation() {
+  if (Enabled && (Stress || HeapSize >= NextCollection)) {
+    gc_collect();
+  }
+}
+
+void gc_track(struct LispDatum* x) {
+  if (!Enabled) {
+    return;
+  }
+
+  if (HeapSize == HeapCapacity) {
+    HeapCapacity = HeapCapacity == 0 ? MIN_COLLECTION : HeapCapacity * 2;
+    Heap = realloc(Heap, sizeof(struct HeapEntry) * HeapCapacity);
+  }
+
+  Heap[HeapSize].datum = x;
+  Heap[HeapSize].serial = Allocations++;
+  ++HeapSize;
+}
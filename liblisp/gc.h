@@ -0,0 +1,89 @@
+#ifndef LISP_GC_H
+#define LISP_GC_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include "data.h"
+
+/**
+ * The roots registered by a call to a Lisp function, kept on the C stack by the function being called. Popping the frame
+ * unregisters every root registered since it was pushed.
+ */
+struct GcFrame {
+  size_t roots;
+  size_t pin;
+};
+
+/**
+ * Switches memory management from reference counting to a tracing collector, which finds the data that are still in
+ * use by following the registered roots, and frees the rest. `retain` and `release` do nothing from then on. Must be
+ * called before anything is allocated.
+ *
+ * @param stress if nonzero, a collection is made before every allocation, rather than once the heap has grown enough.
+ *        This is very slow, but finds missing roots right away.
+ */
+void gc_enable(int stress);
+
+/**
+ * Whether the tracing collector has been enabled.
+ */
+int gc_enabled();
+
+/**
+ * Marks the start of a call to a Lisp function. Nothing that was pinned by the caller is pinned during the call.
+ */
+void gc_push_frame(struct GcFrame* frame);
+
+/**
+ * Marks the end of the call that `frame` was pushed for, unregistering the roots registered during it.
+ */
+void gc_pop_frame(const struct GcFrame* frame);
+
+/**
+ * Registers a variable as a root, so that whatever it holds when a collection is made is kept. The variable must be
+ * initialized, and stay alive until the root is popped.
+ */
+void gc_root(struct LispDatum** variable);
+
+/**
+ * Registers each of the first n elements of an array as a root, like `gc_root`. NULL elements are skipped.
+ */
+void gc_root_array(struct LispDatum** array, uint32_t n);
+
+/**
+ * Unregisters the last n roots registered.
+ */
+void gc_pop_roots(uint32_t n);
+
+/**
+ * Keeps every datum allocated from now on until `gc_unpin` is called, for C code that allocates several data before
+ * storing any of them in a root. Returns the previous pin, to be given to `gc_unpin`.
+ */
+size_t gc_pin();
+
+/**
+ * Restores the pin that `gc_pin` returned.
+ */
+void gc_unpin(size_t pin);
+
+/**
+ * Calls a native function, pinning the data it allocates along the way, since liblisp functions don't register roots.
+ */
+struct LispDatum* call_native(LispFunction f, struct LispDatum** args, uint32_t nargs);
+
+/**
+ * Frees every datum that can't be reached from a root, or from a pinned datum.
+ */
+void gc_collect();
+
+/**
+ * Called by `new_datum` before allocating, to make a collection once one is due.
+ */
+void gc_before_allocation();
+
+/**
+ * Called by `new_datum` to hand a newly allocated datum over to the collector.
+ */
+void gc_track(struct LispDatum* x);
+
+#endif //LISP_GC_H
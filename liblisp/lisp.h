@@ -4,5 +4,6 @@
 #include "data.h"
 #include "stdlisp.h"
 #include "err.h"
+#include "gc.h"
 
 #endif //LISPC_LISP_H
@@ -4,6 +4,7 @@
 #include "stdlisp.h"
 #include "data.h"
 #include "err.h"
+#include "gc.h"
 
 /**
  * Determine if a datum refers to an occupied (not {NULL, NULL}) Cons pair.
@@ -12,10 +13,9 @@ static int is_occupied_node(const struct LispDatum* d) {
   return d != NULL && (d->type == Cons && d->car != NULL);
 }
 
-// TODO(matthew-c21): When garbage collection is added, this sort of behavior should be invoked alongside the gc to
-//    increase reference counts.
 /**
- * Push a new value to the end of a list. Assumes that the given node is already at the end of the list.
+ * Push a new value to the end of a list. Assumes that the given node is already at the end of the list. The list takes
+ * a new reference to the value.
  */
 static void push(struct LispDatum* node, struct LispDatum* value) {
   if (node == NULL || node->type != Cons || node->cdr != NULL) {
@@ -25,11 +25,10 @@ static void push(struct LispDatum* node, struct LispDatum* value) {
 
   // Pushing to an empty node
   if (node->car == NULL) {
-    node->car = value;
+    node->car = retain(value);
   } else {
-    node->cdr = malloc(sizeof(struct LispDatum));
-    node->cdr->type = Cons;
-    node->cdr->car = value;
+    node->cdr = new_datum(Cons);
+    node->cdr->car = retain(value);
     node->cdr->cdr = NULL;
   }
 }
@@ -99,7 +98,7 @@ void copy_lisp_datum(const struct LispDatum* source, struct LispDatum* dest) {
       dest->cdr = source->cdr;
       break;
     case Nil:
-      *dest = *get_nil();
+      dest->int_val = 0;
       break;
     case String:
       dest->content = source->content;
@@ -224,7 +223,7 @@ struct LispDatum* subtract(struct LispDatum** args, uint32_t nargs) {
     // The argument needs to be negated, so it is essentially being subtracted from 0.
     init = new_integer(0);
   } else {
-    init = malloc(sizeof(struct LispDatum));
+    init = new_datum(Integer);
     copy_lisp_datum(args[0], init);
 
     args = args + 1;  // The first argument does not need to be subtracted from itself.
@@ -232,7 +231,8 @@ struct LispDatum* subtract(struct LispDatum** args, uint32_t nargs) {
   }
 
   if (iterative_math_function(args, nargs, init, subtract_aux)) {
-    free(init);
+    write_zero(init);
+    release(init);
     return raise_err(Math, "Error during subtraction.");
   }
 
@@ -266,7 +266,7 @@ struct LispDatum* multiply(struct LispDatum** args, uint32_t nargs) {
   struct LispDatum* init = new_integer(1);
 
   if (iterative_math_function(args, nargs, init, multiply_aux)) {
-    free(init);
+    release(init);
     return raise_err(Math, "Error during multiplication.");
   }
 
@@ -313,14 +313,15 @@ struct LispDatum* divide(struct LispDatum** args, uint32_t nargs) {
   if (nargs == 0) {
     return new_integer(0);
   } else if (nargs == 1) {
-    return args[0];
+    return retain(args[0]);
   }
 
-  struct LispDatum* init = malloc(sizeof(struct LispDatum));
+  struct LispDatum* init = new_datum(Integer);
   copy_lisp_datum(args[0], init);
 
   if (iterative_math_function(args, nargs, init, divide_aux)) {
-    free(init);
+    write_zero(init);
+    release(init);
     return raise_err(Math, "Error during division.");
   }
 
@@ -403,6 +404,9 @@ void display(struct LispDatum* datum) {
     case Lambda:
       if (datum->name == NULL) {
         printf("<anonymous function at 0x%p>", (void*)datum);
+      } else if (datum->name[0] == '<') {
+        // Lambdas that aren't bound to a name are named by where they're written, as in `<lambda main.lisp:3>`.
+        printf("%s", datum->name);
       } else {
         printf("<function %s>", datum->name);
       }
@@ -508,7 +512,7 @@ struct LispDatum* car(struct LispDatum** args, uint32_t nargs) {
     return raise_err(Argument, "Cannot take the `car` of an empty list.");
   }
 
-  return args[0]->car;
+  return retain(args[0]->car);
 }
 
 struct LispDatum* cdr(struct LispDatum** args, uint32_t nargs) {
@@ -519,7 +523,7 @@ struct LispDatum* cdr(struct LispDatum** args, uint32_t nargs) {
   }
 
   if (is_occupied_node(args[0]) && args[0]->cdr != NULL) {
-    return args[0]->cdr;
+    return retain(args[0]->cdr);
   }
 
   return list(NULL, 0);
@@ -557,12 +561,11 @@ struct LispDatum* cons(struct LispDatum** args, uint32_t nargs) {
     return raise_err(Argument, "`cons` takes exactly two arguments.");
   }
 
-  return new_cons(args[0], args[1]);
+  return new_cons(retain(args[0]), retain(args[1]));
 }
 
 struct LispDatum* list(struct LispDatum** args, uint32_t nargs) {
-  struct LispDatum* alist = malloc(sizeof(struct LispDatum));
-  alist->type = Cons;
+  struct LispDatum* alist = new_datum(Cons);
   alist->cdr = NULL;
 
   if (nargs == 0) {
@@ -570,7 +573,7 @@ struct LispDatum* list(struct LispDatum** args, uint32_t nargs) {
     return alist;
   }
 
-  alist->car = args[0];
+  alist->car = retain(args[0]);
 
   struct LispDatum* write_ptr = alist;
 
@@ -594,12 +597,12 @@ struct LispDatum* append(struct LispDatum** args, uint32_t nargs) {
   if (nargs == 0) {
     return list(NULL, 0);
   } else if (nargs == 1) {
-    return args[0];
+    return retain(args[0]);
   }
 
-  struct LispDatum* combination = malloc(sizeof(struct LispDatum));
-  // TODO(matthew-c21): Conditional jump based on uninitialized value.
-  combination->type = Cons;
+  struct LispDatum* combination = new_datum(Cons);
+  combination->car = NULL;
+  combination->cdr = NULL;
 
   struct LispDatum* write_ptr = combination;
 
@@ -608,7 +611,8 @@ struct LispDatum* append(struct LispDatum** args, uint32_t nargs) {
   for (uint32_t i = 0; i < nargs - 1; ++i) {
     struct LispDatum* idx = args[i];
 
-    if (idx->type == Nil) {
+    // Both nil and empty lists contribute nothing.
+    if (idx->type == Nil || idx->car == NULL) {
       continue;
     }
 
@@ -626,13 +630,20 @@ struct LispDatum* append(struct LispDatum** args, uint32_t nargs) {
     }
 
     if (idx != NULL) {
+      release(combination);
       return raise_err(Type, "Non-terminal arguments to `append` should be proper lists");
     }
   }
 
   // If it's an occupied list (proper or otherwise), push it at the end. It must otherwise be empty or nil.
   if (is_occupied_node(args[nargs - 1])) {
-    write_ptr->cdr = args[nargs-1];
+    if (initial_write) {
+      // Every other list was empty, so the last one is shared as is.
+      release(combination);
+      return retain(args[nargs - 1]);
+    }
+
+    write_ptr->cdr = retain(args[nargs-1]);
   }
 
   // Otherwise it's nil and nothing needs to be done.
@@ -650,18 +661,19 @@ struct LispDatum* reverse(struct LispDatum** args, uint32_t nargs) {
 
   // Handle case of empty and singleton list.
   if (args[0]->car == NULL || args[0]->cdr == NULL) {
-    return args[0];
+    return retain(args[0]);
   }
 
   struct LispDatum* reversal = get_nil();
   struct LispDatum* idx = args[0];
 
   while (is_occupied_node(idx)) {
-    reversal = new_cons(idx->car, reversal);
+    reversal = new_cons(retain(idx->car), reversal);
     idx = idx->cdr;
   }
 
   if (idx != NULL) {
+    release(reversal);
     return raise_err(Type, "`reverse` expects a proper list");
   }
 
@@ -785,13 +797,13 @@ struct LispDatum* logical_and(struct LispDatum** args, uint32_t nargs) {
     }
   }
 
-  return last;
+  return retain(last);
 }
 
 struct LispDatum* logical_or(struct LispDatum** args, uint32_t nargs) {
   for (uint32_t i = 0; i < nargs; ++i) {
     if (truthy(args[i])) {  // Direct pointer comparison is bad unless the pointer is static.
-      return args[i];
+      return retain(args[i]);
     }
   }
 
@@ -806,7 +818,51 @@ struct LispDatum* logical_not(struct LispDatum** args, uint32_t nargs) {
   return truthy(args[0]) ? get_false() : get_true();
 }
 
-struct LispDatum* apply(struct LispDatum** args, uint32_t nargs) {
+/** Returned in place of a value by `tail_call`. It is never visible to Lisp code. */
+static struct LispDatum tail_call_marker = {.type = Nil, .int_val = 0};
+
+/** The call most recently deferred by `tail_call`. */
+static struct {
+  LispFunction f;
+  struct LispDatum** args;
+  uint32_t nargs;
+} pending_call;
+
+struct LispDatum* tail_call(LispFunction f, struct LispDatum** args, uint32_t nargs) {
+  // The arguments usually live in the stack frame of the caller, which is gone by the time the call is made. The
+  // references in them are handed over along with them.
+  struct LispDatum** copy = malloc(sizeof(struct LispDatum*) * (nargs + 1));
+
+  for (uint32_t i = 0; i < nargs; ++i) {
+    copy[i] = args[i];
+  }
+
+  pending_call.f = f;
+  pending_call.args = copy;
+  pending_call.nargs = nargs;
+
+  return &tail_call_marker;
+}
+
+struct LispDatum* resolve_tail_calls(struct LispDatum* result) {
+  while (result == &tail_call_marker) {
+    LispFunction f = pending_call.f;
+    struct LispDatum** args = pending_call.args;
+
+    uint32_t nargs = pending_call.nargs;
+
+    // Nothing else refers to the copy of the arguments, so it has to be a root of its own.
+    gc_root_array(args, nargs);
+    result = f(args, nargs);
+    gc_pop_roots(1);
+    release_all(args, nargs);
+    free(args);
+  }
+
+  return result;
+}
+
+struct LispDatum* tail_apply(struct LispDatum** args, uint32_t nargs) {
   if (nargs != 2) {
     return raise_err(Argument, "`apply` requires exactly two arguments.");
   } else if (args[0]->type != Lambda || ((args[1]->type != Cons) && args[1]->type != Nil)) {
@@ -817,31 +873,35 @@ struct LispDatum* apply(struct LispDatum** args, uint32_t nargs) {
   struct LispDatum* i = length(args + 1, 1);
   int len = i->int_val;
 
-  free(i);  // TODO(matthew-c21): Clean up when the garbage collector is implemented.
+  release(i);
 
   struct LispDatum* f_args[len + args[0]->n_captures];
 
   for (uint32_t k = 0; k < args[0]->n_captures; ++k) {
-    f_args[k] = args[0]->captures[k];
+    f_args[k] = retain(args[0]->captures[k]);
   }
 
   uint32_t j = args[0]->n_captures;
   struct LispDatum* ptr;
 
-  // Collect the list into an array.
-  for (ptr = args[1]; ptr != NULL && ptr->type == Cons; ptr = ptr->cdr) {
-    f_args[j++] = ptr->car;
+  // Collect the list into an array. An empty list has no car.
+  for (ptr = args[1]; ptr != NULL && ptr->type == Cons && ptr->car != NULL; ptr = ptr->cdr) {
+    f_args[j++] = retain(ptr->car);
   }
 
   // Improper list, so we give up.
-  if (ptr != NULL) {
+  if (ptr != NULL && !(ptr->type == Cons && ptr->car == NULL)) {
+    release_all(f_args, j);
     return raise_err(Type, "`apply` requires a proper list.");
   }
 
-  return args[0]->f(f_args, j);
+  LispFunction f = args[0]->f;
+  release_all(args, nargs);
+
+  return tail_call(f, f_args, j);
 }
 
-struct LispDatum* funcall(struct LispDatum** args, uint32_t nargs) {
+struct LispDatum* tail_funcall(struct LispDatum** args, uint32_t nargs) {
   if (nargs == 0) {
     return raise_err(Argument, "`funcall` requires at least one argument.");
   } else if (args[0]->type != Lambda) {
@@ -852,7 +912,7 @@ struct LispDatum* funcall(struct LispDatum** args, uint32_t nargs) {
 
   if (args[0]->captures != NULL ) {
     for (size_t i = 0; i < args[0]->n_captures; ++i) {
-      args_with_capture[i] = args[0]->captures[i];
+      args_with_capture[i] = retain(args[0]->captures[i]);
     }
   }
 
@@ -860,7 +920,30 @@ struct LispDatum* funcall(struct LispDatum** args, uint32_t nargs) {
     args_with_capture[i + args[0]->n_captures] = args[1 + i];
   }
 
+  LispFunction f = args[0]->f;
+  uint32_t n = nargs - 1 + args[0]->n_captures;
+
+  // The rest of the arguments are handed over to the call, but the lambda itself isn't.
+  release(args[0]);
+
   // If there's no other arguments, we want to avoid the risk of indexing past the array.
-  return args[0]->f(args_with_capture, nargs - 1 + args[0]->n_captures);
+  return tail_call(f, args_with_capture, n);
+}
+
+struct LispDatum* apply(struct LispDatum** args, uint32_t nargs) {
+  // The tail call takes over the references to the arguments, which are only borrowed here.
+  for (uint32_t i = 0; i < nargs; ++i) {
+    retain(args[i]);
+  }
+
+  return resolve_tail_calls(tail_apply(args, nargs));
+}
+
+struct LispDatum* funcall(struct LispDatum** args, uint32_t nargs) {
+  for (uint32_t i = 0; i < nargs; ++i) {
+    retain(args[i]);
+  }
+
+  return resolve_tail_calls(tail_funcall(args, nargs));
 }
 
@@ -155,4 +155,28 @@ struct LispDatum* apply(struct LispDatum** args, uint32_t nargs);
  */
 struct LispDatum* funcall(struct LispDatum** args, uint32_t nargs);
 
+// TAIL CALLS
+
+/**
+ * Defer a call to f until the caller has returned. The arguments are copied, and a marker is returned in place of the
+ * result, which must be passed to `resolve_tail_calls` before it is used. Only one call may be pending at a time.
+ *
+ * Functions compiled with trampolining enabled use this for calls in tail position, so that mutually recursive
+ * functions run in constant stack space. The type of f is `LispFunction`, which can't be named here, as this header is
+ * included before it is defined.
+ */
+struct LispDatum* tail_call(struct LispDatum* (*f)(struct LispDatum**, uint32_t), struct LispDatum** args,
+                            uint32_t nargs);
+
+/**
+ * Make any calls deferred by `tail_call`, until an actual value is produced. Any other value is returned unchanged.
+ */
+struct LispDatum* resolve_tail_calls(struct LispDatum* result);
+
+/** Equivalent to `apply`, except that the call is deferred using `tail_call`. */
+struct LispDatum* tail_apply(struct LispDatum** args, uint32_t nargs);
+
+/** Equivalent to `funcall`, except that the call is deferred using `tail_call`. */
+struct LispDatum* tail_funcall(struct LispDatum** args, uint32_t nargs);
+
 #endif //LISP_STDLISP_H
@@ -32,3 +32,16 @@ void Test_truthy_types(CuTest* tc) {
 void Test_falsy_values(CuTest* tc) {
   CuAssertTrue(tc, !truthy(get_false()));
 }
+
+void Test_interned_keywords(CuTest* tc) {
+  struct LispDatum* a = keyword("a");
+
+  CuAssertPtrEquals(tc, a, keyword("a"));
+  CuAssertTrue(tc, a != keyword("b"));
+  CuAssertIntEquals(tc, Keyword, a->type);
+  CuAssertStrEquals(tc, "a", a->label);
+
+  // Interned keywords are never freed.
+  release(a);
+  CuAssertPtrEquals(tc, a, keyword("a"));
+}
@@ -1060,6 +1060,28 @@ void Test_append_errors(CuTest* tc) {
   AssertThrows(append(args, 3), Type);
 }
 
+void Test_append_empty_lists(CuTest* tc) {
+  struct LispDatum* digits[2];
+  digits[0] = new_integer(0);
+  digits[1] = new_integer(1);
+
+  struct LispDatum* args[3];
+  args[0] = list(NULL, 0);
+  args[1] = list(digits, 2);
+  args[2] = list(NULL, 0);
+
+  // Leading empty lists are skipped, so the final list is returned as is.
+  struct LispDatum* result = append(args, 2);
+  CuAssertTrue(tc, result == args[1]);
+
+  // Only empty lists produce an empty list.
+  args[1] = list(NULL, 0);
+  result = append(args, 3);
+  CuAssertTrue(tc, result->type == Cons);
+  CuAssertPtrEquals(tc, NULL, result->car);
+  CuAssertPtrEquals(tc, NULL, result->cdr);
+}
+
 void Test_cons(CuTest* tc) {
   struct LispDatum* args[2];
   args[0] = new_integer(1);
@@ -1211,6 +1233,65 @@ void Test_apply(CuTest* tc) {
   CuAssertPtrEquals(tc, NULL, result->cdr->cdr);
 }
 
+/** Counts down from its argument using tail calls. */
+static struct LispDatum* countdown(struct LispDatum** args, uint32_t nargs) {
+  if (nargs != 1 || args[0]->int_val == 0) {
+    return retain(args[0]);
+  }
+
+  struct LispDatum* next = new_integer(args[0]->int_val - 1);
+  return tail_call(countdown, &next, 1);
+}
+
+void Test_tail_call(CuTest* tc) {
+  struct LispDatum* n = new_integer(1000000);
+  struct LispDatum* result = resolve_tail_calls(countdown(&n, 1));
+
+  CuAssertIntEquals(tc, Integer, result->type);
+  CuAssertIntEquals(tc, 0, result->int_val);
+
+  // Anything that isn't a deferred call is returned as is.
+  CuAssertPtrEquals(tc, n, resolve_tail_calls(n));
+}
+
+void Test_tail_funcall(CuTest* tc) {
+  // The call takes over the lambda, which frees its captures along with it.
+  struct LispDatum** captures = malloc(sizeof(struct LispDatum*));
+  captures[0] = new_integer(5);
+
+  struct LispDatum* ac[3];
+  ac[0] = new_lambda(full_lambda, captures, 1, NULL);
+  ac[1] = new_integer(2);
+  ac[2] = new_integer(6);
+
+  struct LispDatum* result = resolve_tail_calls(tail_funcall(ac, 3));
+
+  CuAssert(tc, "lambda did not return correct value.", result->type == Cons);
+  CuAssertIntEquals(tc, 7, result->car->int_val);
+}
+
+void Test_error_recovery(CuTest* tc) {
+  jmp_buf recovery;
+  volatile int recovered = 0;
+
+  set_error_recovery(&recovery);
+  set_global_error_behavior(LogAndRecover);
+
+  if (setjmp(recovery)) {
+    recovered = 1;
+  } else {
+    car(NULL, 0);
+  }
+
+  set_error_recovery(NULL);
+  set_global_error_behavior(LogOnly);
+
+  CuAssert(tc, "Failed `car` jumps to the recovery point", recovered);
+  CuAssertIntEquals(tc, Argument, get_error_state());
+
+  raise_err(None, NULL);
+}
+
 void Test_foo(CuTest* tc) {
   tc = NULL;
 #ifdef __cplusplus
@@ -0,0 +1,12 @@
+; Runtime errors are followed by the Lisp functions that were running, innermost first. Lambdas
+; are named after the variable they're defined as, or else where they're written.
+(define first-of (lambda (xs) (car xs)))
+
+(defun sum-firsts (lists)
+  (if (eqv (length lists) 0)
+      0
+      (+ (funcall first-of (car lists)) (sum-firsts (cdr lists)))))
+
+(format first-of (lambda (x) x))
+(format (sum-firsts (list (list 1) (list 2))))
+(format (funcall (lambda (f) (+ 1 (funcall f (list (list 1) 2)))) sum-firsts))
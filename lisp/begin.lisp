@@ -0,0 +1,26 @@
+;; `begin` (or `progn`) evaluates each form in order, and has the value of the last one.
+(format "Result: " (begin (format "first") (format "second") 3))
+
+(define x (progn 1 2 (+ 1 2)))
+(format "x: " x)
+
+;; Bodies of lambdas, functions, and let forms may hold any number of forms.
+(define greet (lambda (name)
+  (format "Hello, " name)
+  (format "Goodbye, " name)
+  :done))
+
+(format (funcall greet "world"))
+
+(defun countdown (n)
+  (format n)
+  (if (<= n 0)
+      :liftoff
+      (countdown (- n 1))))
+
+(format (countdown 3))
+
+(format (let ((a 1) (b 2))
+  (format "a: " a)
+  (format "b: " b)
+  (if (begin (format "comparing") (< a b)) b a)))
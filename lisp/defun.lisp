@@ -0,0 +1,40 @@
+;; Functions defined with `defun` are compiled to top level C functions that may be called
+;; directly by name, including from within their own bodies.
+(defun fact (n)
+  (if (<= n 1)
+      1
+      (* n (fact (- n 1)))))
+
+(format "5! = " (fact 5))
+
+;; Bodies may refer to globals.
+(define offset 100)
+
+(defun shift (x) (+ x offset))
+
+(format (shift 1))
+
+;; Mutually recursive functions may refer to functions defined after them.
+(defun even? (n) (if (eqv n 0) #t (odd? (- n 1))))
+(defun odd? (n) (if (eqv n 0) #f (even? (- n 1))))
+
+(format (even? 10) " " (odd? 7) " " (even? 3))
+
+;; The name of the function is also usable as a value.
+(defun map (f xs)
+  (if (eqv (length xs) 0)
+      nil
+      (cons (funcall f (car xs)) (map f (cdr xs)))))
+
+(format (map shift (list 1 2 3)))
+(format (apply fact (list 6)))
+
+;; Lambdas created inside of a function can capture its arguments.
+(defun adder (n) (lambda (x) (+ x n)))
+
+(format (funcall (adder 3) 4))
+
+;; Varargs work the same as they do for lambdas.
+(defun count (. xs) (length xs))
+
+(format (count 1 2 3))
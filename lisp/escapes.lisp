@@ -0,0 +1,4 @@
+;; Strings, symbols, and names are written into the C with escapes, whatever they hold.
+(format "zero​width" "bell" :λ 'λ "trigraph??=")
+(defun λ (x) (car x))
+(λ 1)
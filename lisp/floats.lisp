@@ -0,0 +1,3 @@
+; Floats too big for any C integer, and negative zero, keep their values when compiled.
+(format 1e20 -0.0 2.0 1.5e-7)
+(format (* 1e20 2) (- 0.0) (+ 1e20 1e20i))
@@ -0,0 +1,16 @@
+; Each of these is evaluated at compile time, so the C only builds the results.
+(format (+ 6 7 (* 2 7)))
+(format (+ 1 1/2) (- 1/2 1) (* 2 0.25) (+ 1 2i))
+(format (/ 6 2) (mod 7 3))
+(format (< 1 2 3) (= 1 1.0) (not nil) (and 1 "b") (or #f :c))
+
+(define limit (* 10 10))
+
+(defun clamp (x)
+  (if (> x limit) limit x))
+
+(format (clamp (+ 50 60)) (clamp 5))
+(format (if (> 1 2) "bigger" "smaller"))
+
+; Folded floats keep their values, however large, and the sign of zero.
+(format (* 1e10 1e10) (* -1.0 0.0))
@@ -0,0 +1,33 @@
+;; Makes plenty of garbage while keeping a few values alive. The tests also run this with the
+;; tracing collector collecting before every allocation, so that a missing root frees something
+;; that's still in use.
+
+(define kept (list 1 2 3))
+
+;; Each iteration makes a closure and a list that are garbage by the next one.
+(defun churn (n acc)
+  (if (eqv n 0)
+      acc
+      (churn (- n 1) (funcall (lambda (x) (+ x (length (list n n n)))) acc))))
+
+(format (churn 200 0))
+
+;; Lambdas in a `letrec` capture each other, which reference counting can't free.
+(defun cycle (n)
+  (letrec ((ping (lambda (k) (if (eqv k 0) :ping (funcall pong (- k 1)))))
+           (pong (lambda (k) (if (eqv k 0) :pong (funcall ping (- k 1))))))
+    (funcall ping n)))
+
+(defun repeat-cycle (n last) (if (eqv n 0) last (recur (- n 1) (cycle n))))
+
+(format (repeat-cycle 50 nil))
+
+;; Natives allocate several cells before returning any of them.
+(define joined (append kept (reverse kept) '(a b (c d)) (list "e" 6.5)))
+(format joined (length joined))
+
+(defun gather (n . xs) (if (eqv n 0) xs (recur (- n 1) n (list n) '(q))))
+(format (gather 20 0))
+
+(format (apply (lambda (a . rest) (cons a rest)) (list 1 2 3)))
+(format kept)
@@ -0,0 +1,19 @@
+(define x 10)
+
+;; Bindings are evaluated in the enclosing scope, so the inner `x` is 11.
+(format (let ((x (+ x 1)) (y x)) (list x y)))
+
+;; Each binding in a `let*` can see the ones before it.
+(format (let* ((a 1) (b (+ a 1)) (a (* b 10))) (list a b)))
+
+;; Lambdas can capture local bindings, even after leaving the block.
+(define make-adder (lambda (n) (let ((m n)) (lambda (k) (+ m k)))))
+(format (funcall (funcall make-adder 5) 2))
+
+;; Bindings in a `letrec` can refer to each other. These lambdas capture each other, so reference counting
+;; never frees them, which the leak check reports.
+(format (letrec ((even? (lambda (n) (if (eqv n 0) #t (funcall odd? (- n 1)))))
+                 (odd? (lambda (n) (if (eqv n 0) #f (funcall even? (- n 1))))))
+          (funcall even? 10)))
+
+(format (let ((c (if (< x 5) :small :large))) c))
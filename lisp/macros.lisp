@@ -0,0 +1,19 @@
+; Macros are expanded before compilation. Use `--expand` to see the result.
+(defmacro unless (c . body) `(if ,c nil (begin ,@body)))
+
+(define-syntax my-or
+  (syntax-rules ()
+    ((my-or) nil)
+    ((my-or a) a)
+    ((my-or a b ...) (let ((tmp a)) (if tmp tmp (my-or b ...))))))
+
+(define-syntax my-cond
+  (syntax-rules (else)
+    ((my-cond (else e)) e)
+    ((my-cond (c e) clause ...) (if c e (my-cond clause ...)))))
+
+(define tmp :caller)
+
+(format (unless #f :first :second))
+(format (my-or nil #f tmp))
+(format (my-cond ((< 2 1) :less) ((> 2 1) :greater) (else :equal)))
@@ -0,0 +1,8 @@
+; Natives can be used as values, like any other function.
+(format (apply + (list 1 2)))
+
+(define plus +)
+(format (funcall plus 3 4) plus)
+
+(define product (lambda (xs) (apply * xs)))
+(format (funcall product (list 2 3 4)) (* 2 5))
@@ -0,0 +1,32 @@
+;; Quoted forms are data rather than code, so symbols inside of them are never looked up.
+(format 'hello)
+(format '(1 2.5 "three" :four five))
+(format '(a (b c) . d))
+(format '())
+
+;; Literal data is handy for lookup tables.
+(define colors '((red . :warm) (blue . :cool) (green . :cool)))
+
+(defun assoc (key alist)
+  (if (eqv (length alist) 0)
+      nil
+      (if (eqv key (car (car alist)))
+          (car alist)
+          (assoc key (cdr alist)))))
+
+(format (assoc 'blue colors))
+(format (cdr (assoc 'red colors)))
+(format (assoc 'purple colors))
+
+;; Quasiquotes allow parts of the data to be evaluated.
+(define x 10)
+(define xs (list 1 2 3))
+
+(format `(x is ,x))
+(format `(xs is ,xs))
+(format `(xs contains ,@xs and more))
+(format `(1 ,@(list) 2))
+(format `(,@xs))
+(format `(head . ,xs))
+(format `(nested (,(+ x 1) ,@(reverse xs))))
+(format `(1 `(2 ,(3 ,x))))
@@ -0,0 +1,7 @@
+;; Natives are C functions, rather than variables, so they can't be defined over.
+(define car 1)
+(format car)
+
+;; Even after they've been used.
+(format (list 1 2))
+(define list 3)
@@ -0,0 +1,15 @@
+; Self tail calls and `recur` compile into loops, so none of these grow the C stack.
+(defun count-up (n acc)
+  (if (eqv n 0) acc (count-up (- n 1) (cons n acc))))
+
+(format (length (count-up 1000000 (list))))
+
+(define sum (lambda (n acc) (if (eqv n 0) acc (recur (- n 1) (+ acc n)))))
+
+(format (funcall sum 10000 0))
+
+; Mutual recursion only runs in constant space when compiled with `--trampoline`.
+(defun my-even (n) (if (eqv n 0) #t (my-odd (- n 1))))
+(defun my-odd (n) (if (eqv n 0) #f (my-even (- n 1))))
+
+(format (my-even 1001))
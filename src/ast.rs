@@ -1,11 +1,18 @@
 use crate::ast::{Statement::*, Value::*};
+use crate::diagnostic::{
+    Diagnostic, Diagnostics, Span, INTERNAL, INVALID_RECUR, INVALID_SYNTAX, REDEFINED_FUNCTION,
+    UNDEFINED_SYMBOL,
+};
 use crate::lex::{Token, TokenValue::*};
 use crate::parse::ParseTree;
-use json;
+use crate::passes::PassManager;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs;
 
+/// The natives that liblisp defines, which are used unless the compiler is given others.
+const NATIVES: &str = include_str!("../natives.json");
+
 #[derive(Clone, Debug)]
 pub enum ASTNode {
     Value(Value),
@@ -20,29 +27,31 @@ impl ASTNode {
         }
     }
 
-    fn try_from_parse_tree(tree: &ParseTree, scope_id: &mut usize) -> Result<ASTNode, (u32, String)> {
+    fn try_from_parse_tree(tree: &ParseTree, scope_id: &mut usize) -> Result<ASTNode, Diagnostic> {
         match &tree {
             ParseTree::Leaf(t) => Ok(Self::from(t.clone())),
             ParseTree::Branch(elems, start, _stop, None) => {
-                if elems.len() == 0 {
-                    return Err((
+                if elems.is_empty() {
+                    return Err(Diagnostic::error(
+                        INVALID_SYNTAX,
                         *start,
                         String::from("Empty lists are unsupported as syntax elements."),
                     ));
                 }
 
-                return match &elems[0] {
+                match &elems[0] {
                     ParseTree::Leaf(Token {
-                                        line,
+                                        span,
                                         value: Symbol(s),
                                     }) if &s[..] == "if" => {
                         if elems.len() != 4 {
-                            return Err((
-                                *line,
-                                String::from(format!(
+                            return Err(Diagnostic::error(
+                                INVALID_SYNTAX,
+                                *span,
+                                format!(
                                     "Expected exactly 3 arguments in `if` special form. Found {}.",
                                     elems.len() - 1
-                                )),
+                                ),
                             ));
                         }
 
@@ -52,15 +61,15 @@ impl ASTNode {
 
                         match (cond, if_true, if_false) {
                             (ASTNode::Value(a), ASTNode::Value(b), ASTNode::Value(c)) => Ok(ASTNode::Value(Value::Condition(Box::new(a.clone()), Box::new(b.clone()), Box::new(c.clone())))),
-                            _ => Err((*line, String::from("Expected values for condition, true, and false branches of conditional expression.")))
+                            _ => Err(Diagnostic::error(INVALID_SYNTAX, *span, String::from("Expected values for condition, true, and false branches of conditional expression.")))
                         }
                     }
                     ParseTree::Leaf(Token {
-                                        line,
+                                        span,
                                         value: Symbol(s),
                                     }) if &s[..] == "define" => {
                         if elems.len() != 3 {
-                            return Err((*line, String::from(format!("Expected exactly 2 arguments in `define` special form. Found {}.", elems.len() - 1))));
+                            return Err(Diagnostic::error(INVALID_SYNTAX, *span, format!("Expected exactly 2 arguments in `define` special form. Found {}.", elems.len() - 1)));
                         }
 
                         let defined = Self::try_from_parse_tree(&elems[1], scope_id)?;
@@ -69,131 +78,569 @@ impl ASTNode {
                         match (defined, value) {
                             (
                                 ASTNode::Value(Literal(Token {
-                                                           value: Symbol(s), ..
+                                                           value: Symbol(s), span,
                                                        })),
                                 ASTNode::Value(v),
-                            ) => Ok(ASTNode::Statement(Definition(s.clone(), v.clone()))),
+                            ) => Ok(ASTNode::Statement(Definition(s.clone(), v.clone(), span))),
                             (
                                 ASTNode::Value(Literal(Token {
                                                            value: Symbol(_s),
-                                                           line,
+                                                           span,
                                                        })),
                                 _,
-                            ) => Err((line, String::from("Can only assign a symbol to a value."))),
+                            ) => Err(Diagnostic::error(INVALID_SYNTAX, span, String::from("Can only assign a symbol to a value."))),
                             (_, ASTNode::Value(_v)) => {
-                                Err((*line, String::from("Can only assign a value to a symbol.")))
+                                Err(Diagnostic::error(INVALID_SYNTAX, *span, String::from("Can only assign a value to a symbol.")))
                             }
-                            _ => Err((*line, String::from("Invalid definition."))),
+                            _ => Err(Diagnostic::error(INVALID_SYNTAX, *span, String::from("Invalid definition."))),
                         }
                     }
                     ParseTree::Leaf(Token {
-                                        line,
+                                        span,
                                         value: Symbol(s),
                                     }) if &s[..] == "lambda" => {
-                        if elems.len() != 3 {
-                            return Err((*line, format!("Expected exactly 2 arguments in `lambda` special form. Found {}.", elems.len() - 1)));
+                        if elems.len() < 3 {
+                            return Err(Diagnostic::error(INVALID_SYNTAX, *span, format!("Expected at least 2 arguments in `lambda` special form. Found {}.", elems.len() - 1)));
                         }
 
-                        let mut names = Vec::new();
-                        let mut vararg = None;
-
-                        match &elems[1] {
-                            ParseTree::Branch(args, start, _stop, varg) => {
-                                for arg in args {
-                                    if let ParseTree::Leaf(t) = arg {
-                                        match t.value() {
-                                            Symbol(n) => names.push(n.clone()),
-                                            _ => return Err((*start, "All elements in first argument to `lambda` special form should be symbols.".to_string()))
-                                        }
-                                    } else {
-                                        return Err((*start, "All elements in first argument to `lambda` special form should be symbols.".to_string()));
-                                    }
-                                }
+                        let (names, vararg) = Self::try_from_arglist(&elems[1], "lambda", "first", "second")?;
 
-                                if let Some(b) = varg {
-                                    match b.as_ref() {
-                                        ParseTree::Leaf(t) => {
-                                            if let Symbol(n) = t.value() {
-                                                vararg = Some(n.clone());
-                                            } else {
-                                                return Err((t.line(), "Expected a symbol to be used as a vararg.".to_string()));
-                                            }
-                                        }
-                                        ParseTree::Branch(_, start, _, _) => {
-                                            return Err((*start, "All elements in first argument to `lambda` special form should be symbols.".to_string()));
-                                        }
-                                    }
-                                }
-                            }
-                            _ => {
-                                return Err((
-                                    *start,
-                                    "Expected arglist in second position of `lambda` special form."
-                                        .to_string(),
-                                ));
-                            }
+                        let body = Self::try_from_body("lambda", *span, &elems[2..], scope_id)?;
+
+                        *scope_id += 1;
+                        Ok(ASTNode::Value(Lambda(names, vararg, body, *scope_id, *span)))
+                    }
+                    ParseTree::Leaf(Token {
+                                        span,
+                                        value: Symbol(s),
+                                    }) if &s[..] == "defun" => {
+                        if elems.len() < 4 {
+                            return Err(Diagnostic::error(INVALID_SYNTAX, *span, format!("Expected at least 3 arguments in `defun` special form. Found {}.", elems.len() - 1)));
                         }
 
-                        let body = Self::try_from_parse_tree(&elems[2], scope_id)?;
+                        let name = match &elems[1] {
+                            ParseTree::Leaf(Token { value: Symbol(n), .. }) => n.clone(),
+                            _ => return Err(Diagnostic::error(INVALID_SYNTAX, elems[1].span(), "Expected a symbol in second position of `defun` special form.".to_string())),
+                        };
 
-                        if let ASTNode::Statement(_) = body {
-                            return Err((
-                                *line,
-                                "Expected final argument to `lambda` special form to be a value."
-                                    .to_string(),
-                            ));
-                        }
+                        let (names, vararg) = Self::try_from_arglist(&elems[2], "defun", "second", "third")?;
+
+                        let body = Self::try_from_body("defun", *span, &elems[3..], scope_id)?;
 
                         *scope_id += 1;
-                        Ok(ASTNode::Value(Lambda(names, vararg, vec![body], *scope_id)))
+                        Ok(ASTNode::Statement(FunctionDefinition(name, names, vararg, body, *scope_id, elems[1].span())))
+                    }
+                    ParseTree::Leaf(Token {
+                                        span,
+                                        value: Symbol(s),
+                                    }) if &s[..] == "quote" || &s[..] == "quasiquote" => {
+                        if elems.len() != 2 {
+                            return Err(Diagnostic::error(INVALID_SYNTAX, *span, format!("Expected exactly 1 argument in `{}` special form. Found {}.", s, elems.len() - 1)));
+                        }
+
+                        if &s[..] == "quote" {
+                            Ok(ASTNode::Value(Quotation(Self::quoted_from_parse_tree(&elems[1])?)))
+                        } else {
+                            Ok(ASTNode::Value(Self::try_from_quasiquote(&elems[1], 1, scope_id)?))
+                        }
+                    }
+                    ParseTree::Leaf(Token {
+                                        span,
+                                        value: Symbol(s),
+                                    }) if &s[..] == "unquote" || &s[..] == "unquote-splicing" => {
+                        Err(Diagnostic::error(INVALID_SYNTAX, *span, format!("`{}` is only valid inside of `quasiquote`.", s)))
+                    }
+                    ParseTree::Leaf(Token {
+                                        span,
+                                        value: Symbol(s),
+                                    }) if &s[..] == "begin" || &s[..] == "progn" => {
+                        if elems.len() < 2 {
+                            return Err(Diagnostic::error(INVALID_SYNTAX, *span, format!("Expected at least 1 argument in `{}` special form. Found 0.", s)));
+                        }
+
+                        Ok(ASTNode::Value(Begin(Self::try_from_body(s, *span, &elems[1..], scope_id)?)))
+                    }
+                    ParseTree::Leaf(Token {
+                                        span,
+                                        value: Symbol(s),
+                                    }) if &s[..] == "recur" => {
+                        let mut values = Vec::new();
+
+                        for elem in &elems[1..] {
+                            match Self::try_from_parse_tree(elem, scope_id)? {
+                                ASTNode::Value(v) => values.push(v),
+                                _ => return Err(Diagnostic::error(INVALID_SYNTAX, *span, String::from("Expected every argument to `recur` special form to be a value."))),
+                            }
+                        }
+
+                        Ok(ASTNode::Value(Recur(values, *span)))
+                    }
+                    ParseTree::Leaf(Token {
+                                        span,
+                                        value: Symbol(s),
+                                    }) if &s[..] == "let" || &s[..] == "let*" || &s[..] == "letrec" => {
+                        Self::try_from_let(s, *span, elems, scope_id)
                     }
                     ParseTree::Leaf(t) => match &t {
                         Token {
                             value: Symbol(s),
-                            line,
+                            span,
                         } => {
-                            let args: Vec<Result<Self, (u32, String)>> =
-                                (&elems[1..]).iter().map(|e| Self::try_from_parse_tree(e, scope_id)).collect();
+                            let args: Vec<Result<Self, Diagnostic>> =
+                                elems[1..].iter().map(|e| Self::try_from_parse_tree(e, scope_id)).collect();
 
                             let mut values = Vec::new();
                             for arg in args {
                                 match arg {
-                                    Ok(ASTNode::Value(v)) => (values.push(v.clone())),
+                                    Ok(ASTNode::Value(v)) => values.push(v.clone()),
                                     Ok(_) => {
-                                        return Err((*line, format!("Expected a value to be passed as an argument. Found: {:?}.", &elems[0]).to_string()));
+                                        return Err(Diagnostic::error(INVALID_SYNTAX, *span, format!("Expected a value to be passed as an argument. Found: {:?}.", &elems[0]).to_string()));
                                     }
                                     _ => return arg,
                                 }
                             }
-                            Ok(ASTNode::Value(Call(s.clone(), values)))
+                            Ok(ASTNode::Value(Call(s.clone(), values, *span)))
                         }
-                        _ => Err((
-                            t.line(),
+                        _ => Err(Diagnostic::error(
+                            INVALID_SYNTAX,
+                            t.span(),
                             String::from("Symbols are the only literal value that may be invoked."),
                         )),
                     },
-                    ParseTree::Branch(_elems, start, _stop, _) => Err((
+                    ParseTree::Branch(_elems, start, _stop, _) => Err(Diagnostic::error(
+                        INVALID_SYNTAX,
                         *start,
                         String::from("Compound forms cannot be used as function calls."),
                     )),
-                };
+                }
             }
             ParseTree::Branch(_, start, _, _) => {
-                Err((*start, String::from("Unexpected syntax token `.`.")))
+                Err(Diagnostic::error(INVALID_SYNTAX, *start, String::from("Unexpected syntax token `.`.")))
+            }
+        }
+    }
+
+    /// Read the list of required argument names and the optional vararg name used by `lambda` and
+    /// `defun`. The argument and position are only used for error messages.
+    fn try_from_arglist(
+        tree: &ParseTree,
+        form: &str,
+        argument: &str,
+        position: &str,
+    ) -> Result<(Vec<String>, Option<String>), Diagnostic> {
+        let mut names = Vec::new();
+        let mut vararg = None;
+
+        let not_symbol = format!(
+            "All elements in {} argument to `{}` special form should be symbols.",
+            argument, form
+        );
+
+        match tree {
+            ParseTree::Branch(args, start, _stop, varg) => {
+                for arg in args {
+                    if let ParseTree::Leaf(t) = arg {
+                        match t.value() {
+                            Symbol(n) => names.push(n.clone()),
+                            _ => return Err(Diagnostic::error(INVALID_SYNTAX, *start, not_symbol)),
+                        }
+                    } else {
+                        return Err(Diagnostic::error(INVALID_SYNTAX, *start, not_symbol));
+                    }
+                }
+
+                if let Some(b) = varg {
+                    match b.as_ref() {
+                        ParseTree::Leaf(t) => {
+                            if let Symbol(n) = t.value() {
+                                vararg = Some(n.clone());
+                            } else {
+                                return Err(Diagnostic::error(
+                                    INVALID_SYNTAX,
+                                    t.span(),
+                                    "Expected a symbol to be used as a vararg.".to_string(),
+                                ));
+                            }
+                        }
+                        ParseTree::Branch(_, start, _, _) => {
+                            return Err(Diagnostic::error(INVALID_SYNTAX, *start, not_symbol));
+                        }
+                    }
+                }
+            }
+            ParseTree::Leaf(t) => {
+                return Err(Diagnostic::error(
+                    INVALID_SYNTAX,
+                    t.span(),
+                    format!(
+                        "Expected arglist in {} position of `{}` special form.",
+                        position, form
+                    ),
+                ));
+            }
+        }
+
+        Ok((names, vararg))
+    }
+
+    /// Build any of the `let`, `let*`, and `letrec` special forms. A `let*` is expanded into nested
+    /// single binding `let` forms, each with its own scope, so that later initializers can see the
+    /// names bound before them.
+    fn try_from_let(
+        form: &str,
+        span: Span,
+        elems: &[ParseTree],
+        scope_id: &mut usize,
+    ) -> Result<ASTNode, Diagnostic> {
+        if elems.len() < 3 {
+            return Err(Diagnostic::error(
+                INVALID_SYNTAX,
+                span,
+                format!(
+                    "Expected at least 2 arguments in `{}` special form. Found {}.",
+                    form,
+                    elems.len() - 1
+                ),
+            ));
+        }
+
+        let mut bindings: Vec<(String, Value)> = Vec::new();
+
+        match &elems[1] {
+            ParseTree::Branch(pairs, _, _, None) => {
+                for pair in pairs {
+                    match pair {
+                        ParseTree::Branch(pair, start, _, None) if pair.len() == 2 => {
+                            let name = match &pair[0] {
+                                ParseTree::Leaf(Token {
+                                    value: Symbol(n), ..
+                                }) => n.clone(),
+                                _ => {
+                                    return Err(Diagnostic::error(
+                                        INVALID_SYNTAX,
+                                        *start,
+                                        format!(
+                                            "Expected a symbol to be bound in `{}` special form.",
+                                            form
+                                        ),
+                                    ))
+                                }
+                            };
+
+                            if form != "let*" && bindings.iter().any(|(n, _)| n == &name) {
+                                return Err(Diagnostic::error(
+                                    INVALID_SYNTAX,
+                                    *start,
+                                    format!(
+                                        "Duplicate binding for `{}` in `{}` special form.",
+                                        name, form
+                                    ),
+                                ));
+                            }
+
+                            match Self::try_from_parse_tree(&pair[1], scope_id)? {
+                                ASTNode::Value(v) => bindings.push((name, v)),
+                                _ => {
+                                    return Err(Diagnostic::error(
+                                        INVALID_SYNTAX,
+                                        *start,
+                                        format!("Expected a value to be bound to `{}`.", name),
+                                    ))
+                                }
+                            }
+                        }
+                        ParseTree::Leaf(Token { span, .. }) | ParseTree::Branch(_, span, _, _) => {
+                            return Err(Diagnostic::error(
+                                INVALID_SYNTAX,
+                                *span,
+                                format!(
+                                    "Each binding in `{}` special form should be a list containing a symbol and a value.",
+                                    form
+                                ),
+                            ))
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(Diagnostic::error(
+                    INVALID_SYNTAX,
+                    span,
+                    format!(
+                        "Expected binding list in second position of `{}` special form.",
+                        form
+                    ),
+                ))
+            }
+        }
+
+        let body = Self::try_from_body(form, span, &elems[2..], scope_id)?;
+
+        let kind = if form == "letrec" {
+            LetKind::LetRec
+        } else {
+            LetKind::Let
+        };
+
+        let groups: Vec<Vec<(String, Value)>> = if form == "let*" && !bindings.is_empty() {
+            bindings.into_iter().map(|b| vec![b]).collect()
+        } else {
+            vec![bindings]
+        };
+
+        // Wrap from the inside out so that the first binding ends up outermost.
+        let mut body = body;
+
+        for group in groups.into_iter().rev() {
+            let group = group
+                .into_iter()
+                .map(|(name, v)| (name, vec![ASTNode::Value(v)]))
+                .collect();
+
+            *scope_id += 1;
+            body = vec![ASTNode::Value(Let(kind, group, body, *scope_id))];
+        }
+
+        Ok(body.pop().unwrap())
+    }
+
+    /// Convert quoted syntax to data without evaluating any of it.
+    fn quoted_from_parse_tree(tree: &ParseTree) -> Result<Quoted, Diagnostic> {
+        match tree {
+            ParseTree::Leaf(t) => match t.value() {
+                Open | Close | Cons => Err(Diagnostic::error(INVALID_SYNTAX, t.span(), "Unexpected token in quoted data.".to_string())),
+                _ => Ok(Quoted::Atom(t.clone())),
+            },
+            ParseTree::Branch(elems, _, _, tail) => {
+                let mut items = Vec::new();
+
+                for elem in elems {
+                    items.push(Self::quoted_from_parse_tree(elem)?);
+                }
+
+                let tail = match tail {
+                    Some(t) => Some(Box::new(Self::quoted_from_parse_tree(t)?)),
+                    None => None,
+                };
+
+                Ok(Quoted::List(items, tail))
+            }
+        }
+    }
+
+    /// Build a quasiquoted form at a given nesting depth. Only unquotes at depth 1 are evaluated.
+    /// Lists containing them are built at runtime using the `cons` and `append` natives, while any
+    /// part that contains no evaluated unquotes is kept as constant data.
+    fn try_from_quasiquote(
+        tree: &ParseTree,
+        depth: usize,
+        scope_id: &mut usize,
+    ) -> Result<Value, Diagnostic> {
+        let elems = match tree {
+            ParseTree::Leaf(_) => return Ok(Quotation(Self::quoted_from_parse_tree(tree)?)),
+            ParseTree::Branch(elems, _, _, _) => elems,
+        };
+
+        match Self::unquoted(tree, depth) {
+            Some(("unquote", span)) => return Self::try_from_unquote("unquote", span, elems, scope_id),
+            Some((form, span)) => {
+                return Err(Diagnostic::error(
+                    INVALID_SYNTAX,
+                    span,
+                    format!("`{}` can only be used inside of a list.", form),
+                ))
+            }
+            None => (),
+        }
+
+        // The argument to a nested quasiquote or unquote changes the depth, while the symbol
+        // itself is just data.
+        let inner_depth = match elems.as_slice() {
+            [ParseTree::Leaf(Token { value: Symbol(s), .. }), _] if s == "quasiquote" => depth + 1,
+            [ParseTree::Leaf(Token { value: Symbol(s), .. }), _]
+                if s == "unquote" || s == "unquote-splicing" =>
+            {
+                depth - 1
+            }
+            _ => depth,
+        };
+
+        let mut rest = match tree {
+            ParseTree::Branch(_, _, _, Some(tail)) => {
+                Self::try_from_quasiquote(tail, depth, scope_id)?
+            }
+            _ => Quotation(Quoted::List(Vec::new(), None)),
+        };
+
+        for (i, elem) in elems.iter().enumerate().rev() {
+            let d = if i == 0 { depth } else { inner_depth };
+
+            if let (ParseTree::Branch(e, ..), Some(("unquote-splicing", span))) =
+                (elem, Self::unquoted(elem, d))
+            {
+                let spliced = Self::try_from_unquote("unquote-splicing", span, e, scope_id)?;
+                rest = Call("append".to_string(), vec![spliced, rest], span);
+                continue;
+            }
+
+            rest = match (Self::try_from_quasiquote(elem, d, scope_id)?, rest) {
+                (Quotation(car), Quotation(Quoted::List(mut items, tail))) => {
+                    items.insert(0, car);
+                    Quotation(Quoted::List(items, tail))
+                }
+                (Quotation(car), Quotation(cdr)) => Quotation(Quoted::List(vec![car], Some(Box::new(cdr)))),
+                (car, cdr) => Call("cons".to_string(), vec![car, cdr], elem.span()),
+            };
+        }
+
+        Ok(rest)
+    }
+
+    /// Check if a tree is an `unquote` or `unquote-splicing` form that should be evaluated at the
+    /// given quasiquote depth, returning the name of the form and its span if so.
+    fn unquoted(tree: &ParseTree, depth: usize) -> Option<(&'static str, Span)> {
+        if depth != 1 {
+            return None;
+        }
+
+        match tree {
+            ParseTree::Branch(elems, _, _, None) => match elems.first() {
+                Some(ParseTree::Leaf(Token {
+                    value: Symbol(s),
+                    span,
+                })) if s == "unquote" => Some(("unquote", *span)),
+                Some(ParseTree::Leaf(Token {
+                    value: Symbol(s),
+                    span,
+                })) if s == "unquote-splicing" => Some(("unquote-splicing", *span)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Build the value of an unquoted form, which is evaluated like any other value.
+    fn try_from_unquote(
+        form: &str,
+        span: Span,
+        elems: &[ParseTree],
+        scope_id: &mut usize,
+    ) -> Result<Value, Diagnostic> {
+        if elems.len() != 2 {
+            return Err(Diagnostic::error(
+                INVALID_SYNTAX,
+                span,
+                format!(
+                    "Expected exactly 1 argument in `{}` special form. Found {}.",
+                    form,
+                    elems.len() - 1
+                ),
+            ));
+        }
+
+        match Self::try_from_parse_tree(&elems[1], scope_id)? {
+            ASTNode::Value(v) => Ok(v),
+            _ => Err(Diagnostic::error(INVALID_SYNTAX, span, format!("Expected a value in `{}` special form.", form))),
+        }
+    }
+
+    /// Build the body of a `lambda`, `defun`, `let`, or `begin`. Every form must be a value, and
+    /// the last one is used as the result.
+    fn try_from_body(
+        form: &str,
+        span: Span,
+        forms: &[ParseTree],
+        scope_id: &mut usize,
+    ) -> Result<Vec<ASTNode>, Diagnostic> {
+        let mut body = Vec::new();
+
+        for (i, tree) in forms.iter().enumerate() {
+            match Self::try_from_parse_tree(tree, scope_id)? {
+                ASTNode::Statement(_) if i == forms.len() - 1 => {
+                    return Err(Diagnostic::error(
+                        INVALID_SYNTAX,
+                        span,
+                        format!(
+                            "Expected final argument to `{}` special form to be a value.",
+                            form
+                        ),
+                    ))
+                }
+                ASTNode::Statement(_) => {
+                    return Err(Diagnostic::error(
+                        INVALID_SYNTAX,
+                        span,
+                        format!(
+                            "Expected every form in the body of `{}` special form to be a value.",
+                            form
+                        ),
+                    ))
+                }
+                node => body.push(node),
             }
         }
+
+        Ok(body)
     }
 }
 
-pub fn construct_ast(parse_tree: &Vec<ParseTree>) -> Result<Vec<ASTNode>, (u32, String)> {
+#[cfg(any(test, feature = "fuzz"))]
+pub fn construct_ast(parse_tree: &[ParseTree]) -> Result<Vec<ASTNode>, Diagnostic> {
+    let mut diagnostics = Diagnostics::new(1);
+    let ast = construct_ast_all(parse_tree, &mut diagnostics);
+
+    diagnostics.into_result(ast)
+}
+
+/// Like `construct_ast`, but reports errors and moves on to the next top level form. A form that
+/// fails to define a name is replaced by a definition of `nil`, so that later uses of the name
+/// aren't reported as well.
+pub fn construct_ast_all(parse_tree: &[ParseTree], diagnostics: &mut Diagnostics) -> Vec<ASTNode> {
+    construct_ast_after(parse_tree, &mut 0, diagnostics)
+}
+
+/// Like `construct_ast_all`, but numbers scopes after `scope_count`, which is left as the last
+/// scope used. This lets programs that are constructed a piece at a time, like the inputs of the
+/// REPL, share a symbol table.
+pub fn construct_ast_after(
+    parse_tree: &[ParseTree],
+    scope_count: &mut usize,
+    diagnostics: &mut Diagnostics,
+) -> Vec<ASTNode> {
     let mut ast = Vec::new();
-    let mut scope_count: usize = 0;
 
     for tree in parse_tree {
-        ast.push(ASTNode::try_from((tree, &mut scope_count))?);
+        if diagnostics.is_full() {
+            break;
+        }
+
+        match ASTNode::try_from((tree, &mut *scope_count)) {
+            Ok(node) => ast.push(node),
+            Err(d) => {
+                diagnostics.report(d);
+
+                if let ParseTree::Branch(elems, ..) = tree {
+                    if let [ParseTree::Leaf(Token { value: Symbol(form), .. }), ParseTree::Leaf(Token { value: Symbol(name), span }), ..] = elems.as_slice() {
+                        if form == "define" || form == "defun" {
+                            ast.push(ASTNode::Statement(Definition(name.clone(), Literal(Token::from(Nil)), *span)));
+                        }
+                    }
+                }
+            }
+        }
     }
 
-    Ok(ast)
+    ast
+}
+
+/// Applies every visitor to a newly constructed AST, in the order that the transpiler expects.
+/// Errors are reported, and the forms that cause them are left out of the result.
+pub fn apply_visitors(
+    ast: &[ASTNode],
+    sym_table: &mut SymbolTable,
+    diagnostics: &mut Diagnostics,
+) -> Vec<ASTNode> {
+    PassManager::standard().run(ast, sym_table, diagnostics)
 }
 
 impl From<Token> for ASTNode {
@@ -203,7 +650,7 @@ impl From<Token> for ASTNode {
 }
 
 impl TryFrom<(&ParseTree, &mut usize)> for ASTNode {
-    type Error = (u32, String);
+    type Error = Diagnostic;
 
     fn try_from(data: (&ParseTree, &mut usize)) -> Result<Self, Self::Error> {
         let (tree, scope_id) = data;
@@ -216,21 +663,61 @@ pub enum Value {
     // Should only hold valued tokens. Anything else should be removed during the parsing step.
     Literal(Token),
 
-    // obviously callee and arguments
-    Call(String, Vec<Value>),
+    // callee, arguments, span of the callee
+    Call(String, Vec<Value>, Span),
 
-    // required_args, vararg, body, scope ID
-    // The body should be a single element on creation, but may be expanded as a result of other
-    // visitors.
-    Lambda(Vec<String>, Option<String>, Vec<ASTNode>, usize),
+    // required_args, vararg, body, scope ID, span
+    // The body holds one or more values on creation, the last of which is the result. It may be
+    // expanded as a result of other visitors.
+    Lambda(Vec<String>, Option<String>, Vec<ASTNode>, usize, Span),
 
     // condition, value if true, value if false
     Condition(Box<Value>, Box<Value>, Box<Value>),
+
+    // kind, bindings, body, scope ID
+    // Each initializer follows the same rules as a lambda body: a single value on creation, which
+    // may be preceded by statements as a result of other visitors.
+    Let(LetKind, Vec<(String, Vec<ASTNode>)>, Vec<ASTNode>, usize),
+
+    // body
+    // Follows the same rules as a lambda body, but is evaluated in place.
+    Begin(Vec<ASTNode>),
+
+    // Constant data built from `quote`, or the constant parts of a `quasiquote`.
+    Quotation(Quoted),
+
+    // arguments, span
+    // Restarts the enclosing function with new arguments. Only valid in tail position.
+    Recur(Vec<Value>, Span),
+
+    // callee, arguments, span of the callee
+    // A call in tail position of a function body. Only created by `TailCallMarking`.
+    TailCall(String, Vec<Value>, Span),
+}
+
+/// Data produced by quoting. Symbols inside of quoted data are never looked up.
+#[derive(Clone, Debug)]
+pub enum Quoted {
+    Atom(Token),
+    // elements, tail following `.`
+    List(Vec<Quoted>, Option<Box<Quoted>>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LetKind {
+    // Initializers are evaluated in the enclosing scope. `let*` is expanded to nested `Let`s.
+    Let,
+    // Initializers are evaluated in the new scope, so bindings may refer to each other.
+    LetRec,
 }
 
 #[derive(Clone, Debug)]
 pub enum Statement {
-    Definition(String, Value),
+    // name, required_args, vararg, body, scope ID, span of the name
+    // Only allowed at the top level. The body follows the same rules as that of a lambda.
+    FunctionDefinition(String, Vec<String>, Option<String>, Vec<ASTNode>, usize, Span),
+    // name, value, span of the name
+    Definition(String, Value, Span),
     Redefinition(String, Value),
     Declaration(String),
     ExpandedCondition(Value, Vec<ASTNode>, Vec<ASTNode>),
@@ -241,7 +728,7 @@ pub trait ASTVisitor<T> {
         self.try_visit(ast, sym_table).unwrap()
     }
 
-    fn try_visit(&self, ast: &ASTNode, sym_table: &mut SymbolTable) -> Result<T, (u32, String)>;
+    fn try_visit(&self, ast: &ASTNode, sym_table: &mut SymbolTable) -> Result<T, Diagnostic>;
 }
 
 #[derive(Copy, Clone)]
@@ -257,20 +744,24 @@ impl Gensym {
     fn gen(&mut self, symbol: &str, prefix: Option<&str>) -> String {
         self.counter += 1;
         let s = Self::convert(symbol);
-        let prefix = if prefix.is_none() {
-            String::from("")
-        } else {
-            format!("_{}", prefix.unwrap()).as_str().to_owned()
+        let prefix = match prefix {
+            Some(p) => format!("_{}", p),
+            None => String::from(""),
         };
 
-        format!("gensym{}{}_{}", self.counter, prefix, s).to_string()
+        format!("gensym{}{}_{}", self.counter, prefix, s)
+    }
+
+    fn is_generated(name: &str) -> bool {
+        name.strip_prefix("gensym")
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
     }
 
     /// Transform a non-C compliant symbol into a C compliant one.
     fn convert(name: &str) -> String {
         let mut output = String::new();
 
-        if name.chars().nth(0).unwrap().is_digit(10) {
+        if name.chars().next().unwrap().is_ascii_digit() {
             output.push('_');
         }
 
@@ -311,18 +802,18 @@ impl ASTVisitor<Vec<ASTNode>> for FunctionUnfurl {
         &self,
         ast: &ASTNode,
         sym_table: &mut SymbolTable,
-    ) -> Result<Vec<ASTNode>, (u32, String)> {
+    ) -> Result<Vec<ASTNode>, Diagnostic> {
         let mut mapping: Vec<Value> = Vec::new();
         let mut result = Vec::new();
 
         match ast {
-            ASTNode::Value(Call(callee, args)) => {
+            ASTNode::Value(Call(callee, args, span)) => {
                 for arg in args {
                     match arg {
-                        Call(_, _args) => {
+                        Call(..) => {
                             let subexpansion =
                                 self.try_visit(&ASTNode::Value(arg.clone()), sym_table)?;
-                            assert!(subexpansion.len() > 0);
+                            assert!(!subexpansion.is_empty());
 
                             // All the preliminary statements are definitions.
                             for statement in &subexpansion[0..subexpansion.len() - 1] {
@@ -338,16 +829,32 @@ impl ASTVisitor<Vec<ASTNode>> for FunctionUnfurl {
                             mapping.push(Value::Literal(Token::from(Symbol(s.clone()))));
                             let c = subexpansion.last().unwrap();
                             if let ASTNode::Value(v) = c {
-                                result.push(ASTNode::Statement(Definition(s, v.clone())))
+                                result.push(ASTNode::Statement(Definition(s, v.clone(), Span::default())))
                             }
                         }
+                        Lambda(..) | Let(..) | Begin(..) => {
+                            // These are expanded internally, so they remain a single value.
+                            let expansion =
+                                self.try_visit(&ASTNode::Value(arg.clone()), sym_table)?;
+                            mapping.push(expansion[0].as_value().to_owned());
+                        }
                         _ => mapping.push(arg.clone()), // It isn't a function call, so we don't deal with it here.
                     }
                 }
 
-                result.push(ASTNode::Value(Call(callee.clone(), mapping)))
+                result.push(ASTNode::Value(Call(callee.clone(), mapping, *span)))
+            }
+            ASTNode::Value(Recur(args, span)) => {
+                // Arguments are unfurled exactly like those of a call.
+                let mut expansion =
+                    self.try_visit(&ASTNode::Value(Call(String::from("recur"), args.clone(), *span)), sym_table)?;
+
+                if let Some(ASTNode::Value(Call(_, args, _))) = expansion.pop() {
+                    result.append(&mut expansion);
+                    result.push(ASTNode::Value(Recur(args, *span)));
+                }
             }
-            ASTNode::Value(Lambda(args, vararg, body, scope_id)) => {
+            ASTNode::Value(Lambda(args, vararg, body, scope_id, span)) => {
                 let mut new_body = Vec::new();
 
                 for line in body {
@@ -359,6 +866,53 @@ impl ASTVisitor<Vec<ASTNode>> for FunctionUnfurl {
                     vararg.clone(),
                     new_body,
                     *scope_id,
+                    *span,
+                )));
+            }
+            ASTNode::Value(Let(kind, bindings, body, scope_id)) => {
+                let mut new_bindings = Vec::new();
+
+                for (name, init) in bindings {
+                    let mut new_init = Vec::new();
+
+                    for line in init {
+                        new_init.append(&mut self.try_visit(line, sym_table)?)
+                    }
+
+                    new_bindings.push((name.clone(), new_init));
+                }
+
+                let mut new_body = Vec::new();
+
+                for line in body {
+                    new_body.append(&mut self.try_visit(line, sym_table)?)
+                }
+
+                result.push(ASTNode::Value(Let(*kind, new_bindings, new_body, *scope_id)));
+            }
+            ASTNode::Value(Begin(body)) => {
+                let mut new_body = Vec::new();
+
+                for line in body {
+                    new_body.append(&mut self.try_visit(line, sym_table)?)
+                }
+
+                result.push(ASTNode::Value(Begin(new_body)));
+            }
+            ASTNode::Statement(FunctionDefinition(name, args, vararg, body, scope_id, span)) => {
+                let mut new_body = Vec::new();
+
+                for line in body {
+                    new_body.append(&mut self.try_visit(line, sym_table)?)
+                }
+
+                result.push(ASTNode::Statement(FunctionDefinition(
+                    name.clone(),
+                    args.clone(),
+                    vararg.clone(),
+                    new_body,
+                    *scope_id,
+                    *span,
                 )));
             }
             ASTNode::Value(Condition(c, t, f)) => {
@@ -383,10 +937,10 @@ pub struct ConditionUnroll;
 impl ConditionUnroll {
     fn split_condition(
         &self,
-        c: &Box<Value>,
+        c: &Value,
         sym_table: &mut SymbolTable,
     ) -> (Value, Vec<ASTNode>) {
-        let mut prefix = self.visit(&ASTNode::Value(*c.clone()), sym_table);
+        let mut prefix = self.visit(&ASTNode::Value(c.clone()), sym_table);
         let value = prefix.pop().unwrap();
 
         if let ASTNode::Value(v) = value {
@@ -402,7 +956,7 @@ impl ASTVisitor<Vec<ASTNode>> for ConditionUnroll {
         &self,
         ast: &ASTNode,
         sym_table: &mut SymbolTable,
-    ) -> Result<Vec<ASTNode>, (u32, String)> {
+    ) -> Result<Vec<ASTNode>, Diagnostic> {
         let mut output: Vec<ASTNode> = Vec::new();
         let mut iftrue: Vec<ASTNode>;
         let mut iffalse: Vec<ASTNode>;
@@ -415,16 +969,10 @@ impl ASTVisitor<Vec<ASTNode>> for ConditionUnroll {
 
                 iftrue = self.try_visit(&ASTNode::Value(*t.clone()), sym_table)?;
                 iffalse = self.try_visit(&ASTNode::Value(*f.clone()), sym_table)?;
-                let condition;
-
-                match **c {
-                    Condition(_, _, _) => {
-                        let (value, mut prefix) = self.split_condition(c, sym_table);
-                        condition = Box::new(value);
-                        output.append(&mut prefix);
-                    }
-                    _ => condition = c.clone(),
-                }
+                // Conditions may be nested anywhere in the condition itself, including inside of
+                // calls and let forms, so it's always split.
+                let (condition, mut prefix) = self.split_condition(c, sym_table);
+                output.append(&mut prefix);
 
                 // Update each branch to assign to the output variable.
                 let true_value = iftrue.pop().unwrap();
@@ -440,7 +988,7 @@ impl ASTVisitor<Vec<ASTNode>> for ConditionUnroll {
                 )));
 
                 output.push(ASTNode::Statement(ExpandedCondition(
-                    *condition, iftrue, iffalse,
+                    condition, iftrue, iffalse,
                 )));
                 output.push(ASTNode::Value(Literal(Token::from(Symbol(
                     output_name.clone(),
@@ -449,21 +997,21 @@ impl ASTVisitor<Vec<ASTNode>> for ConditionUnroll {
                 Ok(output)
             }
             // Handle the case of a condition used as a value for a definition.
-            ASTNode::Statement(Definition(name, v)) | ASTNode::Statement(Redefinition(name, v)) => {
+            ASTNode::Statement(Definition(name, v, _)) | ASTNode::Statement(Redefinition(name, v)) => {
                 let mut prefix = self.try_visit(&ASTNode::Value(v.clone()), sym_table)?;
                 let value = prefix.pop().unwrap();
 
                 output.append(&mut prefix);
 
-                if let ASTNode::Statement(Definition(..)) = ast {
-                    output.push(ASTNode::Statement(Definition(name.clone(), value.as_value().to_owned())))
+                if let ASTNode::Statement(Definition(_, _, span)) = ast {
+                    output.push(ASTNode::Statement(Definition(name.clone(), value.as_value().to_owned(), *span)))
                 } else {
                     output.push(ASTNode::Statement(Redefinition(name.clone(), value.as_value().to_owned())))
                 }
                 Ok(output)
             }
             // Handle the case of a condition inside a function call.
-            ASTNode::Value(Call(callee, args)) => {
+            ASTNode::Value(Call(callee, args, span)) => {
                 let mut new_args = Vec::new();
 
                 for arg in args {
@@ -482,12 +1030,24 @@ impl ASTVisitor<Vec<ASTNode>> for ConditionUnroll {
                     .map(|node| node.as_value().clone())
                     .collect();
 
-                output.push(ASTNode::Value(Call(callee.clone(), new_args)));
+                output.push(ASTNode::Value(Call(callee.clone(), new_args, *span)));
+
+                Ok(output)
+            }
+            // Handle the case of a condition inside of the arguments to `recur`.
+            ASTNode::Value(Recur(args, span)) => {
+                let mut expansion =
+                    self.try_visit(&ASTNode::Value(Call(String::from("recur"), args.clone(), *span)), sym_table)?;
+
+                if let Some(ASTNode::Value(Call(_, args, _))) = expansion.pop() {
+                    output.append(&mut expansion);
+                    output.push(ASTNode::Value(Recur(args, *span)));
+                }
 
-                return Ok(output);
+                Ok(output)
             }
             // Condition in lambda body.
-            ASTNode::Value(Lambda(args, vararg, body, scope_id)) => {
+            ASTNode::Value(Lambda(args, vararg, body, scope_id, span)) => {
                 let mut new_body = Vec::new();
 
                 for line in body {
@@ -499,57 +1059,199 @@ impl ASTVisitor<Vec<ASTNode>> for ConditionUnroll {
                     vararg.clone(),
                     new_body,
                     *scope_id,
+                    *span,
                 ))])
             }
-            _ => Ok(vec![ast.clone()]),
-        }
-    }
-}
+            // Condition in let initializer or body.
+            ASTNode::Value(Let(kind, bindings, body, scope_id)) => {
+                let mut new_bindings = Vec::new();
 
-pub struct SymbolValidation;
+                for (name, init) in bindings {
+                    let mut new_init = Vec::new();
 
-impl SymbolValidation {
-    fn try_visit_aux(
-        &self,
-        ast: &ASTNode,
-        sym_table: &mut SymbolTable,
-        scope_ids: &mut Vec<usize>,
-    ) -> Result<ASTNode, (u32, String)> {
-        match ast {
-            ASTNode::Statement(Statement::ExpandedCondition(v, t, f)) => {
-                let v = self.try_visit_aux(&ASTNode::Value(v.clone()), sym_table, scope_ids)?;
-                let mut mt = Vec::new();
-                let mut mf = Vec::new();
+                    for line in init {
+                        new_init.append(&mut self.try_visit(line, sym_table)?);
+                    }
 
-                for x in t {
-                    mt.push(self.try_visit_aux(x, sym_table, scope_ids)?);
+                    new_bindings.push((name.clone(), new_init));
                 }
 
-                for x in f {
-                    mf.push(self.try_visit_aux(x, sym_table, scope_ids)?);
+                let mut new_body = Vec::new();
+
+                for line in body {
+                    new_body.append(&mut self.try_visit(line, sym_table)?);
                 }
 
-                Ok(ASTNode::Statement(ExpandedCondition(
-                    v.as_value().clone(),
-                    mt,
-                    mf,
-                )))
-            }
-            ASTNode::Statement(Definition(name, value)) => {
-                let is_definition = sym_table.get(name.as_str(), None).is_none();
+                Ok(vec![ASTNode::Value(Let(
+                    *kind,
+                    new_bindings,
+                    new_body,
+                    *scope_id,
+                ))])
+            }
+            // Condition inside of a begin form.
+            ASTNode::Value(Begin(body)) => {
+                let mut new_body = Vec::new();
+
+                for line in body {
+                    new_body.append(&mut self.try_visit(line, sym_table)?);
+                }
+
+                Ok(vec![ASTNode::Value(Begin(new_body))])
+            }
+            // Condition in function definition body.
+            ASTNode::Statement(FunctionDefinition(name, args, vararg, body, scope_id, span)) => {
+                let mut new_body = Vec::new();
+
+                for line in body {
+                    new_body.append(&mut self.try_visit(line, sym_table)?);
+                }
+
+                Ok(vec![ASTNode::Statement(FunctionDefinition(
+                    name.clone(),
+                    args.clone(),
+                    vararg.clone(),
+                    new_body,
+                    *scope_id,
+                    *span,
+                ))])
+            }
+            _ => Ok(vec![ast.clone()]),
+        }
+    }
+}
+
+pub struct SymbolValidation;
+
+impl SymbolValidation {
+    /// Reports calls to names that aren't functions. This can only be checked once every node of
+    /// the program has been validated, since functions may call others that are defined later.
+    pub fn undefined_calls(&self, ast: &ASTNode, sym_table: &SymbolTable) -> Vec<Diagnostic> {
+        let mut errors = Vec::new();
+        Self::find_calls(std::slice::from_ref(ast), sym_table, &mut errors);
+        errors
+    }
+
+    fn find_calls(body: &[ASTNode], sym_table: &SymbolTable, errors: &mut Vec<Diagnostic>) {
+        for node in body {
+            match node {
+                ASTNode::Value(v)
+                | ASTNode::Statement(Definition(_, v, _))
+                | ASTNode::Statement(Redefinition(_, v)) => Self::find_value_calls(v, sym_table, errors),
+                ASTNode::Statement(ExpandedCondition(c, t, f)) => {
+                    Self::find_value_calls(c, sym_table, errors);
+                    Self::find_calls(t, sym_table, errors);
+                    Self::find_calls(f, sym_table, errors);
+                }
+                ASTNode::Statement(FunctionDefinition(_, _, _, body, _, _)) => Self::find_calls(body, sym_table, errors),
+                ASTNode::Statement(Declaration(_)) => (),
+            }
+        }
+    }
+
+    fn find_value_calls(value: &Value, sym_table: &SymbolTable, errors: &mut Vec<Diagnostic>) {
+        match value {
+            Call(callee, args, span) | TailCall(callee, args, span) => {
+                if !sym_table.contains_fn(callee) {
+                    errors.push(Diagnostic::error(
+                        UNDEFINED_SYMBOL,
+                        *span,
+                        format!("Call to undefined function `{}`.", callee),
+                    ));
+                }
+
+                args.iter().for_each(|a| Self::find_value_calls(a, sym_table, errors));
+            }
+            Recur(args, _) => args.iter().for_each(|a| Self::find_value_calls(a, sym_table, errors)),
+            Condition(c, t, f) => {
+                for v in [c, t, f].iter() {
+                    Self::find_value_calls(v, sym_table, errors);
+                }
+            }
+            Lambda(_, _, body, _, _) | Begin(body) => Self::find_calls(body, sym_table, errors),
+            Let(_, bindings, body, _) => {
+                for (_, init) in bindings {
+                    Self::find_calls(init, sym_table, errors);
+                }
+
+                Self::find_calls(body, sym_table, errors);
+            }
+            Literal(..) | Quotation(..) => (),
+        }
+    }
+
+    /// Validates a node like `try_visit`, but reports every use of an undefined symbol in it,
+    /// rather than only the first.
+    pub fn validate(
+        &self,
+        ast: &ASTNode,
+        sym_table: &mut SymbolTable,
+    ) -> Result<ASTNode, Vec<Diagnostic>> {
+        let mut errors = Vec::new();
+
+        match self.try_visit_aux(ast, sym_table, &mut Vec::new(), &mut errors) {
+            Ok(node) if errors.is_empty() => Ok(node),
+            Ok(_) => Err(errors),
+            Err(d) => {
+                errors.push(d);
+                Err(errors)
+            }
+        }
+    }
+
+    fn try_visit_aux(
+        &self,
+        ast: &ASTNode,
+        sym_table: &mut SymbolTable,
+        scope_ids: &mut Vec<usize>,
+        undefined: &mut Vec<Diagnostic>,
+    ) -> Result<ASTNode, Diagnostic> {
+        match ast {
+            ASTNode::Statement(Statement::ExpandedCondition(v, t, f)) => {
+                let v = self.try_visit_aux(&ASTNode::Value(v.clone()), sym_table, scope_ids, undefined)?;
+                let mut mt = Vec::new();
+                let mut mf = Vec::new();
+
+                for x in t {
+                    mt.push(self.try_visit_aux(x, sym_table, scope_ids, undefined)?);
+                }
+
+                for x in f {
+                    mf.push(self.try_visit_aux(x, sym_table, scope_ids, undefined)?);
+                }
+
+                Ok(ASTNode::Statement(ExpandedCondition(
+                    v.as_value().clone(),
+                    mt,
+                    mf,
+                )))
+            }
+            ASTNode::Statement(Definition(name, value, span)) => {
+                // Natives and functions defined with `defun` aren't variables that can be
+                // reassigned.
+                if sym_table.contains_fn(name.as_str()) {
+                    return Err(Diagnostic::error(
+                        REDEFINED_FUNCTION,
+                        *span,
+                        format!("Cannot redefine function `{}`.", name),
+                    ));
+                }
+
+                let is_definition = sym_table.get(name.as_str(), None).is_none();
 
                 if is_definition {
                     sym_table.register(name.as_str(), None);
                 }
 
                 let value =
-                    self.try_visit_aux(&ASTNode::Value(value.clone()), sym_table, scope_ids)?;
+                    self.try_visit_aux(&ASTNode::Value(value.clone()), sym_table, scope_ids, undefined)?;
 
                 // No scope IDs are required because definitions are only allowed at the top level.
                 if is_definition {
                     Ok(ASTNode::Statement(Definition(
                         name.clone(),
                         value.as_value().to_owned(),
+                        *span,
                     )))
                 } else {
                     Ok(ASTNode::Statement(Redefinition(
@@ -558,13 +1260,60 @@ impl SymbolValidation {
                     )))
                 }
             }
+            ASTNode::Statement(FunctionDefinition(name, args, varargs, body, scope, span)) => {
+                let scope = *scope;
+
+                if sym_table.contains_fn(name.as_str()) {
+                    return Err(Diagnostic::error(
+                        REDEFINED_FUNCTION,
+                        *span,
+                        format!("Cannot redefine function `{}`.", name),
+                    ));
+                }
+
+                // The function is registered before its body is visited so that it may call
+                // itself. The name is also bound globally so that it may be used as a value.
+                sym_table.register_fn(name.as_str());
+                sym_table.register(name.as_str(), None);
+
+                for arg in args {
+                    sym_table.register(arg.as_str(), Some(scope))
+                }
+
+                if varargs.is_some() {
+                    sym_table.register(varargs.as_ref().map(|x| x.as_str()).unwrap(), Some(scope));
+                }
+
+                scope_ids.push(scope);
+
+                let body: Vec<Result<ASTNode, Diagnostic>> = body
+                    .iter()
+                    .map(|n| self.try_visit_aux(n, sym_table, scope_ids, undefined))
+                    .collect();
+
+                scope_ids.pop().unwrap();
+
+                let mut new_body = Vec::new();
+                for n in body {
+                    new_body.push(n?);
+                }
+
+                Ok(ASTNode::Statement(FunctionDefinition(
+                    name.clone(),
+                    args.clone(),
+                    varargs.clone(),
+                    new_body,
+                    scope,
+                    *span,
+                )))
+            }
             ASTNode::Statement(Redefinition(name, value)) => {
                 // No scope IDs are required because definitions are only allowed at the top level.
-                if !sym_table.get(name.as_str(), None).is_some() {
-                    Err((0, format!("Cannot redefine symbol `{}` as it does not exist. Contact the developer.", name)))
+                if sym_table.get(name.as_str(), None).is_none() {
+                    Err(Diagnostic::error(INTERNAL, Span::default(), format!("Cannot redefine symbol `{}` as it does not exist. Contact the developer.", name)))
                 } else {
                     let value =
-                        self.try_visit_aux(&ASTNode::Value(value.clone()), sym_table, scope_ids)?;
+                        self.try_visit_aux(&ASTNode::Value(value.clone()), sym_table, scope_ids, undefined)?;
                     Ok(ASTNode::Statement(Redefinition(
                         name.clone(),
                         value.as_value().to_owned(),
@@ -574,8 +1323,9 @@ impl SymbolValidation {
             ASTNode::Statement(Declaration(name)) => {
                 // No scope IDs are required because definitions are only allowed at the top level.
                 if sym_table.get(name.as_str(), None).is_some() {
-                    Err((
-                        0,
+                    Err(Diagnostic::error(
+                        INTERNAL,
+                        Span::default(),
                         format!(
                             "Redeclaration of existing name `{}`. Contact the developer.",
                             name
@@ -589,11 +1339,11 @@ impl SymbolValidation {
             }
             ASTNode::Value(Condition(c, t, f)) => {
                 let c =
-                    self.try_visit_aux(&ASTNode::Value(c.as_ref().clone()), sym_table, scope_ids)?;
+                    self.try_visit_aux(&ASTNode::Value(c.as_ref().clone()), sym_table, scope_ids, undefined)?;
                 let t =
-                    self.try_visit_aux(&ASTNode::Value(t.as_ref().clone()), sym_table, scope_ids)?;
+                    self.try_visit_aux(&ASTNode::Value(t.as_ref().clone()), sym_table, scope_ids, undefined)?;
                 let f =
-                    self.try_visit_aux(&ASTNode::Value(f.as_ref().clone()), sym_table, scope_ids)?;
+                    self.try_visit_aux(&ASTNode::Value(f.as_ref().clone()), sym_table, scope_ids, undefined)?;
 
                 Ok(ASTNode::Value(Condition(
                     Box::new(c.as_value().to_owned()),
@@ -601,29 +1351,52 @@ impl SymbolValidation {
                     Box::new(f.as_value().to_owned()),
                 )))
             }
-            ASTNode::Value(Call(callee, args)) => {
+            ASTNode::Value(Call(callee, args, span)) => {
+                let mut margs = Vec::new();
+
+                for arg in args {
+                    margs.push(
+                        (self.try_visit_aux(&ASTNode::Value(arg.clone()), sym_table, scope_ids, undefined)?)
+                            .as_value()
+                            .to_owned(),
+                    );
+                }
+
+                Ok(ASTNode::Value(Call(callee.clone(), margs, *span)))
+            }
+            ASTNode::Value(Recur(args, span)) => {
                 let mut margs = Vec::new();
 
                 for arg in args {
                     margs.push(
-                        (self.try_visit_aux(&ASTNode::Value(arg.clone()), sym_table, scope_ids)?)
+                        (self.try_visit_aux(&ASTNode::Value(arg.clone()), sym_table, scope_ids, undefined)?)
                             .as_value()
                             .to_owned(),
                     );
                 }
 
-                Ok(ASTNode::Value(Call(callee.clone(), margs)))
+                Ok(ASTNode::Value(Recur(margs, *span)))
+            }
+            ASTNode::Value(TailCall(..)) => {
+                panic!("Tail calls are marked after symbol validation. Contact the developer.")
             }
             ASTNode::Value(Literal(t)) => {
                 if let Symbol(name) = t.value() {
-                    if !sym_table.get(name.as_str(), Some(scope_ids)).is_some() {
-                        return Err((t.line(), format!("Use of undefined variable: {}.", name)));
+                    // Each use is independent, so the rest of the node is still validated.
+                    if sym_table.get(name.as_str(), Some(scope_ids)).is_none() {
+                        undefined.push(Diagnostic::error(
+                            UNDEFINED_SYMBOL,
+                            t.span(),
+                            format!("Use of undefined variable: {}.", name),
+                        ));
                     }
                 }
 
                 Ok(ast.clone())
             }
-            ASTNode::Value(Lambda(args, varargs, body, scope)) => {
+            // Symbols inside of quoted data are never looked up.
+            ASTNode::Value(Quotation(_)) => Ok(ast.clone()),
+            ASTNode::Value(Lambda(args, varargs, body, scope, span)) => {
                 let scope = *scope;
 
                 // Register the known variables and vararg.
@@ -638,9 +1411,9 @@ impl SymbolValidation {
                 // Visit the bodies with the added context of the new scope.
                 scope_ids.push(scope);
 
-                let body: Vec<Result<ASTNode, (u32, String)>> = body
+                let body: Vec<Result<ASTNode, Diagnostic>> = body
                     .iter()
-                    .map(|n| self.try_visit_aux(n, sym_table, scope_ids))
+                    .map(|n| self.try_visit_aux(n, sym_table, scope_ids, undefined))
                     .collect();
 
                 // Invalidate the scope.
@@ -648,11 +1421,7 @@ impl SymbolValidation {
 
                 let mut new_body = Vec::new();
                 for n in body {
-                    if n.is_err() {
-                        return n;
-                    } else {
-                        new_body.push(n.unwrap())
-                    }
+                    new_body.push(n?);
                 }
 
                 Ok(ASTNode::Value(Lambda(
@@ -660,8 +1429,66 @@ impl SymbolValidation {
                     varargs.clone(),
                     new_body,
                     scope,
+                    *span,
                 )))
             }
+            ASTNode::Value(Let(kind, bindings, body, scope)) => {
+                let scope = *scope;
+
+                // Recursive bindings are visible to their own initializers, while regular ones
+                // only become visible in the body.
+                if *kind == LetKind::LetRec {
+                    for (name, _) in bindings {
+                        sym_table.register_local(name.as_str(), scope);
+                    }
+
+                    scope_ids.push(scope);
+                }
+
+                let mut new_bindings = Vec::new();
+
+                for (name, init) in bindings {
+                    let mut new_init = Vec::new();
+
+                    for n in init {
+                        new_init.push(self.try_visit_aux(n, sym_table, scope_ids, undefined)?);
+                    }
+
+                    new_bindings.push((name.clone(), new_init));
+                }
+
+                if *kind == LetKind::Let {
+                    for (name, _) in bindings {
+                        sym_table.register_local(name.as_str(), scope);
+                    }
+
+                    scope_ids.push(scope);
+                }
+
+                let body: Vec<Result<ASTNode, Diagnostic>> = body
+                    .iter()
+                    .map(|n| self.try_visit_aux(n, sym_table, scope_ids, undefined))
+                    .collect();
+
+                // Invalidate the scope.
+                scope_ids.pop().unwrap();
+
+                let mut new_body = Vec::new();
+                for n in body {
+                    new_body.push(n?);
+                }
+
+                Ok(ASTNode::Value(Let(*kind, new_bindings, new_body, scope)))
+            }
+            ASTNode::Value(Begin(body)) => {
+                let mut new_body = Vec::new();
+
+                for n in body {
+                    new_body.push(self.try_visit_aux(n, sym_table, scope_ids, undefined)?);
+                }
+
+                Ok(ASTNode::Value(Begin(new_body)))
+            }
         }
     }
 }
@@ -671,8 +1498,176 @@ impl ASTVisitor<ASTNode> for SymbolValidation {
         &self,
         ast: &ASTNode,
         sym_table: &mut SymbolTable,
-    ) -> Result<ASTNode, (u32, String)> {
-        self.try_visit_aux(ast, sym_table, &mut Vec::new())
+    ) -> Result<ASTNode, Diagnostic> {
+        self.validate(ast, sym_table).map_err(|mut errors| errors.remove(0))
+    }
+}
+
+/// Replaces calls in tail position of a function body with `TailCall`, and ensures that `recur` is
+/// only used in tail position. Must run after `ConditionUnroll`, so the result of a condition is
+/// found in the assignments at the end of each of its branches.
+pub struct TailCallMarking;
+
+// required args, whether there is a vararg
+type Function = (usize, bool);
+
+impl TailCallMarking {
+    /// Mark the nodes of a body. If the body is in tail position, then so is its final value.
+    fn mark_body(
+        &self,
+        body: &[ASTNode],
+        tail: bool,
+        function: Option<Function>,
+    ) -> Result<Vec<ASTNode>, Diagnostic> {
+        let mut output = Vec::new();
+        let n = body.len();
+
+        for (i, node) in body.iter().enumerate() {
+            let is_result = tail && i + 1 == n;
+
+            output.push(match node {
+                ASTNode::Value(v) => ASTNode::Value(self.mark_value(v, is_result, function)?),
+                // The final node in a branch of a condition assigns its result.
+                ASTNode::Statement(Redefinition(name, v)) => ASTNode::Statement(Redefinition(
+                    name.clone(),
+                    self.mark_value(v, is_result, function)?,
+                )),
+                ASTNode::Statement(Definition(name, v, span)) => ASTNode::Statement(Definition(
+                    name.clone(),
+                    self.mark_value(v, false, function)?,
+                    *span,
+                )),
+                ASTNode::Statement(ExpandedCondition(c, t, f)) => {
+                    // The branches are in tail position if the condition's result is immediately
+                    // used as the result of the body.
+                    let feeds_result = tail && i + 2 == n && Self::assigns_result(node, &body[n - 1]);
+
+                    ASTNode::Statement(ExpandedCondition(
+                        self.mark_value(c, false, function)?,
+                        self.mark_body(t, feeds_result, function)?,
+                        self.mark_body(f, feeds_result, function)?,
+                    ))
+                }
+                ASTNode::Statement(FunctionDefinition(name, args, vararg, body, scope_id, span)) => {
+                    ASTNode::Statement(FunctionDefinition(
+                        name.clone(),
+                        args.clone(),
+                        vararg.clone(),
+                        self.mark_body(body, true, Some((args.len(), vararg.is_some())))?,
+                        *scope_id,
+                        *span,
+                    ))
+                }
+                ASTNode::Statement(Declaration(..)) => node.clone(),
+            });
+        }
+
+        Ok(output)
+    }
+
+    /// Check if a condition assigns to the variable holding the result of a body.
+    fn assigns_result(condition: &ASTNode, result: &ASTNode) -> bool {
+        let result = match result {
+            ASTNode::Value(Literal(t)) | ASTNode::Statement(Redefinition(_, Literal(t))) => t.value(),
+            _ => return false,
+        };
+
+        let assigns = |branch: &[ASTNode]| match branch.last() {
+            Some(ASTNode::Statement(Redefinition(name, _))) => Symbol(name.clone()) == result,
+            _ => false,
+        };
+
+        match condition {
+            ASTNode::Statement(ExpandedCondition(_, t, f)) => assigns(t) && assigns(f),
+            _ => false,
+        }
+    }
+
+    fn mark_values(&self, values: &[Value], function: Option<Function>) -> Result<Vec<Value>, Diagnostic> {
+        let mut output = Vec::new();
+
+        for v in values {
+            output.push(self.mark_value(v, false, function)?);
+        }
+
+        Ok(output)
+    }
+
+    fn mark_value(
+        &self,
+        value: &Value,
+        tail: bool,
+        function: Option<Function>,
+    ) -> Result<Value, Diagnostic> {
+        match value {
+            Call(callee, args, span) | TailCall(callee, args, span) => {
+                let args = self.mark_values(args, function)?;
+
+                // Calls made directly from main can't be optimized.
+                if tail && function.is_some() {
+                    Ok(TailCall(callee.clone(), args, *span))
+                } else {
+                    Ok(Call(callee.clone(), args, *span))
+                }
+            }
+            Recur(args, span) => {
+                let (n_args, vararg) = match function {
+                    Some(f) => f,
+                    None => return Err(Diagnostic::error(INVALID_RECUR, *span, String::from("`recur` may only be used inside of a function."))),
+                };
+
+                if !tail {
+                    return Err(Diagnostic::error(INVALID_RECUR, *span, String::from("`recur` may only be used in tail position."))
+                        .with_note("the result of `recur` can't be used, so it must be the last form evaluated by the function"));
+                }
+
+                if args.len() < n_args || (!vararg && args.len() > n_args) {
+                    return Err(Diagnostic::error(
+                        INVALID_RECUR,
+                        *span,
+                        format!(
+                            "Expected {}{} argument(s) to `recur`. Found {}.",
+                            if vararg { "at least " } else { "exactly " },
+                            n_args,
+                            args.len()
+                        ),
+                    ));
+                }
+
+                Ok(Recur(self.mark_values(args, function)?, *span))
+            }
+            Lambda(args, vararg, body, scope_id, span) => Ok(Lambda(
+                args.clone(),
+                vararg.clone(),
+                self.mark_body(body, true, Some((args.len(), vararg.is_some())))?,
+                *scope_id,
+                *span,
+            )),
+            Let(kind, bindings, body, scope_id) => {
+                let mut new_bindings = Vec::new();
+
+                for (name, init) in bindings {
+                    new_bindings.push((name.clone(), self.mark_body(init, false, function)?));
+                }
+
+                Ok(Let(*kind, new_bindings, self.mark_body(body, tail, function)?, *scope_id))
+            }
+            Begin(body) => Ok(Begin(self.mark_body(body, tail, function)?)),
+            Condition(..) => {
+                panic!("Conditions should have been unrolled before marking tail calls. Contact the developer.")
+            }
+            Literal(..) | Quotation(..) => Ok(value.clone()),
+        }
+    }
+}
+
+impl ASTVisitor<ASTNode> for TailCallMarking {
+    fn try_visit(
+        &self,
+        ast: &ASTNode,
+        _sym_table: &mut SymbolTable,
+    ) -> Result<ASTNode, Diagnostic> {
+        Ok(self.mark_body(std::slice::from_ref(ast), false, None)?.remove(0))
     }
 }
 
@@ -680,6 +1675,7 @@ impl ASTVisitor<ASTNode> for SymbolValidation {
 // TODO(matthew-c21): Auto-generate lambdas for native functions.
 pub struct SymbolTable {
     natives: HashMap<String, String>,
+    functions: HashMap<String, String>,
     defs: Vec<HashMap<String, String>>,
     factories: HashMap<String, String>,
     gensym: Gensym,
@@ -692,9 +1688,21 @@ impl SymbolTable {
         self.gensym.gen(base_name, None)
     }
 
+    /// Checks if a name was made by `generate` or `generate_symbol`, rather than written in the
+    /// program.
+    pub fn is_generated(name: &str) -> bool {
+        Gensym::is_generated(name)
+    }
+
+    /// Creates a fresh Lisp symbol for a name bound inside of a macro template, so that it can't
+    /// capture a name from the macro's caller.
+    pub fn generate_symbol(&mut self, base_name: &str) -> String {
+        self.gensym.gen(base_name, Some("macro"))
+    }
+
     // Adds a new name to the table, generating a SymbolTableEntry containing the corresponding C
     // variable name.
-    fn register(&mut self, name: &str, scope_id: Option<usize>) {
+    pub fn register(&mut self, name: &str, scope_id: Option<usize>) {
         let scope_id = scope_id.unwrap_or(0);
 
         while self.defs.len() <= scope_id {
@@ -704,15 +1712,65 @@ impl SymbolTable {
         let key = name.to_string();
         let c_name = Gensym::convert(&key);
 
-        if self.get(name, Some(&vec![scope_id])).is_none() {
+        if !self.defs[scope_id].contains_key(name) {
             self.defs[scope_id].insert(key, c_name);
         }
     }
 
+    /// Adds a new name to a local scope under a generated C name. Since the C name is unique, it
+    /// can safely be declared in the same C function as a variable of the same name from an
+    /// enclosing scope.
+    fn register_local(&mut self, name: &str, scope_id: usize) {
+        while self.defs.len() <= scope_id {
+            self.defs.push(HashMap::new());
+        }
+
+        if !self.defs[scope_id].contains_key(name) {
+            let c_name = self.gensym.gen(name, None);
+            self.defs[scope_id].insert(name.to_string(), c_name);
+        }
+    }
+
+    /// Adds a function defined using `defun`, generating the name of the C function that
+    /// implements it.
+    fn register_fn(&mut self, name: &str) {
+        if !self.functions.contains_key(name) {
+            let c_name = self.gensym.gen(name, Some("defun"));
+            self.functions.insert(name.to_string(), c_name);
+        }
+    }
+
+    /// Checks if a name can be called directly, either as a native or as a function defined
+    /// using `defun`.
     pub fn contains_fn(&self, name: &str) -> bool {
+        self.natives.contains_key(name) || self.functions.contains_key(name)
+    }
+
+    /// Check if a function was created using `defun`, rather than being native.
+    pub fn is_defun(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Check if a name refers to a native function, which can't be shadowed.
+    pub fn is_native(&self, name: &str) -> bool {
         self.natives.contains_key(name)
     }
 
+    /// Finds the C function to call for a given function name.
+    pub fn get_fn(&self, name: &str) -> Option<&String> {
+        self.natives.get(name).or_else(|| self.functions.get(name))
+    }
+
+    /// The native functions, with the C functions that implement them.
+    pub fn natives(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.natives.iter()
+    }
+
+    /// The global variables, with their C names.
+    pub fn globals(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.defs.iter().take(1).flatten()
+    }
+
     // TODO(matthew-c21): Test this function.
     /// Finds the C name of a lisp variable given an optional list of scopes in which to search. If
     /// multiple are found, the name from the last scope found is returned. Always searches global
@@ -722,10 +1780,15 @@ impl SymbolTable {
             return self.natives.get(name);
         }
 
-        let mut scope_ids = if scope_ids.is_some() {
-            scope_ids.unwrap().clone()
-        } else {
-            vec![0]
+        self.scope_of(name, scope_ids).and_then(|id| self.defs[id].get(name))
+    }
+
+    /// Finds the scope that a lisp variable is bound in, searching the scopes like `get` does.
+    /// Natives aren't bound in any scope.
+    pub fn scope_of(&self, name: &str, scope_ids: Option<&Vec<usize>>) -> Option<usize> {
+        let mut scope_ids = match scope_ids {
+            Some(ids) => ids.clone(),
+            None => vec![0],
         };
 
         if scope_ids.is_empty() {
@@ -734,15 +1797,10 @@ impl SymbolTable {
             scope_ids.insert(0, 0);
         }
 
-        let mut result = None;
-
-        for id in scope_ids {
-            if id < self.defs.len() && self.defs[id].contains_key(name) {
-                result = self.defs[id].get(name)
-            }
-        }
-
-        result
+        scope_ids
+            .into_iter()
+            .rev()
+            .find(|&id| id < self.defs.len() && self.defs[id].contains_key(name))
     }
 
     pub fn get_factory(&self, name: &str) -> &String {
@@ -752,38 +1810,43 @@ impl SymbolTable {
     pub fn dummy() -> Self {
         Self {
             natives: HashMap::new(),
+            functions: HashMap::new(),
             defs: Vec::new(),
             factories: HashMap::new(),
             gensym: Gensym::new(),
         }
     }
 
-    fn validate_json(obj: &json::JsonValue) {
+    fn validate_json(obj: &json::JsonValue) -> Result<(), String> {
         let required_keys = vec!["functions", "variables", "factories"];
+        let required_factories = vec![
+            "int", "float", "complex", "rational", "string", "keyword", "true", "false", "lambda",
+            "symbol", "cons",
+        ];
 
-        assert!(obj.is_object());
-
-        for key in &required_keys {
-            assert!(obj.has_key(key));
+        if !obj.is_object() {
+            return Err("expected a JSON object".to_string());
         }
 
         for name in &required_keys {
-            assert!(obj[name.to_string()].is_object());
+            if !obj[*name].is_object() {
+                return Err(format!("expected `{}` to be an object", name));
+            }
 
-            for (_, value) in obj[name.to_string()].entries() {
-                assert!(value.is_string());
+            for (key, value) in obj[*name].entries() {
+                if !value.is_string() {
+                    return Err(format!("expected `{}.{}` to be a string", name, key));
+                }
             }
         }
 
-        assert!(obj["factories"].has_key("int"));
-        assert!(obj["factories"].has_key("float"));
-        assert!(obj["factories"].has_key("complex"));
-        assert!(obj["factories"].has_key("rational"));
-        assert!(obj["factories"].has_key("string"));
-        assert!(obj["factories"].has_key("keyword"));
-        assert!(obj["factories"].has_key("true"));
-        assert!(obj["factories"].has_key("false"));
-        assert!(obj["factories"].has_key("lambda"));
+        for factory in &required_factories {
+            if !obj["factories"].has_key(factory) {
+                return Err(format!("missing the `{}` factory", factory));
+            }
+        }
+
+        Ok(())
     }
 
     fn json_to_map(obj: &json::JsonValue, name: &str) -> HashMap<String, String> {
@@ -796,32 +1859,38 @@ impl SymbolTable {
         map
     }
 
-    pub fn load(filename: Option<&str>) -> Self {
-        let filename = match filename {
-            Some(s) => s,
-            None => "natives.json",
+    /// Reads the native definitions from a JSON file, or uses the ones that liblisp is built with
+    /// if there's none.
+    pub fn load(filename: Option<&str>) -> Result<Self, String> {
+        let (filename, contents) = match filename {
+            Some(filename) => {
+                let contents = fs::read_to_string(filename)
+                    .map_err(|e| format!("couldn't read `{}`: {}", filename, e))?;
+                (filename, contents)
+            }
+            None => ("natives.json", NATIVES.to_string()),
         };
 
-        let contents = fs::read_to_string(filename).expect("Something went wrong reading the file");
-
-        let obj = json::parse(contents.as_str()).unwrap();
+        let obj = json::parse(contents.as_str())
+            .map_err(|e| format!("couldn't parse `{}`: {}", filename, e))?;
 
         // Ensure that the JSON object is formed properly.
-        Self::validate_json(&obj);
+        Self::validate_json(&obj).map_err(|e| format!("invalid natives in `{}`: {}", filename, e))?;
 
         let defs = vec![Self::json_to_map(&obj, "variables")];
 
-        Self {
+        Ok(Self {
             defs,
             natives: Self::json_to_map(&obj, "functions"),
+            functions: HashMap::new(),
             factories: Self::json_to_map(&obj, "factories"),
             gensym: Gensym::new(),
-        }
+        })
     }
 }
 
 #[cfg(test)]
-mod test_utils {
+pub(crate) mod test_utils {
     use crate::ast::*;
     use crate::lex::start;
     use crate::parse::parse;
@@ -832,7 +1901,7 @@ mod test_utils {
         construct_ast(&parse_tree).unwrap()
     }
 
-    pub fn from_line(input: &str) -> Result<ASTNode, (u32, String)> {
+    pub fn from_line(input: &str) -> Result<ASTNode, Diagnostic> {
         let mut ast = construct_ast(&parse(&start(input).unwrap()).unwrap())?;
 
         assert_eq!(1, ast.len());
@@ -855,17 +1924,13 @@ mod visitor_tests {
 
         assert_eq!(ast.len(), 2);
 
-        if let ASTNode::Statement(Definition(_name, value)) = &ast[0] {
-            if let Call(_plus, args) = value {
-                for arg in args {
-                    if let Literal(t) = arg {
-                        assert_eq!(t.value(), Int(1))
-                    } else {
-                        panic!()
-                    }
+        if let ASTNode::Statement(Definition(_name, Call(_plus, args, _), _)) = &ast[0] {
+            for arg in args {
+                if let Literal(t) = arg {
+                    assert_eq!(t.value(), Int(1))
+                } else {
+                    panic!()
                 }
-            } else {
-                panic!()
             }
         } else {
             panic!()
@@ -974,7 +2039,7 @@ mod visitor_tests {
 
         assert_eq!(1, expansion.len());
 
-        if let Lambda(_, _, body, _) = &expansion[0].as_value() {
+        if let Lambda(_, _, body, _, _) = &expansion[0].as_value() {
             assert_eq!(2, body.len());
         } else {
             panic!("Inside of lambda not expanded.")
@@ -989,60 +2054,335 @@ mod visitor_tests {
 
         assert_eq!(1, expansion.len());
 
-        if let Lambda(_, _, body, _) = &expansion[0].as_value() {
+        if let Lambda(_, _, body, _, _) = &expansion[0].as_value() {
             // 2 declarations, 2 conditions, and the output value.
             assert_eq!(5, body.len());
         } else {
-            panic!("Inside of lambda not expanded.")
+            panic!("Inside of lambda not expanded.")
+        }
+    }
+
+    #[test]
+    fn symbol_validation_in_lambda() {
+        let s = SymbolValidation;
+
+        let ast = from_line("(lambda (x) x)").unwrap();
+        s.visit(&ast, &mut SymbolTable::dummy());
+
+        let mut t = SymbolTable::dummy();
+        let ast = force_from("(define x 10) (lambda () x)");
+        s.visit(&ast[0], &mut t);
+        s.visit(&ast[1], &mut t);
+    }
+
+    #[test]
+    fn symbols_invalid_outside_lambda() {
+        let s = SymbolValidation;
+        let mut t = SymbolTable::dummy();
+
+        let ast = force_from("(lambda (x) x) x");
+
+        s.visit(&ast[0], &mut t);
+
+        assert!(s.try_visit(&ast[1], &mut t).is_err());
+    }
+
+    #[test]
+    fn undeclared_var_in_lambda() {
+        let s = SymbolValidation;
+
+        let ast = from_line("(lambda (a b) c)").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_err())
+    }
+
+    #[test]
+    fn function_unroll_in_condition() {
+        let ast = force_from("(if (x (y)) a b)");
+        assert_eq!(1, ast.len());
+
+        let u = FunctionUnfurl;
+        let ast: Vec<ASTNode> = ast
+            .iter()
+            .flat_map(|n| u.visit(n, &mut SymbolTable::dummy()))
+            .collect();
+
+        assert_eq!(2, ast.len());
+    }
+
+    #[test]
+    fn let_bindings_valid_in_body() {
+        let s = SymbolValidation;
+
+        let ast = from_line("(let ((x 1) (y 2)) (list x y))").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_ok());
+    }
+
+    #[test]
+    fn let_bindings_invalid_in_initializers() {
+        let s = SymbolValidation;
+
+        let ast = from_line("(let ((x 1) (y x)) y)").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_err());
+
+        let ast = from_line("(let* ((x 1) (y x)) y)").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_ok());
+
+        let ast = from_line("(letrec ((f (lambda () g)) (g (lambda () f))) f)").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_ok());
+    }
+
+    #[test]
+    fn let_bindings_invalid_outside_let() {
+        let s = SymbolValidation;
+        let mut t = SymbolTable::dummy();
+
+        let ast = force_from("(let ((x 1)) x) x");
+
+        s.visit(&ast[0], &mut t);
+
+        assert!(s.try_visit(&ast[1], &mut t).is_err());
+    }
+
+    #[test]
+    fn let_bindings_shadow_globals() {
+        let s = SymbolValidation;
+        let mut t = SymbolTable::dummy();
+
+        let ast = force_from("(define x 1) (let ((x x)) x)");
+        s.visit(&ast[0], &mut t);
+        s.visit(&ast[1], &mut t);
+
+        if let ASTNode::Value(Let(_, _, _, scope)) = &ast[1] {
+            let local = t.get("x", Some(&vec![*scope])).unwrap();
+            assert_ne!("x", local.as_str());
+            assert_eq!("x", t.get("x", None).unwrap().as_str());
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn conditional_expansion_works_on_let() {
+        let ce = ConditionUnroll;
+        let ast = from_line("(let ((x (if a b c))) (if x y z))").unwrap();
+        let expansion = ce.try_visit(&ast, &mut SymbolTable::dummy()).unwrap();
+
+        assert_eq!(1, expansion.len());
+
+        if let Let(_, bindings, body, _) = &expansion[0].as_value() {
+            // A declaration, a condition, and the output value.
+            assert_eq!(3, bindings[0].1.len());
+            assert_eq!(3, body.len());
+        } else {
+            panic!("Inside of let not expanded.")
+        }
+    }
+
+    #[test]
+    fn expansion_works_on_begin() {
+        let ast = from_line("(f (begin (g (h)) (if a b c)))").unwrap();
+        let mut t = SymbolTable::dummy();
+
+        let ast = ConditionUnroll.visit(&ast, &mut t);
+        assert_eq!(1, ast.len());
+        let ast = FunctionUnfurl.visit(&ast[0], &mut t);
+        assert_eq!(1, ast.len());
+
+        if let Call(_, args, _) = ast[0].as_value() {
+            if let Begin(body) = &args[0] {
+                // The unwrapped call to h, the call to g, then the expanded condition.
+                assert_eq!(5, body.len());
+            } else {
+                panic!()
+            }
+        } else {
+            panic!("Inside of begin not expanded.")
+        }
+    }
+
+    #[test]
+    fn begin_symbols_validated() {
+        let s = SymbolValidation;
+
+        let ast = from_line("(begin 1 x)").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_err());
+
+        let ast = from_line("(lambda (x) (begin x x))").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_ok());
+    }
+
+    #[test]
+    fn quoted_symbols_not_validated() {
+        let s = SymbolValidation;
+
+        let ast = from_line("'(undefined symbols)").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_ok());
+
+        let ast = from_line("`(undefined ,symbols)").unwrap();
+        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_err());
+    }
+
+    #[test]
+    fn defun_registers_function() {
+        let s = SymbolValidation;
+        let mut t = SymbolTable::dummy();
+
+        let ast = force_from("(defun f (n . rest) (f n rest)) f");
+        s.visit(&ast[0], &mut t);
+
+        assert!(t.contains_fn("f"));
+        assert_ne!(t.get_fn("f"), t.get("f", None));
+
+        // The name is also bound as a global value.
+        assert!(s.try_visit(&ast[1], &mut t).is_ok());
+    }
+
+    #[test]
+    fn defun_args_invalid_outside_defun() {
+        let s = SymbolValidation;
+        let mut t = SymbolTable::dummy();
+
+        let ast = force_from("(defun f (n) n) n");
+        s.visit(&ast[0], &mut t);
+
+        assert!(s.try_visit(&ast[1], &mut t).is_err());
+    }
+
+    #[test]
+    fn defun_cannot_be_redefined() {
+        let s = SymbolValidation;
+        let mut t = SymbolTable::dummy();
+
+        let ast = force_from("(defun f () nil) (defun f () 1)");
+        s.visit(&ast[0], &mut t);
+
+        assert!(s.try_visit(&ast[1], &mut t).is_err());
+    }
+
+    #[test]
+    fn functions_cannot_be_defined_over() {
+        let s = SymbolValidation;
+        let mut t = SymbolTable::load(None).unwrap();
+
+        let ast = force_from("(defun f () nil) (define f 1) (define car 1)");
+        s.visit(&ast[0], &mut t);
+
+        assert_eq!(REDEFINED_FUNCTION, s.try_visit(&ast[1], &mut t).unwrap_err().code);
+        assert_eq!(REDEFINED_FUNCTION, s.try_visit(&ast[2], &mut t).unwrap_err().code);
+    }
+
+    #[test]
+    fn expansion_works_on_defun() {
+        let ast = from_line("(defun f (x) (g (if x 1 2) (h x)))").unwrap();
+        let mut t = SymbolTable::dummy();
+
+        let ast = ConditionUnroll.visit(&ast, &mut t);
+        assert_eq!(1, ast.len());
+        let ast = FunctionUnfurl.visit(&ast[0], &mut t);
+        assert_eq!(1, ast.len());
+
+        if let ASTNode::Statement(FunctionDefinition(_, _, _, body, ..)) = &ast[0] {
+            // A declaration, a condition, the unwrapped call to h, and the final call.
+            assert_eq!(4, body.len());
+        } else {
+            panic!("Inside of defun not expanded.")
+        }
+    }
+
+    fn mark_tail_calls(input: &str) -> Result<ASTNode, Diagnostic> {
+        let mut t = SymbolTable::dummy();
+        let ast = ConditionUnroll.visit(&from_line(input)?, &mut t);
+        assert_eq!(1, ast.len());
+
+        TailCallMarking.try_visit(&ast[0], &mut t)
+    }
+
+    fn count_tail_calls(ast: &ASTNode) -> usize {
+        format!("{:?}", ast).matches("TailCall(").count()
+    }
+
+    #[test]
+    fn tail_calls_marked() {
+        let ast = mark_tail_calls("(defun f (x) (if x (g x) (h (k x))))").unwrap();
+        assert_eq!(2, count_tail_calls(&ast));
+
+        if let ASTNode::Statement(FunctionDefinition(_, _, _, body, ..)) = &ast {
+            match &body[1] {
+                ASTNode::Statement(ExpandedCondition(_, t, f)) => {
+                    match t.last() {
+                        Some(ASTNode::Statement(Redefinition(_, TailCall(g, ..)))) => assert_eq!("g", g),
+                        _ => panic!(),
+                    }
+                    match f.last() {
+                        Some(ASTNode::Statement(Redefinition(_, TailCall(h, args, _)))) => {
+                            assert_eq!("h", h);
+                            assert!(matches!(args[0], Call(..)));
+                        }
+                        _ => panic!(),
+                    }
+                }
+                _ => panic!(),
+            }
+        } else {
+            panic!()
         }
     }
 
     #[test]
-    fn symbol_validation_in_lambda() {
-        let s = SymbolValidation;
+    fn tail_calls_in_nested_forms() {
+        let ast = mark_tail_calls("(lambda (x) (if x (if y (g) 1) (let ((z (f))) (begin (f) (h z)))))");
+        assert_eq!(2, count_tail_calls(&ast.unwrap()));
 
-        let ast = from_line("(lambda (x) x)").unwrap();
-        s.visit(&ast, &mut SymbolTable::dummy());
+        let ast = mark_tail_calls("(lambda (x) (g (h x)) (k (lambda () (m x))))");
+        assert_eq!(2, count_tail_calls(&ast.unwrap()));
 
-        let mut t = SymbolTable::dummy();
-        let ast = force_from("(define x 10) (lambda () x)");
-        s.visit(&ast[0], &mut t);
-        s.visit(&ast[1], &mut t);
+        let ast = mark_tail_calls("(lambda (x) (if (g x) 1 2))");
+        assert_eq!(0, count_tail_calls(&ast.unwrap()));
     }
 
     #[test]
-    fn symbols_invalid_outside_lambda() {
-        let s = SymbolValidation;
-        let mut t = SymbolTable::dummy();
-
-        let ast = force_from("(lambda (x) x) x");
-
-        s.visit(&ast[0], &mut t);
-
-        assert!(s.try_visit(&ast[1], &mut t).is_err());
+    fn main_has_no_tail_calls() {
+        assert_eq!(0, count_tail_calls(&mark_tail_calls("(g x)").unwrap()));
+        assert_eq!(0, count_tail_calls(&mark_tail_calls("(begin (if x (g) (h)))").unwrap()));
     }
 
     #[test]
-    fn undeclared_var_in_lambda() {
-        let s = SymbolValidation;
-
-        let ast = from_line("(lambda (a b) c)").unwrap();
-        assert!(s.try_visit(&ast, &mut SymbolTable::dummy()).is_err())
+    fn recur_in_tail_position() {
+        assert!(mark_tail_calls("(lambda (x) (if x (recur nil) 1))").is_ok());
+        assert!(mark_tail_calls("(defun f (x) (let ((y x)) (begin 1 (recur y))))").is_ok());
+        assert!(mark_tail_calls("(lambda (x . ys) (recur 1 2 3))").is_ok());
     }
 
     #[test]
-    fn function_unroll_in_condition() {
-        let ast = force_from("(if (x (y)) a b)");
-        assert_eq!(1, ast.len());
+    fn recur_outside_tail_position() {
+        let error = mark_tail_calls("(recur 1)").unwrap_err();
+        assert_eq!(INVALID_RECUR, error.code);
+        assert_eq!(Span::new(1, 2, 5), error.span);
+        assert_eq!("`recur` may only be used inside of a function.", error.message);
+
+        let error = mark_tail_calls("(lambda (x)\n (+ 1 (recur x)))").unwrap_err();
+        assert_eq!(Span::new(2, 8, 5), error.span);
+        assert_eq!("`recur` may only be used in tail position.", error.message);
+
+        assert!(mark_tail_calls("(lambda (x) (recur x) x)").is_err());
+        assert!(mark_tail_calls("(lambda (x) (if (recur x) 1 2))").is_err());
+        assert!(mark_tail_calls("(defun f (x) (let ((y (recur x))) y))").is_err());
+        assert!(mark_tail_calls("(lambda (x) (lambda () (f (recur))))").is_err());
+    }
 
-        let u = FunctionUnfurl;
-        let ast: Vec<ASTNode> = ast
-            .iter()
-            .map(|n| u.visit(n, &mut SymbolTable::dummy()))
-            .flatten()
-            .collect();
+    #[test]
+    fn recur_argument_count() {
+        assert_eq!(
+            "Expected exactly 1 argument(s) to `recur`. Found 0.",
+            mark_tail_calls("(lambda (x) (recur))").unwrap_err().message
+        );
+        assert!(mark_tail_calls("(lambda (x . ys) (recur))").is_err());
+    }
 
-        assert_eq!(2, ast.len());
+    #[test]
+    fn recur_symbols_validated() {
+        let ast = from_line("(lambda (x) (recur y))").unwrap();
+        assert!(SymbolValidation.try_visit(&ast, &mut SymbolTable::dummy()).is_err());
     }
 }
 
@@ -1068,7 +2408,7 @@ mod ast_tests {
         let ast = force_from("(define foobar \"foo bar\")");
         assert_eq!(1, ast.len());
 
-        if let ASTNode::Statement(Definition(name, value)) = &ast[0] {
+        if let ASTNode::Statement(Definition(name, value, _)) = &ast[0] {
             assert_eq!("foobar", name.as_str());
 
             match value {
@@ -1082,66 +2422,66 @@ mod ast_tests {
 
     #[test]
     fn define_non_symbol() {
-        let result: Result<ASTNode, (u32, String)> = from_line("(define 123 456)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(define 123 456)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!("Can only assign a value to a symbol.", msg.as_str())
         }
     }
 
     #[test]
     fn define_non_value() {
-        let result: Result<ASTNode, (u32, String)> = from_line("(define a (define b 1))");
+        let result: Result<ASTNode, Diagnostic> = from_line("(define a (define b 1))");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!("Can only assign a symbol to a value.", msg.as_str())
         }
     }
 
     #[test]
     fn fully_malformed_define() {
-        let result: Result<ASTNode, (u32, String)> = from_line("(define 8 (define c 1))");
+        let result: Result<ASTNode, Diagnostic> = from_line("(define 8 (define c 1))");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!("Invalid definition.", msg.as_str())
         }
     }
 
     #[test]
     fn wrong_number_define() {
-        let result: Result<ASTNode, (u32, String)> = from_line("(define a 1 2)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(define a 1 2)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected exactly 2 arguments in `define` special form. Found 3.",
                 msg.as_str()
             )
         }
 
-        let result: Result<ASTNode, (u32, String)> = from_line("(define a)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(define a)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected exactly 2 arguments in `define` special form. Found 1.",
                 msg.as_str()
             )
         }
 
-        let result: Result<ASTNode, (u32, String)> = from_line("(define)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(define)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected exactly 2 arguments in `define` special form. Found 0.",
                 msg.as_str()
@@ -1151,44 +2491,44 @@ mod ast_tests {
 
     #[test]
     fn wrong_number_condition() {
-        let result: Result<ASTNode, (u32, String)> = from_line("(if a b 1 2)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(if a b 1 2)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected exactly 3 arguments in `if` special form. Found 4.",
                 msg.as_str()
             )
         }
 
-        let result: Result<ASTNode, (u32, String)> = from_line("(if a b)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(if a b)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected exactly 3 arguments in `if` special form. Found 2.",
                 msg.as_str()
             )
         }
 
-        let result: Result<ASTNode, (u32, String)> = from_line("(if a)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(if a)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected exactly 3 arguments in `if` special form. Found 1.",
                 msg.as_str()
             )
         }
 
-        let result: Result<ASTNode, (u32, String)> = from_line("(if)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(if)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected exactly 3 arguments in `if` special form. Found 0.",
                 msg.as_str()
@@ -1198,44 +2538,44 @@ mod ast_tests {
 
     #[test]
     fn conditional_non_values() {
-        let result: Result<ASTNode, (u32, String)> = from_line("(if #t (define a 1) (define b 2))");
+        let result: Result<ASTNode, Diagnostic> = from_line("(if #t (define a 1) (define b 2))");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected values for condition, true, and false branches of conditional expression.",
                 msg.as_str()
             )
         }
 
-        let result: Result<ASTNode, (u32, String)> = from_line("(if #t (define a 1) b)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(if #t (define a 1) b)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected values for condition, true, and false branches of conditional expression.",
                 msg.as_str()
             )
         }
 
-        let result: Result<ASTNode, (u32, String)> = from_line("(if #t a (define b 2))");
+        let result: Result<ASTNode, Diagnostic> = from_line("(if #t a (define b 2))");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected values for condition, true, and false branches of conditional expression.",
                 msg.as_str()
             )
         }
 
-        let result: Result<ASTNode, (u32, String)> = from_line("(if (define t #t) a b)");
+        let result: Result<ASTNode, Diagnostic> = from_line("(if (define t #t) a b)");
 
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected values for condition, true, and false branches of conditional expression.",
                 msg.as_str()
@@ -1269,12 +2609,12 @@ mod ast_tests {
         assert_eq!(1, ast.len());
 
         match &ast[0] {
-            ASTNode::Value(Lambda(args, None, body, 1)) => {
+            ASTNode::Value(Lambda(args, None, body, 1, _)) => {
                 assert_eq!(2, args.len());
                 assert_eq!("x", args[0].as_str());
                 assert_eq!("y", args[1].as_str());
 
-                if let ASTNode::Value(Call(name, args)) = &body[0] {
+                if let ASTNode::Value(Call(name, args, _)) = &body[0] {
                     assert_eq!("+", name.as_str());
 
                     if let (Literal(t1), Literal(t2)) = (&args[0], &args[1]) {
@@ -1301,7 +2641,7 @@ mod ast_tests {
         assert_eq!(3, lambdas.len());
 
         for (i, lambda) in lambdas.iter().enumerate() {
-            if let ASTNode::Value(Lambda(_, _, _, j)) = lambda {
+            if let ASTNode::Value(Lambda(_, _, _, j, _)) = lambda {
                 assert_eq!(i + 1, *j);
             } else {
                 panic!()
@@ -1315,7 +2655,7 @@ mod ast_tests {
         assert_eq!(1, ast.len());
 
         match &ast[0] {
-            ASTNode::Value(Lambda(args, Some(v), _, 1)) => {
+            ASTNode::Value(Lambda(args, Some(v), _, 1, _)) => {
                 assert!(args.is_empty());
                 assert_eq!("zs", v.as_str());
             }
@@ -1329,7 +2669,7 @@ mod ast_tests {
         assert_eq!(1, ast.len());
 
         match &ast[0] {
-            ASTNode::Value(Lambda(args, None, body, 1)) if args.is_empty() => match &body[0] {
+            ASTNode::Value(Lambda(args, None, body, 1, _)) if args.is_empty() => match &body[0] {
                 ASTNode::Value(Literal(t)) => assert_eq!(Keyword("empty".to_string()), t.value()),
                 _ => panic!(),
             },
@@ -1342,9 +2682,9 @@ mod ast_tests {
         let result = from_line("(lambda)");
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
-                "Expected exactly 2 arguments in `lambda` special form. Found 0.",
+                "Expected at least 2 arguments in `lambda` special form. Found 0.",
                 msg.as_str()
             );
         } else {
@@ -1354,21 +2694,32 @@ mod ast_tests {
         let result = from_line("(lambda (a b c))");
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
-                "Expected exactly 2 arguments in `lambda` special form. Found 1.",
+                "Expected at least 2 arguments in `lambda` special form. Found 1.",
                 msg.as_str()
             );
         } else {
             panic!()
         }
 
+    }
+
+    #[test]
+    fn multiple_body_lambda() {
         let result = from_line("(lambda (a b c) + (a b c))");
-        assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Ok(ASTNode::Value(Lambda(_, _, body, _, _))) = result {
+            assert_eq!(2, body.len());
+        } else {
+            panic!()
+        }
+
+        let result = from_line("(lambda () (format 1) (define x 2) x)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
-                "Expected exactly 2 arguments in `lambda` special form. Found 3.",
+                "Expected every form in the body of `lambda` special form to be a value.",
                 msg.as_str()
             );
         } else {
@@ -1381,7 +2732,7 @@ mod ast_tests {
         let result = from_line("(lambda :not-args :error)");
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected arglist in second position of `lambda` special form.",
                 msg.as_str()
@@ -1396,7 +2747,7 @@ mod ast_tests {
         let result = from_line("(lambda () (define t #t))");
         assert!(result.is_err());
 
-        if let Err((_, msg)) = result {
+        if let Err(Diagnostic { message: msg, .. }) = result {
             assert_eq!(
                 "Expected final argument to `lambda` special form to be a value.",
                 msg.as_str()
@@ -1429,4 +2780,348 @@ mod ast_tests {
         assert!(from_line("(lambda () . 1)").is_err());
         assert!(from_line("(lambda (a b) a . b)").is_err());
     }
+
+    #[test]
+    fn from_let() {
+        let ast = force_from("(let ((x 1) (y :a)) y)");
+        assert_eq!(1, ast.len());
+
+        match &ast[0] {
+            ASTNode::Value(Let(LetKind::Let, bindings, body, 1)) => {
+                assert_eq!(2, bindings.len());
+                assert_eq!("x", bindings[0].0.as_str());
+                assert_eq!("y", bindings[1].0.as_str());
+                assert_eq!(1, body.len());
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn from_let_star() {
+        let ast = force_from("(let* ((x 1) (y x)) y)");
+        assert_eq!(1, ast.len());
+
+        // Each binding is given its own scope, with the first binding outermost.
+        match &ast[0] {
+            ASTNode::Value(Let(LetKind::Let, outer, body, 2)) => {
+                assert_eq!("x", outer[0].0.as_str());
+
+                match &body[0] {
+                    ASTNode::Value(Let(LetKind::Let, inner, _, 1)) => {
+                        assert_eq!("y", inner[0].0.as_str())
+                    }
+                    _ => panic!(),
+                }
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn from_letrec() {
+        let ast = force_from("(letrec ((f (lambda () (funcall f)))) f)");
+        assert_eq!(1, ast.len());
+
+        match &ast[0] {
+            ASTNode::Value(Let(LetKind::LetRec, bindings, _, 2)) => {
+                assert_eq!(1, bindings.len());
+                match &bindings[0].1[0] {
+                    ASTNode::Value(Lambda(_, _, _, 1, _)) => (),
+                    _ => panic!(),
+                }
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn empty_let() {
+        assert!(from_line("(let () 1)").is_ok());
+        assert!(from_line("(let* () 1)").is_ok());
+        assert!(from_line("(letrec () 1)").is_ok());
+    }
+
+    #[test]
+    fn malformed_let() {
+        let result = from_line("(let ((x 1)))");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Expected at least 2 arguments in `let` special form. Found 1.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        let result = from_line("(let* x x)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Expected binding list in second position of `let*` special form.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        let result = from_line("(letrec ((x)) x)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Each binding in `letrec` special form should be a list containing a symbol and a value.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        let result = from_line("(let ((1 x)) x)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Expected a symbol to be bound in `let` special form.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn duplicate_let_bindings() {
+        let result = from_line("(let ((x 1) (x 2)) x)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Duplicate binding for `x` in `let` special form.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        // Sequential bindings are allowed to shadow each other.
+        assert!(from_line("(let* ((x 1) (x 2)) x)").is_ok());
+    }
+
+    #[test]
+    fn non_valued_let() {
+        assert!(from_line("(let ((x (define y 1))) x)").is_err());
+        assert!(from_line("(let ((x 1)) (define y x))").is_err());
+    }
+
+    #[test]
+    fn multiple_body_let() {
+        let ast = force_from("(let* ((x 1) (y 2)) (format x) (format y) y)");
+
+        if let ASTNode::Value(Let(_, _, outer, _)) = &ast[0] {
+            assert_eq!(1, outer.len());
+
+            if let ASTNode::Value(Let(_, _, inner, _)) = &outer[0] {
+                assert_eq!(3, inner.len());
+            } else {
+                panic!()
+            }
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn from_begin() {
+        let ast = force_from("(begin 1 2 3) (progn (f) x)");
+        assert_eq!(2, ast.len());
+
+        match (&ast[0], &ast[1]) {
+            (ASTNode::Value(Begin(a)), ASTNode::Value(Begin(b))) => {
+                assert_eq!(3, a.len());
+                assert_eq!(2, b.len());
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn malformed_begin() {
+        let result = from_line("(begin)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Expected at least 1 argument in `begin` special form. Found 0.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        assert!(from_line("(progn 1 (define x 2))").is_err());
+        assert!(from_line("(begin (define x 2) 1)").is_err());
+    }
+
+    #[test]
+    fn from_recur() {
+        match from_line("(recur 1 (f x))").unwrap() {
+            ASTNode::Value(Recur(args, span)) => {
+                assert_eq!(2, args.len());
+                assert_eq!(Span::new(1, 2, 5), span);
+            }
+            _ => panic!(),
+        }
+
+        assert!(from_line("(recur (define x 1))").is_err());
+    }
+
+    #[test]
+    fn from_quote() {
+        let ast = force_from("'x '(1 (a) . b) '()");
+        assert_eq!(3, ast.len());
+
+        match ast[0].as_value() {
+            Quotation(Quoted::Atom(t)) => assert_eq!(Symbol("x".to_string()), t.value()),
+            _ => panic!(),
+        }
+
+        match ast[1].as_value() {
+            Quotation(Quoted::List(items, Some(tail))) => {
+                assert_eq!(2, items.len());
+                assert!(matches!(&items[1], Quoted::List(inner, None) if inner.len() == 1));
+                assert!(matches!(tail.as_ref(), Quoted::Atom(_)));
+            }
+            _ => panic!(),
+        }
+
+        assert!(matches!(ast[2].as_value(), Quotation(Quoted::List(items, None)) if items.is_empty()));
+    }
+
+    #[test]
+    fn constant_quasiquote() {
+        let ast = force_from("`(a (b ,c) d)");
+
+        // Only the list containing the unquote needs to be built at runtime.
+        match ast[0].as_value() {
+            Call(cons, args, _) => {
+                assert_eq!("cons", cons.as_str());
+                assert!(matches!(&args[0], Quotation(Quoted::Atom(_))));
+                assert!(matches!(&args[1], Call(..)));
+            }
+            _ => panic!(),
+        }
+
+        assert!(matches!(force_from("`(a b)")[0].as_value(), Quotation(_)));
+        assert!(matches!(force_from("`(a `(b ,c))")[0].as_value(), Quotation(_)));
+    }
+
+    #[test]
+    fn quasiquote_splicing() {
+        let ast = force_from("`(,@xs)");
+
+        match ast[0].as_value() {
+            Call(append, args, _) => {
+                assert_eq!("append", append.as_str());
+                assert_eq!(2, args.len());
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn malformed_quote() {
+        let result = from_line("(quote a b)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Expected exactly 1 argument in `quote` special form. Found 2.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        let result = from_line(",x");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!("`unquote` is only valid inside of `quasiquote`.", msg.as_str());
+        } else {
+            panic!()
+        }
+
+        let result = from_line("`,@x");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!("`unquote-splicing` can only be used inside of a list.", msg.as_str());
+        } else {
+            panic!()
+        }
+
+        assert!(from_line("`(a ,(define x 1))").is_err());
+    }
+
+    #[test]
+    fn from_defun() {
+        let ast = force_from("(defun add (x y . zs) (+ x y))");
+        assert_eq!(1, ast.len());
+
+        match &ast[0] {
+            ASTNode::Statement(FunctionDefinition(name, args, Some(vararg), body, 1, span)) => {
+                assert_eq!(Span::new(1, 8, 3), *span);
+                assert_eq!("add", name.as_str());
+                assert_eq!(vec!["x".to_string(), "y".to_string()], *args);
+                assert_eq!("zs", vararg.as_str());
+                assert_eq!(1, body.len());
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn malformed_defun() {
+        let result = from_line("(defun f (x))");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Expected at least 3 arguments in `defun` special form. Found 2.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        let result = from_line("(defun (f) (x) x)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Expected a symbol in second position of `defun` special form.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        let result = from_line("(defun f x x)");
+
+        if let Err(Diagnostic { message: msg, .. }) = result {
+            assert_eq!(
+                "Expected arglist in third position of `defun` special form.",
+                msg.as_str()
+            );
+        } else {
+            panic!()
+        }
+
+        assert!(from_line("(defun f (1) 1)").is_err());
+        assert!(from_line("(defun f () (define x 1))").is_err());
+    }
+
+    #[test]
+    fn load_natives() {
+        let sym_table = SymbolTable::load(None).unwrap();
+        assert!(sym_table.natives.contains_key("format"));
+        assert!(SymbolTable::load(Some("natives.json")).unwrap().natives.contains_key("format"));
+
+        let missing = SymbolTable::load(Some("missing.json")).err().unwrap();
+        assert!(missing.starts_with("couldn't read `missing.json`"));
+        assert!(SymbolTable::load(Some("Cargo.toml")).is_err());
+    }
 }
@@ -0,0 +1,319 @@
+use crate::diagnostic::{Diagnostic, Span, C_COMPILER};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs, io};
+
+// The runtime library's sources, relative to the runtime directory.
+const RUNTIME_SOURCES: [&str; 4] = ["data.c", "stdlisp.c", "err.c", "gc.c"];
+
+pub enum BuildError {
+    // The C compiler couldn't be run, or a file couldn't be read or written.
+    Io(String),
+    // The C compiler ran, but rejected the code, leaving these messages.
+    Rejected(Vec<Diagnostic>),
+}
+
+impl From<io::Error> for BuildError {
+    fn from(e: io::Error) -> Self {
+        BuildError::Io(e.to_string())
+    }
+}
+
+/// Compiles generated C code into executables, linking against liblisp.
+pub struct Toolchain {
+    cc: String,
+    flags: Vec<String>,
+    // The directory holding liblisp's sources.
+    runtime: PathBuf,
+    // The directory used to keep the compiled runtime between builds.
+    cache: PathBuf,
+}
+
+impl Toolchain {
+    /// Configures the toolchain from the environment. The compiler is `$CC`, or `cc`, and is
+    /// given the flags in `$CFLAGS`. liblisp is found at `$LISPC_RUNTIME`, or in the crate, and
+    /// its compiled objects are kept in `$LISPC_CACHE`, or the user's cache directory.
+    pub fn from_env() -> Self {
+        let var = |name| env::var(name).ok().filter(|v: &String| !v.is_empty());
+
+        let cache = var("LISPC_CACHE")
+            .map(PathBuf::from)
+            .or_else(|| var("XDG_CACHE_HOME").map(|d| Path::new(&d).join("lispc")))
+            .or_else(|| var("HOME").map(|d| Path::new(&d).join(".cache").join("lispc")))
+            .unwrap_or_else(|| env::temp_dir().join("lispc-cache"));
+
+        Toolchain {
+            cc: var("CC").unwrap_or_else(|| "cc".to_string()),
+            flags: var("CFLAGS")
+                .map(|f| f.split_whitespace().map(String::from).collect())
+                .unwrap_or_default(),
+            runtime: var("LISPC_RUNTIME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("liblisp")),
+            cache,
+        }
+    }
+
+    /// Compiles the C code in `source` into an executable at `exe`. The code is written to a
+    /// file next to the executable, named by `c_file`, which is removed afterwards.
+    pub fn build(&self, source: &str, c_file: &Path, exe: &Path) -> Result<(), BuildError> {
+        let objects = self.runtime_objects(false)?;
+
+        self.compile(source, c_file, exe, &[], &objects)
+    }
+
+    /// Compiles the C code in `source` into a shared object at `object`, which leaves references
+    /// to liblisp, and to other shared objects, to be resolved when it's loaded.
+    pub fn build_shared(&self, source: &str, c_file: &Path, object: &Path) -> Result<(), BuildError> {
+        self.compile(source, c_file, object, &["-shared", "-fPIC"], &[])
+    }
+
+    /// Returns liblisp built as a shared object, for programs made of shared objects.
+    pub fn runtime_library(&self) -> Result<PathBuf, BuildError> {
+        let objects = self.runtime_objects(true)?;
+        let library = objects[0].with_file_name("liblisp.so");
+
+        if !library.exists() {
+            let partial = library.with_extension(format!("{}.tmp", std::process::id()));
+
+            let output = Command::new(&self.cc)
+                .args(&self.flags)
+                .arg("-shared")
+                .arg("-o")
+                .arg(&partial)
+                .args(&objects)
+                .output()
+                .map_err(|e| BuildError::Io(format!("couldn't run `{}`: {}", self.cc, e)))?;
+
+            if !output.status.success() {
+                return Err(BuildError::Io(format!(
+                    "couldn't link the runtime:\n{}",
+                    String::from_utf8_lossy(&output.stderr)
+                )));
+            }
+
+            fs::rename(&partial, &library)?;
+        }
+
+        Ok(library)
+    }
+
+    fn compile(
+        &self,
+        source: &str,
+        c_file: &Path,
+        output: &Path,
+        extra_flags: &[&str],
+        objects: &[PathBuf],
+    ) -> Result<(), BuildError> {
+        fs::write(c_file, source)?;
+
+        let result = Command::new(&self.cc)
+            .args(&self.flags)
+            .args(extra_flags)
+            .arg("-std=c11")
+            .arg("-I")
+            .arg(&self.runtime)
+            .arg("-o")
+            .arg(output)
+            .arg(c_file)
+            .args(objects)
+            .output()
+            .map_err(|e| BuildError::Io(format!("couldn't run `{}`: {}", self.cc, e)));
+
+        fs::remove_file(c_file)?;
+        let result = result?;
+
+        if result.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            Err(BuildError::Rejected(parse_errors(&stderr)))
+        }
+    }
+
+    /// Returns liblisp's object files, compiling them if the cache doesn't already hold a copy
+    /// built from the same sources, compiler, and flags. Position independent objects are kept
+    /// apart from the rest.
+    fn runtime_objects(&self, pic: bool) -> Result<Vec<PathBuf>, BuildError> {
+        let mut flags = self.flags.clone();
+
+        if pic {
+            flags.push("-fPIC".to_string());
+        }
+
+        let mut hasher = DefaultHasher::new();
+        self.cc.hash(&mut hasher);
+        flags.hash(&mut hasher);
+
+        let mut entries = fs::read_dir(&self.runtime)
+            .map_err(|e| {
+                BuildError::Io(format!("couldn't read `{}`: {}", self.runtime.display(), e))
+            })?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        entries.sort();
+
+        // Headers count too, since a change to one can change every object.
+        for path in entries {
+            if matches!(path.extension().and_then(|e| e.to_str()), Some("c") | Some("h")) {
+                path.file_name().hash(&mut hasher);
+                fs::read(&path)?.hash(&mut hasher);
+            }
+        }
+
+        let dir = self.cache.join(format!("runtime-{:016x}", hasher.finish()));
+        fs::create_dir_all(&dir)?;
+
+        let mut objects = Vec::new();
+
+        for source in RUNTIME_SOURCES.iter() {
+            let object = dir.join(Path::new(source).with_extension("o"));
+
+            if !object.exists() {
+                // Compiling to a temporary name first means that an interrupted build can't
+                // leave a broken object in the cache.
+                let partial = object.with_extension(format!("{}.tmp", std::process::id()));
+
+                let output = Command::new(&self.cc)
+                    .args(&flags)
+                    .arg("-std=c11")
+                    .arg("-c")
+                    .arg("-o")
+                    .arg(&partial)
+                    .arg(self.runtime.join(source))
+                    .output()
+                    .map_err(|e| BuildError::Io(format!("couldn't run `{}`: {}", self.cc, e)))?;
+
+                if !output.status.success() {
+                    return Err(BuildError::Io(format!(
+                        "couldn't compile the runtime:\n{}",
+                        String::from_utf8_lossy(&output.stderr)
+                    )));
+                }
+
+                fs::rename(&partial, &object)?;
+            }
+
+            objects.push(object);
+        }
+
+        Ok(objects)
+    }
+}
+
+/// Reads the errors out of a C compiler's output, which GCC and Clang both write as
+/// `file:line:column: error: message`. Each diagnostic names the file that the C compiler
+/// reported, so that it can be matched to a LISP source if it has one.
+fn parse_errors(stderr: &str) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+
+    for line in stderr.lines() {
+        let (location, message) = match line
+            .split_once(": error: ")
+            .or_else(|| line.split_once(": fatal error: "))
+        {
+            Some(parts) => parts,
+            None => continue,
+        };
+
+        let parts: Vec<&str> = location.rsplitn(3, ':').collect();
+
+        let (file, span) = match parts.as_slice() {
+            [column, line, file] => match (line.parse(), column.parse()) {
+                (Ok(l), Ok(c)) => (*file, Span::new(l, c, 1)),
+                _ => (location, Span::default()),
+            },
+            _ => (location, Span::default()),
+        };
+
+        errors.push(Diagnostic::error(C_COMPILER, span, message.to_string()).with_file(file));
+    }
+
+    // Some errors, like a linker failing, don't follow the format at all.
+    if errors.is_empty() {
+        errors.push(
+            Diagnostic::error(C_COMPILER, Span::default(), "The C compiler failed.".to_string())
+                .with_note(stderr.trim()),
+        );
+    }
+
+    errors
+}
+
+/// Runs an executable, returning the status it exits with. A program killed by a signal is
+/// given a status of 128 plus the signal number, as shells do.
+pub fn run(exe: &Path) -> io::Result<i32> {
+    let status = Command::new(exe).status()?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            return Ok(128 + signal);
+        }
+    }
+
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::build::*;
+
+    #[test]
+    fn errors_from_gcc() {
+        let stderr = "out.c: In function 'main':\n\
+                      out.c:4:5: error: 'x' undeclared (first use in this function)\n\
+                      \x20   4 | x = 1;\n\
+                      ack.lisp:2:10: error: expected ';' before 'return'\n";
+
+        let errors = parse_errors(stderr);
+
+        assert_eq!(2, errors.len());
+        assert_eq!(Some("out.c".to_string()), errors[0].file);
+        assert_eq!(Span::new(4, 5, 1), errors[0].span);
+        assert_eq!("'x' undeclared (first use in this function)", errors[0].message);
+        assert_eq!(Some("ack.lisp".to_string()), errors[1].file);
+        assert_eq!(Span::new(2, 10, 1), errors[1].span);
+    }
+
+    #[test]
+    fn unformatted_errors() {
+        let errors = parse_errors("ld: symbol(s) not found\n");
+
+        assert_eq!(1, errors.len());
+        assert_eq!(vec!["ld: symbol(s) not found".to_string()], errors[0].notes);
+    }
+
+    #[test]
+    fn build_and_run() {
+        let mut toolchain = Toolchain::from_env();
+        let dir = env::temp_dir().join(format!("lispc-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        toolchain.cache = dir.join("cache");
+
+        let exe = dir.join("status");
+        let c_file = dir.join("status.c");
+        let program = "#include \"lisp.h\"\nint main(void) { return 3; }\n";
+
+        // Without a C compiler, there's nothing to test.
+        match toolchain.build(program, &c_file, &exe) {
+            Err(BuildError::Io(e)) if e.starts_with("couldn't run") => return,
+            result => assert!(result.is_ok()),
+        }
+
+        assert_eq!(3, run(&exe).unwrap());
+        assert!(!c_file.exists());
+
+        match toolchain.build("int main(void) { return x; }", &c_file, &exe) {
+            Err(BuildError::Rejected(errors)) => assert_eq!(Span::new(1, 25, 1), errors[0].span),
+            _ => panic!("Expected the C compiler to fail."),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,394 @@
+use lispc::{Collector, CompileOptions, Emit, PassManager};
+
+pub const USAGE: &str = "\
+Usage: lispc [OPTIONS] <FILE>...
+       lispc build [OPTIONS] <FILE>...
+       lispc run [OPTIONS] <FILE>...
+       lispc repl [OPTIONS] [FILE]...
+       lispc interp [OPTIONS] <FILE>...
+       lispc fmt [--check] <FILE>...
+       lispc lsp [--natives <FILE>]
+
+Compiles LISP source files into a single C program. Use `-` to read from stdin.
+
+`build` compiles the program into an executable using the C compiler named by `$CC`, and `run`
+builds the program and runs it, exiting with the program's status. `repl` starts an interactive
+session, after loading the files given. `interp` runs the program with an interpreter instead of
+the C compiler, writing the same output that the compiled program would. `fmt` formats the files
+in place, or only checks that they're formatted with `--check`. `lsp` runs a language server for
+editors, which talks to the editor over stdin and stdout.
+
+Options:
+    -o, --output <FILE>     Write the output to FILE instead of stdout, or name the executable
+        --natives <FILE>    Read native definitions from FILE instead of the built-in ones
+        --source-map <FILE> Write a JSON map from the lines of the C to the LISP source to FILE
+        --emit <KIND>       Output tokens, parse, ast, expanded-ast, or c [default: c]
+        --expand            Output the program after macro expansion
+        --check             Only check the program for errors
+        --trampoline        Run every tail call in constant stack space
+        --leak-check        Report data the program never freed when it exits
+        --gc <KIND>         Manage memory with refcount, tracing, or stress [default: refcount]
+        --error-limit <N>   Stop after N errors, or never if N is 0 [default: 20]
+        --dump-after <PASS> Print the AST to stderr after PASS, which may be given more than once
+        --time-passes       Print how long each stage of compilation took to stderr
+        --keep-dead-code    Keep unused definitions and expressions, for debugging
+    -h, --help              Print this message
+";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    // Writes the output of a stage of compilation, C by default.
+    Compile,
+    // Compiles the C into an executable.
+    Build,
+    // Builds an executable in a temporary directory, and runs it.
+    Run,
+    // Reads and runs forms interactively.
+    Repl,
+    // Runs the program without compiling it to C.
+    Interp,
+    // Formats the source files.
+    Fmt,
+    // Serves the Language Server Protocol over stdin and stdout.
+    Lsp,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub inputs: Vec<String>,
+    pub output: Option<String>,
+    pub natives: Option<String>,
+    pub source_map: Option<String>,
+    pub emit: Emit,
+    pub check: bool,
+    pub trampoline: bool,
+    pub leak_check: bool,
+    pub gc: Collector,
+    pub error_limit: usize,
+    pub dump_after: Vec<String>,
+    pub time_passes: bool,
+    pub keep_dead_code: bool,
+    pub help: bool,
+}
+
+impl Options {
+    /// The options that are passed on to the compiler.
+    pub fn compile_options(&self) -> CompileOptions {
+        CompileOptions {
+            natives: self.natives.clone(),
+            emit: self.emit,
+            check: self.check,
+            trampoline: self.trampoline,
+            leak_check: self.leak_check,
+            collector: self.gc,
+            error_limit: self.error_limit,
+            c_file: self.output.clone(),
+            dump_after: self.dump_after.clone(),
+            time_passes: self.time_passes,
+            keep_dead_code: self.keep_dead_code,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            command: Command::Compile,
+            inputs: Vec::new(),
+            output: None,
+            natives: None,
+            source_map: None,
+            emit: Emit::C,
+            check: false,
+            trampoline: false,
+            leak_check: false,
+            gc: Collector::RefCount,
+            error_limit: 20,
+            dump_after: Vec::new(),
+            time_passes: false,
+            keep_dead_code: false,
+            help: false,
+        }
+    }
+}
+
+/// Reads the command line arguments, not including the program name.
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut emit = None;
+    let mut args = args.iter().peekable();
+
+    options.command = match args.peek().map(|a| a.as_str()) {
+        Some("build") => Command::Build,
+        Some("run") => Command::Run,
+        Some("repl") => Command::Repl,
+        Some("interp") => Command::Interp,
+        Some("fmt") => Command::Fmt,
+        Some("lsp") => Command::Lsp,
+        _ => Command::Compile,
+    };
+
+    if options.command != Command::Compile {
+        args.next();
+    }
+
+    while let Some(arg) = args.next() {
+        // Options that take a value accept both `--name value` and `--name=value`.
+        let (name, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_string())),
+            _ => (arg.as_str(), None),
+        };
+
+        let mut value = || match inline.clone() {
+            Some(v) => Ok(v),
+            None => args
+                .next()
+                .cloned()
+                .ok_or(format!("`{}` expects a value", name)),
+        };
+
+        match name {
+            "-o" | "--output" => options.output = Some(value()?),
+            "--natives" => options.natives = Some(value()?),
+            "--source-map" => options.source_map = Some(value()?),
+            "--emit" => emit = Some(emit_kind(&value()?)?),
+            "--expand" => emit = Some(Emit::Expanded),
+            "--check" => options.check = true,
+            "--trampoline" => options.trampoline = true,
+            "--leak-check" => options.leak_check = true,
+            "--gc" => options.gc = collector(&value()?)?,
+            "--error-limit" => {
+                let n = value()?;
+                options.error_limit = n
+                    .parse()
+                    .map_err(|_| format!("expected a number of errors, found `{}`", n))?;
+            }
+            "--dump-after" => options.dump_after.push(value()?),
+            "--time-passes" => options.time_passes = true,
+            "--keep-dead-code" => options.keep_dead_code = true,
+            "-h" | "--help" => options.help = true,
+            "-" => options.inputs.push(arg.clone()),
+            _ if name.starts_with('-') => return Err(format!("unknown option `{}`", name)),
+            _ => options.inputs.push(arg.clone()),
+        }
+    }
+
+    if options.check && (emit.is_some() || options.output.is_some()) {
+        return Err("`--check` doesn't produce any output".to_string());
+    }
+
+    if options.source_map.is_some()
+        && (options.command != Command::Compile || emit.is_some_and(|e| e != Emit::C) || options.check)
+    {
+        return Err("`--source-map` describes the generated C, so it's only written along with it".to_string());
+    }
+
+    if options.leak_check && !matches!(options.command, Command::Compile | Command::Build | Command::Run) {
+        return Err(format!("`{}` doesn't compile a program to check for leaks", command_name(options.command)));
+    }
+
+    if options.gc != Collector::RefCount
+        && !matches!(options.command, Command::Compile | Command::Build | Command::Run)
+    {
+        return Err(format!("`{}` only supports reference counting", command_name(options.command)));
+    }
+
+    if (!options.dump_after.is_empty() || options.time_passes)
+        && !matches!(options.command, Command::Compile | Command::Build | Command::Run)
+    {
+        return Err(format!("`{}` doesn't report on the passes", command_name(options.command)));
+    }
+
+    if !options.dump_after.is_empty() && matches!(emit, Some(Emit::Tokens | Emit::Parse | Emit::Expanded | Emit::Ast)) {
+        return Err("`--dump-after` needs the passes to run, which they don't before that stage".to_string());
+    }
+
+    if options.keep_dead_code
+        && !matches!(options.command, Command::Compile | Command::Build | Command::Run | Command::Interp)
+    {
+        return Err(format!("`{}` never removes dead code", command_name(options.command)));
+    }
+
+    let passes = if options.keep_dead_code { PassManager::standard() } else { PassManager::optimized() };
+
+    if let Some(name) = options.dump_after.iter().find(|n| !passes.names().contains(&n.as_str())) {
+        return Err(format!("unknown pass `{}`, expected one of {}", name, passes.names().join(", ")));
+    }
+
+    if options.command == Command::Fmt && (emit.is_some() || options.output.is_some()) {
+        return Err("`fmt` only rewrites the files in place".to_string());
+    }
+
+    if options.command == Command::Lsp
+        && (!options.inputs.is_empty() || emit.is_some() || options.output.is_some() || options.check)
+    {
+        return Err("`lsp` reads the files from the editor, and only takes `--natives`".to_string());
+    }
+
+    if !matches!(options.command, Command::Compile | Command::Fmt) && (emit.is_some() || options.check) {
+        return Err(format!("`{}` always compiles the whole program", command_name(options.command)));
+    }
+
+    if options.command == Command::Run && options.output.is_some() {
+        return Err("`run` doesn't keep the executable, use `build` to name it".to_string());
+    }
+
+    if matches!(options.command, Command::Repl | Command::Interp) && options.output.is_some() {
+        return Err(format!("`{}` doesn't write any output", command_name(options.command)));
+    }
+
+    if options.inputs.is_empty() && !options.help && !matches!(options.command, Command::Repl | Command::Lsp) {
+        return Err("no input files".to_string());
+    }
+
+    options.emit = emit.unwrap_or(Emit::C);
+
+    Ok(options)
+}
+
+fn command_name(command: Command) -> &'static str {
+    match command {
+        Command::Compile => "lispc",
+        Command::Build => "build",
+        Command::Run => "run",
+        Command::Repl => "repl",
+        Command::Interp => "interp",
+        Command::Fmt => "fmt",
+        Command::Lsp => "lsp",
+    }
+}
+
+fn collector(kind: &str) -> Result<Collector, String> {
+    match kind {
+        "refcount" => Ok(Collector::RefCount),
+        "tracing" => Ok(Collector::Tracing),
+        "stress" => Ok(Collector::Stress),
+        _ => Err(format!(
+            "unknown garbage collector `{}`, expected one of refcount, tracing, or stress",
+            kind
+        )),
+    }
+}
+
+fn emit_kind(kind: &str) -> Result<Emit, String> {
+    match kind {
+        "tokens" => Ok(Emit::Tokens),
+        "parse" => Ok(Emit::Parse),
+        "ast" => Ok(Emit::Ast),
+        "expanded-ast" => Ok(Emit::ExpandedAst),
+        "c" => Ok(Emit::C),
+        _ => Err(format!(
+            "unknown kind of output `{}`, expected one of tokens, parse, ast, expanded-ast, or c",
+            kind
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cli::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(&args.iter().map(|a| a.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&["a.lisp"]).unwrap();
+
+        assert_eq!(vec!["a.lisp".to_string()], options.inputs);
+        assert_eq!(Command::Compile, options.command);
+        assert_eq!(Emit::C, options.emit);
+        assert_eq!(20, options.error_limit);
+        assert_eq!(None, options.output);
+        assert!(!options.check);
+    }
+
+    #[test]
+    fn values() {
+        let options = parse(&[
+            "-o", "out.c", "--natives=n.json", "--emit", "ast", "a.lisp", "-", "b.lisp",
+            "--error-limit", "0",
+        ])
+        .unwrap();
+
+        assert_eq!(vec!["a.lisp", "-", "b.lisp"], options.inputs);
+        assert_eq!(Some("out.c".to_string()), options.output);
+        assert_eq!(Some("n.json".to_string()), options.natives);
+        assert_eq!(Emit::Ast, options.emit);
+        assert_eq!(0, options.error_limit);
+
+        let options = parse(&["-o", "out.c", "--source-map", "out.map", "a.lisp"]).unwrap();
+        assert_eq!(Some("out.map".to_string()), options.source_map);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["a.lisp", "-o"]).is_err());
+        assert!(parse(&["a.lisp", "--emit", "asm"]).is_err());
+        assert!(parse(&["a.lisp", "--error-limit=x"]).is_err());
+        assert!(parse(&["a.lisp", "--verbose"]).is_err());
+        assert!(parse(&["a.lisp", "--check", "--emit=c"]).is_err());
+        assert!(parse(&["a.lisp", "--source-map", "a.map", "--emit=ast"]).is_err());
+        assert!(parse(&["build", "--source-map", "a.map", "a.lisp"]).is_err());
+        assert!(parse(&["interp", "--leak-check", "a.lisp"]).is_err());
+        assert!(parse(&["repl", "--leak-check"]).is_err());
+        assert!(parse(&["a.lisp", "--gc", "arena"]).is_err());
+        assert!(parse(&["interp", "--gc=tracing", "a.lisp"]).is_err());
+        assert!(parse(&["--help"]).is_ok());
+    }
+
+    #[test]
+    fn commands() {
+        let options = parse(&["build", "-o", "ack", "ack.lisp"]).unwrap();
+        assert_eq!(Command::Build, options.command);
+        assert_eq!(vec!["ack.lisp".to_string()], options.inputs);
+
+        assert_eq!(Command::Run, parse(&["run", "ack.lisp"]).unwrap().command);
+        assert!(parse(&["run", "--leak-check", "ack.lisp"]).unwrap().leak_check);
+        assert_eq!(Collector::Stress, parse(&["run", "--gc=stress", "ack.lisp"]).unwrap().gc);
+        assert!(parse(&["build"]).is_err());
+        assert!(parse(&["build", "--emit=ast", "ack.lisp"]).is_err());
+        assert!(parse(&["run", "-o", "ack", "ack.lisp"]).is_err());
+
+        let options = parse(&["repl"]).unwrap();
+        assert_eq!(Command::Repl, options.command);
+        assert!(options.inputs.is_empty());
+        assert!(parse(&["repl", "--check"]).is_err());
+
+        assert_eq!(Command::Interp, parse(&["interp", "ack.lisp"]).unwrap().command);
+        assert!(parse(&["interp"]).is_err());
+        assert!(parse(&["interp", "-o", "out", "ack.lisp"]).is_err());
+
+        let options = parse(&["fmt", "--check", "ack.lisp"]).unwrap();
+        assert_eq!(Command::Fmt, options.command);
+        assert!(options.check);
+        assert!(parse(&["fmt", "-o", "out.lisp", "ack.lisp"]).is_err());
+        assert!(parse(&["fmt", "--emit=ast", "ack.lisp"]).is_err());
+
+        let options = parse(&["lsp", "--natives", "n.json"]).unwrap();
+        assert_eq!(Command::Lsp, options.command);
+        assert_eq!(Some("n.json".to_string()), options.natives);
+        assert!(parse(&["lsp", "ack.lisp"]).is_err());
+        assert!(parse(&["lsp", "--check"]).is_err());
+    }
+
+    #[test]
+    fn passes() {
+        let options = parse(&["--dump-after", "condition-unroll", "--dump-after=tail-call-marking", "a.lisp"]).unwrap();
+        assert_eq!(vec!["condition-unroll".to_string(), "tail-call-marking".to_string()], options.dump_after);
+        assert!(parse(&["run", "--time-passes", "a.lisp"]).unwrap().time_passes);
+
+        assert!(parse(&["--dump-after=optimize", "a.lisp"]).is_err());
+        assert!(parse(&["--emit=ast", "--dump-after=condition-unroll", "a.lisp"]).is_err());
+        assert!(parse(&["--emit=expanded-ast", "--dump-after=condition-unroll", "a.lisp"]).is_ok());
+        assert!(parse(&["interp", "--time-passes", "a.lisp"]).is_err());
+
+        assert!(parse(&["--dump-after=dead-code-elimination", "a.lisp"]).is_ok());
+        assert!(parse(&["--keep-dead-code", "--dump-after=dead-code-elimination", "a.lisp"]).is_err());
+        assert!(parse(&["interp", "--keep-dead-code", "a.lisp"]).unwrap().keep_dead_code);
+        assert!(parse(&["repl", "--keep-dead-code"]).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use crate::ast::{self, ASTNode, SymbolTable};
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::expand::MacroExpander;
+use crate::passes::{PassManager, PassRecord};
+use crate::transpile::{Collector, Transpiler};
+use crate::{lex, parse};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The stage of compilation that is written as output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emit {
+    Tokens,
+    // Parse trees, before macro expansion.
+    Parse,
+    // Source code after macro expansion.
+    Expanded,
+    // The AST as it's first constructed.
+    Ast,
+    // The AST after every pass has been applied, right before translation.
+    ExpandedAst,
+    C,
+}
+
+/// A file of LISP source. Spans in diagnostics refer to sources by their index in the list that
+/// was compiled.
+#[derive(Clone, Debug)]
+pub struct Source {
+    pub name: String,
+    pub contents: String,
+}
+
+impl Source {
+    pub fn new(name: &str, contents: &str) -> Self {
+        Source { name: name.to_string(), contents: contents.to_string() }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    /// The JSON file defining the natives, or none for the `natives.json` that lispc is built with.
+    pub natives: Option<String>,
+    /// The stage whose output is returned.
+    pub emit: Emit,
+    /// Only look for errors, without producing any output.
+    pub check: bool,
+    /// Run every tail call in constant stack space. See `Transpiler::set_trampoline`.
+    pub trampoline: bool,
+    /// Report leaked data when the program exits. See `Transpiler::set_leak_check`.
+    pub leak_check: bool,
+    pub collector: Collector,
+    /// Stop after this many errors, or never if it's 0.
+    pub error_limit: usize,
+    /// The name the C will be written under, which is recorded in the source map.
+    pub c_file: Option<String>,
+    /// The passes to dump the AST after.
+    pub dump_after: Vec<String>,
+    /// Measure how long each stage and pass takes.
+    pub time_passes: bool,
+    /// Skip `DeadCodeElimination`, so that every definition is translated, for debugging.
+    pub keep_dead_code: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            natives: None,
+            emit: Emit::C,
+            check: false,
+            trampoline: false,
+            leak_check: false,
+            collector: Collector::RefCount,
+            error_limit: 20,
+            c_file: None,
+            dump_after: Vec::new(),
+            time_passes: false,
+            keep_dead_code: false,
+        }
+    }
+}
+
+/// The result of compiling a program.
+#[derive(Clone, Debug)]
+pub struct Compilation {
+    /// The generated C, or the output of the stage asked for. There's none if there were errors,
+    /// or if the program was only checked.
+    pub output: Option<String>,
+    /// Every error found, in the order they were found.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Whether compilation stopped early, after reaching the error limit.
+    pub limited: bool,
+    /// A JSON map from the lines of the generated C to the source, if C was generated.
+    pub source_map: Option<String>,
+    /// The AST after each pass named by `dump_after`, by the name of the pass.
+    pub dumps: Vec<(String, String)>,
+    /// How long each stage and pass took, if `time_passes` is on.
+    pub timings: Vec<(String, Duration)>,
+}
+
+impl Compilation {
+    pub fn is_ok(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+}
+
+/// A program that has been through every pass, ready to be translated or interpreted.
+pub struct Program {
+    pub ast: Vec<ASTNode>,
+    pub sym_table: SymbolTable,
+}
+
+/// How far `Compiler::run_stages` went.
+enum Analysis {
+    // The output of the stage that `emit` asked for.
+    Emitted(String),
+    // The AST after every pass, ready to be translated or interpreted.
+    Program(Vec<ASTNode>),
+}
+
+/// Compiles LISP programs into C. A compiler can be used for any number of programs, which don't
+/// share anything but the natives.
+pub struct Compiler {
+    options: CompileOptions,
+    sym_table: SymbolTable,
+    passes: PassManager,
+}
+
+impl Compiler {
+    /// Creates a compiler, reading the natives named by the options.
+    pub fn new(options: CompileOptions) -> Result<Self, String> {
+        let sym_table = SymbolTable::load(options.natives.as_deref())?;
+        Self::with_symbol_table(options, sym_table)
+    }
+
+    /// Creates a compiler with natives that have already been read. Fails if `dump_after` names
+    /// a pass that doesn't exist.
+    pub fn with_symbol_table(options: CompileOptions, sym_table: SymbolTable) -> Result<Self, String> {
+        let mut passes = if options.keep_dead_code { PassManager::standard() } else { PassManager::optimized() };
+        passes.set_dump_after(options.dump_after.clone())?;
+        passes.set_time_passes(options.time_passes);
+
+        Ok(Compiler { options, sym_table, passes })
+    }
+
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    /// The passes that each program goes through, which passes of your own can be inserted into.
+    pub fn passes_mut(&mut self) -> &mut PassManager {
+        &mut self.passes
+    }
+
+    /// The symbol table that each program starts with, which only holds the natives.
+    pub fn symbol_table(&self) -> &SymbolTable {
+        &self.sym_table
+    }
+
+    /// Compiles a program given as a string. Positions in the C refer to it as `<string>`.
+    pub fn compile_str(&self, source: &str) -> Compilation {
+        self.compile(&[Source::new("<string>", source)])
+    }
+
+    /// Compiles a program read from a file.
+    pub fn compile_file(&self, path: impl AsRef<Path>) -> io::Result<Compilation> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        Ok(self.compile(&[Source::new(&path.display().to_string(), &contents)]))
+    }
+
+    /// Compiles the sources together as a single program, in order. The C points back to the
+    /// sources with `#line` directives.
+    pub fn compile(&self, sources: &[Source]) -> Compilation {
+        let mut sym_table = self.sym_table.clone();
+        let mut diagnostics = Diagnostics::new(self.options.error_limit);
+        let mut record = PassRecord::default();
+        let analysis = self.run_stages(sources, self.options.emit, &mut sym_table, &mut diagnostics, &mut record);
+
+        let (output, source_map) = match analysis {
+            _ if !diagnostics.is_empty() || self.options.check => (None, None),
+            Analysis::Emitted(output) => (Some(output), None),
+            Analysis::Program(ast) => {
+                let mut transpiler = Transpiler::new(sym_table);
+                transpiler.set_trampoline(self.options.trampoline);
+                transpiler.set_leak_check(self.options.leak_check);
+                transpiler.set_collector(self.options.collector);
+                transpiler.set_sources(sources.iter().map(|s| s.name.clone()).collect());
+
+                let c = self.timed("translate", &mut record, || transpiler.translate(&ast));
+                (Some(c), Some(transpiler.source_map(self.options.c_file.as_deref())))
+            }
+        };
+
+        Compilation {
+            output,
+            limited: diagnostics.is_full(),
+            diagnostics: diagnostics.into_vec(),
+            source_map,
+            dumps: record.dumps,
+            timings: record.timings,
+        }
+    }
+
+    /// Runs the sources through every pass, without translating them, for programs that are run
+    /// some other way, like the interpreter. Ignores `emit`.
+    pub fn analyze(&self, sources: &[Source]) -> Result<Program, Box<Compilation>> {
+        let mut sym_table = self.sym_table.clone();
+        let mut diagnostics = Diagnostics::new(self.options.error_limit);
+        let mut record = PassRecord::default();
+
+        match self.run_stages(sources, Emit::C, &mut sym_table, &mut diagnostics, &mut record) {
+            Analysis::Program(ast) if diagnostics.is_empty() => Ok(Program { ast, sym_table }),
+            _ => Err(Box::new(Compilation {
+                output: None,
+                limited: diagnostics.is_full(),
+                diagnostics: diagnostics.into_vec(),
+                source_map: None,
+                dumps: record.dumps,
+                timings: record.timings,
+            })),
+        }
+    }
+
+    /// Runs the sources through every stage before translation as a single program, stopping
+    /// early at the stage that `emit` asks for. Each stage reports its errors and carries on with
+    /// the forms that passed, so that every independent error is found. The result is only valid
+    /// if no errors were reported.
+    fn run_stages(
+        &self,
+        sources: &[Source],
+        emit: Emit,
+        sym_table: &mut SymbolTable,
+        diagnostics: &mut Diagnostics,
+        record: &mut PassRecord,
+    ) -> Analysis {
+        let mut tokens = Vec::new();
+        let mut parse_tree = Vec::new();
+
+        // Files are read separately, so that a list can't be left open from one file to the next.
+        self.timed("parse", record, || {
+            for (i, source) in sources.iter().enumerate() {
+                let mut file_tokens = lex::tokenize(&source.contents, diagnostics);
+
+                for t in file_tokens.iter_mut() {
+                    t.span.source = i as u32;
+                }
+
+                parse_tree.append(&mut parse::parse_all(&file_tokens, diagnostics));
+                tokens.append(&mut file_tokens);
+            }
+        });
+
+        match emit {
+            Emit::Tokens => {
+                return Analysis::Emitted(join(&tokens, |t| {
+                    let file = &sources[t.span.source as usize].name;
+                    format!("{}:{}:{}: {:?}", file, t.span.line, t.span.column, t.value)
+                }))
+            }
+            Emit::Parse => return Analysis::Emitted(join(&parse_tree, |t| t.to_string())),
+            _ => (),
+        }
+
+        let parse_tree = self.timed("expand", record, || {
+            MacroExpander::new().expand_all(&parse_tree, sym_table, diagnostics)
+        });
+
+        if emit == Emit::Expanded {
+            return Analysis::Emitted(join(&parse_tree, |t| t.to_string()));
+        }
+
+        let ast = self.timed("construct-ast", record, || ast::construct_ast_all(&parse_tree, diagnostics));
+
+        if emit == Emit::Ast {
+            return Analysis::Emitted(join(&ast, |n| format!("{:#?}", n)));
+        }
+
+        let marked = self.passes.run_recorded(&ast, sym_table, diagnostics, record);
+
+        if emit == Emit::ExpandedAst {
+            return Analysis::Emitted(join(&marked, |n| format!("{:#?}", n)));
+        }
+
+        Analysis::Program(marked)
+    }
+
+    /// Runs a stage, recording how long it took if `time_passes` is on.
+    fn timed<T>(&self, stage: &str, record: &mut PassRecord, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+
+        if self.options.time_passes {
+            record.timings.push((stage.to_string(), start.elapsed()));
+        }
+
+        result
+    }
+}
+
+fn join<T>(items: &[T], f: impl Fn(&T) -> String) -> String {
+    items.iter().map(f).collect::<Vec<String>>().join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::*;
+
+    fn compiler(options: CompileOptions) -> Compiler {
+        Compiler::new(options).unwrap()
+    }
+
+    #[test]
+    fn compile_str() {
+        let compilation = compiler(CompileOptions::default()).compile_str("(define x 1)\n(format (+ x 2))\n");
+
+        assert!(compilation.is_ok());
+        assert!(!compilation.limited);
+
+        let c = compilation.output.unwrap();
+        assert!(c.contains("int main(void)"));
+        assert!(c.contains("#line 2 \"<string>\""));
+        assert!(compilation.source_map.unwrap().contains("\"<string>\""));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let compilation = compiler(CompileOptions::default()).compile_str("(format y)\n(car)\n(format z)");
+
+        assert_eq!(None, compilation.output);
+        assert_eq!(2, compilation.diagnostics.len());
+        assert_eq!(1, compilation.diagnostics[0].span.line);
+        assert_eq!(3, compilation.diagnostics[1].span.line);
+
+        let options = CompileOptions { error_limit: 1, ..CompileOptions::default() };
+        let compilation = compiler(options).compile_str("(format y)\n(format z)");
+        assert_eq!(1, compilation.diagnostics.len());
+        assert!(compilation.limited);
+
+        // The stand-in for a token that couldn't be read isn't folded into an error of its own.
+        let compilation = compiler(CompileOptions::default()).compile_str("(format (+ 1 1e999))");
+        assert_eq!(1, compilation.diagnostics.len());
+    }
+
+    #[test]
+    fn undefined_call_spans() {
+        let compilation = compiler(CompileOptions::default()).compile_str("(format 1)\n(defun f (x)\n  (g x))\n(foo 2)");
+
+        assert_eq!(2, compilation.diagnostics.len());
+        assert_eq!((3, 4), (compilation.diagnostics[0].span.line, compilation.diagnostics[0].span.column));
+        assert_eq!((4, 2), (compilation.diagnostics[1].span.line, compilation.diagnostics[1].span.column));
+    }
+
+    #[test]
+    fn options() {
+        let options = CompileOptions { emit: Emit::Tokens, ..CompileOptions::default() };
+        let tokens = compiler(options).compile_str("(+ 1 2)").output.unwrap();
+        assert!(tokens.starts_with("<string>:1:1: "));
+
+        let options = CompileOptions { check: true, ..CompileOptions::default() };
+        let compilation = compiler(options).compile_str("(format 1)");
+        assert!(compilation.is_ok());
+        assert_eq!(None, compilation.output);
+
+        let options = CompileOptions { leak_check: true, ..CompileOptions::default() };
+        assert!(compiler(options).compile_str("(format 1)").output.unwrap().contains("report_leaks();"));
+    }
+
+    #[test]
+    fn several_sources() {
+        let sources = [Source::new("a.lisp", "(define x 1)"), Source::new("b.lisp", "(format x y)")];
+        let compilation = compiler(CompileOptions::default()).compile(&sources);
+
+        assert_eq!(1, compilation.diagnostics.len());
+        assert_eq!(1, compilation.diagnostics[0].span.source);
+
+        let options = CompileOptions { keep_dead_code: true, ..CompileOptions::default() };
+        let program = compiler(options).analyze(&sources[..1]).unwrap();
+        assert_eq!(1, program.ast.len());
+        assert!(compiler(CompileOptions::default()).analyze(&sources).is_err());
+    }
+
+    #[test]
+    fn dead_code() {
+        let program = "(defun unused (x) (+ x 1))\n(define y 2)\n(format 3)";
+
+        let c = compiler(CompileOptions::default()).compile_str(program).output.unwrap();
+        assert!(!c.contains("unused"));
+
+        let options = CompileOptions { keep_dead_code: true, ..CompileOptions::default() };
+        let c = compiler(options).compile_str(program).output.unwrap();
+        assert!(c.contains("unused"));
+    }
+
+    #[test]
+    fn passes() {
+        let options = CompileOptions {
+            dump_after: vec!["condition-unroll".to_string()],
+            time_passes: true,
+            ..CompileOptions::default()
+        };
+        let compilation = compiler(options).compile_str("(define x 1)\n(format (if x 2 3))");
+
+        assert_eq!(1, compilation.dumps.len());
+        assert!(compilation.dumps[0].1.contains("ExpandedCondition"));
+
+        let stages: Vec<&str> = compilation.timings.iter().map(|(s, _)| s.as_str()).collect();
+        assert_eq!("parse", stages[0]);
+        assert_eq!("translate", stages[stages.len() - 1]);
+        assert!(stages.contains(&"tail-call-marking"));
+
+        let options = CompileOptions { dump_after: vec!["optimize".to_string()], ..CompileOptions::default() };
+        assert!(Compiler::new(options).is_err());
+    }
+
+    #[test]
+    fn compile_file() {
+        let compilation = compiler(CompileOptions::default()).compile_file("lisp/ack.lisp").unwrap();
+        assert!(compilation.output.unwrap().contains("#line 2 \"lisp/ack.lisp\""));
+        assert!(compiler(CompileOptions::default()).compile_file("lisp/missing.lisp").is_err());
+    }
+}
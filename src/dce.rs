@@ -0,0 +1,364 @@
+use crate::ast::{ASTNode, Statement::*, SymbolTable, Value, Value::*};
+use crate::lex::TokenValue;
+use std::collections::{HashMap, HashSet};
+
+/// Removes code that can't change what the program does: values that are computed without side
+/// effects and never used, temporaries made by the other passes that are never read, and globals
+/// and functions that nothing reachable from the top level refers to. The lambdas defined only by
+/// removed code go with it, so they're never translated. Runs once conditions have been unrolled
+/// and nested calls unfurled, so that every temporary is visible.
+pub struct DeadCodeElimination;
+
+impl DeadCodeElimination {
+    /// Removes dead code from the whole program. Removing code can leave other code unused, so
+    /// this repeats until nothing more is removed.
+    pub fn eliminate(&self, ast: Vec<ASTNode>) -> Vec<ASTNode> {
+        let mut ast = ast;
+
+        loop {
+            let mut changed = false;
+            let mut read = HashSet::new();
+            ast.iter().for_each(|n| references(n, &mut read));
+
+            ast = self.prune(ast, false, &read, &mut changed);
+            ast = self.shake(ast, &mut changed);
+
+            if !changed {
+                return ast;
+            }
+        }
+    }
+
+    /// Removes the statements of a body that have no effect. If `keep_last` is set, the last
+    /// node is the value of the body, so it's kept.
+    fn prune(&self, body: Vec<ASTNode>, keep_last: bool, read: &HashSet<String>, changed: &mut bool) -> Vec<ASTNode> {
+        let n = body.len();
+        let mut output = Vec::new();
+
+        for (i, node) in body.into_iter().enumerate() {
+            let node = self.prune_node(node, read, changed);
+
+            if keep_last && i + 1 == n {
+                output.push(node);
+                continue;
+            }
+
+            let unread = |name: &str| SymbolTable::is_generated(name) && !read.contains(name);
+
+            match node {
+                ASTNode::Statement(Declaration(name)) if unread(&name) => *changed = true,
+                ASTNode::Statement(Definition(name, v, _)) | ASTNode::Statement(Redefinition(name, v))
+                    if unread(&name) =>
+                {
+                    *changed = true;
+
+                    if !is_pure(&v) {
+                        output.push(ASTNode::Value(v));
+                    }
+                }
+                ASTNode::Statement(ExpandedCondition(c, t, f)) if t.is_empty() && f.is_empty() && is_pure(&c) => {
+                    *changed = true
+                }
+                ASTNode::Value(v) if is_pure(&v) => *changed = true,
+                _ => output.push(node),
+            }
+        }
+
+        output
+    }
+
+    fn prune_node(&self, node: ASTNode, read: &HashSet<String>, changed: &mut bool) -> ASTNode {
+        match node {
+            ASTNode::Value(v) => ASTNode::Value(self.prune_value(v, read, changed)),
+            ASTNode::Statement(Definition(name, v, span)) => {
+                ASTNode::Statement(Definition(name, self.prune_value(v, read, changed), span))
+            }
+            ASTNode::Statement(Redefinition(name, v)) => {
+                ASTNode::Statement(Redefinition(name, self.prune_value(v, read, changed)))
+            }
+            ASTNode::Statement(FunctionDefinition(name, args, vararg, body, scope, span)) => ASTNode::Statement(
+                FunctionDefinition(name, args, vararg, self.prune(body, true, read, changed), scope, span),
+            ),
+            // The result of a condition is assigned in its branches, so nothing in them is kept
+            // for its value.
+            ASTNode::Statement(ExpandedCondition(c, t, f)) => ASTNode::Statement(ExpandedCondition(
+                self.prune_value(c, read, changed),
+                self.prune(t, false, read, changed),
+                self.prune(f, false, read, changed),
+            )),
+            ASTNode::Statement(Declaration(_)) => node,
+        }
+    }
+
+    fn prune_value(&self, value: Value, read: &HashSet<String>, changed: &mut bool) -> Value {
+        match value {
+            Call(callee, args, span) => Call(callee, self.prune_values(args, read, changed), span),
+            TailCall(callee, args, span) => TailCall(callee, self.prune_values(args, read, changed), span),
+            Recur(args, span) => Recur(self.prune_values(args, read, changed), span),
+            Condition(c, t, f) => Condition(
+                Box::new(self.prune_value(*c, read, changed)),
+                Box::new(self.prune_value(*t, read, changed)),
+                Box::new(self.prune_value(*f, read, changed)),
+            ),
+            Lambda(args, vararg, body, scope, span) => {
+                Lambda(args, vararg, self.prune(body, true, read, changed), scope, span)
+            }
+            Let(kind, bindings, body, scope) => {
+                let bindings = bindings
+                    .into_iter()
+                    .map(|(name, init)| (name, self.prune(init, true, read, changed)))
+                    .collect();
+
+                Let(kind, bindings, self.prune(body, true, read, changed), scope)
+            }
+            Begin(body) => Begin(self.prune(body, true, read, changed)),
+            Literal(_) | Quotation(_) => value,
+        }
+    }
+
+    fn prune_values(&self, values: Vec<Value>, read: &HashSet<String>, changed: &mut bool) -> Vec<Value> {
+        values.into_iter().map(|v| self.prune_value(v, read, changed)).collect()
+    }
+
+    /// Removes the top level definitions of names that can't be reached from the rest of the
+    /// program. A definition whose value has side effects is replaced by the value.
+    fn shake(&self, ast: Vec<ASTNode>, changed: &mut bool) -> Vec<ASTNode> {
+        let mut definitions: HashMap<&str, Vec<&ASTNode>> = HashMap::new();
+        let mut pending = HashSet::new();
+
+        for node in &ast {
+            // Globals that are assigned below the top level are kept, since the assignment is.
+            nested_definitions(node, &mut pending);
+
+            match node {
+                ASTNode::Statement(Definition(name, v, _)) | ASTNode::Statement(Redefinition(name, v)) => {
+                    definitions.entry(name).or_default().push(node);
+
+                    if !is_pure(v) {
+                        value_references(v, &mut pending);
+                    }
+                }
+                ASTNode::Statement(FunctionDefinition(name, ..)) => definitions.entry(name).or_default().push(node),
+                _ => references(node, &mut pending),
+            }
+        }
+
+        let mut live = HashSet::new();
+
+        while let Some(name) = pending.iter().next().cloned() {
+            pending.remove(&name);
+
+            for node in definitions.get(name.as_str()).into_iter().flatten() {
+                let mut found = HashSet::new();
+                references(node, &mut found);
+                pending.extend(found.into_iter().filter(|n| !live.contains(n) && *n != name));
+            }
+
+            live.insert(name);
+        }
+
+        let mut output = Vec::new();
+
+        for node in ast {
+            match node {
+                ASTNode::Statement(Definition(name, v, _)) | ASTNode::Statement(Redefinition(name, v))
+                    if !live.contains(&name) =>
+                {
+                    *changed = true;
+
+                    if !is_pure(&v) {
+                        output.push(ASTNode::Value(v));
+                    }
+                }
+                ASTNode::Statement(FunctionDefinition(ref name, ..)) if !live.contains(name) => *changed = true,
+                _ => output.push(node),
+            }
+        }
+
+        output
+    }
+}
+
+/// Checks that evaluating a value can't have an effect, including raising an error. Any call might.
+fn is_pure(value: &Value) -> bool {
+    match value {
+        Literal(_) | Quotation(_) | Lambda(..) => true,
+        Condition(c, t, f) => is_pure(c) && is_pure(t) && is_pure(f),
+        Let(_, bindings, body, _) => bindings.iter().all(|(_, init)| is_pure_body(init)) && is_pure_body(body),
+        Begin(body) => is_pure_body(body),
+        Call(..) | TailCall(..) | Recur(..) => false,
+    }
+}
+
+fn is_pure_body(body: &[ASTNode]) -> bool {
+    body.iter().all(|n| match n {
+        ASTNode::Value(v) => is_pure(v),
+        ASTNode::Statement(Declaration(_)) => true,
+        _ => false,
+    })
+}
+
+/// Adds the names of the variables defined inside of a node, other than temporaries, but not the
+/// name that the node itself defines.
+fn nested_definitions(node: &ASTNode, names: &mut HashSet<String>) {
+    fn body(nodes: &[ASTNode], names: &mut HashSet<String>) {
+        for n in nodes {
+            if let ASTNode::Statement(Definition(name, _, _)) | ASTNode::Statement(Redefinition(name, _)) = n {
+                if !SymbolTable::is_generated(name) {
+                    names.insert(name.clone());
+                }
+            }
+
+            nested_definitions(n, names);
+        }
+    }
+
+    fn value(v: &Value, names: &mut HashSet<String>) {
+        match v {
+            Call(_, args, _) | TailCall(_, args, _) | Recur(args, _) => args.iter().for_each(|a| value(a, names)),
+            Condition(c, t, f) => {
+                for v in [c, t, f].iter() {
+                    value(v, names);
+                }
+            }
+            Lambda(_, _, nodes, _, _) | Begin(nodes) => body(nodes, names),
+            Let(_, bindings, nodes, _) => {
+                for (_, init) in bindings {
+                    body(init, names);
+                }
+
+                body(nodes, names);
+            }
+            Literal(_) | Quotation(_) => (),
+        }
+    }
+
+    match node {
+        ASTNode::Value(v) | ASTNode::Statement(Definition(_, v, _)) | ASTNode::Statement(Redefinition(_, v)) => {
+            value(v, names)
+        }
+        ASTNode::Statement(FunctionDefinition(_, _, _, nodes, _, _)) => body(nodes, names),
+        ASTNode::Statement(ExpandedCondition(c, t, f)) => {
+            value(c, names);
+            body(t, names);
+            body(f, names);
+        }
+        ASTNode::Statement(Declaration(_)) => (),
+    }
+}
+
+/// Adds the names that a node reads or calls, anywhere inside of it. The names that it defines
+/// aren't included, unless they're also read.
+fn references(node: &ASTNode, names: &mut HashSet<String>) {
+    match node {
+        ASTNode::Value(v) | ASTNode::Statement(Definition(_, v, _)) | ASTNode::Statement(Redefinition(_, v)) => {
+            value_references(v, names)
+        }
+        ASTNode::Statement(FunctionDefinition(_, _, _, body, _, _)) => body.iter().for_each(|n| references(n, names)),
+        ASTNode::Statement(ExpandedCondition(c, t, f)) => {
+            value_references(c, names);
+            t.iter().chain(f).for_each(|n| references(n, names));
+        }
+        ASTNode::Statement(Declaration(_)) => (),
+    }
+}
+
+fn value_references(value: &Value, names: &mut HashSet<String>) {
+    match value {
+        Literal(t) => {
+            if let TokenValue::Symbol(name) = &t.value {
+                names.insert(name.clone());
+            }
+        }
+        Call(callee, args, _) | TailCall(callee, args, _) => {
+            names.insert(callee.clone());
+            args.iter().for_each(|a| value_references(a, names));
+        }
+        Recur(args, _) => args.iter().for_each(|a| value_references(a, names)),
+        Condition(c, t, f) => {
+            for v in [c, t, f].iter() {
+                value_references(v, names);
+            }
+        }
+        Lambda(_, _, body, _, _) | Begin(body) => body.iter().for_each(|n| references(n, names)),
+        Let(_, bindings, body, _) => {
+            for (_, init) in bindings {
+                init.iter().for_each(|n| references(n, names));
+            }
+
+            body.iter().for_each(|n| references(n, names));
+        }
+        Quotation(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::SymbolTable;
+    use crate::compiler::{CompileOptions, Compiler, Source};
+    use crate::dce::*;
+
+    /// Runs every pass over a program, including this one, giving the names of the top level
+    /// definitions that are left, and the number of other top level nodes, including the
+    /// definitions of temporaries.
+    fn eliminate(program: &str) -> (Vec<String>, usize) {
+        let compiler = Compiler::new(CompileOptions::default()).unwrap();
+        let program = compiler.analyze(&[Source::new("<string>", program)]).ok().unwrap();
+
+        let mut names = Vec::new();
+        let mut others = 0;
+
+        for node in program.ast {
+            match node {
+                ASTNode::Statement(Definition(name, _, _)) | ASTNode::Statement(FunctionDefinition(name, ..))
+                    if !SymbolTable::is_generated(&name) =>
+                {
+                    names.push(name)
+                }
+                _ => others += 1,
+            }
+        }
+
+        (names, others)
+    }
+
+    #[test]
+    fn pure_expressions() {
+        assert_eq!((vec![], 0), eliminate("1 \"two\" :three '(4) (lambda (x) x) (begin 5 6)"));
+        assert_eq!((vec![], 1), eliminate("1 (format 2) 3"));
+
+        // The temporaries made for a condition are only kept if the condition has an effect.
+        assert_eq!((vec!["x".to_string()], 3), eliminate("(define x 1) (format (if x 2 3))"));
+        assert_eq!((vec![], 0), eliminate("(define x 1) (if x 2 3)"));
+        assert_eq!((vec!["x".to_string()], 1), eliminate("(define x 1) (if x (format 2) 3)"));
+    }
+
+    #[test]
+    fn unused_definitions() {
+        let program = "
+            (define unused 1)
+            (define used 2)
+            (defun helper (x) (+ x used))
+            (defun dead (x) (helper x))
+            (define reached (lambda () (helper 1)))
+            (format (funcall reached))";
+
+        assert_eq!(
+            (vec!["used".to_string(), "helper".to_string(), "reached".to_string()], 2),
+            eliminate(program)
+        );
+
+        // The value is still computed, for its effect.
+        assert_eq!((vec![], 1), eliminate("(define x (format 1))"));
+
+        // Functions that only refer to each other are removed together.
+        assert_eq!((vec![], 0), eliminate("(defun f (x) (g x)) (defun g (x) (f x))"));
+    }
+
+    #[test]
+    fn bodies() {
+        // The last value of a body is its result. The call to `f` is unwrapped into a temporary.
+        let program = "(defun f (x) 1 (format x) x) (format (f 2))";
+        assert_eq!((vec!["f".to_string()], 2), eliminate(program));
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+
+// Error codes, grouped by the stage that reports them.
+
+/// Text that can't be read as any token.
+pub const INVALID_TOKEN: &str = "E0001";
+/// A string without a closing quote.
+pub const UNTERMINATED_STRING: &str = "E0002";
+/// A list that is closed without being opened, or never closed.
+pub const UNBALANCED_LIST: &str = "E0101";
+/// Misuse of `.` or of a reader shorthand like `'`.
+pub const MALFORMED_LIST: &str = "E0102";
+/// A `defmacro` or `define-syntax` form that can't be used as a macro.
+pub const INVALID_MACRO: &str = "E0201";
+/// A macro use that can't be expanded.
+pub const MACRO_MISMATCH: &str = "E0202";
+/// Macro expansion that keeps producing more macro uses, past the maximum depth.
+pub const EXPANSION_DEPTH: &str = "E0203";
+/// A special form or function call with the wrong shape.
+pub const INVALID_SYNTAX: &str = "E0301";
+/// A symbol used before it has been bound.
+pub const UNDEFINED_SYMBOL: &str = "E0401";
+/// A function defined more than once using `defun`, or a native or `defun` defined over with
+/// `define`.
+pub const REDEFINED_FUNCTION: &str = "E0402";
+/// `recur` outside of a function, outside of tail position, or with the wrong number of arguments.
+pub const INVALID_RECUR: &str = "E0403";
+/// A call that's certain to fail, found by evaluating it at compile time.
+pub const CONSTANT_EVALUATION: &str = "E0501";
+/// A state that a correct compiler never reaches.
+pub const INTERNAL: &str = "E0900";
+/// Generated C that the C compiler rejects.
+pub const C_COMPILER: &str = "E0901";
+
+/// A position in source code. Lines and columns start at 1, and 0 marks an unknown position, as is
+/// the case for code generated by the compiler.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+    // The number of characters covered, used to underline the offending text.
+    pub length: u32,
+    // The index of the input file, for programs made up of several files.
+    pub source: u32,
+}
+
+impl Span {
+    pub fn new(line: u32, column: u32, length: u32) -> Self {
+        Span {
+            line,
+            column,
+            length,
+            source: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A problem found while compiling, shared by every stage of the compiler.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub file: Option<String>,
+    pub span: Span,
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, span: Span, message: String) -> Self {
+        Diagnostic {
+            file: None,
+            span,
+            severity: Severity::Error,
+            code,
+            message,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    /// Formats the diagnostic like rustc does, quoting the offending line of the source and
+    /// pointing to the span with carets.
+    pub fn render(&self, source: &str) -> String {
+        let mut output = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+
+        let file = self.file.as_deref().unwrap_or("<input>");
+        let text = match self.span.line {
+            0 => None,
+            n => source.lines().nth(n as usize - 1),
+        };
+
+        let text = match text {
+            Some(t) => t,
+            None => {
+                output.push_str(&format!(" --> {}\n", file));
+
+                for note in &self.notes {
+                    output.push_str(&format!(" = note: {}\n", note));
+                }
+
+                return output;
+            }
+        };
+
+        let gutter = self.span.line.to_string();
+        let pad = " ".repeat(gutter.len());
+
+        if self.span.column == 0 {
+            output.push_str(&format!("{}--> {}:{}\n", pad, file, self.span.line));
+        } else {
+            output.push_str(&format!(
+                "{}--> {}:{}:{}\n",
+                pad, file, self.span.line, self.span.column
+            ));
+        }
+
+        output.push_str(&format!("{} |\n", pad));
+        output.push_str(&format!("{} | {}\n", gutter, text));
+
+        if self.span.column != 0 {
+            // Tabs are kept so that the carets line up however the source line is displayed.
+            let indent: String = text
+                .chars()
+                .take(self.span.column as usize - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            output.push_str(&format!(
+                "{} | {}{}\n",
+                pad,
+                indent,
+                "^".repeat(self.span.length.max(1) as usize)
+            ));
+        }
+
+        if !self.notes.is_empty() {
+            output.push_str(&format!("{} |\n", pad));
+        }
+
+        for note in &self.notes {
+            output.push_str(&format!("{} = note: {}\n", pad, note));
+        }
+
+        output
+    }
+}
+
+/// Collects diagnostics so that a single compile can report every independent problem. Once the
+/// limit is reached, further diagnostics are dropped, and stages should stop early.
+pub struct Diagnostics {
+    reported: Vec<Diagnostic>,
+    // A limit of 0 means that there is no limit.
+    limit: usize,
+}
+
+impl Diagnostics {
+    pub fn new(limit: usize) -> Self {
+        Diagnostics {
+            reported: Vec::new(),
+            limit,
+        }
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        if !self.is_full() {
+            self.reported.push(diagnostic);
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.limit != 0 && self.reported.len() >= self.limit
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reported.is_empty()
+    }
+
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.reported
+    }
+
+    /// Converts the result of a stage run with a limit of 1 back into a single error.
+    pub fn into_result<T>(self, value: T) -> Result<T, Diagnostic> {
+        match self.reported.into_iter().next() {
+            Some(d) => Err(d),
+            None => Ok(value),
+        }
+    }
+}
+
+/// The short form of a diagnostic, used where the source isn't available.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+
+        write!(
+            f,
+            "{}:{}: {}[{}]: {}",
+            self.span.line, self.span.column, self.severity, self.code, self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostic::*;
+
+    #[test]
+    fn render_with_caret() {
+        let source = "(define x 1)\n(format (+ x y))\n";
+        let d = Diagnostic::error(
+            UNDEFINED_SYMBOL,
+            Span::new(2, 14, 1),
+            "Use of undefined variable: y.".to_string(),
+        )
+        .with_file("test.lisp")
+        .with_note("symbols must be defined before they are used");
+
+        assert_eq!(
+            d.render(source),
+            "error[E0401]: Use of undefined variable: y.\n \
+             --> test.lisp:2:14\n  \
+             |\n\
+             2 | (format (+ x y))\n  \
+             |              ^\n  \
+             |\n  \
+             = note: symbols must be defined before they are used\n"
+        );
+    }
+
+    #[test]
+    fn render_underlines_span() {
+        let d = Diagnostic::error(INVALID_TOKEN, Span::new(1, 3, 4), "Bad.".to_string());
+
+        assert!(d.render("  #tff ").ends_with("1 |   #tff \n  |   ^^^^\n"));
+    }
+
+    #[test]
+    fn render_unknown_position() {
+        let d = Diagnostic::error(INTERNAL, Span::default(), "Oops.".to_string());
+
+        assert_eq!(d.render("(a)"), "error[E0900]: Oops.\n --> <input>\n");
+    }
+
+    #[test]
+    fn render_without_column() {
+        let d = Diagnostic::error(INVALID_SYNTAX, Span::new(1, 0, 0), "Bad.".to_string());
+
+        assert_eq!(d.render("(a)"), "error[E0301]: Bad.\n --> <input>:1\n  |\n1 | (a)\n");
+    }
+
+    #[test]
+    fn limit() {
+        let error = || Diagnostic::error(INVALID_SYNTAX, Span::default(), "Bad.".to_string());
+        let mut diagnostics = Diagnostics::new(2);
+
+        assert!(diagnostics.is_empty());
+        diagnostics.report(error());
+        assert!(!diagnostics.is_full());
+        diagnostics.report(error());
+        diagnostics.report(error());
+        assert!(diagnostics.is_full());
+        assert_eq!(2, diagnostics.into_vec().len());
+
+        let mut unlimited = Diagnostics::new(0);
+
+        for _ in 0..100 {
+            unlimited.report(error());
+        }
+
+        assert!(!unlimited.is_full());
+    }
+
+    #[test]
+    fn display() {
+        let d = Diagnostic::error(INVALID_SYNTAX, Span::new(3, 7, 1), "Bad.".to_string())
+            .with_file("a.lisp");
+
+        assert_eq!(d.to_string(), "a.lisp:3:7: error[E0301]: Bad.");
+    }
+}
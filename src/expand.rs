@@ -0,0 +1,1197 @@
+use crate::ast::SymbolTable;
+use crate::diagnostic::{
+    Diagnostic, Diagnostics, Span, EXPANSION_DEPTH, INVALID_MACRO, MACRO_MISMATCH,
+};
+use crate::lex::{Token, TokenValue::*};
+use crate::parse::ParseTree;
+use std::collections::HashMap;
+
+/// Names that can't be redefined as macros.
+const SPECIAL_FORMS: [&str; 15] = [
+    "if",
+    "define",
+    "lambda",
+    "defun",
+    "let",
+    "let*",
+    "letrec",
+    "begin",
+    "progn",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "defmacro",
+    "define-syntax",
+];
+
+/// Guards against macros that expand into themselves forever.
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+#[derive(Clone, Debug)]
+enum Macro {
+    // params, vararg, template
+    // Defined by `defmacro`. The template is a parameter, or a quoted or quasiquoted form.
+    Template(Vec<String>, Option<String>, ParseTree),
+
+    // literals, (pattern, template) clauses
+    // Defined by `define-syntax` using `syntax-rules`. Patterns don't include the keyword.
+    Rules(Vec<String>, Vec<(ParseTree, ParseTree)>),
+}
+
+/// Values matched by pattern variables in `syntax-rules`. Variables followed by an ellipsis match
+/// any number of forms.
+#[derive(Clone, Debug)]
+enum Binding {
+    One(ParseTree),
+    Many(Vec<HashMap<String, Binding>>),
+}
+
+/// Expands macros in the parse tree, before the AST is constructed. Macros are kept between calls
+/// to `expand`, so a single expander may be used for several inputs.
+#[derive(Clone, Default)]
+pub struct MacroExpander {
+    macros: HashMap<String, Macro>,
+}
+
+impl MacroExpander {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove all macro definitions from the input, and expand every use of them. Symbols bound by
+    /// macro templates are renamed using the symbol table so that they can't capture user names.
+    #[cfg(test)]
+    pub fn expand(
+        &mut self,
+        trees: &[ParseTree],
+        sym_table: &mut SymbolTable,
+    ) -> Result<Vec<ParseTree>, Diagnostic> {
+        let mut diagnostics = Diagnostics::new(1);
+        let output = self.expand_all(trees, sym_table, &mut diagnostics);
+
+        diagnostics.into_result(output)
+    }
+
+    /// Like `expand`, but reports errors and moves on to the next top level form, which is left
+    /// out of the output.
+    pub fn expand_all(
+        &mut self,
+        trees: &[ParseTree],
+        sym_table: &mut SymbolTable,
+        diagnostics: &mut Diagnostics,
+    ) -> Vec<ParseTree> {
+        let mut output = Vec::new();
+
+        for tree in trees {
+            if diagnostics.is_full() {
+                break;
+            }
+
+            match self.expand_top_level(tree, sym_table, 0) {
+                Ok(Some(expansion)) => output.push(expansion),
+                Ok(None) => (),
+                Err(d) => diagnostics.report(d),
+            }
+        }
+
+        output
+    }
+
+    /// Macro definitions are only allowed at the top level, including those produced by other
+    /// macros. Returns `None` when the tree defined a macro.
+    fn expand_top_level(
+        &mut self,
+        tree: &ParseTree,
+        sym_table: &mut SymbolTable,
+        depth: usize,
+    ) -> Result<Option<ParseTree>, Diagnostic> {
+        match head(tree) {
+            Some(("defmacro", span)) => {
+                self.define_template(tree, span)?;
+                Ok(None)
+            }
+            Some(("define-syntax", span)) => {
+                self.define_rules(tree, span)?;
+                Ok(None)
+            }
+            Some((s, span)) if self.macros.contains_key(s) => {
+                let expansion = self.apply(s, span, tree, sym_table, depth)?;
+                self.expand_top_level(&expansion, sym_table, depth + 1)
+            }
+            _ => Ok(Some(self.expand_tree(tree, sym_table, depth)?)),
+        }
+    }
+
+    fn expand_tree(
+        &self,
+        tree: &ParseTree,
+        sym_table: &mut SymbolTable,
+        depth: usize,
+    ) -> Result<ParseTree, Diagnostic> {
+        let (elems, start, stop, tail) = match tree {
+            ParseTree::Leaf(_) => return Ok(tree.clone()),
+            ParseTree::Branch(elems, start, stop, tail) => (elems, *start, *stop, tail),
+        };
+
+        match head(tree) {
+            Some(("quote", _)) => return Ok(tree.clone()),
+            Some(("quasiquote", _)) => return self.expand_quasiquote(tree, sym_table, 0, depth),
+            Some((s, span)) if s == "defmacro" || s == "define-syntax" => {
+                return Err(Diagnostic::error(INVALID_MACRO, span, format!("`{}` may only be used at the top level.", s)))
+            }
+            Some((s, span)) if self.macros.contains_key(s) => {
+                let expansion = self.apply(s, span, tree, sym_table, depth)?;
+                return self.expand_tree(&expansion, sym_table, depth + 1);
+            }
+            _ => (),
+        }
+
+        // Macro uses inside of an expansion count towards its depth, since a macro can also
+        // expand into a larger use of itself.
+        let mut new_elems = Vec::new();
+
+        for elem in elems {
+            new_elems.push(self.expand_tree(elem, sym_table, depth)?);
+        }
+
+        let tail = match tail {
+            Some(t) => Some(Box::new(self.expand_tree(t, sym_table, depth)?)),
+            None => None,
+        };
+
+        Ok(ParseTree::Branch(new_elems, start, stop, tail))
+    }
+
+    /// Only the unquoted parts of a quasiquote are code, so nothing else is expanded. The level
+    /// counts how many quasiquotes the tree is nested in, while the depth is the macro expansion
+    /// depth, as for `expand_tree`.
+    fn expand_quasiquote(
+        &self,
+        tree: &ParseTree,
+        sym_table: &mut SymbolTable,
+        level: usize,
+        depth: usize,
+    ) -> Result<ParseTree, Diagnostic> {
+        match tree {
+            ParseTree::Leaf(_) => Ok(tree.clone()),
+            ParseTree::Branch(elems, start, stop, tail) => {
+                let level = match head(tree) {
+                    Some(("quasiquote", _)) => level + 1,
+                    Some((s, _)) if s == "unquote" || s == "unquote-splicing" => level - 1,
+                    _ => level,
+                };
+
+                if level == 0 {
+                    return self.expand_tree(tree, sym_table, depth);
+                }
+
+                let mut new_elems = Vec::new();
+
+                for elem in elems {
+                    new_elems.push(self.expand_quasiquote(elem, sym_table, level, depth)?);
+                }
+
+                let tail = match tail {
+                    Some(t) => Some(Box::new(self.expand_quasiquote(t, sym_table, level, depth)?)),
+                    None => None,
+                };
+
+                Ok(ParseTree::Branch(new_elems, *start, *stop, tail))
+            }
+        }
+    }
+
+    fn check_name(
+        &self,
+        tree: Option<&ParseTree>,
+        form: &str,
+        span: Span,
+    ) -> Result<String, Diagnostic> {
+        match tree {
+            Some(ParseTree::Leaf(Token {
+                value: Symbol(s), ..
+            })) => {
+                if SPECIAL_FORMS.contains(&s.as_str()) {
+                    Err(Diagnostic::error(
+                        INVALID_MACRO,
+                        span,
+                        format!(
+                            "Cannot define a macro named `{}`, as it is a special form.",
+                            s
+                        ),
+                    ))
+                } else {
+                    Ok(s.clone())
+                }
+            }
+            _ => Err(Diagnostic::error(
+                INVALID_MACRO,
+                span,
+                format!(
+                    "Expected a symbol in second position of `{}` special form.",
+                    form
+                ),
+            )),
+        }
+    }
+
+    /// Handle `(defmacro name (params . vararg) template)`.
+    fn define_template(&mut self, tree: &ParseTree, span: Span) -> Result<(), Diagnostic> {
+        let elems = children(tree);
+
+        if elems.len() != 4 {
+            return Err(Diagnostic::error(
+                INVALID_MACRO,
+                span,
+                format!(
+                    "Expected exactly 3 arguments in `defmacro` special form. Found {}.",
+                    elems.len() - 1
+                ),
+            ));
+        }
+
+        let name = self.check_name(elems.get(1), "defmacro", span)?;
+
+        let not_symbol = Diagnostic::error(
+            INVALID_MACRO,
+            span,
+            "All elements in second argument to `defmacro` special form should be symbols."
+                .to_string(),
+        );
+
+        let (params, vararg) = match &elems[2] {
+            ParseTree::Branch(args, _, _, tail) => {
+                let mut params = Vec::new();
+
+                for arg in args {
+                    params.push(symbol(arg).ok_or_else(|| not_symbol.clone())?);
+                }
+
+                let vararg = match tail {
+                    Some(t) => Some(symbol(t).ok_or_else(|| not_symbol.clone())?),
+                    None => None,
+                };
+
+                (params, vararg)
+            }
+            _ => {
+                return Err(Diagnostic::error(
+                    INVALID_MACRO,
+                    span,
+                    "Expected parameter list in third position of `defmacro` special form."
+                        .to_string(),
+                ))
+            }
+        };
+
+        let template = elems[3].clone();
+        let is_param = |t: &ParseTree| {
+            symbol(t).is_some_and(|s| params.contains(&s) || vararg.as_ref() == Some(&s))
+        };
+
+        match head(&template) {
+            _ if is_param(&template) => (),
+            Some((s, _)) if (s == "quote" || s == "quasiquote") && children(&template).len() == 2 => (),
+            _ => {
+                return Err(Diagnostic::error(
+                    INVALID_MACRO,
+                    span,
+                    format!(
+                        "The body of macro `{}` should be a parameter, or a quoted or quasiquoted template.",
+                        name
+                    ),
+                ))
+            }
+        }
+
+        self.macros
+            .insert(name, Macro::Template(params, vararg, template));
+
+        Ok(())
+    }
+
+    /// Handle `(define-syntax name (syntax-rules (literals) (pattern template) ...))`.
+    fn define_rules(&mut self, tree: &ParseTree, span: Span) -> Result<(), Diagnostic> {
+        let elems = children(tree);
+
+        if elems.len() != 3 {
+            return Err(Diagnostic::error(
+                INVALID_MACRO,
+                span,
+                format!(
+                    "Expected exactly 2 arguments in `define-syntax` special form. Found {}.",
+                    elems.len() - 1
+                ),
+            ));
+        }
+
+        let name = self.check_name(elems.get(1), "define-syntax", span)?;
+
+        let rules =
+            match head(&elems[2]) {
+                Some(("syntax-rules", _)) => children(&elems[2]),
+                _ => return Err(Diagnostic::error(
+                    INVALID_MACRO,
+                    span,
+                    "Expected `syntax-rules` in third position of `define-syntax` special form."
+                        .to_string(),
+                )),
+            };
+
+        let literals = match rules.get(1) {
+            Some(ParseTree::Branch(lits, _, _, None)) => {
+                let mut literals = Vec::new();
+
+                for lit in lits {
+                    literals.push(symbol(lit).ok_or(Diagnostic::error(
+                        INVALID_MACRO,
+                        span,
+                        "Literals in `syntax-rules` should be symbols.".to_string(),
+                    ))?);
+                }
+
+                literals
+            }
+            _ => {
+                return Err(Diagnostic::error(
+                    INVALID_MACRO,
+                    span,
+                    "Expected a list of literals in second position of `syntax-rules`.".to_string(),
+                ))
+            }
+        };
+
+        let mut clauses = Vec::new();
+
+        for clause in &rules[2..] {
+            match clause {
+                ParseTree::Branch(pair, start, stop, None)
+                    if pair.len() == 2 && !children(&pair[0]).is_empty() =>
+                {
+                    // The keyword position is ignored.
+                    let pattern = ParseTree::Branch(
+                        children(&pair[0])[1..].to_vec(),
+                        *start,
+                        *stop,
+                        tail(&pair[0]),
+                    );
+
+                    check_ellipses(&pattern, *start)?;
+                    clauses.push((pattern, pair[1].clone()))
+                }
+                ParseTree::Leaf(Token { span, .. }) | ParseTree::Branch(_, span, _, _) => {
+                    return Err(Diagnostic::error(
+                        INVALID_MACRO,
+                        *span,
+                        "Each rule in `syntax-rules` should be a list containing a pattern and a template."
+                            .to_string(),
+                    ))
+                }
+            }
+        }
+
+        self.macros.insert(name, Macro::Rules(literals, clauses));
+
+        Ok(())
+    }
+
+    /// Expand a single use of a macro, without expanding the result.
+    fn apply(
+        &self,
+        name: &str,
+        span: Span,
+        tree: &ParseTree,
+        sym_table: &mut SymbolTable,
+        depth: usize,
+    ) -> Result<ParseTree, Diagnostic> {
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(Diagnostic::error(
+                EXPANSION_DEPTH,
+                span,
+                format!("Expansion of macro `{}` exceeded the maximum depth.", name),
+            ));
+        }
+
+        match (&self.macros[name], tree) {
+            (Macro::Template(..), ParseTree::Branch(_, _, _, Some(_)))
+            | (Macro::Rules(..), ParseTree::Branch(_, _, _, Some(_))) => Err(Diagnostic::error(
+                MACRO_MISMATCH,
+                span,
+                format!("Cannot use `.` in arguments to macro `{}`.", name),
+            )),
+            (Macro::Template(params, vararg, template), _) => {
+                let args = &children(tree)[1..];
+
+                if args.len() < params.len() || (vararg.is_none() && args.len() > params.len()) {
+                    return Err(Diagnostic::error(
+                        MACRO_MISMATCH,
+                        span,
+                        format!(
+                            "Expected {}{} argument(s) to macro `{}`. Found {}.",
+                            if vararg.is_some() {
+                                "at least "
+                            } else {
+                                "exactly "
+                            },
+                            params.len(),
+                            name,
+                            args.len()
+                        ),
+                    ));
+                }
+
+                let mut bindings = HashMap::new();
+
+                for (param, arg) in params.iter().zip(args) {
+                    bindings.insert(param.clone(), arg.clone());
+                }
+
+                if let Some(v) = vararg {
+                    bindings.insert(
+                        v.clone(),
+                        ParseTree::Branch(args[params.len()..].to_vec(), span, span, None),
+                    );
+                }
+
+                if let Some(arg) = symbol(template).and_then(|s| bindings.get(&s)) {
+                    return Ok(arg.clone());
+                }
+
+                let (form, body) = match head(template) {
+                    Some((s, _)) => (s.to_string(), &children(template)[1]),
+                    None => panic!("Macro template was not validated. Contact the developer."),
+                };
+
+                let renames = generate_renames(body, &|t| unquoted(t, 1), sym_table);
+
+                if form == "quote" {
+                    Ok(relabel(body, span, &renames))
+                } else {
+                    fill(name, body, 1, &bindings, &renames, span)
+                }
+            }
+            (Macro::Rules(literals, clauses), _) => {
+                for (pattern, template) in clauses {
+                    let mut bindings = HashMap::new();
+                    let form = ParseTree::Branch(children(tree)[1..].to_vec(), span, span, None);
+
+                    if match_pattern(pattern, &form, literals, &mut bindings) {
+                        let renames = generate_renames(
+                            template,
+                            &|t| symbol(t).is_some_and(|s| bindings.contains_key(&s)),
+                            sym_table,
+                        );
+
+                        return instantiate(name, template, &bindings, &renames, span);
+                    }
+                }
+
+                Err(Diagnostic::error(
+                    MACRO_MISMATCH,
+                    span,
+                    format!("No rule of macro `{}` matches this use.", name),
+                ))
+            }
+        }
+    }
+}
+
+fn children(tree: &ParseTree) -> &[ParseTree] {
+    match tree {
+        ParseTree::Branch(elems, ..) => elems,
+        ParseTree::Leaf(_) => &[],
+    }
+}
+
+fn tail(tree: &ParseTree) -> Option<Box<ParseTree>> {
+    match tree {
+        ParseTree::Branch(_, _, _, t) => t.clone(),
+        ParseTree::Leaf(_) => None,
+    }
+}
+
+fn symbol(tree: &ParseTree) -> Option<String> {
+    match tree {
+        ParseTree::Leaf(Token {
+            value: Symbol(s), ..
+        }) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// The symbol at the start of a list, along with its span.
+fn head(tree: &ParseTree) -> Option<(&str, Span)> {
+    match tree {
+        ParseTree::Branch(elems, _, _, _) => match elems.first() {
+            Some(ParseTree::Leaf(Token {
+                value: Symbol(s),
+                span,
+            })) => Some((s.as_str(), *span)),
+            _ => None,
+        },
+        ParseTree::Leaf(_) => None,
+    }
+}
+
+fn is_ellipsis(tree: &ParseTree) -> bool {
+    symbol(tree).is_some_and(|s| s == "...")
+}
+
+/// Check if a tree is an `unquote` or `unquote-splicing` form at a given quasiquote depth.
+fn unquoted(tree: &ParseTree, depth: usize) -> bool {
+    depth == 1
+        && matches!(head(tree), Some((s, _)) if s == "unquote" || s == "unquote-splicing")
+        && children(tree).len() == 2
+}
+
+/// Find the names bound by `let`, `let*`, `letrec`, `lambda`, and `defun` forms in a template,
+/// and create a fresh symbol for each of them. Subtrees matching `skip` come from the user, so
+/// they are never searched.
+fn generate_renames(
+    template: &ParseTree,
+    skip: &dyn Fn(&ParseTree) -> bool,
+    sym_table: &mut SymbolTable,
+) -> HashMap<String, String> {
+    let mut binders = Vec::new();
+    find_binders(template, skip, &mut binders);
+
+    binders
+        .into_iter()
+        .map(|b| {
+            let renamed = sym_table.generate_symbol(&b);
+            (b, renamed)
+        })
+        .collect()
+}
+
+fn find_binders(tree: &ParseTree, skip: &dyn Fn(&ParseTree) -> bool, binders: &mut Vec<String>) {
+    if skip(tree) {
+        return;
+    }
+
+    let elems = children(tree);
+    let mut add = |t: &ParseTree| {
+        if let Some(s) = symbol(t) {
+            if !skip(t) && !binders.contains(&s) {
+                binders.push(s);
+            }
+        }
+    };
+
+    match (head(tree), elems.get(1), elems.get(2)) {
+        (Some((s, _)), Some(ParseTree::Branch(bindings, ..)), _)
+            if s == "let" || s == "let*" || s == "letrec" =>
+        {
+            for binding in bindings {
+                if let Some(name) = children(binding).first() {
+                    add(name);
+                }
+            }
+        }
+        (Some(("lambda", _)), Some(args @ ParseTree::Branch(..)), _) => {
+            children(args).iter().for_each(&mut add);
+            tail(args).iter().for_each(|t| add(t));
+        }
+        (Some(("defun", _)), _, Some(args @ ParseTree::Branch(..))) => {
+            children(args).iter().for_each(&mut add);
+            tail(args).iter().for_each(|t| add(t));
+        }
+        _ => (),
+    }
+
+    for elem in elems {
+        find_binders(elem, skip, binders);
+    }
+
+    if let Some(t) = tail(tree) {
+        find_binders(&t, skip, binders);
+    }
+}
+
+/// Copy a tree from a template, renaming symbols and moving it to the position of the macro use.
+fn relabel(tree: &ParseTree, span: Span, renames: &HashMap<String, String>) -> ParseTree {
+    match tree {
+        ParseTree::Leaf(t) => {
+            let value = match t.value() {
+                Symbol(s) if renames.contains_key(&s) => Symbol(renames[&s].clone()),
+                v => v,
+            };
+
+            ParseTree::Leaf(Token { span, value })
+        }
+        ParseTree::Branch(elems, _, _, tail) => ParseTree::Branch(
+            elems.iter().map(|e| relabel(e, span, renames)).collect(),
+            span,
+            span,
+            tail.as_ref().map(|t| Box::new(relabel(t, span, renames))),
+        ),
+    }
+}
+
+/// Fill in a quasiquoted `defmacro` template, substituting arguments for unquoted parameters.
+fn fill(
+    name: &str,
+    tree: &ParseTree,
+    depth: usize,
+    args: &HashMap<String, ParseTree>,
+    renames: &HashMap<String, String>,
+    span: Span,
+) -> Result<ParseTree, Diagnostic> {
+    if unquoted(tree, depth) {
+        let (form, _) = head(tree).unwrap();
+
+        if form == "unquote-splicing" {
+            return Err(Diagnostic::error(
+                INVALID_MACRO,
+                span,
+                format!(
+                    "`unquote-splicing` can only be used inside of a list in the template of macro `{}`.",
+                    name
+                ),
+            ));
+        }
+
+        return param(name, &children(tree)[1], args, span).cloned();
+    }
+
+    let (elems, tl) = match tree {
+        ParseTree::Leaf(_) => return Ok(relabel(tree, span, renames)),
+        ParseTree::Branch(elems, _, _, tl) => (elems, tl),
+    };
+
+    let inner_depth = match (head(tree), elems.len()) {
+        (Some(("quasiquote", _)), 2) => depth + 1,
+        (Some((s, _)), 2) if s == "unquote" || s == "unquote-splicing" => depth - 1,
+        _ => depth,
+    };
+
+    let mut new_elems = Vec::new();
+
+    for (i, elem) in elems.iter().enumerate() {
+        let d = if i == 0 { depth } else { inner_depth };
+
+        if unquoted(elem, d) && head(elem).unwrap().0 == "unquote-splicing" {
+            match param(name, &children(elem)[1], args, span)? {
+                ParseTree::Branch(spliced, _, _, None) => new_elems.extend(spliced.iter().cloned()),
+                _ => {
+                    return Err(Diagnostic::error(
+                        MACRO_MISMATCH,
+                        span,
+                        format!(
+                            "Expected a list to splice into the expansion of macro `{}`.",
+                            name
+                        ),
+                    ))
+                }
+            }
+        } else {
+            new_elems.push(fill(name, elem, d, args, renames, span)?);
+        }
+    }
+
+    let tl = match tl {
+        Some(t) => Some(Box::new(fill(name, t, depth, args, renames, span)?)),
+        None => None,
+    };
+
+    Ok(ParseTree::Branch(new_elems, span, span, tl))
+}
+
+/// Find the argument for a parameter that was unquoted in a `defmacro` template.
+fn param<'a>(
+    name: &str,
+    tree: &ParseTree,
+    args: &'a HashMap<String, ParseTree>,
+    span: Span,
+) -> Result<&'a ParseTree, Diagnostic> {
+    symbol(tree).and_then(|s| args.get(&s)).ok_or(Diagnostic::error(
+        INVALID_MACRO,
+        span,
+        format!(
+            "Only parameters may be unquoted in the template of macro `{}`. Found `{}`.",
+            name, tree
+        ),
+    ))
+}
+
+/// Ensure that no list in a pattern contains more than one ellipsis, and that each one follows
+/// another element.
+fn check_ellipses(pattern: &ParseTree, span: Span) -> Result<(), Diagnostic> {
+    let elems = children(pattern);
+    let count = elems.iter().filter(|e| is_ellipsis(e)).count();
+
+    if count > 1 || elems.first().is_some_and(is_ellipsis) {
+        return Err(Diagnostic::error(
+            INVALID_MACRO,
+            span,
+            format!(
+                "Invalid use of `...` in `syntax-rules` pattern {}.",
+                pattern
+            ),
+        ));
+    }
+
+    for elem in elems {
+        check_ellipses(elem, span)?;
+    }
+
+    Ok(())
+}
+
+/// Match a form against a `syntax-rules` pattern, recording the values of pattern variables.
+fn match_pattern(
+    pattern: &ParseTree,
+    form: &ParseTree,
+    literals: &[String],
+    bindings: &mut HashMap<String, Binding>,
+) -> bool {
+    match pattern {
+        ParseTree::Leaf(Token {
+            value: Symbol(s), ..
+        }) => {
+            if literals.contains(s) {
+                symbol(form).as_ref() == Some(s)
+            } else {
+                bindings.insert(s.clone(), Binding::One(form.clone()));
+                true
+            }
+        }
+        ParseTree::Leaf(t) => matches!(form, ParseTree::Leaf(f) if f.value() == t.value()),
+        ParseTree::Branch(pats, _, _, pat_tail) => {
+            let (forms, start, stop, form_tail) = match form {
+                ParseTree::Branch(forms, start, stop, form_tail) => {
+                    (forms, *start, *stop, form_tail)
+                }
+                ParseTree::Leaf(_) => return false,
+            };
+
+            let ellipsis = pats.iter().position(is_ellipsis);
+
+            let (before, repeated, after) = match ellipsis {
+                Some(i) => (&pats[..i - 1], Some(&pats[i - 1]), &pats[i + 1..]),
+                None => (&pats[..], None, &pats[0..0]),
+            };
+
+            let fixed = before.len() + after.len();
+
+            // Without a tail or ellipsis, every element must match exactly.
+            if forms.len() < fixed
+                || (repeated.is_none() && pat_tail.is_none() && forms.len() != fixed)
+                || (pat_tail.is_none() && form_tail.is_some())
+            {
+                return false;
+            }
+
+            for (p, f) in before.iter().zip(forms) {
+                if !match_pattern(p, f, literals, bindings) {
+                    return false;
+                }
+            }
+
+            let rest = &forms[before.len()..];
+
+            if let Some(repeated) = repeated {
+                let (middle, end) = rest.split_at(rest.len() - after.len());
+                let mut matches = Vec::new();
+
+                for f in middle {
+                    let mut inner = HashMap::new();
+
+                    if !match_pattern(repeated, f, literals, &mut inner) {
+                        return false;
+                    }
+
+                    matches.push(inner);
+                }
+
+                // Every variable in the repeated pattern is bound, even if nothing matched it.
+                let mut vars = Vec::new();
+                pattern_variables(repeated, literals, &mut vars);
+
+                for var in vars {
+                    bindings.insert(var, Binding::Many(matches.clone()));
+                }
+
+                for (p, f) in after.iter().zip(end) {
+                    if !match_pattern(p, f, literals, bindings) {
+                        return false;
+                    }
+                }
+
+                true
+            } else if let Some(pat_tail) = pat_tail {
+                let rest = ParseTree::Branch(rest.to_vec(), start, stop, form_tail.clone());
+                match_pattern(pat_tail, &rest, literals, bindings)
+            } else {
+                true
+            }
+        }
+    }
+}
+
+fn pattern_variables(pattern: &ParseTree, literals: &[String], vars: &mut Vec<String>) {
+    match pattern {
+        ParseTree::Leaf(Token {
+            value: Symbol(s), ..
+        }) if s != "..." && !literals.contains(s) => vars.push(s.clone()),
+        ParseTree::Leaf(_) => (),
+        ParseTree::Branch(elems, _, _, tail) => {
+            for elem in elems {
+                pattern_variables(elem, literals, vars);
+            }
+
+            if let Some(t) = tail {
+                pattern_variables(t, literals, vars);
+            }
+        }
+    }
+}
+
+/// Fill in a `syntax-rules` template using the bindings from a successful match.
+fn instantiate(
+    name: &str,
+    template: &ParseTree,
+    bindings: &HashMap<String, Binding>,
+    renames: &HashMap<String, String>,
+    span: Span,
+) -> Result<ParseTree, Diagnostic> {
+    let (elems, tl) = match template {
+        ParseTree::Leaf(_) => {
+            return match symbol(template).and_then(|s| bindings.get(&s)) {
+                Some(Binding::One(t)) => Ok(t.clone()),
+                Some(Binding::Many(_)) => Err(Diagnostic::error(
+                    INVALID_MACRO,
+                    span,
+                    format!(
+                        "Pattern variable `{}` must be followed by `...` in the template of macro `{}`.",
+                        template, name
+                    ),
+                )),
+                None => Ok(relabel(template, span, renames)),
+            }
+        }
+        ParseTree::Branch(elems, _, _, tl) => (elems, tl),
+    };
+
+    let mut new_elems = Vec::new();
+    let mut i = 0;
+
+    while i < elems.len() {
+        let elem = &elems[i];
+
+        if elems.get(i + 1).is_some_and(is_ellipsis) {
+            // Every variable used in the element that matched repeatedly must have matched the
+            // same number of times.
+            let mut vars = Vec::new();
+            pattern_variables(elem, &[], &mut vars);
+
+            let sequences: Vec<(&String, &Vec<HashMap<String, Binding>>)> = vars
+                .iter()
+                .filter_map(|v| match bindings.get(v) {
+                    Some(Binding::Many(seq)) => Some((v, seq)),
+                    _ => None,
+                })
+                .collect();
+
+            if sequences.is_empty() {
+                return Err(Diagnostic::error(
+                    INVALID_MACRO,
+                    span,
+                    format!(
+                        "No repeated pattern variable precedes `...` in the template of macro `{}`.",
+                        name
+                    ),
+                ));
+            }
+
+            let n = sequences[0].1.len();
+
+            if sequences.iter().any(|(_, seq)| seq.len() != n) {
+                return Err(Diagnostic::error(
+                    MACRO_MISMATCH,
+                    span,
+                    format!(
+                        "Pattern variables followed by `...` matched different numbers of forms in macro `{}`.",
+                        name
+                    ),
+                ));
+            }
+
+            for j in 0..n {
+                let mut inner = bindings.clone();
+
+                for (var, seq) in &sequences {
+                    inner.remove(*var);
+                    inner.extend(seq[j].clone());
+                }
+
+                new_elems.push(instantiate(name, elem, &inner, renames, span)?);
+            }
+
+            i += 2;
+        } else {
+            new_elems.push(instantiate(name, elem, bindings, renames, span)?);
+            i += 1;
+        }
+    }
+
+    let tl = match tl {
+        Some(t) => Some(Box::new(instantiate(name, t, bindings, renames, span)?)),
+        None => None,
+    };
+
+    Ok(ParseTree::Branch(new_elems, span, span, tl))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::SymbolTable;
+    use crate::diagnostic::{
+        Diagnostic, Diagnostics, Span, EXPANSION_DEPTH, INVALID_MACRO, MACRO_MISMATCH,
+    };
+    use crate::expand::MacroExpander;
+    use crate::lex::start;
+    use crate::parse::{parse, ParseTree};
+
+    fn expand(program: &str) -> Result<Vec<ParseTree>, Diagnostic> {
+        let trees = parse(&start(program).unwrap()).unwrap();
+        MacroExpander::new().expand(&trees, &mut SymbolTable::dummy())
+    }
+
+    fn expand_str(program: &str) -> String {
+        expand(program)
+            .unwrap()
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn no_macros() {
+        let program = "(define x '(a b)) (format (+ x 1))";
+        assert_eq!(
+            expand(program).unwrap(),
+            parse(&start(program).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn defmacro_quasiquote() {
+        assert_eq!(
+            "(format (if c nil (+ 1 2)))",
+            expand_str("(defmacro unless (c body) `(if ,c nil ,body)) (format (unless c (+ 1 2)))")
+        );
+    }
+
+    #[test]
+    fn defmacro_vararg_splicing() {
+        assert_eq!(
+            "(if c (begin a b) nil)\n(if c (begin) nil)",
+            expand_str(
+                "(defmacro when (c . body) `(if ,c (begin ,@body) nil)) (when c a b) (when c)"
+            )
+        );
+    }
+
+    #[test]
+    fn defmacro_param_and_quote() {
+        assert_eq!(
+            "(+ 1 2)\nx",
+            expand_str("(defmacro id (x) x) (defmacro sym (x) 'x) (id (+ 1 2)) (sym 3)")
+        );
+    }
+
+    #[test]
+    fn nested_uses_expanded() {
+        assert_eq!(
+            "(list (if a nil (if b nil c)))",
+            expand_str(
+                "(defmacro unless (c body) `(if ,c nil ,body)) (list (unless a (unless b c)))"
+            )
+        );
+    }
+
+    #[test]
+    fn quoted_uses_not_expanded() {
+        assert_eq!(
+            "(quote (m 1))\n(quasiquote (m (unquote 1)))",
+            expand_str("(defmacro m (x) x) '(m 1) `(m ,(m 1))")
+        );
+    }
+
+    #[test]
+    fn bound_names_renamed() {
+        let expanded =
+            expand_str("(defmacro my-or (a b) `(let ((tmp ,a)) (if tmp tmp ,b))) (my-or nil tmp)");
+
+        assert_eq!(
+            "(let ((gensym1_macro_tmp nil)) (if gensym1_macro_tmp gensym1_macro_tmp tmp))",
+            expanded
+        );
+    }
+
+    #[test]
+    fn lambda_args_renamed() {
+        assert_eq!(
+            "(lambda (gensym1_macro_x) (+ gensym1_macro_x y))",
+            expand_str("(defmacro adder (n) `(lambda (x) (+ x ,n))) (adder y)")
+        );
+    }
+
+    #[test]
+    fn syntax_rules_ellipsis() {
+        let program = "
+        (define-syntax my-or
+          (syntax-rules ()
+            ((my-or) nil)
+            ((my-or a) a)
+            ((my-or a b ...) (let ((tmp a)) (if tmp tmp (my-or b ...))))))
+        (my-or)
+        (my-or x y tmp)";
+
+        assert_eq!(
+            "nil\n(let ((gensym1_macro_tmp x)) (if gensym1_macro_tmp gensym1_macro_tmp \
+             (let ((gensym2_macro_tmp y)) (if gensym2_macro_tmp gensym2_macro_tmp tmp))))",
+            expand_str(program)
+        );
+    }
+
+    #[test]
+    fn syntax_rules_nested_ellipsis() {
+        let program = "
+        (define-syntax my-let
+          (syntax-rules ()
+            ((my-let ((name val) ...) body) ((lambda (name ...) body) val ...))))
+        (my-let ((a 1) (b 2)) (+ a b))";
+
+        assert_eq!("((lambda (a b) (+ a b)) 1 2)", expand_str(program));
+    }
+
+    #[test]
+    fn syntax_rules_literals() {
+        let program = "
+        (define-syntax arrow
+          (syntax-rules (=>)
+            ((arrow a => f) (f a))
+            ((arrow a b) (list a b))))
+        (arrow 1 => g)
+        (arrow 1 2)";
+
+        assert_eq!("(g 1)\n(list 1 2)", expand_str(program));
+    }
+
+    #[test]
+    fn macros_defining_macros() {
+        let program = "
+        (defmacro def-id (name) `(defmacro ,name (x) x))
+        (def-id same)
+        (same 3)";
+
+        assert_eq!("3", expand_str(program));
+    }
+
+    #[test]
+    fn expansion_uses_call_line() {
+        let trees = expand("(defmacro m (x)\n\n `(f ,x))\n\n(m 1)").unwrap();
+
+        match &trees[0] {
+            ParseTree::Branch(elems, start, _, None) if start.line == 5 => match &elems[0] {
+                ParseTree::Leaf(t) => assert_eq!(Span::new(5, 2, 1), t.span()),
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn malformed_definitions() {
+        assert_eq!(1, expand("(defmacro m (x))").unwrap_err().span.line);
+        assert!(expand("(defmacro 1 (x) x)").is_err());
+        assert!(expand("(defmacro m (1) x)").is_err());
+        assert!(expand("(defmacro m (x) (list x))").is_err());
+        assert!(expand("(defmacro lambda (x) x)").is_err());
+        assert!(expand("(define-syntax m (x))").is_err());
+        assert!(expand("(define-syntax m (syntax-rules () (m)))").is_err());
+        assert!(expand("(define-syntax m (syntax-rules () ((m ... a) a)))").is_err());
+        assert_eq!(2, expand("(list\n (defmacro m (x) x))").unwrap_err().span.line);
+    }
+
+    #[test]
+    fn errors_in_several_forms() {
+        let trees = parse(&start("(defmacro m (x) x)\n(m)\n(f 1)\n(m 1 2)").unwrap()).unwrap();
+        let mut diagnostics = Diagnostics::new(0);
+        let output = MacroExpander::new().expand_all(&trees, &mut SymbolTable::dummy(), &mut diagnostics);
+
+        assert_eq!(1, output.len());
+        assert_eq!(
+            vec![2, 4],
+            diagnostics.into_vec().iter().map(|d| d.span.line).collect::<Vec<u32>>()
+        );
+    }
+
+    #[test]
+    fn error_codes() {
+        let error = expand("(defmacro m (x) (list x))").unwrap_err();
+        assert_eq!(INVALID_MACRO, error.code);
+        assert_eq!(Span::new(1, 2, 8), error.span);
+
+        let error = expand("(defmacro m (x) `(f ,x))\n(list (m))").unwrap_err();
+        assert_eq!(MACRO_MISMATCH, error.code);
+        assert_eq!(Span::new(2, 8, 1), error.span);
+    }
+
+    #[test]
+    fn malformed_uses() {
+        let defs = "(defmacro m (x) `(f ,x)) (define-syntax s (syntax-rules () ((s a) a)))\n";
+
+        let error = expand(&format!("{}(m 1 2)", defs)).unwrap_err();
+        assert_eq!(2, error.span.line);
+        assert_eq!(
+            "Expected exactly 1 argument(s) to macro `m`. Found 2.",
+            error.message
+        );
+
+        let error = expand(&format!("{}(s)", defs)).unwrap_err();
+        assert_eq!(2, error.span.line);
+        assert_eq!("No rule of macro `s` matches this use.", error.message);
+        assert!(expand(&format!("{}(m . 1)", defs)).is_err());
+        assert!(expand("(defmacro m (x) `(f ,y)) (m 1)").is_err());
+        assert!(expand("(defmacro m (x) `(f ,@x)) (m 1)").is_err());
+    }
+
+    #[test]
+    fn ellipsis_mismatch() {
+        let program = "
+        (define-syntax zip
+          (syntax-rules ()
+            ((zip (a ...) (b ...)) (list (cons a b) ...))))
+        (zip (1 2) (3))";
+
+        assert!(expand(program).is_err());
+        assert!(
+            expand("(define-syntax m (syntax-rules () ((m a ...) (list a)))) (m 1 2)").is_err()
+        );
+    }
+
+    #[test]
+    fn infinite_expansion() {
+        let err = expand("(defmacro loop (x) `(loop ,x)) (loop 1)").unwrap_err();
+
+        assert_eq!(EXPANSION_DEPTH, err.code);
+        assert_eq!(1, err.span.line);
+        assert_eq!(
+            "Expansion of macro `loop` exceeded the maximum depth.",
+            err.message
+        );
+
+        // Uses nested inside of the expansion count towards the same depth.
+        for program in &[
+            "(defmacro nest (x) `((nest ,x))) (nest 1)",
+            "(defmacro nest (x) `(quasiquote (unquote (nest ,x)))) (nest 1)",
+        ] {
+            assert_eq!(EXPANSION_DEPTH, expand(program).unwrap_err().code);
+        }
+    }
+}
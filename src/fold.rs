@@ -0,0 +1,236 @@
+use crate::ast::{ASTNode, ASTVisitor, Statement::*, SymbolTable, Value, Value::*};
+use crate::diagnostic::{Diagnostic, Span, CONSTANT_EVALUATION};
+use crate::interp::{self, Cause};
+use crate::lex::{Token, TokenValue};
+
+/// Evaluates calls to pure natives, like `+` and `<`, whose arguments are all literals, and picks
+/// the branch of conditions whose condition is a literal. Numbers follow the same rules as in
+/// liblisp, since the calls are made by the interpreter. Calls that are certain to fail, like a
+/// division by a literal zero, are reported. Runs before `ConditionUnroll`, so that constant
+/// conditions are removed before they're expanded.
+pub struct ConstantFolding;
+
+impl ConstantFolding {
+    fn fold_body(&self, body: &[ASTNode], sym_table: &SymbolTable) -> Result<Vec<ASTNode>, Diagnostic> {
+        body.iter().map(|n| self.fold_node(n, sym_table)).collect()
+    }
+
+    fn fold_node(&self, node: &ASTNode, sym_table: &SymbolTable) -> Result<ASTNode, Diagnostic> {
+        Ok(match node {
+            ASTNode::Value(v) => ASTNode::Value(self.fold_value(v, sym_table)?),
+            ASTNode::Statement(Definition(name, v, span)) => {
+                ASTNode::Statement(Definition(name.clone(), self.fold_value(v, sym_table)?, *span))
+            }
+            ASTNode::Statement(Redefinition(name, v)) => {
+                ASTNode::Statement(Redefinition(name.clone(), self.fold_value(v, sym_table)?))
+            }
+            ASTNode::Statement(FunctionDefinition(name, args, vararg, body, scope, span)) => {
+                ASTNode::Statement(FunctionDefinition(
+                    name.clone(),
+                    args.clone(),
+                    vararg.clone(),
+                    self.fold_body(body, sym_table)?,
+                    *scope,
+                    *span,
+                ))
+            }
+            // The branches have already been expanded into statements, so both are kept.
+            ASTNode::Statement(ExpandedCondition(c, t, f)) => ASTNode::Statement(ExpandedCondition(
+                self.fold_value(c, sym_table)?,
+                self.fold_body(t, sym_table)?,
+                self.fold_body(f, sym_table)?,
+            )),
+            ASTNode::Statement(Declaration(_)) => node.clone(),
+        })
+    }
+
+    fn fold_value(&self, value: &Value, sym_table: &SymbolTable) -> Result<Value, Diagnostic> {
+        Ok(match value {
+            Call(callee, args, span) => {
+                let args = self.fold_args(args, sym_table)?;
+
+                match self.fold_call(callee, &args, *span, sym_table)? {
+                    Some(token) => Literal(token),
+                    None => Call(callee.clone(), args, *span),
+                }
+            }
+            TailCall(callee, args, span) => TailCall(callee.clone(), self.fold_args(args, sym_table)?, *span),
+            Recur(args, span) => Recur(self.fold_args(args, sym_table)?, *span),
+            Condition(c, t, f) => {
+                let c = self.fold_value(c, sym_table)?;
+
+                // Only false and nil are falsy.
+                match constant(&c).map(|t| t.value) {
+                    Some(TokenValue::False) | Some(TokenValue::Nil) => self.fold_value(f, sym_table)?,
+                    Some(_) => self.fold_value(t, sym_table)?,
+                    None => Condition(
+                        Box::new(c),
+                        Box::new(self.fold_value(t, sym_table)?),
+                        Box::new(self.fold_value(f, sym_table)?),
+                    ),
+                }
+            }
+            Lambda(args, vararg, body, scope, span) => {
+                Lambda(args.clone(), vararg.clone(), self.fold_body(body, sym_table)?, *scope, *span)
+            }
+            Let(kind, bindings, body, scope) => {
+                let mut folded = Vec::new();
+
+                for (name, init) in bindings {
+                    folded.push((name.clone(), self.fold_body(init, sym_table)?));
+                }
+
+                Let(*kind, folded, self.fold_body(body, sym_table)?, *scope)
+            }
+            Begin(body) => Begin(self.fold_body(body, sym_table)?),
+            Literal(_) | Quotation(_) => value.clone(),
+        })
+    }
+
+    fn fold_args(&self, args: &[Value], sym_table: &SymbolTable) -> Result<Vec<Value>, Diagnostic> {
+        args.iter().map(|a| self.fold_value(a, sym_table)).collect()
+    }
+
+    /// Evaluates a call whose arguments have been folded, if it's a call to a pure native, and
+    /// every argument is a literal.
+    fn fold_call(
+        &self,
+        callee: &str,
+        args: &[Value],
+        span: Span,
+        sym_table: &SymbolTable,
+    ) -> Result<Option<Token>, Diagnostic> {
+        // Natives can't be redefined, so a name that isn't a function made by `defun` always
+        // calls the native.
+        let c_name = match sym_table.get_fn(callee) {
+            Some(c_name) if !sym_table.is_defun(callee) => c_name,
+            _ => return Ok(None),
+        };
+
+        let tokens = match args.iter().map(constant).collect::<Option<Vec<Token>>>() {
+            Some(tokens) => tokens,
+            None => return Ok(None),
+        };
+
+        let values: Vec<TokenValue> = tokens.iter().map(|t| t.value()).collect();
+
+        match interp::eval_pure(c_name, &values) {
+            None => Ok(None),
+            Some(Ok(value)) => Ok(Some(Token { span, value })),
+            Some(Err(e)) if e.cause == Cause::ZeroDivision => {
+                // The zero is the argument that's to blame.
+                let zero = tokens.iter().find(|t| is_zero(&t.value)).map(|t| t.span).unwrap_or(span);
+
+                Err(Diagnostic::error(
+                    CONSTANT_EVALUATION,
+                    zero,
+                    format!("Division by zero in call to `{}`.", callee),
+                ))
+            }
+            Some(Err(e)) => Err(Diagnostic::error(
+                CONSTANT_EVALUATION,
+                span,
+                format!("Call to `{}` always fails with {}", callee, e),
+            )),
+        }
+    }
+}
+
+impl ASTVisitor<ASTNode> for ConstantFolding {
+    fn try_visit(&self, ast: &ASTNode, sym_table: &mut SymbolTable) -> Result<ASTNode, Diagnostic> {
+        self.fold_node(ast, sym_table)
+    }
+}
+
+/// The literal token that a value is, if it's a constant. Symbols are variables, so they aren't.
+fn constant(value: &Value) -> Option<Token> {
+    match value {
+        Literal(t) if !matches!(t.value, TokenValue::Symbol(_)) => Some(t.clone()),
+        _ => None,
+    }
+}
+
+fn is_zero(value: &TokenValue) -> bool {
+    match value {
+        TokenValue::Int(x) | TokenValue::Rational(x, _) => *x == 0,
+        TokenValue::Float(x) => *x == 0.0,
+        TokenValue::Complex(x, y) => *x == 0.0 && *y == 0.0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::test_utils::force_from;
+    use crate::fold::*;
+
+    fn fold(input: &str) -> Result<Vec<ASTNode>, Diagnostic> {
+        let sym_table = SymbolTable::load(None).unwrap();
+        force_from(input).iter().map(|n| ConstantFolding.fold_node(n, &sym_table)).collect()
+    }
+
+    fn folded(input: &str) -> TokenValue {
+        match fold(input).unwrap().as_slice() {
+            [ASTNode::Value(Literal(t))] => t.value(),
+            other => panic!("Expected a single literal, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(TokenValue::Int(27), folded("(+ 6 7 (* 2 7))"));
+        assert_eq!(TokenValue::Rational(3, 2), folded("(+ 1 1/2)"));
+        assert_eq!(TokenValue::Float(2.5), folded("(+ 2 0.5)"));
+        assert_eq!(TokenValue::Complex(1.0, 2.0), folded("(+ 1 2i)"));
+        assert_eq!(TokenValue::Int(-3), folded("(- 3)"));
+        // As in liblisp, the first argument is also divided by itself.
+        assert_eq!(TokenValue::Float(0.5), folded("(/ 6 2)"));
+        assert_eq!(TokenValue::Int(1), folded("(mod 7 3)"));
+        assert_eq!(TokenValue::Float(1e20), folded("(* 1e10 1e10)"));
+        assert!(matches!(folded("(* -1.0 0.0)"), TokenValue::Float(x) if x == 0.0 && x.is_sign_negative()));
+    }
+
+    #[test]
+    fn logic() {
+        assert_eq!(TokenValue::True, folded("(< 1 2 3)"));
+        assert_eq!(TokenValue::False, folded("(= 1 2)"));
+        assert_eq!(TokenValue::Str("b".to_string()), folded("(and 1 \"b\")"));
+        assert_eq!(TokenValue::False, folded("(not 0)"));
+        assert_eq!(TokenValue::True, folded("(eqv :a :a)"));
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(TokenValue::Int(2), folded("(if (> 1 2) 1 2)"));
+        assert_eq!(TokenValue::Int(1), folded("(if 0 1 2)"));
+        assert_eq!(TokenValue::Int(2), folded("(if nil 1 2)"));
+
+        // The condition isn't constant, but the branches are folded.
+        match fold("(if x (+ 1 1) 3)").unwrap().as_slice() {
+            [ASTNode::Value(Condition(_, t, _))] => {
+                assert!(matches!(t.as_ref(), Literal(t) if t.value == TokenValue::Int(2)))
+            }
+            other => panic!("Expected a condition, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unfolded() {
+        // Variables, impure natives, and results that aren't literals are left as calls.
+        assert!(matches!(fold("(+ x 1)").unwrap().as_slice(), [ASTNode::Value(Call(..))]));
+        assert!(matches!(fold("(format 1)").unwrap().as_slice(), [ASTNode::Value(Call(..))]));
+        assert!(matches!(fold("(div 7 3)").unwrap().as_slice(), [ASTNode::Value(Call(..))]));
+        assert!(matches!(fold("(* 1e300 1e300)").unwrap().as_slice(), [ASTNode::Value(Call(..))]));
+    }
+
+    #[test]
+    fn errors() {
+        let e = fold("(format (/ 1 0))").unwrap_err();
+        assert_eq!(CONSTANT_EVALUATION, e.code);
+        assert_eq!(14, e.span.column);
+
+        assert!(fold("(mod 1 0)").is_err());
+        assert!(fold("(+ 1 \"a\")").is_err());
+        assert!(fold("(defun f () (/ 2 0.0))").is_err());
+    }
+}
@@ -0,0 +1,478 @@
+//! Random testing of the front end and the formatter. Programs are generated from the grammar, and then broken in
+//! small ways, so that most inputs get past the lexer and exercise the later stages.
+//!
+//! The targets take raw bytes, and panic if a property fails. They're public with the `fuzz`
+//! feature, which the `cargo fuzz` crate in `fuzz/` builds them with. The tests run each of them
+//! over a fixed range of seeds. `LISPC_FUZZ_SEED` picks the first seed, to reproduce a failure,
+//! and `LISPC_FUZZ_ITERATIONS` sets how many inputs each test tries.
+
+use crate::ast::{self, SymbolTable};
+use crate::compiler::{CompileOptions, Compiler, Source};
+use crate::diagnostic::{Diagnostic, Diagnostics, Span, INTERNAL};
+use crate::lex;
+use crate::parse::{self, ParseTree};
+use crate::pretty;
+
+/// Checks that a diagnostic could be shown against the input it came from.
+fn check_diagnostic(d: &Diagnostic, input: &str) {
+    assert_ne!(INTERNAL, d.code, "internal error: {}", d.message);
+    assert!(!d.message.is_empty());
+
+    // Generated code has no position.
+    if d.span != Span::default() {
+        let lines = input.lines().count().max(1) as u32;
+        assert!(d.span.line >= 1 && d.span.line <= lines + 1, "bad line in {:?}", d);
+        assert!(d.span.column >= 1, "bad column in {:?}", d);
+    }
+
+    d.clone().with_file("<fuzz>").render(input);
+}
+
+/// Runs the input through every stage before translation, which must not panic, and must give a
+/// structured error for anything that it rejects.
+pub fn front_end(data: &[u8]) {
+    let input = String::from_utf8_lossy(data);
+    let options = CompileOptions { error_limit: 100, keep_dead_code: true, ..CompileOptions::default() };
+    let compiler = Compiler::with_symbol_table(options, SymbolTable::dummy()).unwrap();
+
+    if let Err(compilation) = compiler.analyze(&[Source::new("<fuzz>", &input)]) {
+        for d in &compilation.diagnostics {
+            check_diagnostic(d, &input);
+        }
+    }
+
+    // The single error versions must agree with the rest on whether there's an error.
+    let mut diagnostics = Diagnostics::new(100);
+    let tokens = lex::tokenize(&input, &mut diagnostics);
+
+    match lex::start(&input) {
+        Ok(t) => assert!(diagnostics.is_empty() && t == tokens),
+        Err(d) => {
+            assert!(!diagnostics.is_empty());
+            check_diagnostic(&d, &input);
+        }
+    }
+
+    if diagnostics.is_empty() {
+        let trees = parse::parse_all(&tokens, &mut diagnostics);
+
+        match parse::parse(&tokens) {
+            Ok(t) => assert!(diagnostics.is_empty() && t == trees),
+            Err(d) => {
+                assert!(!diagnostics.is_empty());
+                check_diagnostic(&d, &input);
+            }
+        }
+
+        if let Err(d) = ast::construct_ast(&trees) {
+            check_diagnostic(&d, &input);
+        }
+    }
+}
+
+/// Checks that a program which parses is written out as source code that parses into the same
+/// trees.
+pub fn round_trip(data: &[u8]) {
+    let input = String::from_utf8_lossy(data);
+
+    let trees = match lex::start(&input).and_then(|t| parse::parse(&t)) {
+        Ok(trees) => trees,
+        Err(_) => return,
+    };
+
+    let printed: Vec<String> = trees.iter().map(|t| t.to_string()).collect();
+    let printed = printed.join("\n");
+
+    let reparsed = match lex::start(&printed).and_then(|t| parse::parse(&t)) {
+        Ok(trees) => trees,
+        Err(d) => panic!("`{}` was printed as `{}`, which doesn't parse: {}", input, printed, d.message),
+    };
+
+    assert_eq!(
+        trees.iter().map(ParseTree::without_spans).collect::<Vec<_>>(),
+        reparsed.iter().map(ParseTree::without_spans).collect::<Vec<_>>(),
+        "`{}` was printed as `{}`",
+        input,
+        printed
+    );
+}
+
+/// Formats the input, which must keep its meaning, and must not change when it's formatted again.
+pub fn format(data: &[u8]) {
+    let input = String::from_utf8_lossy(data);
+
+    let mut diagnostics = Diagnostics::new(100);
+    let formatted = pretty::format_source(&input, &mut diagnostics);
+
+    if !diagnostics.is_empty() {
+        for d in diagnostics.into_vec() {
+            check_diagnostic(&d, &input);
+        }
+
+        return;
+    }
+
+    let mut diagnostics = Diagnostics::new(1);
+    let again = pretty::format_source(&formatted, &mut diagnostics);
+
+    assert!(diagnostics.is_empty(), "`{}` was formatted as `{}`, which doesn't format", input, formatted);
+    assert_eq!(formatted, again, "`{}` was formatted differently the second time", input);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fuzz::*;
+    use std::panic::{self, AssertUnwindSafe};
+
+    const DEFAULT_ITERATIONS: u64 = 1000;
+
+    // Keeps generated programs small enough to read when a test fails.
+    const MAX_DEPTH: usize = 4;
+
+    const SYMBOLS: [&str; 14] = [
+        "x", "y", "xs", "f", "g", "acc", "nil", "+", "-", "car", "cons", "format", "my-fn", "λ",
+    ];
+
+    const ATOMS: [&str; 25] = [
+        "0", "1", "-7", "2147483647", "-2147483647", "1.5", "-0.0", "2.5e-3", "1e10", "1/2", "-3/4",
+        "1/0", "1+2i", "3i", "-2.5-1i", "1+i", "\"\"", "\"a b\"", "\"\\\"q\\\"\\n\"", ":key",
+        ":other", "#t", "#f", "nil", "...",
+    ];
+
+    // Text inserted into programs to break them.
+    const FRAGMENTS: [&str; 23] = [
+        "(", ")", ".", " . ", "'", "`", ",", ",@", "\"", "#", ";", "\n", "1/", "e", "+", "i",
+        "99999999999", "1e999", ".5", "λ", "\\", ":", "()",
+    ];
+
+    /// A small, deterministic random number generator (xorshift64*), so that a failure can be
+    /// reproduced from its seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn new(seed: u64) -> Self {
+            // The state must never be 0.
+            Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+        }
+
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn chance(&mut self, one_in: usize) -> bool {
+            self.below(one_in) == 0
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    /// Writes random programs that lex and parse, although they usually have later errors.
+    struct Generator {
+        rng: Rng,
+        out: String,
+    }
+
+    impl Generator {
+        fn program(seed: u64) -> String {
+            let mut g = Generator {
+                rng: Rng::new(seed),
+                out: String::new(),
+            };
+
+            for _ in 0..=g.rng.below(4) {
+                g.form(0);
+                g.space();
+            }
+
+            g.out
+        }
+
+        fn space(&mut self) {
+            match self.rng.below(8) {
+                0 => self.out.push('\n'),
+                1 => self.out.push_str(" ; a comment\n"),
+                2 => self.out.push_str("\t "),
+                _ => self.out.push(' '),
+            }
+        }
+
+        fn symbol(&mut self) {
+            let s = self.rng.pick(&SYMBOLS);
+            self.out.push_str(s);
+        }
+
+        fn atom(&mut self) {
+            if self.rng.chance(2) {
+                self.symbol();
+            } else {
+                let a = self.rng.pick(&ATOMS);
+                self.out.push_str(a);
+            }
+        }
+
+        /// Writes `(head ...)`, where `f` writes everything after the head.
+        fn list(&mut self, head: &str, f: impl FnOnce(&mut Self)) {
+            self.out.push('(');
+            self.out.push_str(head);
+            f(self);
+            self.out.push(')');
+        }
+
+        fn forms(&mut self, depth: usize, min: usize) {
+            for _ in 0..min + self.rng.below(3) {
+                self.space();
+                self.form(depth + 1);
+            }
+        }
+
+        fn params(&mut self) {
+            self.space();
+            self.out.push('(');
+
+            for _ in 0..self.rng.below(3) {
+                self.symbol();
+                self.out.push(' ');
+            }
+
+            if self.rng.chance(4) {
+                self.out.push_str(". ");
+                self.symbol();
+            }
+
+            self.out.push(')');
+        }
+
+        fn bindings(&mut self, depth: usize) {
+            self.space();
+            self.out.push('(');
+
+            for _ in 0..self.rng.below(3) {
+                self.out.push('(');
+                self.symbol();
+                self.space();
+                self.form(depth + 1);
+                self.out.push(')');
+            }
+
+            self.out.push(')');
+        }
+
+        /// Writes quoted data, which may include dotted pairs.
+        fn datum(&mut self, depth: usize) {
+            if depth >= MAX_DEPTH || self.rng.chance(2) {
+                self.atom();
+                return;
+            }
+
+            self.out.push('(');
+
+            for _ in 0..self.rng.below(4) {
+                self.datum(depth + 1);
+                self.space();
+            }
+
+            if self.rng.chance(4) {
+                self.out.push_str(". ");
+                self.datum(depth + 1);
+            }
+
+            self.out.push(')');
+        }
+
+        fn form(&mut self, depth: usize) {
+            if depth >= MAX_DEPTH {
+                self.atom();
+                return;
+            }
+
+            match self.rng.below(16) {
+                0 => self.list("define", |g| {
+                    g.space();
+                    g.symbol();
+                    g.space();
+                    g.form(depth + 1);
+                }),
+                1 => self.list("defun", |g| {
+                    g.space();
+                    g.symbol();
+                    g.params();
+                    g.forms(depth, 1);
+                }),
+                2 => self.list("lambda", |g| {
+                    g.params();
+                    g.forms(depth, 1);
+                }),
+                3 => self.list("if", |g| g.forms(depth, 3)),
+                4 => {
+                    let head = self.rng.pick(&["let", "let*", "letrec"]);
+                    self.list(head, |g| {
+                        g.bindings(depth);
+                        g.forms(depth, 1);
+                    })
+                }
+                5 => self.list("begin", |g| g.forms(depth, 1)),
+                6 => {
+                    self.out.push('\'');
+                    self.datum(depth + 1);
+                }
+                7 => {
+                    self.out.push_str("`(");
+                    self.symbol();
+
+                    for _ in 0..self.rng.below(3) {
+                        self.out.push(' ');
+                        self.out.push_str(self.rng.pick(&[",", ",@", ""]));
+                        self.form(depth + 1);
+                    }
+
+                    self.out.push(')');
+                }
+                8 => self.list("defmacro", |g| {
+                    g.space();
+                    g.symbol();
+                    g.params();
+                    g.space();
+                    g.out.push('`');
+                    g.datum(depth + 1);
+                }),
+                9 => self.list("recur", |g| g.forms(depth, 0)),
+                10 => self.list("funcall", |g| g.forms(depth, 1)),
+                11 | 12 => {
+                    self.out.push('(');
+                    self.symbol();
+                    self.forms(depth, 0);
+                    self.out.push(')');
+                }
+                _ => self.atom(),
+            }
+        }
+    }
+
+    /// Breaks a program in a few small ways, so that it's usually still close to valid.
+    fn mutate(program: &str, seed: u64) -> String {
+        let mut rng = Rng::new(seed ^ 0xD1B5_4A32_D192_ED03);
+        let mut chars: Vec<char> = program.chars().collect();
+
+        for _ in 0..=rng.below(3) {
+            let at = rng.below(chars.len() + 1);
+
+            match rng.below(5) {
+                0 if at < chars.len() => {
+                    let end = (at + 1 + rng.below(4)).min(chars.len());
+                    chars.drain(at..end);
+                }
+                1 => {
+                    chars.truncate(at);
+                }
+                2 if at < chars.len() => {
+                    let end = (at + 1 + rng.below(8)).min(chars.len());
+                    let copy: Vec<char> = chars[at..end].to_vec();
+                    let to = rng.below(chars.len() + 1);
+                    chars.splice(to..to, copy);
+                }
+                _ => {
+                    let fragment = rng.pick(&FRAGMENTS);
+                    chars.splice(at..at, fragment.chars());
+                }
+            }
+        }
+
+        chars.into_iter().collect()
+    }
+
+    /// Random bytes, which are mostly not valid UTF-8, and rarely contain a token.
+    fn noise(seed: u64) -> Vec<u8> {
+        let mut rng = Rng::new(seed);
+        let n = rng.below(64);
+
+        (0..n)
+            .map(|_| match rng.below(4) {
+                // Bias towards ASCII, including the characters the lexer cares about.
+                0 => b"()'`,.\";#:\\ \n"[rng.below(13)],
+                1 => b'0' + rng.below(10) as u8,
+                _ => rng.next() as u8,
+            })
+            .collect()
+    }
+
+    /// Runs a target over inputs made from a range of seeds, reporting the seed and input that fail.
+    fn run(target: fn(&[u8]), input: impl Fn(u64) -> Vec<u8>) {
+        let var = |name, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+
+        let first = var("LISPC_FUZZ_SEED", 0);
+        let iterations = var("LISPC_FUZZ_ITERATIONS", DEFAULT_ITERATIONS);
+
+        for seed in first..first + iterations {
+            let data = input(seed);
+
+            if panic::catch_unwind(AssertUnwindSafe(|| target(&data))).is_err() {
+                panic!(
+                    "Failed on seed {}, with the input:\n{}",
+                    seed,
+                    String::from_utf8_lossy(&data)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn generated_programs_parse() {
+        run(
+            |data| {
+                let input = String::from_utf8_lossy(data);
+                let tokens = lex::start(&input).unwrap();
+                parse::parse(&tokens).unwrap();
+            },
+            |seed| Generator::program(seed).into_bytes(),
+        );
+    }
+
+    #[test]
+    fn front_end_on_generated_programs() {
+        run(front_end, |seed| Generator::program(seed).into_bytes());
+    }
+
+    #[test]
+    fn front_end_on_mutated_programs() {
+        run(front_end, |seed| mutate(&Generator::program(seed), seed).into_bytes());
+    }
+
+    #[test]
+    fn front_end_on_noise() {
+        run(front_end, noise);
+    }
+
+    #[test]
+    fn round_trip_generated_programs() {
+        run(round_trip, |seed| Generator::program(seed).into_bytes());
+    }
+
+    #[test]
+    fn round_trip_mutated_programs() {
+        run(round_trip, |seed| mutate(&Generator::program(seed), seed).into_bytes());
+    }
+
+    #[test]
+    fn format_generated_programs() {
+        run(format, |seed| Generator::program(seed).into_bytes());
+    }
+
+    #[test]
+    fn format_mutated_programs() {
+        run(format, |seed| mutate(&Generator::program(seed), seed).into_bytes());
+    }
+}
@@ -0,0 +1,1467 @@
+use crate::ast::{ASTNode, LetKind, Quoted, Statement, Statement::*, SymbolTable, Value, Value::*};
+use crate::diagnostic::Span;
+use crate::lex::TokenValue;
+use crate::transpile::Transpiler;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+pub type Ref = Rc<Datum>;
+
+/// A native function, written in Rust in place of the one in liblisp.
+pub type NativeFn = fn(&mut Interpreter, &[Ref]) -> Result<Ref, RuntimeError>;
+
+// required args, vararg, body, names of the captured variables, name it's defined as, label
+type LambdaDefinition = (Vec<String>, Option<String>, Vec<ASTNode>, Vec<String>, Option<String>, String);
+
+// Names and their values. Scopes are small, so they're searched in order.
+type Scope = Vec<(String, Ref)>;
+
+/// A value at runtime, mirroring `struct LispDatum` in liblisp. Values are never changed once
+/// they're made, so they're shared freely, as they are by the runtime.
+#[derive(Debug)]
+pub enum Datum {
+    Integer(i32),
+    // numerator, denominator
+    Rational(i32, i32),
+    Real(f64),
+    // real part, imaginary part
+    Complex(f64, f64),
+    String(String),
+    Symbol(String),
+    Bool(bool),
+    // car, cdr
+    // As in liblisp, an empty list is a cons cell without a car, and the last cell of a list has
+    // no cdr.
+    Cons(Option<Ref>, Option<Ref>),
+    Nil,
+    Lambda(Function),
+    Keyword(String),
+}
+
+#[derive(Debug)]
+pub enum Function {
+    // LISP name, implementation
+    Native(String, NativeFn),
+    // name
+    // Made by `defun`, so the function never has captures.
+    Defun(String),
+    // scope ID, label, captured values in the order given by `Transpiler::find_captures`
+    // Captures are only changed to let the lambdas bound by `letrec` refer to each other.
+    Closure(usize, String, RefCell<Vec<Ref>>),
+}
+
+/// The causes of runtime errors, as liblisp reports them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cause {
+    Type,
+    Argument,
+    ZeroDivision,
+    Math,
+    Generic,
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub cause: Cause,
+    pub message: String,
+    // The functions that were running when the error happened, innermost first.
+    pub backtrace: Vec<String>,
+}
+
+// The number of frames in a backtrace that are shown, as in liblisp.
+const BACKTRACE_LIMIT: usize = 16;
+
+impl RuntimeError {
+    fn new(cause: Cause, message: impl Into<String>) -> Self {
+        RuntimeError {
+            cause,
+            message: message.into(),
+            backtrace: Vec::new(),
+        }
+    }
+}
+
+/// Written the same way as `raise_err`.
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cause = match self.cause {
+            Cause::ZeroDivision => "Division by Zero",
+            Cause::Math => "Math Exception",
+            Cause::Generic => "Runtime Exception",
+            Cause::Type => "Type Mismatch Exception",
+            Cause::Argument => "Invalid Argument Exception",
+        };
+
+        write!(f, "{}: {}", cause, self.message)?;
+
+        for frame in self.backtrace.iter().take(BACKTRACE_LIMIT) {
+            write!(f, "\n  in {}", frame)?;
+        }
+
+        if self.backtrace.len() > BACKTRACE_LIMIT {
+            write!(f, "\n  ... and {} more", self.backtrace.len() - BACKTRACE_LIMIT)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs programs by walking the same AST that the transpiler translates, after every visitor has
+/// been applied. Values, native functions, and the way that values are printed all follow
+/// liblisp, so that a program writes exactly what its compiled version would.
+pub struct Interpreter<'a> {
+    sym_table: SymbolTable,
+    out: &'a mut dyn Write,
+    // The names of the input files, which lambdas that aren't defined as a variable are named by.
+    sources: Vec<String>,
+    lambdas: HashMap<usize, Rc<LambdaDefinition>>,
+    // Each function made by `defun`, along with the value its name is bound to.
+    defuns: HashMap<String, (Rc<LambdaDefinition>, Ref)>,
+    globals: HashMap<String, Ref>,
+    // The scopes of the function being run, innermost last. Top level forms don't have any.
+    scopes: Vec<Scope>,
+    // The function being run, which `recur` restarts.
+    current: Option<Ref>,
+    // A call in tail position, which is made once the function that made it has returned, so that
+    // tail calls run in constant stack space, as they do with `--trampoline`.
+    pending: Option<(Ref, Vec<Ref>)>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Creates an interpreter that writes the program's output to `out`.
+    pub fn new(sym_table: SymbolTable, out: &'a mut dyn Write) -> Self {
+        Self {
+            sym_table,
+            out,
+            sources: Vec::new(),
+            lambdas: HashMap::new(),
+            defuns: HashMap::new(),
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+            current: None,
+            pending: None,
+        }
+    }
+
+    /// Names the input files, as `Transpiler::set_sources` does, so that lambdas are labelled
+    /// the same way as in the compiled program.
+    pub fn set_sources(&mut self, sources: Vec<String>) {
+        self.sources = sources;
+    }
+
+    /// Runs each top level form in order, stopping at the first runtime error. The output is
+    /// flushed either way.
+    pub fn run(&mut self, ast: &[ASTNode]) -> Result<(), RuntimeError> {
+        for (args, vararg, body, scope_id, _, name, span) in
+            Transpiler::extract_lambda_definitions(ast, &mut Vec::new())
+        {
+            let captures = Transpiler::find_captures(&args, &vararg, &body, &self.sym_table);
+            let label = self.label(name.as_deref(), span);
+            self.lambdas
+                .insert(scope_id, Rc::new((args, vararg, body, captures, name, label)));
+        }
+
+        // Like C functions, every function made by `defun` exists before the program starts.
+        for node in ast {
+            if let ASTNode::Statement(FunctionDefinition(name, args, vararg, body, ..)) = node {
+                let definition = (
+                    args.clone(),
+                    vararg.clone(),
+                    body.clone(),
+                    Vec::new(),
+                    Some(name.clone()),
+                    name.clone(),
+                );
+                let value = Rc::new(Datum::Lambda(Function::Defun(name.clone())));
+
+                self.defuns
+                    .insert(name.clone(), (Rc::new(definition), value));
+            }
+        }
+
+        let result = ast.iter().try_for_each(|node| self.eval(node).map(|_| ()));
+        let _ = self.out.flush();
+
+        result
+    }
+
+    fn eval(&mut self, node: &ASTNode) -> Result<Ref, RuntimeError> {
+        match node {
+            ASTNode::Value(v) => self.eval_value(v),
+            ASTNode::Statement(s) => {
+                self.execute(s)?;
+                Ok(nil())
+            }
+        }
+    }
+
+    /// Evaluates each node of a body, returning the value of the last.
+    fn eval_body(&mut self, body: &[ASTNode]) -> Result<Ref, RuntimeError> {
+        let mut value = nil();
+
+        for node in body {
+            value = self.eval(node)?;
+        }
+
+        Ok(value)
+    }
+
+    fn eval_args(&mut self, args: &[Value]) -> Result<Vec<Ref>, RuntimeError> {
+        args.iter().map(|a| self.eval_value(a)).collect()
+    }
+
+    fn eval_value(&mut self, value: &Value) -> Result<Ref, RuntimeError> {
+        match value {
+            Literal(t) => match &t.value {
+                TokenValue::Symbol(s) => self.lookup(s),
+                v => literal(v),
+            },
+            Quotation(q) => quoted(q),
+            Call(callee, args, _) => {
+                let args = self.eval_args(args)?;
+                self.call_named(callee, args)
+            }
+            TailCall(callee, args, _) => {
+                let args = self.eval_args(args)?;
+
+                let call = match self.sym_table.get_fn(callee).map(String::as_str) {
+                    Some("funcall") => funcall_target(&args)?,
+                    Some("apply") => apply_target(&args)?,
+                    _ if self.sym_table.is_defun(callee) => (self.defuns[callee].1.clone(), args),
+                    _ => return self.call_named(callee, args),
+                };
+
+                // The value is never used, since the function returns right away.
+                self.pending = Some(call);
+                Ok(nil())
+            }
+            Recur(args, _) => {
+                let args = self.eval_args(args)?;
+                let current = self.current.clone().expect("`recur` used outside of a function. Contact the developer.");
+
+                self.pending = Some((current, args));
+                Ok(nil())
+            }
+            Condition(c, t, f) => {
+                if truthy(&*self.eval_value(c)?) {
+                    self.eval_value(t)
+                } else {
+                    self.eval_value(f)
+                }
+            }
+            Lambda(_, _, _, scope_id, _) => {
+                let definition = self.lambdas[scope_id].clone();
+                let captures = definition
+                    .3
+                    .iter()
+                    .map(|name| self.lookup(name))
+                    .collect::<Result<Vec<Ref>, RuntimeError>>()?;
+
+                Ok(Rc::new(Datum::Lambda(Function::Closure(
+                    *scope_id,
+                    definition.5.clone(),
+                    RefCell::new(captures),
+                ))))
+            }
+            Let(LetKind::Let, bindings, body, _) => {
+                let mut scope = Scope::new();
+
+                for (name, init) in bindings {
+                    let value = self.eval_body(init)?;
+                    bind(&mut scope, name, value);
+                }
+
+                self.scopes.push(scope);
+                let value = self.eval_body(body);
+                self.scopes.pop();
+
+                value
+            }
+            Let(LetKind::LetRec, bindings, body, _) => {
+                self.scopes
+                    .push(bindings.iter().map(|(name, _)| (name.clone(), nil())).collect());
+
+                for (name, init) in bindings {
+                    let value = self.eval_body(init)?;
+                    self.assign(name, value);
+                }
+
+                // Lambdas capture by value, so any that captured a binding before it was
+                // initialized are patched to refer to the final value, as the transpiler does.
+                for (name, init) in bindings {
+                    if let [ASTNode::Value(Lambda(..))] = init.as_slice() {
+                        let value = self.lookup(name)?;
+
+                        if let Datum::Lambda(Function::Closure(scope_id, _, captures)) = &*value {
+                            for (i, capture) in self.lambdas[scope_id].3.iter().enumerate() {
+                                if bindings.iter().any(|(n, _)| n == capture) {
+                                    captures.borrow_mut()[i] = self.lookup(capture)?;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let value = self.eval_body(body);
+                self.scopes.pop();
+
+                value
+            }
+            Begin(body) => self.eval_body(body),
+        }
+    }
+
+    fn execute(&mut self, statement: &Statement) -> Result<(), RuntimeError> {
+        match statement {
+            Definition(name, value, _) => {
+                let value = self.eval_value(value)?;
+                self.define(name, value);
+            }
+            Redefinition(name, value) => {
+                let value = self.eval_value(value)?;
+                self.assign(name, value);
+            }
+            Declaration(name) => self.define(name, nil()),
+            FunctionDefinition(name, ..) => {
+                let value = self.defuns[name].1.clone();
+                self.globals.insert(name.clone(), value);
+            }
+            ExpandedCondition(c, t, f) => {
+                let branch = if truthy(&*self.eval_value(c)?) { t } else { f };
+
+                for node in branch {
+                    self.eval(node)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds the value of a variable. Natives are treated as lambdas.
+    fn lookup(&self, name: &str) -> Result<Ref, RuntimeError> {
+        let local = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.iter().find(|(n, _)| n == name));
+
+        if let Some((_, value)) = local {
+            return Ok(value.clone());
+        }
+
+        if let Ok(native) = self.native(name) {
+            return Ok(Rc::new(Datum::Lambda(Function::Native(name.to_string(), native))));
+        }
+
+        self.globals
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RuntimeError::new(Cause::Generic, format!("`{}` is undefined.", name)))
+    }
+
+    /// Binds a name in the innermost scope, which is global for top level forms.
+    fn define(&mut self, name: &str, value: Ref) {
+        match self.scopes.last_mut() {
+            Some(scope) => bind(scope, name, value),
+            None => {
+                self.globals.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Changes the value of the innermost variable with the given name.
+    fn assign(&mut self, name: &str, value: Ref) {
+        match self.scopes.iter_mut().rev().find(|s| s.iter().any(|(n, _)| n == name)) {
+            Some(scope) => bind(scope, name, value),
+            None => {
+                self.globals.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Finds the native function that implements a name, by its name in C.
+    fn native(&self, name: &str) -> Result<NativeFn, RuntimeError> {
+        let c_name = match self.sym_table.get_fn(name) {
+            Some(c_name) if !self.sym_table.is_defun(name) => c_name,
+            _ => return Err(RuntimeError::new(Cause::Generic, format!("`{}` isn't a native function.", name))),
+        };
+
+        native(c_name).ok_or_else(|| {
+            RuntimeError::new(Cause::Generic, format!("`{}` isn't available in the interpreter.", c_name))
+        })
+    }
+
+    /// Calls a function by name, as the transpiler does for a `Call`.
+    fn call_named(&mut self, callee: &str, args: Vec<Ref>) -> Result<Ref, RuntimeError> {
+        if self.sym_table.is_defun(callee) {
+            let f = self.defuns[callee].1.clone();
+            return self.call(&f, args);
+        }
+
+        let native = self.native(callee)?;
+        native(self, &args)
+    }
+
+    /// Calls a lambda, along with any calls that it, and the functions it calls, make in tail
+    /// position.
+    pub fn call(&mut self, f: &Ref, args: Vec<Ref>) -> Result<Ref, RuntimeError> {
+        let mut call = (f.clone(), args);
+
+        loop {
+            let value = self.call_once(&call.0, call.1)?;
+
+            match self.pending.take() {
+                Some(next) => call = next,
+                None => return Ok(value),
+            }
+        }
+    }
+
+    fn call_once(&mut self, f: &Ref, args: Vec<Ref>) -> Result<Ref, RuntimeError> {
+        let (definition, captures) = match &**f {
+            Datum::Lambda(Function::Native(_, native)) => return native(self, &args),
+            Datum::Lambda(Function::Defun(name)) => (self.defuns[name].0.clone(), Vec::new()),
+            Datum::Lambda(Function::Closure(scope_id, _, captures)) => {
+                (self.lambdas[scope_id].clone(), captures.borrow().clone())
+            }
+            _ => return Err(RuntimeError::new(Cause::Type, "Expected lambda.")),
+        };
+
+        let (params, vararg, body, capture_names, name, label) = &*definition;
+
+        if args.len() < params.len() || (vararg.is_none() && args.len() != params.len()) {
+            let description = match (&**f, name) {
+                (Datum::Lambda(Function::Defun(name)), _) => format!("function `{}`", name),
+                (_, Some(name)) => format!("lambda `{}`", name),
+                (_, None) => label.clone(),
+            };
+
+            let qualifier = if vararg.is_some() { "at least " } else { "exactly " };
+
+            return Err(RuntimeError::new(
+                Cause::Argument,
+                format!("Expected {}{} argument(s) to {}.", qualifier, params.len(), description),
+            ));
+        }
+
+        let mut scope: Scope = capture_names.iter().cloned().zip(captures).collect();
+
+        for (param, arg) in params.iter().zip(&args) {
+            bind(&mut scope, param, arg.clone());
+        }
+
+        if let Some(vararg) = vararg {
+            bind(&mut scope, vararg, list_of(&args[params.len()..]));
+        }
+
+        let scopes = std::mem::replace(&mut self.scopes, vec![scope]);
+        let current = self.current.replace(f.clone());
+
+        let value = self.eval_body(body);
+
+        self.scopes = scopes;
+        self.current = current;
+
+        value.map_err(|mut e| {
+            e.backtrace.push(label.clone());
+            e
+        })
+    }
+
+    fn label(&self, name: Option<&str>, span: Span) -> String {
+        Transpiler::lambda_label(name, span, &self.sources)
+    }
+
+    fn write(&mut self, text: &str) {
+        // Like `printf`, a failed write doesn't stop the program.
+        let _ = self.out.write_all(text.as_bytes());
+    }
+}
+
+/// Binds a name in a scope, replacing any value it already has there.
+fn bind(scope: &mut Scope, name: &str, value: Ref) {
+    match scope.iter_mut().find(|(n, _)| n == name) {
+        Some(binding) => binding.1 = value,
+        None => scope.push((name.to_string(), value)),
+    }
+}
+
+fn nil() -> Ref {
+    Rc::new(Datum::Nil)
+}
+
+fn boolean(b: bool) -> Ref {
+    Rc::new(Datum::Bool(b))
+}
+
+/// Only false and nil are falsy.
+fn truthy(x: &Datum) -> bool {
+    !matches!(x, Datum::Bool(false) | Datum::Nil)
+}
+
+/// Makes a cons cell, which ends the list if `cdr` is nil or an empty list, as `new_cons` does.
+fn new_cons(car: Ref, cdr: Ref) -> Ref {
+    let cdr = match &*cdr {
+        Datum::Nil | Datum::Cons(None, _) => None,
+        _ => Some(cdr),
+    };
+
+    Rc::new(Datum::Cons(Some(car), cdr))
+}
+
+fn list_of(items: &[Ref]) -> Ref {
+    items
+        .iter()
+        .rev()
+        .fold(None, |cdr, car| Some(Rc::new(Datum::Cons(Some(car.clone()), cdr))))
+        .unwrap_or_else(|| Rc::new(Datum::Cons(None, None)))
+}
+
+/// Splits a list into its elements, and whatever ends it if it isn't a proper list. Anything that
+/// isn't a list with elements, including an empty list, is returned as the end.
+fn elements(list: &Ref) -> (Vec<Ref>, Option<Ref>) {
+    let mut items = Vec::new();
+    let mut node = Some(list.clone());
+
+    while let Some(n) = node.clone() {
+        match &*n {
+            Datum::Cons(Some(car), cdr) => {
+                items.push(car.clone());
+                node = cdr.clone();
+            }
+            _ => break,
+        }
+    }
+
+    (items, node)
+}
+
+fn literal(value: &TokenValue) -> Result<Ref, RuntimeError> {
+    let datum = match value {
+        TokenValue::Int(x) => Datum::Integer(*x),
+        TokenValue::Float(x) => Datum::Real(*x),
+        TokenValue::Complex(x, y) => Datum::Complex(*x, *y),
+        TokenValue::Rational(x, y) => return Ok(Rc::new(simplify(Number::Rational(*x, *y))?.into_datum())),
+        TokenValue::Str(s) => Datum::String(s.clone()),
+        TokenValue::Keyword(s) => Datum::Keyword(s.clone()),
+        TokenValue::True => Datum::Bool(true),
+        TokenValue::False => Datum::Bool(false),
+        TokenValue::Nil => Datum::Nil,
+        _ => panic!("Encountered invalid token literal in AST. Contact the developer."),
+    };
+
+    Ok(Rc::new(datum))
+}
+
+fn quoted(q: &Quoted) -> Result<Ref, RuntimeError> {
+    match q {
+        Quoted::Atom(t) => match &t.value {
+            TokenValue::Symbol(s) => Ok(Rc::new(Datum::Symbol(s.clone()))),
+            v => literal(v),
+        },
+        Quoted::List(items, None) if items.is_empty() => Ok(list_of(&[])),
+        Quoted::List(items, tail) => {
+            let mut output = match tail {
+                Some(t) => quoted(t)?,
+                None => nil(),
+            };
+
+            for item in items.iter().rev() {
+                output = new_cons(quoted(item)?, output);
+            }
+
+            Ok(output)
+        }
+    }
+}
+
+/// Writes a value as `display` does.
+pub fn display(datum: &Datum, out: &mut String) {
+    match datum {
+        Datum::Integer(x) => out.push_str(&x.to_string()),
+        Datum::Rational(n, d) => out.push_str(&format!("{}/{}", n, d)),
+        Datum::Real(x) => out.push_str(&c_float(*x, false)),
+        Datum::Complex(r, i) => out.push_str(&format!("{}{}i", c_float(*r, false), c_float(*i, true))),
+        Datum::Symbol(s) => out.push_str(s),
+        Datum::Keyword(s) => out.push_str(&format!(":{}", s)),
+        Datum::Cons(car, cdr) => {
+            out.push('(');
+
+            if let Some(car) = car {
+                display(car, out);
+            }
+
+            let mut end = cdr.clone();
+
+            while let Some(node) = end.clone() {
+                match &*node {
+                    Datum::Cons(Some(car), cdr) => {
+                        out.push(' ');
+                        display(car, out);
+                        end = cdr.clone();
+                    }
+                    _ => break,
+                }
+            }
+
+            // liblisp checks for the end of an improper list by reading its last element as if it
+            // were a cons cell, so any value that starts with zeroes ends the list silently.
+            if let Some(end) = end {
+                if !starts_with_zero(&end) {
+                    out.push_str(" . ");
+                    display(&end, out);
+                }
+            }
+
+            out.push(')');
+        }
+        Datum::Nil => out.push_str("nil"),
+        Datum::String(s) => out.push_str(s),
+        Datum::Bool(b) => out.push_str(if *b { "#t" } else { "#f" }),
+        Datum::Lambda(Function::Native(name, _)) | Datum::Lambda(Function::Defun(name)) => {
+            out.push_str(&format!("<function {}>", name))
+        }
+        // Lambdas that aren't defined as a variable are labelled by where they're written.
+        Datum::Lambda(Function::Closure(_, label, _)) if label.starts_with('<') => out.push_str(label),
+        Datum::Lambda(Function::Closure(_, label, _)) => out.push_str(&format!("<function {}>", label)),
+    }
+}
+
+/// Checks if liblisp would read the start of a value as a null pointer.
+fn starts_with_zero(datum: &Datum) -> bool {
+    match datum {
+        Datum::Integer(x) => *x == 0,
+        Datum::Real(x) | Datum::Complex(x, _) => x.to_bits() == 0,
+        Datum::Bool(b) => !b,
+        Datum::Nil | Datum::Cons(None, _) => true,
+        _ => false,
+    }
+}
+
+/// Writes a float as `printf` does with `%f`, or `%+f` if `signed`.
+fn c_float(x: f64, signed: bool) -> String {
+    let sign = if x.is_sign_negative() {
+        "-"
+    } else if signed {
+        "+"
+    } else {
+        ""
+    };
+
+    if x.is_nan() {
+        format!("{}nan", sign)
+    } else if x.is_infinite() {
+        format!("{}inf", sign)
+    } else if signed {
+        format!("{:+.6}", x)
+    } else {
+        format!("{:.6}", x)
+    }
+}
+
+/// A numeric value. The variants are ordered as in liblisp, so that a number may be promoted to
+/// any later variant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Number {
+    Integer(i32),
+    Rational(i32, i32),
+    Real(f64),
+    Complex(f64, f64),
+}
+
+impl Number {
+    fn from_datum(datum: &Datum) -> Option<Self> {
+        match datum {
+            Datum::Integer(x) => Some(Number::Integer(*x)),
+            Datum::Rational(n, d) => Some(Number::Rational(*n, *d)),
+            Datum::Real(x) => Some(Number::Real(*x)),
+            Datum::Complex(r, i) => Some(Number::Complex(*r, *i)),
+            _ => None,
+        }
+    }
+
+    fn into_datum(self) -> Datum {
+        match self {
+            Number::Integer(x) => Datum::Integer(x),
+            Number::Rational(n, d) => Datum::Rational(n, d),
+            Number::Real(x) => Datum::Real(x),
+            Number::Complex(r, i) => Datum::Complex(r, i),
+        }
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            Number::Integer(_) => 0,
+            Number::Rational(..) => 1,
+            Number::Real(_) => 2,
+            Number::Complex(..) => 3,
+        }
+    }
+
+    /// Promotes the number one step at a time until it reaches `rank`, as `promote` does.
+    fn promote(self, rank: u8) -> Self {
+        if rank <= self.rank() {
+            return self;
+        }
+
+        let promoted = match self {
+            Number::Integer(x) => Number::Rational(x, 1),
+            Number::Rational(n, d) => Number::Real(n as f64 / d as f64),
+            Number::Real(x) => Number::Complex(x, 0.0),
+            Number::Complex(..) => self,
+        };
+
+        promoted.promote(rank)
+    }
+}
+
+/// Euclid's algorithm, with C's signed remainder.
+fn gcd(mut a: i32, mut b: i32) -> i32 {
+    while b != 0 {
+        let temp = a.wrapping_rem(b);
+        a = b;
+        b = temp;
+    }
+
+    a
+}
+
+/// Reduces a rational number, keeping its sign in the numerator. Other numbers are unchanged.
+fn simplify(n: Number) -> Result<Number, RuntimeError> {
+    let (mut num, mut den) = match n {
+        Number::Rational(num, den) => (num, den),
+        _ => return Ok(n),
+    };
+
+    if den == 0 {
+        return Err(RuntimeError::new(
+            Cause::ZeroDivision,
+            "Division by 0 in simplification of rational number",
+        ));
+    }
+
+    let g = gcd(num, den);
+
+    if g != 1 {
+        num = num.wrapping_div(g);
+        den = den.wrapping_div(g);
+    }
+
+    if den < 0 {
+        num = num.wrapping_neg();
+        den = den.wrapping_neg();
+    }
+
+    Ok(Number::Rational(num, den))
+}
+
+/// Reduces a rational number the same way that liblisp does when it's made, or gives `None` if its
+/// denominator is 0.
+pub fn reduce_rational(num: i32, den: i32) -> Option<(i32, i32)> {
+    match simplify(Number::Rational(num, den)) {
+        Ok(Number::Rational(num, den)) => Some((num, den)),
+        _ => None,
+    }
+}
+
+/// Checks if two numbers are equal, after promoting both to the same type.
+fn numeric_eq(a: Number, b: Number) -> bool {
+    let rank = a.rank().max(b.rank());
+
+    match (a.promote(rank), b.promote(rank)) {
+        (Number::Integer(x), Number::Integer(y)) => x == y,
+        (Number::Rational(an, ad), Number::Rational(bn, bd)) => an == bn && ad == bd,
+        (Number::Real(x), Number::Real(y)) => x == y,
+        (Number::Complex(ar, ai), Number::Complex(br, bi)) => ar == br && ai == bi,
+        _ => panic!("Numbers were promoted to different types. Contact the developer."),
+    }
+}
+
+/// Orders two numbers as `cmp` does, returning a negative number, zero, or a positive number.
+/// Complex numbers are ordered by their real parts, then their imaginary parts.
+fn compare(a: Number, b: Number) -> i32 {
+    fn order(equal: bool, greater: bool) -> i32 {
+        if equal {
+            0
+        } else if greater {
+            1
+        } else {
+            -1
+        }
+    }
+
+    let rank = a.rank().max(b.rank());
+
+    match (a.promote(rank), b.promote(rank)) {
+        (Number::Integer(x), Number::Integer(y)) => order(x == y, x > y),
+        (Number::Rational(an, ad), Number::Rational(bn, bd)) => {
+            order(an == bn && ad == bd, an as f64 / ad as f64 > bn as f64 / bd as f64)
+        }
+        (Number::Real(x), Number::Real(y)) => order(x == y, x > y),
+        (Number::Complex(ar, ai), Number::Complex(br, bi)) => {
+            if ar == br {
+                order(ai == bi, ai > bi)
+            } else {
+                order(false, ar > br)
+            }
+        }
+        _ => panic!("Numbers were promoted to different types. Contact the developer."),
+    }
+}
+
+/// Folds `f` over the arguments, promoting the accumulator and each argument to the same type
+/// first, as `iterative_math_function` does. Rationals are reduced after every step.
+fn fold(
+    args: &[Ref],
+    mut acc: Number,
+    f: fn(Number, Number) -> Result<Number, RuntimeError>,
+    error: &str,
+) -> Result<Ref, RuntimeError> {
+    for arg in args {
+        let x = Number::from_datum(arg).ok_or_else(|| RuntimeError::new(Cause::Math, error))?;
+        let rank = acc.rank().max(x.rank());
+
+        acc = simplify(f(acc.promote(rank), x.promote(rank))?)?;
+    }
+
+    Ok(Rc::new(acc.into_datum()))
+}
+
+// Integer arithmetic wraps, rather than being undefined as it is in C.
+
+fn add_aux(a: Number, b: Number) -> Result<Number, RuntimeError> {
+    Ok(match (a, b) {
+        (Number::Integer(x), Number::Integer(y)) => Number::Integer(x.wrapping_add(y)),
+        (Number::Rational(an, ad), Number::Rational(bn, bd)) => Number::Rational(
+            bd.wrapping_mul(an).wrapping_add(ad.wrapping_mul(bn)),
+            bd.wrapping_mul(ad),
+        ),
+        (Number::Real(x), Number::Real(y)) => Number::Real(x + y),
+        (Number::Complex(ar, ai), Number::Complex(br, bi)) => Number::Complex(ar + br, ai + bi),
+        _ => panic!("Numbers were promoted to different types. Contact the developer."),
+    })
+}
+
+fn subtract_aux(a: Number, b: Number) -> Result<Number, RuntimeError> {
+    Ok(match (a, b) {
+        (Number::Integer(x), Number::Integer(y)) => Number::Integer(x.wrapping_sub(y)),
+        (Number::Rational(an, ad), Number::Rational(bn, bd)) => Number::Rational(
+            bd.wrapping_mul(an).wrapping_sub(ad.wrapping_mul(bn)),
+            bd.wrapping_mul(ad),
+        ),
+        (Number::Real(x), Number::Real(y)) => Number::Real(x - y),
+        (Number::Complex(ar, ai), Number::Complex(br, bi)) => Number::Complex(ar - br, ai - bi),
+        _ => panic!("Numbers were promoted to different types. Contact the developer."),
+    })
+}
+
+fn multiply_aux(a: Number, b: Number) -> Result<Number, RuntimeError> {
+    Ok(match (a, b) {
+        (Number::Integer(x), Number::Integer(y)) => Number::Integer(x.wrapping_mul(y)),
+        (Number::Rational(an, ad), Number::Rational(bn, bd)) => {
+            Number::Rational(an.wrapping_mul(bn), ad.wrapping_mul(bd))
+        }
+        (Number::Real(x), Number::Real(y)) => Number::Real(x * y),
+        (Number::Complex(ar, ai), Number::Complex(br, bi)) => {
+            Number::Complex(ar * br - ai * bi, ar * bi + ai * br)
+        }
+        _ => panic!("Numbers were promoted to different types. Contact the developer."),
+    })
+}
+
+fn divide_aux(a: Number, b: Number) -> Result<Number, RuntimeError> {
+    if numeric_eq(b, Number::Integer(0)) {
+        // liblisp raises this error without a message, which glibc prints as `(null)`.
+        return Err(RuntimeError::new(Cause::ZeroDivision, "(null)"));
+    }
+
+    Ok(match (a, b) {
+        (Number::Integer(x), Number::Integer(y)) => {
+            if x.wrapping_rem(y) == 0 {
+                Number::Integer(x.wrapping_div(y))
+            } else {
+                Number::Real(x as f64 / y as f64)
+            }
+        }
+        (Number::Rational(an, ad), Number::Rational(bn, bd)) => {
+            Number::Rational(an.wrapping_mul(bd), ad.wrapping_mul(bn))
+        }
+        (Number::Real(x), Number::Real(y)) => Number::Real(x / y),
+        (Number::Complex(ar, ai), Number::Complex(br, bi)) => {
+            let d = br * br + bi * bi;
+            let real = (ar * br + ai * bi) / d;
+
+            // liblisp uses the new real part to find the imaginary part.
+            Number::Complex(real, (ai * br - real * bi) / d)
+        }
+        _ => panic!("Numbers were promoted to different types. Contact the developer."),
+    })
+}
+
+/// Gives the function and arguments of a call made by `funcall`.
+fn funcall_target(args: &[Ref]) -> Result<(Ref, Vec<Ref>), RuntimeError> {
+    match args.first().map(|f| &**f) {
+        None => Err(RuntimeError::new(Cause::Argument, "`funcall` requires at least one argument.")),
+        Some(Datum::Lambda(_)) => Ok((args[0].clone(), args[1..].to_vec())),
+        Some(_) => Err(RuntimeError::new(Cause::Type, "Expected lambda.")),
+    }
+}
+
+/// Gives the function and arguments of a call made by `apply`.
+fn apply_target(args: &[Ref]) -> Result<(Ref, Vec<Ref>), RuntimeError> {
+    if args.len() != 2 {
+        return Err(RuntimeError::new(Cause::Argument, "`apply` requires exactly two arguments."));
+    }
+
+    match (&*args[0], &*args[1]) {
+        (Datum::Lambda(_), Datum::Cons(..)) | (Datum::Lambda(_), Datum::Nil) => (),
+        _ => {
+            return Err(RuntimeError::new(
+                Cause::Type,
+                "Expected `lambda` and `cons` type arguments to `apply`.",
+            ))
+        }
+    }
+
+    // Improper lists are caught by `length` first.
+    length_of(&args[1])?;
+
+    match elements(&args[1]) {
+        (items, None) => Ok((args[0].clone(), items)),
+        // An empty list gives no arguments.
+        (items, Some(end)) if matches!(*end, Datum::Cons(None, _)) => Ok((args[0].clone(), items)),
+        _ => Err(RuntimeError::new(Cause::Type, "`apply` requires a proper list.")),
+    }
+}
+
+fn length_of(list: &Ref) -> Result<i32, RuntimeError> {
+    match &**list {
+        Datum::Nil => return Ok(0),
+        Datum::String(s) => return Ok(s.len() as i32),
+        Datum::Cons(..) => (),
+        _ => return Err(RuntimeError::new(Cause::Type, "`length` expected list argument")),
+    }
+
+    match elements(list) {
+        (items, Some(_)) if !items.is_empty() => Err(RuntimeError::new(
+            Cause::Type,
+            "`length` expected list argument. Received pair.",
+        )),
+        (items, _) => Ok(items.len() as i32),
+    }
+}
+
+/// Finds the Rust version of a function in liblisp, by its name in C.
+fn native(c_name: &str) -> Option<NativeFn> {
+    let f: NativeFn = match c_name {
+        "add" => add,
+        "subtract" => subtract,
+        "multiply" => multiply,
+        "divide" => divide,
+        "mod" => modulo,
+        "division" => division,
+        "format" => format,
+        "eqv" => eqv,
+        "less_than" => less_than,
+        "greater_than" => greater_than,
+        "num_equals" => num_equals,
+        "less_than_eql" => less_than_eql,
+        "greater_than_eql" => greater_than_eql,
+        "logical_and" => logical_and,
+        "logical_or" => logical_or,
+        "logical_not" => logical_not,
+        "list" => list,
+        "car" => car,
+        "cdr" => cdr,
+        "length" => length,
+        "cons" => cons,
+        "append" => append,
+        "reverse" => reverse,
+        "funcall" => funcall,
+        "apply" => apply,
+        _ => return None,
+    };
+
+    Some(f)
+}
+
+/// The functions in liblisp that neither have side effects nor depend on anything but their
+/// arguments, by their names in C.
+const PURE: &[&str] = &[
+    "add",
+    "subtract",
+    "multiply",
+    "divide",
+    "mod",
+    "division",
+    "eqv",
+    "less_than",
+    "greater_than",
+    "num_equals",
+    "less_than_eql",
+    "greater_than_eql",
+    "logical_and",
+    "logical_or",
+    "logical_not",
+];
+
+/// Calls a pure native on literal arguments, for evaluating calls at compile time. Gives `None`
+/// if the native isn't pure, or if the result can't be written as a literal, like a list or a
+/// number that isn't finite.
+pub fn eval_pure(c_name: &str, args: &[TokenValue]) -> Option<Result<TokenValue, RuntimeError>> {
+    if !PURE.contains(&c_name) {
+        return None;
+    }
+
+    let args = match args.iter().map(literal).collect::<Result<Vec<Ref>, RuntimeError>>() {
+        Ok(args) => args,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let mut sink = std::io::sink();
+    let mut interpreter = Interpreter::new(SymbolTable::dummy(), &mut sink);
+
+    let result = match native(c_name)?(&mut interpreter, &args) {
+        Ok(result) => result,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let value = match &*result {
+        Datum::Integer(x) => TokenValue::Int(*x),
+        Datum::Rational(n, d) => TokenValue::Rational(*n, *d),
+        Datum::Real(x) if x.is_finite() => TokenValue::Float(*x),
+        Datum::Complex(r, i) if r.is_finite() && i.is_finite() => TokenValue::Complex(*r, *i),
+        Datum::String(s) => TokenValue::Str(s.clone()),
+        Datum::Keyword(s) => TokenValue::Keyword(s.clone()),
+        Datum::Bool(true) => TokenValue::True,
+        Datum::Bool(false) => TokenValue::False,
+        Datum::Nil => TokenValue::Nil,
+        _ => return None,
+    };
+
+    Some(Ok(value))
+}
+
+fn add(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    fold(args, Number::Integer(0), add_aux, "Addition error.")
+}
+
+fn subtract(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let error = "Error during subtraction.";
+
+    match args {
+        [] => Err(RuntimeError::new(Cause::Argument, "Too few calls to subtract.")),
+        // The argument is negated, by subtracting it from 0.
+        [_] => fold(args, Number::Integer(0), subtract_aux, error),
+        [first, rest @ ..] => {
+            let acc = Number::from_datum(first).ok_or_else(|| RuntimeError::new(Cause::Math, error))?;
+            fold(rest, acc, subtract_aux, error)
+        }
+    }
+}
+
+fn multiply(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    fold(args, Number::Integer(1), multiply_aux, "Error during multiplication.")
+}
+
+fn divide(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let error = "Error during division.";
+
+    match args {
+        [] => Ok(Rc::new(Datum::Integer(0))),
+        [x] => Ok(x.clone()),
+        // As in liblisp, the first argument is divided by every argument, including itself.
+        [first, ..] => {
+            let acc = Number::from_datum(first).ok_or_else(|| RuntimeError::new(Cause::Math, error))?;
+            fold(args, acc, divide_aux, error)
+        }
+    }
+}
+
+/// Takes two integers, as `mod` and `division` do.
+fn integers(args: &[Ref], error: &str) -> Result<(i32, i32), RuntimeError> {
+    if args.len() != 2 {
+        return Err(RuntimeError::new(Cause::Argument, "Incorrect number of arguments passed to mod."));
+    }
+
+    match (&*args[0], &*args[1]) {
+        // liblisp crashes instead.
+        (Datum::Integer(_), Datum::Integer(0)) => {
+            Err(RuntimeError::new(Cause::ZeroDivision, "Integer division by 0."))
+        }
+        (Datum::Integer(a), Datum::Integer(b)) => Ok((*a, *b)),
+        _ => Err(RuntimeError::new(Cause::Math, error)),
+    }
+}
+
+fn modulo(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let (a, b) = integers(args, "Cannot perform modulus operation on non-integer values.")?;
+
+    Ok(Rc::new(Datum::Integer(a.wrapping_rem(b))))
+}
+
+/// Gives the remainder, then the quotient.
+fn division(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let (a, b) = integers(args, "Cannot perform division algorithm on non-integer values.")?;
+    let d = Rc::new(Datum::Integer(a.wrapping_div(b)));
+    let r = Rc::new(Datum::Integer(a.wrapping_rem(b)));
+
+    Ok(list_of(&[r, d]))
+}
+
+/// Writes each argument followed by a space, then ends the line.
+fn format(interp: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let mut output = String::new();
+
+    for arg in args {
+        display(arg, &mut output);
+        output.push(' ');
+    }
+
+    output.push('\n');
+    interp.write(&output);
+
+    Ok(nil())
+}
+
+/// Compares two values as `datum_cmp` does. Numbers are compared by value, lists by their
+/// elements, and lambdas by identity.
+fn datum_eq(a: &Ref, b: &Ref) -> bool {
+    if let (Some(x), Some(y)) = (Number::from_datum(a), Number::from_datum(b)) {
+        return numeric_eq(x, y);
+    }
+
+    match (&**a, &**b) {
+        (Datum::Nil, Datum::Nil) => true,
+        (Datum::String(x), Datum::String(y))
+        | (Datum::Symbol(x), Datum::Symbol(y))
+        | (Datum::Keyword(x), Datum::Keyword(y)) => x == y,
+        (Datum::Bool(x), Datum::Bool(y)) => x == y,
+        (Datum::Lambda(_), Datum::Lambda(_)) => Rc::ptr_eq(a, b),
+        (Datum::Cons(None, _), Datum::Cons(None, _)) => true,
+        (Datum::Cons(Some(x), xs), Datum::Cons(Some(y), ys)) => {
+            datum_eq(x, y)
+                && match (xs, ys) {
+                    (None, None) => true,
+                    (Some(xs), Some(ys)) => datum_eq(xs, ys),
+                    _ => false,
+                }
+        }
+        _ => false,
+    }
+}
+
+fn eqv(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    Ok(boolean(args.windows(2).all(|w| datum_eq(&w[0], &w[1]))))
+}
+
+/// Checks that `valid` holds for the comparison of each pair of neighboring arguments.
+fn comparator(args: &[Ref], valid: fn(i32) -> bool) -> Result<Ref, RuntimeError> {
+    let mut is_true = true;
+
+    for pair in args.windows(2) {
+        match (Number::from_datum(&pair[0]), Number::from_datum(&pair[1])) {
+            (Some(a), Some(b)) => is_true = is_true && valid(compare(a, b)),
+            _ => return Err(RuntimeError::new(Cause::Generic, "Compared values must be numeric.")),
+        }
+    }
+
+    Ok(boolean(is_true))
+}
+
+fn less_than(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    comparator(args, |c| c < 0)
+}
+
+fn greater_than(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    comparator(args, |c| c > 0)
+}
+
+fn num_equals(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    comparator(args, |c| c == 0)
+}
+
+fn less_than_eql(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    comparator(args, |c| c <= 0)
+}
+
+fn greater_than_eql(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    comparator(args, |c| c >= 0)
+}
+
+fn logical_and(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let mut last = boolean(true);
+
+    for arg in args {
+        if !truthy(arg) {
+            return Ok(boolean(false));
+        }
+
+        last = arg.clone();
+    }
+
+    Ok(last)
+}
+
+fn logical_or(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    Ok(args
+        .iter()
+        .find(|a| truthy(a))
+        .cloned()
+        .unwrap_or_else(|| boolean(false)))
+}
+
+fn logical_not(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    match args {
+        [x] => Ok(boolean(!truthy(x))),
+        _ => Err(RuntimeError::new(Cause::Generic, "Wrong number of arguments passed to not")),
+    }
+}
+
+fn list(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    Ok(list_of(args))
+}
+
+fn car(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    match args {
+        [x] => match &**x {
+            Datum::Cons(Some(car), _) => Ok(car.clone()),
+            Datum::Cons(None, _) => {
+                Err(RuntimeError::new(Cause::Argument, "Cannot take the `car` of an empty list."))
+            }
+            _ => Err(RuntimeError::new(Cause::Type, "`car` expected proper list argument")),
+        },
+        _ => Err(RuntimeError::new(Cause::Argument, "`car` takes a single argument.")),
+    }
+}
+
+fn cdr(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    match args {
+        [x] => match &**x {
+            Datum::Cons(Some(_), Some(cdr)) => Ok(cdr.clone()),
+            Datum::Cons(..) | Datum::Nil => Ok(list_of(&[])),
+            _ => Err(RuntimeError::new(Cause::Type, "`cdr` expected a list valued argument.")),
+        },
+        _ => Err(RuntimeError::new(Cause::Argument, "`cdr` expects exactly one argument")),
+    }
+}
+
+fn length(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    match args {
+        [x] => Ok(Rc::new(Datum::Integer(length_of(x)?))),
+        _ => Err(RuntimeError::new(Cause::Argument, "`length` takes a single argument.")),
+    }
+}
+
+fn cons(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    match args {
+        [car, cdr] => Ok(new_cons(car.clone(), cdr.clone())),
+        _ => Err(RuntimeError::new(Cause::Argument, "`cons` takes exactly two arguments.")),
+    }
+}
+
+/// Joins lists, sharing the last one rather than copying it.
+fn append(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    if args.iter().any(|a| !matches!(**a, Datum::Cons(..) | Datum::Nil)) {
+        return Err(RuntimeError::new(Cause::Type, "Expected list in argument to `append`."));
+    }
+
+    let (last, lists) = match args {
+        [] => return Ok(list_of(&[])),
+        [x] => return Ok(x.clone()),
+        [lists @ .., last] => (last, lists),
+    };
+
+    let mut items = Vec::new();
+
+    for list in lists {
+        let (mut elements, end) = elements(list);
+
+        // Both nil and empty lists contribute nothing.
+        if elements.is_empty() {
+            continue;
+        }
+
+        if end.is_some() {
+            return Err(RuntimeError::new(
+                Cause::Type,
+                "Non-terminal arguments to `append` should be proper lists",
+            ));
+        }
+
+        items.append(&mut elements);
+    }
+
+    let tail = match &**last {
+        Datum::Cons(Some(_), _) if items.is_empty() => return Ok(last.clone()),
+        Datum::Cons(Some(_), _) => Some(last.clone()),
+        _ => None,
+    };
+
+    Ok(items
+        .into_iter()
+        .rev()
+        .fold(tail, |cdr, car| Some(Rc::new(Datum::Cons(Some(car), cdr))))
+        .unwrap_or_else(|| list_of(&[])))
+}
+
+fn reverse(_: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let x = match args {
+        [x] => x,
+        _ => return Err(RuntimeError::new(Cause::Argument, "`reverse` takes exactly one argument")),
+    };
+
+    match &**x {
+        // Lists too short to reverse are returned as they are.
+        Datum::Nil | Datum::Cons(None, _) | Datum::Cons(_, None) => return Ok(x.clone()),
+        Datum::Cons(..) => (),
+        _ => return Err(RuntimeError::new(Cause::Type, "`reverse` expected list argument")),
+    }
+
+    match elements(x) {
+        (items, None) => Ok(items
+            .into_iter()
+            .fold(nil(), |reversal, item| new_cons(item, reversal))),
+        _ => Err(RuntimeError::new(Cause::Type, "`reverse` expects a proper list")),
+    }
+}
+
+fn funcall(interp: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let (f, args) = funcall_target(args)?;
+    interp.call(&f, args)
+}
+
+fn apply(interp: &mut Interpreter, args: &[Ref]) -> Result<Ref, RuntimeError> {
+    let (f, args) = apply_target(args)?;
+    interp.call(&f, args)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::{CompileOptions, Compiler, Source};
+    use crate::interp::*;
+
+    /// Runs a program, returning what it printed, and the error that stopped it, if any.
+    fn run(program: &str) -> (String, Option<String>) {
+        let compiler = Compiler::new(CompileOptions::default()).unwrap();
+        let program = compiler.analyze(&[Source::new("<string>", program)]).ok().unwrap();
+
+        let mut out = Vec::new();
+        let result = Interpreter::new(program.sym_table, &mut out).run(&program.ast);
+
+        (String::from_utf8(out).unwrap(), result.err().map(|e| e.to_string()))
+    }
+
+    fn output(program: &str) -> String {
+        let (out, error) = run(program);
+        assert_eq!(None, error);
+        out
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!("3 \n", output("(format (+ 1 2))"));
+        assert_eq!("3/2 \n", output("(format (+ 1 1/2))"));
+        assert_eq!("-1/2 \n", output("(format (- 1/2 1))"));
+        assert_eq!("2.500000 \n", output("(format (+ 2 0.5))"));
+        assert_eq!("1.000000+2.000000i \n", output("(format (+ 1 2i))"));
+        // Like liblisp, the first argument is also divided by itself.
+        assert_eq!("0.500000 \n", output("(format (/ 6 2))"));
+        assert_eq!("(1 2) \n", output("(format (div 7 3))"));
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!("(1 2 3) \n", output("(format '(1 2 3))"));
+        assert_eq!("(1 . 2) \n", output("(format (cons 1 2))"));
+        assert_eq!("(1) \n", output("(format (cons 1 #f))"));
+        assert_eq!("() \n", output("(format '())"));
+        assert_eq!("(3 2 1) \n", output("(format (reverse (list 1 2 3)))"));
+        assert_eq!("(1 2 3 4) \n", output("(format (append '(1 2) '(3 4)))"));
+        assert_eq!("6 \n", output("(format (apply + '(1 2 3)))"));
+    }
+
+    #[test]
+    fn closures() {
+        // Captures are copied when the lambda is made.
+        let program = "(define x 1) (define f (lambda () x)) (define x 2) (format (funcall f) x)";
+        assert_eq!("1 2 \n", output(program));
+
+        let program = "
+            (letrec ((even? (lambda (n) (if (eqv n 0) #t (funcall odd? (- n 1)))))
+                     (odd? (lambda (n) (if (eqv n 0) #f (funcall even? (- n 1))))))
+              (format (funcall even? 10) (funcall odd? 10)))";
+        assert_eq!("#t #f \n", output(program));
+
+        let program = "(defun f (x . xs) (format x xs)) (f 1 2 3)";
+        assert_eq!("1 (2 3) \n", output(program));
+    }
+
+    #[test]
+    fn tail_calls() {
+        let program = "(defun spin (n) (if (eqv n 0) 'done (spin (- n 1)))) (format (spin 100000))";
+        assert_eq!("done \n", output(program));
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let (out, error) = run("(format 1) (car 1) (format 2)");
+        assert_eq!("1 \n", out);
+        assert!(error.is_some());
+
+        let (_, error) = run("(defun f (x) x) (f)");
+        assert_eq!(
+            Some("Invalid Argument Exception: Expected exactly 1 argument(s) to function `f`.".to_string()),
+            error
+        );
+
+        let (_, error) = run("(define g (lambda (x) x)) (funcall g)");
+        assert_eq!(
+            Some("Invalid Argument Exception: Expected exactly 1 argument(s) to lambda `g`.".to_string()),
+            error
+        );
+
+        // Without the names of the input files, there's nothing else to call a lambda by.
+        let (_, error) = run("(funcall (lambda (x) x))");
+        assert_eq!(
+            Some("Invalid Argument Exception: Expected exactly 1 argument(s) to <lambda>.".to_string()),
+            error
+        );
+    }
+
+    #[test]
+    fn lambda_names() {
+        assert_eq!("<function h> \n", output("(define h (lambda () 1)) (format h)"));
+        assert_eq!("<lambda> \n", output("(format (lambda () 1))"));
+        // Names made by the compiler aren't used.
+        assert_eq!("<lambda> \n", output("(format (if #t (lambda () 1) 2))"));
+    }
+
+    #[test]
+    fn backtraces() {
+        let program = "(defun inner (x) (car x)) (define middle (lambda (x) (+ 1 (inner x)))) (funcall middle 1)";
+        let (_, error) = run(program);
+        assert_eq!(
+            Some("Type Mismatch Exception: `car` expected proper list argument\n  in inner\n  in middle".to_string()),
+            error
+        );
+
+        // Functions that made a call in tail position have already returned.
+        let program = "(defun inner (x) (car x)) (define middle (lambda (x) (inner x))) (funcall middle 1)";
+        let (_, error) = run(program);
+        assert_eq!(
+            Some("Type Mismatch Exception: `car` expected proper list argument\n  in inner".to_string()),
+            error
+        );
+
+        // Only the innermost calls of deep recursion are shown.
+        let program = "(defun down (n) (if (eqv n 0) (car n) (+ 1 (down (- n 1))))) (down 20)";
+        let error = run(program).1.unwrap();
+        assert_eq!(16, error.matches("\n  in down").count());
+        assert!(error.ends_with("\n  ... and 5 more"));
+    }
+}
@@ -2,11 +2,12 @@ use nom::branch::alt;
 use nom::bytes::complete::take_while;
 use nom::bytes::complete::{tag, take_while1};
 use nom::character::complete::{char, digit0, digit1};
-use nom::combinator::{complete, map, opt, recognize, value};
+use nom::combinator::{complete, map, map_res, opt, recognize, value};
 use nom::error::Error;
 use nom::multi::many0;
 use nom::sequence::{delimited, pair, preceded, tuple};
 use nom::IResult;
+use crate::diagnostic::{Diagnostic, Diagnostics, Span, INVALID_TOKEN, UNTERMINATED_STRING};
 use std::fmt::Debug;
 use std::str::FromStr;
 
@@ -14,26 +15,37 @@ use std::str::FromStr;
 pub struct Token {
     // This technically limits file sizes, but the maximum size is tremendous.
     // TODO(matthew-c21): Mark these private later.
-    pub line: u32,
+    pub span: Span,
     pub value: TokenValue,
 }
 
 impl Token {
     /// Quick factory function primarily used for testing. Try to avoid this for real code.
     pub fn from(value: TokenValue) -> Self {
-        Token { line: 0, value }
+        Token {
+            span: Span::default(),
+            value,
+        }
     }
 
     pub fn value(&self) -> TokenValue {
         self.value.clone()
     }
 
-    pub fn line(&self) -> u32 {
-        self.line.clone()
+    pub fn span(&self) -> Span {
+        self.span
     }
 }
 
-// TODO(matthew-c21): Add macro symbols ('), and other special symbols (#', .)
+/// A `;` comment, running to the end of its line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub span: Span,
+    // The text of the comment, including the `;`, without trailing whitespace.
+    pub text: String,
+}
+
+// TODO(matthew-c21): Add other special symbols (#')
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenValue {
     Int(i32),
@@ -50,29 +62,64 @@ pub enum TokenValue {
     False,
     Cons,
     Nil,
+    // Reader shorthands for quote ('), quasiquote (`), unquote (,), and unquote-splicing (,@).
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
 }
 
-// NOTE(matthew-c21): Consider adding the offending text to this as well.
-#[derive(PartialEq, Debug)]
-pub struct LexError {
-    msg: String,
-    line: u32,
+/// Writes the token as it could appear in source code.
+impl std::fmt::Display for TokenValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TokenValue::Int(x) => write!(f, "{}", x),
+            // Debug formatting always includes a decimal point or exponent.
+            TokenValue::Float(x) => write!(f, "{:?}", x),
+            TokenValue::Complex(x, y) => write!(f, "{:?}{:+?}i", x, y),
+            TokenValue::Rational(x, y) => write!(f, "{}/{}", x, y),
+            TokenValue::Str(s) => write!(f, "\"{}\"", s),
+            TokenValue::Keyword(s) => write!(f, ":{}", s),
+            TokenValue::Symbol(s) => write!(f, "{}", s),
+            TokenValue::Open => write!(f, "("),
+            TokenValue::Close => write!(f, ")"),
+            TokenValue::True => write!(f, "#t"),
+            TokenValue::False => write!(f, "#f"),
+            TokenValue::Cons => write!(f, "."),
+            TokenValue::Nil => write!(f, "nil"),
+            TokenValue::Quote => write!(f, "'"),
+            TokenValue::Quasiquote => write!(f, "`"),
+            TokenValue::Unquote => write!(f, ","),
+            TokenValue::UnquoteSplicing => write!(f, ",@"),
+        }
+    }
 }
 
-impl ToString for LexError {
-    fn to_string(&self) -> String {
-        format!("On line {}: {}", self.line, self.msg)
-    }
+pub fn start(input: &str) -> Result<Vec<Token>, Diagnostic> {
+    let mut diagnostics = Diagnostics::new(1);
+    let tokens = tokenize(input, &mut diagnostics);
+
+    diagnostics.into_result(tokens)
 }
 
-pub fn start(input: &str) -> Result<Vec<Token>, LexError> {
+/// Reads every token from the input. Text that can't be read is reported, and replaced by `nil`
+/// so that the rest of the form it appears in can still be checked.
+pub fn tokenize(input: &str, diagnostics: &mut Diagnostics) -> Vec<Token> {
+    tokenize_lossless(input, diagnostics).0
+}
+
+/// Like `tokenize`, but also keeps the comments, for tools that rewrite source code.
+pub fn tokenize_lossless(input: &str, diagnostics: &mut Diagnostics) -> (Vec<Token>, Vec<Comment>) {
     let mut tokens: Vec<Token> = Vec::new();
+    let mut comments = Vec::new();
 
     let mut rest = input;
 
     let mut line = 1;
+    // Byte offset of the start of the current line.
+    let mut line_start = 0;
 
-    while !rest.is_empty() {
+    while !rest.is_empty() && !diagnostics.is_full() {
         // Consume whitespace.
         let x: IResult<&str, u32, Error<&str>> = whitespace(rest);
 
@@ -80,13 +127,21 @@ pub fn start(input: &str) -> Result<Vec<Token>, LexError> {
 
         line += lines_read;
 
+        if lines_read > 0 {
+            let offset = input.len() - s.len();
+            line_start = input[..offset].rfind('\n').unwrap() + 1;
+        }
+
+        // The column is counted in characters, rather than bytes.
+        let column = input[line_start..input.len() - s.len()].chars().count() as u32 + 1;
+
         // Since whitespace may have been consumed, it's possible that input was exhausted.
         if s.is_empty() {
-            return Ok(tokens);
+            return (tokens, comments);
         };
 
         // Ignore line comments.
-        if s.chars().nth(0).unwrap() == ';' {
+        if s.starts_with(';') {
             let x: IResult<&str, &str, Error<&str>> = take_while(|x| x != '\n')(s);
 
             let (s, comment) = x.unwrap();
@@ -95,83 +150,119 @@ pub fn start(input: &str) -> Result<Vec<Token>, LexError> {
             // more comments. To get around this, we just restart the loop until no comment is
             // found.
             if !comment.is_empty() {
+                comments.push(Comment {
+                    span: Span::new(line, column, comment.chars().count() as u32),
+                    text: comment.trim_end().to_string(),
+                });
+
                 rest = s;
                 continue;
             }
         }
 
         // All leading comments and whitespace have been stripped, so we can move to parsers.
+        let token_start = s;
 
         // Check below for what's going on here. Essentially, strings can't be quickly taken in
         // chunks, so we handle them separately.
-        let x = if s.chars().nth(0).unwrap() == '"' {
+        // There's something left in the stream, so we first try to consume everything up to the
+        // next token terminal. Invalid text is skipped up to this point as well.
+        let (after_token, next_token) =
+            take_while::<fn(char) -> bool, &str, Error<&str>>(|x| !is_token_terminal(x))(s)
+                .unwrap();
+
+        let x = if s.starts_with('"') {
             match string(s) {
-                Err(_) => Err(LexError {
-                    line,
-                    msg: format!("Error while attempting to read string content"),
-                }),
+                Err(_) => Err(Diagnostic::error(
+                    UNTERMINATED_STRING,
+                    Span::new(line, column, 1),
+                    String::from("Error while attempting to read string content"),
+                )
+                .with_note("strings must end with an unescaped `\"`")),
                 Ok((s, v)) => Ok((s, v)),
             }
         } else {
-            // There's something left in the stream, so we first try to consume everything up to the
-            // next token terminal.
-            let (s, next_token) =
-                take_while::<fn(char) -> bool, &str, Error<&str>>(|x| !is_token_terminal(x))(s)
-                    .unwrap();
+            let s = after_token;
 
             // If the next token is empty, that means the next character is a token terminal. Whitespace
             // has already been stripped, so it must be a single character token.
             if next_token.is_empty() {
-                match s.chars().nth(0).unwrap() {
+                match s.chars().next().unwrap() {
                     '(' => Ok((&s[1..], TokenValue::Open)),
                     ')' => Ok((&s[1..], TokenValue::Close)),
+                    '\'' => Ok((&s[1..], TokenValue::Quote)),
+                    '`' => Ok((&s[1..], TokenValue::Quasiquote)),
+                    ',' if s.starts_with(",@") => Ok((&s[2..], TokenValue::UnquoteSplicing)),
+                    ',' => Ok((&s[1..], TokenValue::Unquote)),
                     _ => panic!("This should theoretically be unreachable."),
                 }
             } else {
                 // Now we try each parser.
-                let mut parsers = Vec::new();
-                parsers.push(int as fn(&str) -> IResult<&str, TokenValue>);
-                parsers.push(float);
-                parsers.push(rational);
-                parsers.push(complex);
-                parsers.push(cons);
-                parsers.push(boolean);
-                parsers.push(string);
-                parsers.push(keyword);
-                parsers.push(symbol);
+                let parsers: Vec<TokenParser> = vec![
+                    int, float, rational, complex, cons, ellipsis, boolean, string, keyword, symbol,
+                ];
                 let mut possibilities: Vec<TokenValue> = parsers
                     .iter()
-                    .map(|f| match f(next_token) {
-                        Ok(("", v)) => Ok(v),
-                        _ => Err(""),
+                    .filter_map(|f| match f(next_token) {
+                        Ok(("", v)) => Some(v),
+                        _ => None,
                     })
-                    .filter(Result::is_ok)
-                    .map(Result::unwrap)
                     .collect();
 
+                let span = Span::new(line, column, next_token.chars().count() as u32);
+
                 if possibilities.is_empty() {
-                    Err(LexError {
-                        line,
-                        msg: format!("Unable to match `{}` to a token value.", next_token),
-                    })
+                    Err(Diagnostic::error(
+                        INVALID_TOKEN,
+                        span,
+                        format!("Unable to match `{}` to a token value.", next_token),
+                    ))
+                } else if out_of_range(&possibilities[0]) {
+                    Err(Diagnostic::error(
+                        INVALID_TOKEN,
+                        span,
+                        format!("The number `{}` is out of range.", next_token),
+                    )
+                    .with_note("floats must be finite"))
                 } else {
                     Ok((s, possibilities.remove(0)))
                 }
             }
-        }?;
+        };
+
+        let (s, value) = match x {
+            Ok(x) => x,
+            Err(d) => {
+                diagnostics.report(d);
+                (after_token, TokenValue::Nil)
+            }
+        };
 
-        let (s, value) = x;
+        let text = &token_start[..token_start.len() - s.len()];
         rest = s;
 
-        tokens.push(Token { line, value })
+        tokens.push(Token {
+            span: Span::new(line, column, text.chars().count() as u32),
+            value,
+        });
     }
 
-    Ok(tokens)
+    (tokens, comments)
 }
 
 // Auxiliary functions
 fn is_token_terminal(ch: char) -> bool {
-    ch.is_whitespace() || ch == '(' || ch == ')'
+    ch.is_whitespace() || ch == '(' || ch == ')' || ch == '\'' || ch == '`' || ch == ','
+}
+
+/// Checks for numbers too large to be represented, which would otherwise be read as infinity.
+/// Integers that don't fit in an `i32` are read as floats instead.
+fn out_of_range(value: &TokenValue) -> bool {
+    match value {
+        TokenValue::Float(x) => !x.is_finite(),
+        TokenValue::Complex(x, y) => !x.is_finite() || !y.is_finite(),
+        _ => false,
+    }
 }
 
 fn is_symbolic_start(ch: char) -> bool {
@@ -186,10 +277,10 @@ fn is_symbolic_part(ch: char) -> bool {
     is_symbolic_start(ch) || ch.is_numeric()
 }
 
-fn signed<T>(
-    f: &'static dyn Fn(&str) -> IResult<&str, T>,
-    required: bool,
-) -> Box<dyn Fn(&str) -> IResult<&str, T>>
+type Parser<T> = dyn Fn(&str) -> IResult<&str, T>;
+type TokenParser = fn(&str) -> IResult<&str, TokenValue>;
+
+fn signed<T>(f: &'static Parser<T>, required: bool) -> Box<Parser<T>>
 where
     T: FromStr,
     <T as FromStr>::Err: Debug,
@@ -236,6 +327,11 @@ fn cons(input: &str) -> IResult<&str, TokenValue> {
     tag(".")(input).map(|(rest, _cons)| (rest, TokenValue::Cons))
 }
 
+// `...` is only meaningful in `syntax-rules`, but is otherwise an ordinary symbol.
+fn ellipsis(input: &str) -> IResult<&str, TokenValue> {
+    tag("...")(input).map(|(rest, e)| (rest, TokenValue::Symbol(e.to_string())))
+}
+
 named!(symbol_content<&str, String>,
     map!(
         recognize!(
@@ -266,10 +362,17 @@ fn floating(input: &str) -> IResult<&str, f64> {
     )(input)
 }
 
+// Fails for integers that don't fit in an `i32`. The sign is read along with the digits, so that
+// `i32::MIN` fits.
+fn integer(input: &str) -> IResult<&str, i32> {
+    map_res(recognize(pair(signopt, digit1)), FromStr::from_str)(input)
+}
+
+// Fails for integers that don't fit in an `i32`.
 named!(digits <&str, i32>,
-    map!(
+    map_res!(
         digit1,
-        |x| FromStr::from_str(x).unwrap()
+        FromStr::from_str
     )
 );
 
@@ -299,7 +402,7 @@ fn escape(input: &str) -> IResult<&str, &str> {
 // Main Parsers
 
 fn int(input: &str) -> IResult<&str, TokenValue> {
-    let r: (&str, i32) = signed(&digits, false)(input)?;
+    let r: (&str, i32) = integer(input)?;
 
     Ok((r.0, TokenValue::Int(r.1)))
 }
@@ -312,8 +415,7 @@ fn float(input: &str) -> IResult<&str, TokenValue> {
 
 // TODO(matthew-c21): Maybe check for zero division here.
 fn rational(input: &str) -> IResult<&str, TokenValue> {
-    let top = signed(&digits, false);
-    let r = tuple!(input, top, tag!("/"), digits)?;
+    let r = tuple!(input, integer, tag!("/"), digits)?;
 
     let x = r.1;
     let num = x.0;
@@ -383,6 +485,7 @@ fn boolean(input: &str) -> IResult<&str, TokenValue> {
 
 #[cfg(test)]
 mod tests {
+    use crate::diagnostic::{Diagnostic, Diagnostics, Span, INVALID_TOKEN, UNTERMINATED_STRING};
     use crate::lex::TokenValue::*;
     use crate::lex::*;
 
@@ -445,7 +548,7 @@ mod tests {
         assert_eq!(
             start("+1/+2"),
             Ok(vec!(Token {
-                line: 1,
+                span: Span::new(1, 1, 5),
                 value: Symbol("+1/+2".to_string()),
             }))
         );
@@ -459,71 +562,71 @@ mod tests {
             ),
             Ok(vec!(
                 Token {
-                    line: 1,
+                    span: Span::new(1, 1, 3),
                     value: Rational(1, 3),
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 5, 4),
                     value: Float(2.5),
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 10, 1),
                     value: Open,
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 11, 1),
                     value: Close,
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 12, 1),
                     value: Close,
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 1, 1),
                     value: Open,
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 3, 1),
                     value: Open,
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 4, 1),
                     value: Int(1),
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 5, 1),
                     value: Close,
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 7, 1),
                     value: Open,
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 8, 4),
                     value: Symbol("list".to_string()),
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 13, 2),
                     value: Keyword("a".to_string()),
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 16, 2),
                     value: Keyword("b".to_string()),
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 19, 2),
                     value: Keyword("c".to_string()),
                 },
                 Token {
-                    line: 2,
+                    span: Span::new(2, 21, 1),
                     value: Close,
                 },
                 Token {
-                    line: 4,
+                    span: Span::new(4, 1, 2),
                     value: True,
                 },
                 Token {
-                    line: 5,
+                    span: Span::new(5, 1, 14),
                     value: Str("hello, world".to_string()),
                 }
             ))
@@ -535,45 +638,115 @@ mod tests {
         // Boolean not followed by terminal
         assert_eq!(
             start("#tfalse"),
-            Err(LexError {
-                line: 1,
-                msg: "Unable to match `#tfalse` to a token value.".to_string(),
-            })
+            Err(Diagnostic::error(
+                INVALID_TOKEN,
+                Span::new(1, 1, 7),
+                "Unable to match `#tfalse` to a token value.".to_string()
+            ))
         );
 
         // Colon not followed by keyword
         assert_eq!(
             start(": "),
-            Err(LexError {
-                line: 1,
-                msg: "Unable to match `:` to a token value.".to_string(),
-            })
+            Err(Diagnostic::error(
+                INVALID_TOKEN,
+                Span::new(1, 1, 1),
+                "Unable to match `:` to a token value.".to_string()
+            ))
         );
         assert_eq!(
             start(" :( "),
-            Err(LexError {
-                line: 1,
-                msg: "Unable to match `:` to a token value.".to_string(),
-            })
+            Err(Diagnostic::error(
+                INVALID_TOKEN,
+                Span::new(1, 2, 1),
+                "Unable to match `:` to a token value.".to_string()
+            ))
         );
         assert_eq!(
             start(" :)"),
-            Err(LexError {
-                line: 1,
-                msg: "Unable to match `:` to a token value.".to_string(),
-            })
+            Err(Diagnostic::error(
+                INVALID_TOKEN,
+                Span::new(1, 2, 1),
+                "Unable to match `:` to a token value.".to_string()
+            ))
         );
 
         // Floating point number not starting with a digit.
         assert_eq!(
             start(" asdf \n.123 ( )"),
-            Err(LexError {
-                line: 2,
-                msg: "Unable to match `.123` to a token value.".to_string(),
-            })
+            Err(Diagnostic::error(
+                INVALID_TOKEN,
+                Span::new(2, 1, 4),
+                "Unable to match `.123` to a token value.".to_string()
+            ))
         );
     }
 
+    #[test]
+    fn numbers_out_of_range() {
+        for number in &["1e999", "-1e999", "1e999+1i"] {
+            let error = start(number).unwrap_err();
+
+            assert_eq!(INVALID_TOKEN, error.code);
+            assert_eq!(format!("The number `{}` is out of range.", number), error.message);
+        }
+
+        assert!(start("99999999999/2").is_err());
+        assert!(start("99999999999x").is_err());
+        assert_eq!(Int(2147483647), start("2147483647").unwrap()[0].value());
+        assert_eq!(Int(-2147483648), start("-2147483648").unwrap()[0].value());
+        assert_eq!(Rational(-2147483648, 3), start("-2147483648/3").unwrap()[0].value());
+
+        // Integers that don't fit are read as floats.
+        assert_eq!(Float(2147483648.0), start("2147483648").unwrap()[0].value());
+        assert_eq!(Float(-99999999999.0), start("-99999999999").unwrap()[0].value());
+        assert_eq!(Float(1e10), start("10000000000.0").unwrap()[0].value());
+    }
+
+    #[test]
+    fn columns() {
+        let spans: Vec<Span> = start("(λ x)\n  \"a b\" ;; c\n\t:c").unwrap().iter().map(Token::span).collect();
+
+        // Columns count characters, rather than bytes.
+        assert_eq!(
+            vec![
+                Span::new(1, 1, 1),
+                Span::new(1, 2, 1),
+                Span::new(1, 4, 1),
+                Span::new(1, 5, 1),
+                Span::new(2, 3, 5),
+                Span::new(3, 2, 2),
+            ],
+            spans
+        );
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let error = start("(a \"b)").unwrap_err();
+
+        assert_eq!(UNTERMINATED_STRING, error.code);
+        assert_eq!(Span::new(1, 4, 1), error.span);
+    }
+
+    #[test]
+    fn resume_after_invalid_tokens() {
+        let mut diagnostics = Diagnostics::new(0);
+        let tokens = tokenize("(a #q b\n#z)", &mut diagnostics);
+
+        // Each invalid token is replaced by `nil`, so that parsing can carry on.
+        let values: Vec<TokenValue> = tokens.iter().map(|t| t.value()).collect();
+        assert_eq!(
+            vec![Open, Symbol("a".to_string()), Nil, Symbol("b".to_string()), Nil, Close],
+            values
+        );
+
+        let errors = diagnostics.into_vec();
+        assert_eq!(2, errors.len());
+        assert_eq!(Span::new(1, 4, 2), errors[0].span);
+        assert_eq!(Span::new(2, 1, 2), errors[1].span);
+    }
+
     #[test]
     fn booleans() {
         assert_eq!(boolean("#t"), Ok(("", True)));
@@ -605,55 +778,55 @@ mod tests {
             start(". (1 2 . 3) (a b . zs)"),
             Ok(vec!(
                 Token {
-                    line: 1,
+                    span: Span::new(1, 1, 1),
                     value: Cons,
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 3, 1),
                     value: Open,
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 4, 1),
                     value: Int(1),
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 6, 1),
                     value: Int(2),
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 8, 1),
                     value: Cons,
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 10, 1),
                     value: Int(3),
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 11, 1),
                     value: Close,
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 13, 1),
                     value: Open,
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 14, 1),
                     value: Symbol("a".to_string()),
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 16, 1),
                     value: Symbol("b".to_string()),
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 18, 1),
                     value: Cons,
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 20, 2),
                     value: Symbol("zs".to_string()),
                 },
                 Token {
-                    line: 1,
+                    span: Span::new(1, 22, 1),
                     value: Close,
                 },
             ))
@@ -664,38 +837,102 @@ mod tests {
     fn near_cons_pairs() {
         assert_eq!(
             start(". (1 2 . 3) (a b .zs)"),
-            Err(LexError {
-                line: 1,
-                msg: "Unable to match `.zs` to a token value.".to_string()
-            })
+            Err(Diagnostic::error(
+                INVALID_TOKEN,
+                Span::new(1, 18, 3),
+                "Unable to match `.zs` to a token value.".to_string()
+            ))
         );
         assert_eq!(
             start(". (1 2 . 3) (a b .3)"),
-            Err(LexError {
-                line: 1,
-                msg: "Unable to match `.3` to a token value.".to_string()
-            })
+            Err(Diagnostic::error(
+                INVALID_TOKEN,
+                Span::new(1, 18, 2),
+                "Unable to match `.3` to a token value.".to_string()
+            ))
         );
     }
 
+    #[test]
+    fn ellipsis() {
+        assert_eq!(
+            start("(x ...)").unwrap()[2].value(),
+            Symbol("...".to_string())
+        );
+        assert!(start("..").is_err());
+        assert!(start("....").is_err());
+    }
+
     #[test]
     fn nil() {
         assert_eq!(
             start("nil"),
             Ok(vec![Token {
-                line: 1,
+                span: Span::new(1, 1, 3),
                 value: Nil
             }])
         )
     }
 
+    #[test]
+    fn display_round_trip() {
+        let source = "12 -1.5 2.0 1e20 3.0-2.0i 1/2 \"a\\tb\" :key sym #t #f nil ( . ) ' ` , ,@";
+        let tokens = start(source).unwrap();
+        let printed: Vec<String> = tokens.iter().map(|t| t.value().to_string()).collect();
+
+        assert_eq!(
+            tokens.iter().map(Token::value).collect::<Vec<TokenValue>>(),
+            start(printed.join(" ").as_str())
+                .unwrap()
+                .iter()
+                .map(Token::value)
+                .collect::<Vec<TokenValue>>()
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        let values: Vec<TokenValue> = start("'x `(a ,b ,@c) '()")
+            .unwrap()
+            .iter()
+            .map(Token::value)
+            .collect();
+
+        assert_eq!(
+            vec![
+                Quote,
+                Symbol("x".to_string()),
+                Quasiquote,
+                Open,
+                Symbol("a".to_string()),
+                Unquote,
+                Symbol("b".to_string()),
+                UnquoteSplicing,
+                Symbol("c".to_string()),
+                Close,
+                Quote,
+                Open,
+                Close,
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn quotes_end_tokens() {
+        assert_eq!(
+            vec![Symbol("a".to_string()), Quote, Symbol("b".to_string())],
+            start("a'b").unwrap().iter().map(Token::value).collect::<Vec<TokenValue>>()
+        );
+    }
+
     #[test]
     fn symbols_cant_contain_underscores() {
-        assert_eq!(start("he_llo"), Err(LexError {line: 1, msg: "Unable to match `he_llo` to a token value.".to_string()}))
+        assert_eq!(start("he_llo"), Err(Diagnostic::error(INVALID_TOKEN, Span::new(1, 1, 6), "Unable to match `he_llo` to a token value.".to_string())))
     }
 
     #[test]
     fn symbols_cant_start_with_underscores() {
-        assert_eq!(start("_hello"), Err(LexError {line: 1, msg: "Unable to match `_hello` to a token value.".to_string()}))
+        assert_eq!(start("_hello"), Err(Diagnostic::error(INVALID_TOKEN, Span::new(1, 1, 6), "Unable to match `_hello` to a token value.".to_string())))
     }
 }
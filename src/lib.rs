@@ -0,0 +1,51 @@
+//! lispc compiles a dialect of LISP into C, which is linked against the liblisp runtime.
+//!
+//! Most tools only need [`Compiler`], which runs the whole front end and translates the program:
+//!
+//! ```no_run
+//! use lispc::{CompileOptions, Compiler};
+//!
+//! let compiler = Compiler::new(CompileOptions::default()).unwrap();
+//! let compilation = compiler.compile_str("(format (+ 1 2))");
+//!
+//! match compilation.output {
+//!     Some(c) => println!("{}", c),
+//!     None => eprintln!("{} error(s)", compilation.diagnostics.len()),
+//! }
+//! ```
+//!
+//! The stages are also public, for tools that need one of them on its own. In order, they are
+//! `lex::tokenize`, `parse::parse_all`, `expand::MacroExpander`, `ast::construct_ast_all`, the
+//! passes run by [`PassManager`], and `transpile::Transpiler`. Passes of your own can be added
+//! with [`Compiler::passes_mut`]. [`Compiler`], [`CompileOptions`], and the types re-exported here
+//! are the stable API, while the modules may change between versions.
+
+#[macro_use]
+extern crate nom;
+
+pub mod ast;
+pub mod build;
+mod compiler;
+pub mod dce;
+pub mod diagnostic;
+pub mod expand;
+pub mod fold;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+pub mod interp;
+pub mod lex;
+pub mod lsp;
+pub mod parse;
+pub mod passes;
+pub mod pretty;
+#[cfg(unix)]
+pub mod repl;
+pub mod transpile;
+
+pub use crate::ast::{ASTNode, ASTVisitor, SymbolTable};
+pub use crate::compiler::{Compilation, CompileOptions, Compiler, Emit, Program, Source};
+pub use crate::diagnostic::{Diagnostic, Span};
+pub use crate::lex::Token;
+pub use crate::parse::ParseTree;
+pub use crate::passes::{Pass, PassManager, Property};
+pub use crate::transpile::Collector;
@@ -0,0 +1,979 @@
+//! A language server, so that editors can show errors and look up names. It speaks JSON-RPC over
+//! stdin and stdout, as described by the Language Server Protocol, and treats each open file as a
+//! program of its own.
+
+use crate::ast::SymbolTable;
+use crate::compiler::{CompileOptions, Compiler, Source};
+use crate::diagnostic::{Diagnostic, Diagnostics, Severity, Span};
+use crate::lex::{self, Token, TokenValue};
+use crate::parse::{self, ParseTree};
+use json::{object, JsonValue};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// Error codes from JSON-RPC, and from the protocol.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const SERVER_NOT_INITIALIZED: i32 = -32002;
+
+// Kinds of completion item, as numbered by the protocol.
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_VARIABLE: u32 = 6;
+
+// A line and column, which orders anything in the source.
+type Position = (u32, u32);
+
+// A code, and a message, to respond to a request with.
+type Error = (i32, String);
+
+/// What a name is bound to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Global,
+    Function,
+    Macro,
+    Parameter,
+    Local,
+}
+
+impl Kind {
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::Global => "global variable",
+            Kind::Function => "function",
+            Kind::Macro => "macro",
+            Kind::Parameter => "parameter",
+            Kind::Local => "local variable",
+        }
+    }
+}
+
+/// A name bound in the source, and the part of the source that can use it.
+struct Definition {
+    name: String,
+    kind: Kind,
+    span: Span,
+    scope: (Position, Position),
+}
+
+/// A symbol in the source, and the definition that it refers to, if it's bound in the file.
+struct Use {
+    name: String,
+    span: Span,
+    definition: Option<usize>,
+}
+
+/// Where each name in a file is bound, and used. Names are looked up in a `SymbolTable` with a
+/// scope for each `lambda` and `let`, like `SymbolValidation` does, but in the source as it was
+/// written, since the AST doesn't keep the positions of names.
+struct Index {
+    definitions: Vec<Definition>,
+    uses: Vec<Use>,
+    sym_table: SymbolTable,
+    // The definition of each name, by the scope it's bound in.
+    bound: HashMap<(usize, String), usize>,
+    scope_count: usize,
+}
+
+fn symbol(tree: &ParseTree) -> Option<&Token> {
+    match tree {
+        ParseTree::Leaf(t @ Token {
+            value: TokenValue::Symbol(_),
+            ..
+        }) => Some(t),
+        _ => None,
+    }
+}
+
+fn symbol_name(token: &Token) -> &str {
+    match &token.value {
+        TokenValue::Symbol(s) => s,
+        _ => "",
+    }
+}
+
+/// The name that a top level form defines, if any.
+fn definition(tree: &ParseTree) -> Option<(Kind, &Token)> {
+    let elems = match tree {
+        ParseTree::Branch(elems, ..) if elems.len() >= 2 => elems,
+        _ => return None,
+    };
+
+    let kind = match (symbol(&elems[0]).map(symbol_name), elems.get(2)) {
+        (Some("define"), Some(ParseTree::Branch(value, ..)))
+            if value.first().and_then(symbol).map(symbol_name) == Some("lambda") =>
+        {
+            Kind::Function
+        }
+        (Some("define"), _) => Kind::Global,
+        (Some("defun"), _) => Kind::Function,
+        (Some("defmacro"), _) | (Some("define-syntax"), _) => Kind::Macro,
+        _ => return None,
+    };
+
+    symbol(&elems[1]).map(|t| (kind, t))
+}
+
+impl Index {
+    fn new(trees: &[ParseTree]) -> Self {
+        let mut index = Index {
+            definitions: Vec::new(),
+            uses: Vec::new(),
+            sym_table: SymbolTable::dummy(),
+            bound: HashMap::new(),
+            scope_count: 0,
+        };
+
+        // Globals are bound first, since functions may use names that are defined after them.
+        for (kind, token) in trees.iter().filter_map(definition) {
+            index.bind(token, kind, 0, ((0, 0), (u32::MAX, u32::MAX)));
+        }
+
+        for tree in trees {
+            index.walk(tree, &mut Vec::new());
+        }
+
+        index
+    }
+
+    /// Binds a name in a scope, unless it's already bound there, in which case the first binding
+    /// is kept, as it is by the symbol table.
+    fn bind(&mut self, token: &Token, kind: Kind, scope_id: usize, scope: (Position, Position)) {
+        let key = (scope_id, symbol_name(token).to_string());
+
+        if !self.bound.contains_key(&key) {
+            self.sym_table.register(&key.1, Some(scope_id));
+            self.definitions.push(Definition {
+                name: key.1.clone(),
+                kind,
+                span: token.span,
+                scope,
+            });
+            self.bound.insert(key, self.definitions.len() - 1);
+        }
+    }
+
+    fn refer(&mut self, token: &Token, scope_ids: &Vec<usize>) {
+        if let TokenValue::Symbol(name) = &token.value {
+            let definition = self
+                .sym_table
+                .scope_of(name, Some(scope_ids))
+                .and_then(|id| self.bound.get(&(id, name.clone())).copied());
+
+            self.uses.push(Use {
+                name: name.clone(),
+                span: token.span,
+                definition,
+            });
+        }
+    }
+
+    fn new_scope(&mut self) -> usize {
+        self.scope_count += 1;
+        self.scope_count
+    }
+
+    fn walk(&mut self, tree: &ParseTree, scope_ids: &mut Vec<usize>) {
+        let (elems, tail, scope) = match tree {
+            ParseTree::Leaf(t) => return self.refer(t, scope_ids),
+            ParseTree::Branch(elems, open, close, tail) => {
+                (elems, tail, ((open.line, open.column), (close.line, close.column)))
+            }
+        };
+
+        let head = elems.first().and_then(symbol).map_or("", symbol_name);
+
+        match (head, elems.len()) {
+            ("quote", _) => (),
+            ("quasiquote", 2) => self.unquoted(&elems[1], scope_ids),
+            ("define", n) | ("defun", n) | ("defmacro", n) | ("define-syntax", n) if n >= 2 => {
+                self.walk(&elems[1], scope_ids);
+
+                match head {
+                    "define" => elems[2..].iter().for_each(|e| self.walk(e, scope_ids)),
+                    // The rules of `define-syntax` are patterns, rather than code.
+                    "define-syntax" => (),
+                    _ if n >= 3 => self.function(&elems[2], &elems[3..], scope, scope_ids),
+                    _ => (),
+                }
+            }
+            ("lambda", n) if n >= 2 => self.function(&elems[1], &elems[2..], scope, scope_ids),
+            ("let", n) | ("let*", n) | ("letrec", n) if n >= 2 => {
+                self.bindings(head, &elems[1], &elems[2..], scope, scope_ids)
+            }
+            _ => {
+                for e in elems.iter().chain(tail.as_deref()) {
+                    self.walk(e, scope_ids);
+                }
+            }
+        }
+    }
+
+    /// Walks the unquoted parts of a quasiquoted tree.
+    fn unquoted(&mut self, tree: &ParseTree, scope_ids: &mut Vec<usize>) {
+        if let ParseTree::Branch(elems, _, _, tail) = tree {
+            match elems.first().and_then(symbol).map(symbol_name) {
+                Some("unquote") | Some("unquote-splicing") if elems.len() == 2 => {
+                    self.walk(&elems[1], scope_ids)
+                }
+                _ => {
+                    for e in elems.iter().chain(tail.as_deref()) {
+                        self.unquoted(e, scope_ids);
+                    }
+                }
+            }
+        }
+    }
+
+    fn function(
+        &mut self,
+        params: &ParseTree,
+        body: &[ParseTree],
+        scope: (Position, Position),
+        scope_ids: &mut Vec<usize>,
+    ) {
+        let scope_id = self.new_scope();
+
+        let params: Vec<&Token> = match params {
+            ParseTree::Branch(args, _, _, vararg) => args.iter().chain(vararg.as_deref()).filter_map(symbol).collect(),
+            _ => Vec::new(),
+        };
+
+        for param in &params {
+            self.bind(param, Kind::Parameter, scope_id, scope);
+        }
+
+        scope_ids.push(scope_id);
+
+        for param in params {
+            self.refer(param, scope_ids);
+        }
+
+        body.iter().for_each(|e| self.walk(e, scope_ids));
+        scope_ids.pop();
+    }
+
+    /// Walks a `let`, `let*`, or `letrec`. Their initializers see the same names as they do when
+    /// the form is validated.
+    fn bindings(
+        &mut self,
+        form: &str,
+        bindings: &ParseTree,
+        body: &[ParseTree],
+        scope: (Position, Position),
+        scope_ids: &mut Vec<usize>,
+    ) {
+        let bindings: Vec<(&Token, Option<&ParseTree>)> = match bindings {
+            ParseTree::Branch(bindings, ..) => bindings
+                .iter()
+                .filter_map(|b| match b {
+                    ParseTree::Branch(pair, ..) => pair.first().and_then(symbol).map(|t| (t, pair.get(1))),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let depth = scope_ids.len();
+
+        match form {
+            "let*" => {
+                for (name, init) in bindings {
+                    init.iter().for_each(|e| self.walk(e, scope_ids));
+
+                    let scope_id = self.new_scope();
+                    self.bind(name, Kind::Local, scope_id, scope);
+                    scope_ids.push(scope_id);
+                    self.refer(name, scope_ids);
+                }
+            }
+            _ => {
+                if form == "let" {
+                    bindings.iter().filter_map(|b| b.1).for_each(|e| self.walk(e, scope_ids));
+                }
+
+                let scope_id = self.new_scope();
+
+                for (name, _) in &bindings {
+                    self.bind(name, Kind::Local, scope_id, scope);
+                }
+
+                scope_ids.push(scope_id);
+
+                for (name, _) in &bindings {
+                    self.refer(name, scope_ids);
+                }
+
+                if form == "letrec" {
+                    bindings.iter().filter_map(|b| b.1).for_each(|e| self.walk(e, scope_ids));
+                }
+            }
+        }
+
+        body.iter().for_each(|e| self.walk(e, scope_ids));
+        scope_ids.truncate(depth);
+    }
+
+    /// The symbol at a position, which may be just after its last character.
+    fn at(&self, (line, column): Position) -> Option<&Use> {
+        self.uses
+            .iter()
+            .find(|u| u.span.line == line && u.span.column <= column && column <= u.span.column + u.span.length)
+    }
+
+    /// The definitions that can be used at a position. Where several have the same name, the
+    /// innermost one is kept.
+    fn in_scope(&self, position: Position) -> Vec<&Definition> {
+        let mut visible: Vec<&Definition> = self
+            .definitions
+            .iter()
+            .filter(|d| d.scope.0 <= position && position <= d.scope.1)
+            .collect();
+
+        visible.sort_by_key(|d| d.scope.0);
+
+        let mut by_name = HashMap::new();
+        for d in visible {
+            by_name.insert(d.name.as_str(), d);
+        }
+
+        by_name.into_values().collect()
+    }
+}
+
+/// An open file, and its lines, for converting positions.
+struct Document {
+    uri: String,
+    text: String,
+}
+
+impl Document {
+    fn lines(&self) -> Vec<&str> {
+        self.text.split('\n').collect()
+    }
+
+    fn index(&self) -> Index {
+        let mut diagnostics = Diagnostics::new(0);
+        let tokens = lex::tokenize(&self.text, &mut diagnostics);
+        Index::new(&parse::parse_all(&tokens, &mut diagnostics))
+    }
+
+    /// Converts a line and column to a position in the protocol, which counts lines from 0, and
+    /// characters in UTF-16 code units.
+    fn position(&self, (line, column): Position) -> JsonValue {
+        let text = match line {
+            0 => "",
+            n => self.lines().get(n as usize - 1).copied().unwrap_or(""),
+        };
+
+        let character: usize = text
+            .chars()
+            .take(column.saturating_sub(1) as usize)
+            .map(char::len_utf16)
+            .sum();
+
+        object! { line: line.saturating_sub(1), character: character }
+    }
+
+    fn range(&self, span: Span) -> JsonValue {
+        object! {
+            start: self.position((span.line, span.column)),
+            end: self.position((span.line, span.column + span.length)),
+        }
+    }
+
+    fn location(&self, span: Span) -> JsonValue {
+        object! { uri: self.uri.clone(), range: self.range(span) }
+    }
+
+    /// Converts a position in the protocol to a line and column.
+    fn line_and_column(&self, position: &JsonValue) -> Option<Position> {
+        let line = position["line"].as_u32()?;
+        let character = position["character"].as_usize()?;
+        let text = self.lines().get(line as usize).copied()?;
+
+        let mut units = 0;
+        let mut column = 1;
+
+        for c in text.chars() {
+            if units >= character {
+                break;
+            }
+
+            units += c.len_utf16();
+            column += 1;
+        }
+
+        Some((line + 1, column))
+    }
+
+    /// Compiles the file up to translation, returning every error.
+    fn check(&self, sym_table: &SymbolTable) -> Vec<Diagnostic> {
+        let options = CompileOptions { error_limit: 0, keep_dead_code: true, ..CompileOptions::default() };
+        let compiler = Compiler::with_symbol_table(options, sym_table.clone()).unwrap();
+
+        match compiler.analyze(&[Source::new("<lsp>", &self.text)]) {
+            Ok(_) => Vec::new(),
+            Err(compilation) => compilation.diagnostics,
+        }
+    }
+
+    fn diagnostic(&self, d: &Diagnostic) -> JsonValue {
+        let mut message = d.message.clone();
+
+        for note in &d.notes {
+            message += &format!("\nnote: {}", note);
+        }
+
+        object! {
+            range: self.range(d.span),
+            severity: match d.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+                Severity::Note => 3,
+            },
+            code: d.code,
+            source: "lispc",
+            message: message,
+        }
+    }
+}
+
+struct Server<W: Write> {
+    // The natives, which every file is checked against.
+    sym_table: SymbolTable,
+    documents: HashMap<String, Document>,
+    output: W,
+    initialized: bool,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, message: JsonValue) -> io::Result<()> {
+        let body = message.dump();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, id: JsonValue, result: Result<JsonValue, Error>) -> io::Result<()> {
+        match result {
+            Ok(result) => self.send(object! { jsonrpc: "2.0", id: id, result: result }),
+            Err((code, message)) => self.send(object! {
+                jsonrpc: "2.0",
+                id: id,
+                error: { code: code, message: message },
+            }),
+        }
+    }
+
+    /// Checks a file, and sends its errors to the client.
+    fn publish(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(doc) => doc.check(&self.sym_table).iter().map(|d| doc.diagnostic(d)).collect(),
+            None => Vec::new(),
+        };
+
+        self.send(object! {
+            jsonrpc: "2.0",
+            method: "textDocument/publishDiagnostics",
+            params: { uri: uri, diagnostics: JsonValue::Array(diagnostics) },
+        })
+    }
+
+    /// Handles a message, returning the status to exit with if it's time to stop.
+    fn handle(&mut self, message: &JsonValue) -> io::Result<Option<i32>> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+
+        // Notifications have no ID, and get no response.
+        if message["id"].is_null() {
+            match method {
+                "exit" => return Ok(Some(if self.shutdown { 0 } else { 1 })),
+                "textDocument/didOpen" => {
+                    let text = params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                    self.documents.insert(uri.clone(), Document { uri: uri.clone(), text });
+                    self.publish(&uri)?;
+                }
+                // Files are synchronized in full, so the last change holds the whole text.
+                "textDocument/didChange" => {
+                    let text = params["contentChanges"].members().last().and_then(|c| c["text"].as_str());
+
+                    if let (Some(doc), Some(text)) = (self.documents.get_mut(&uri), text) {
+                        doc.text = text.to_string();
+                    }
+                }
+                "textDocument/didSave" => {
+                    if let (Some(doc), Some(text)) = (self.documents.get_mut(&uri), params["text"].as_str()) {
+                        doc.text = text.to_string();
+                    }
+
+                    self.publish(&uri)?;
+                }
+                "textDocument/didClose" => {
+                    self.documents.remove(&uri);
+                    self.publish(&uri)?;
+                }
+                _ => (),
+            }
+
+            return Ok(None);
+        }
+
+        let result = if self.shutdown {
+            Err((INVALID_REQUEST, "The server is shutting down.".to_string()))
+        } else if !self.initialized && method != "initialize" {
+            Err((SERVER_NOT_INITIALIZED, "The server hasn't been initialized.".to_string()))
+        } else {
+            match method {
+                "initialize" => {
+                    self.initialized = true;
+                    Ok(capabilities())
+                }
+                "shutdown" => {
+                    self.shutdown = true;
+                    Ok(JsonValue::Null)
+                }
+                "textDocument/definition" => self.definition(params),
+                "textDocument/references" => self.references(params),
+                "textDocument/hover" => self.hover(params),
+                "textDocument/completion" => self.completion(params),
+                _ => Err((METHOD_NOT_FOUND, format!("Unknown method `{}`.", method))),
+            }
+        };
+
+        self.respond(message["id"].clone(), result)?;
+        Ok(None)
+    }
+
+    /// Finds the file, and the position in it, that a request is about.
+    fn document(&self, params: &JsonValue) -> Result<(&Document, Position), Error> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+
+        let doc = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("`{}` isn't open.", uri)))?;
+
+        let position = doc
+            .line_and_column(&params["position"])
+            .ok_or_else(|| (INVALID_PARAMS, "The position isn't in the file.".to_string()))?;
+
+        Ok((doc, position))
+    }
+
+    fn definition(&self, params: &JsonValue) -> Result<JsonValue, Error> {
+        let (doc, position) = self.document(params)?;
+        let index = doc.index();
+
+        Ok(match index.at(position).and_then(|u| u.definition) {
+            Some(i) => doc.location(index.definitions[i].span),
+            None => JsonValue::Null,
+        })
+    }
+
+    fn references(&self, params: &JsonValue) -> Result<JsonValue, Error> {
+        let (doc, position) = self.document(params)?;
+        let index = doc.index();
+        let declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+
+        let i = match index.at(position).and_then(|u| u.definition) {
+            Some(i) => i,
+            None => return Ok(JsonValue::Null),
+        };
+
+        let definition = index.definitions[i].span;
+
+        Ok(JsonValue::Array(
+            index
+                .uses
+                .iter()
+                .filter(|u| u.definition == Some(i) && (declaration || u.span != definition))
+                .map(|u| doc.location(u.span))
+                .collect(),
+        ))
+    }
+
+    fn hover(&self, params: &JsonValue) -> Result<JsonValue, Error> {
+        let (doc, position) = self.document(params)?;
+        let index = doc.index();
+
+        let symbol = match index.at(position) {
+            Some(u) => u,
+            None => return Ok(JsonValue::Null),
+        };
+
+        let name = &symbol.name;
+
+        let text = match (symbol.definition, self.sym_table.get_fn(name), self.sym_table.get(name, None)) {
+            (Some(i), ..) => {
+                let d = &index.definitions[i];
+                format!("`{}` is a {}, bound on line {}.", name, d.kind.describe(), d.span.line)
+            }
+            (None, Some(c), _) => format!("`{}` is a native function, implemented by `{}` in liblisp.", name, c),
+            (None, None, Some(c)) => format!("`{}` is a native variable, named `{}` in liblisp.", name, c),
+            _ => return Ok(JsonValue::Null),
+        };
+
+        Ok(object! {
+            contents: { kind: "markdown", value: text },
+            range: doc.range(symbol.span),
+        })
+    }
+
+    fn completion(&self, params: &JsonValue) -> Result<JsonValue, Error> {
+        let (doc, position) = self.document(params)?;
+        let index = doc.index();
+
+        let mut items: Vec<(String, u32, String)> = self
+            .sym_table
+            .natives()
+            .map(|(name, c)| (name.clone(), COMPLETION_FUNCTION, format!("native, implemented by `{}`", c)))
+            .chain(
+                self.sym_table
+                    .globals()
+                    .map(|(name, c)| (name.clone(), COMPLETION_VARIABLE, format!("native, named `{}`", c))),
+            )
+            .collect();
+
+        for d in index.in_scope(position) {
+            let kind = match d.kind {
+                Kind::Function | Kind::Macro => COMPLETION_FUNCTION,
+                _ => COMPLETION_VARIABLE,
+            };
+
+            items.retain(|(name, ..)| *name != d.name);
+            items.push((d.name.clone(), kind, d.kind.describe().to_string()));
+        }
+
+        items.sort();
+
+        Ok(JsonValue::Array(
+            items
+                .into_iter()
+                .map(|(label, kind, detail)| object! { label: label, kind: kind, detail: detail })
+                .collect(),
+        ))
+    }
+}
+
+fn capabilities() -> JsonValue {
+    object! {
+        capabilities: {
+            // Changes send the whole file, and saves include it.
+            textDocumentSync: { openClose: true, change: 1, save: { includeText: true } },
+            definitionProvider: true,
+            referencesProvider: true,
+            hoverProvider: true,
+            completionProvider: { triggerCharacters: ["("] },
+        },
+        serverInfo: { name: "lispc", version: env!("CARGO_PKG_VERSION") },
+    }
+}
+
+/// Reads a message, which is a header giving its length, followed by JSON. Returns `None` at the
+/// end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "a message has no length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Serves requests until the client asks the server to exit, or closes the input. Returns the
+/// status to exit with, which is only 0 if the client shut the server down first.
+pub fn serve(mut input: impl BufRead, output: impl Write, sym_table: SymbolTable) -> i32 {
+    let mut server = Server {
+        sym_table,
+        documents: HashMap::new(),
+        output,
+        initialized: false,
+        shutdown: false,
+    };
+
+    loop {
+        let handled = match read_message(&mut input) {
+            Ok(Some(message)) => match json::parse(&message) {
+                Ok(message) => server.handle(&message),
+                Err(e) => server
+                    .respond(JsonValue::Null, Err((PARSE_ERROR, e.to_string())))
+                    .map(|_| None),
+            },
+            Ok(None) => break,
+            Err(e) => Err(e),
+        };
+
+        match handled {
+            Ok(Some(status)) => return status,
+            Ok(None) => (),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        }
+    }
+
+    if server.shutdown {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lsp::*;
+    use std::io::Cursor;
+
+    const URI: &str = "file:///test.lisp";
+
+    fn frame(messages: &[JsonValue]) -> Vec<u8> {
+        let mut input = Vec::new();
+
+        for m in messages {
+            let body = m.dump();
+            input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes());
+        }
+
+        input
+    }
+
+    /// Runs a session with the messages, after initializing it and opening a file, and returns
+    /// the status and every message sent back, leaving out the response to `initialize`.
+    fn session(text: &str, messages: &[JsonValue]) -> (i32, Vec<JsonValue>) {
+        let mut all = vec![
+            object! { jsonrpc: "2.0", id: 0, method: "initialize", params: {} },
+            object! {
+                jsonrpc: "2.0",
+                method: "textDocument/didOpen",
+                params: { textDocument: { uri: URI, languageId: "lisp", version: 1, text: text } },
+            },
+        ];
+        all.extend(messages.iter().cloned());
+
+        let mut output = Vec::new();
+        let sym_table = SymbolTable::load(None).unwrap();
+        let status = serve(Cursor::new(frame(&all)), &mut output, sym_table);
+
+        let mut input = Cursor::new(output);
+        let mut sent = Vec::new();
+
+        while let Some(message) = read_message(&mut input).unwrap() {
+            sent.push(json::parse(&message).unwrap());
+        }
+
+        (status, sent.split_off(1))
+    }
+
+    fn request(id: u32, method: &str, line: u32, character: u32) -> JsonValue {
+        object! {
+            jsonrpc: "2.0",
+            id: id,
+            method: method,
+            params: {
+                textDocument: { uri: URI },
+                position: { line: line, character: character },
+                context: { includeDeclaration: true },
+            },
+        }
+    }
+
+    fn range(line: u32, start: u32, end: u32) -> JsonValue {
+        object! {
+            start: { line: line, character: start },
+            end: { line: line, character: end },
+        }
+    }
+
+    #[test]
+    fn lifecycle() {
+        let requests = [
+            object! { jsonrpc: "2.0", id: 1, method: "workspace/symbol", params: {} },
+            object! { jsonrpc: "2.0", id: 2, method: "shutdown" },
+            object! { jsonrpc: "2.0", id: 3, method: "shutdown" },
+            object! { jsonrpc: "2.0", method: "exit" },
+        ];
+
+        let (status, sent) = session("", &requests);
+        assert_eq!(0, status);
+        assert_eq!(METHOD_NOT_FOUND, sent[1]["error"]["code"]);
+        assert_eq!(JsonValue::Null, sent[2]["result"]);
+        assert_eq!(INVALID_REQUEST, sent[3]["error"]["code"]);
+
+        // Exiting without shutting down first is an error, as is closing the input.
+        assert_eq!(1, session("", &[object! { jsonrpc: "2.0", method: "exit" }]).0);
+        assert_eq!(1, session("", &[]).0);
+
+        let mut output = Vec::new();
+        let input = frame(&[request(1, "textDocument/hover", 0, 0)]);
+        serve(Cursor::new(input), &mut output, SymbolTable::dummy());
+        let response = json::parse(&read_message(&mut Cursor::new(output)).unwrap().unwrap()).unwrap();
+        assert_eq!(SERVER_NOT_INITIALIZED, response["error"]["code"]);
+    }
+
+    #[test]
+    fn diagnostics_on_open_and_save() {
+        let save = object! {
+            jsonrpc: "2.0",
+            method: "textDocument/didSave",
+            params: { textDocument: { uri: URI }, text: "(define x 1)\n(format x)\n" },
+        };
+
+        let (_, sent) = session("(define x 1)\n(format (+ x y))\n", &[save]);
+
+        assert_eq!("textDocument/publishDiagnostics", sent[0]["method"]);
+        let diagnostics = &sent[0]["params"]["diagnostics"];
+        assert_eq!(1, diagnostics.len());
+        assert_eq!("E0401", diagnostics[0]["code"]);
+        assert_eq!(range(1, 13, 14), diagnostics[0]["range"]);
+
+        // The error is fixed when the file is saved.
+        assert_eq!(URI, sent[1]["params"]["uri"]);
+        assert!(sent[1]["params"]["diagnostics"].is_empty());
+    }
+
+    #[test]
+    fn definitions_and_references() {
+        let text = "(define x 1)\n(defun f (y . ys) (+ x y))\n(f x)\n";
+
+        let (_, sent) = session(
+            text,
+            &[
+                request(1, "textDocument/definition", 1, 21),
+                request(2, "textDocument/definition", 1, 24),
+                request(3, "textDocument/references", 0, 8),
+                request(4, "textDocument/definition", 2, 1),
+                request(5, "textDocument/definition", 1, 19),
+            ],
+        );
+
+        assert_eq!(range(0, 8, 9), sent[1]["result"]["range"]);
+        assert_eq!(range(1, 10, 11), sent[2]["result"]["range"]);
+
+        let references: Vec<&JsonValue> = sent[3]["result"].members().map(|l| &l["range"]).collect();
+        assert_eq!(vec![&range(0, 8, 9), &range(1, 21, 22), &range(2, 3, 4)], references);
+
+        // Functions may be used before they're defined, but natives aren't defined in the file.
+        assert_eq!(range(1, 7, 8), sent[4]["result"]["range"]);
+        assert_eq!(JsonValue::Null, sent[5]["result"]);
+    }
+
+    #[test]
+    fn scopes() {
+        let text = "(define x 1)\n(define f (lambda (x) x))\n(let* ((a x) (b a)) (letrec ((c c)) b))\n'x\n";
+
+        let (_, sent) = session(
+            text,
+            &[
+                request(1, "textDocument/definition", 1, 22),
+                request(2, "textDocument/definition", 2, 10),
+                request(3, "textDocument/definition", 2, 17),
+                request(4, "textDocument/definition", 2, 33),
+                request(5, "textDocument/definition", 2, 37),
+                request(6, "textDocument/definition", 3, 1),
+            ],
+        );
+
+        // A parameter shadows a global, but only in the lambda's body.
+        assert_eq!(range(1, 19, 20), sent[1]["result"]["range"]);
+        assert_eq!(range(0, 8, 9), sent[2]["result"]["range"]);
+
+        // Each binding of a `let*` sees the ones before it, and a `letrec` binding sees itself.
+        assert_eq!(range(2, 8, 9), sent[3]["result"]["range"]);
+        assert_eq!(range(2, 30, 31), sent[4]["result"]["range"]);
+        assert_eq!(range(2, 14, 15), sent[5]["result"]["range"]);
+
+        // Quoted symbols aren't names.
+        assert_eq!(JsonValue::Null, sent[6]["result"]);
+    }
+
+    #[test]
+    fn hover() {
+        let (_, sent) = session(
+            "(define f 1)\n(lambda (n) (+ n 1))\n",
+            &[
+                request(1, "textDocument/hover", 1, 14),
+                request(2, "textDocument/hover", 1, 15),
+                request(3, "textDocument/hover", 1, 20),
+            ],
+        );
+
+        assert_eq!(
+            "`+` is a native function, implemented by `add` in liblisp.",
+            sent[1]["result"]["contents"]["value"]
+        );
+        assert_eq!(range(1, 13, 14), sent[1]["result"]["range"]);
+        assert_eq!("`n` is a parameter, bound on line 2.", sent[2]["result"]["contents"]["value"]);
+        assert_eq!(JsonValue::Null, sent[3]["result"]);
+    }
+
+    #[test]
+    fn completion() {
+        let text = "(defun f (arg) (let ((local 1)) ))\n(define g 2)\n";
+
+        let (_, sent) = session(
+            text,
+            &[
+                request(1, "textDocument/completion", 0, 32),
+                request(2, "textDocument/completion", 1, 12),
+            ],
+        );
+
+        let labels = |response: &JsonValue| -> Vec<String> {
+            response["result"].members().map(|i| i["label"].to_string()).collect()
+        };
+
+        let inside = labels(&sent[1]);
+        for name in &["car", "funcall", "f", "g", "arg", "local"] {
+            assert!(inside.contains(&name.to_string()), "`{}` is missing from {:?}", name, inside);
+        }
+
+        let outside = labels(&sent[2]);
+        assert!(outside.contains(&"f".to_string()) && !outside.contains(&"arg".to_string()));
+
+        let arg = sent[1]["result"].members().find(|i| i["label"] == "arg").unwrap();
+        assert_eq!(COMPLETION_VARIABLE, arg["kind"]);
+        assert_eq!("parameter", arg["detail"]);
+    }
+
+    #[test]
+    fn positions_count_utf16() {
+        // The emoji takes two UTF-16 code units, but is one character in a span.
+        let text = "(define λx 1)\n(format \"😀\" λx)\n";
+
+        let (_, sent) = session(
+            text,
+            &[request(1, "textDocument/definition", 1, 14), request(2, "textDocument/hover", 1, 14)],
+        );
+
+        assert_eq!(range(0, 8, 10), sent[1]["result"]["range"]);
+        assert_eq!(range(1, 13, 15), sent[2]["result"]["range"]);
+    }
+}
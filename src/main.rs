@@ -1,104 +1,346 @@
-#[macro_use]
-extern crate nom;
+use crate::cli::{Command, Options};
+use lispc::build::{self, BuildError, Toolchain};
+use lispc::diagnostic::Diagnostics;
+#[cfg(unix)]
+use lispc::repl;
+use lispc::{interp, lsp, pretty, Compilation, Compiler, Diagnostic, Program, Source, SymbolTable};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::{env, fs, io, process, thread};
 
-use crate::ast::*;
-use std::{env, fs};
+mod cli;
 
-mod ast;
-mod lex;
-mod parse;
-mod transpile;
+// Exit codes, for scripts that run the compiler.
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_TOOLCHAIN: i32 = 4;
+// The status that compiled programs exit with after a runtime error, since liblisp calls `exit(-1)`.
+const EXIT_RUNTIME_ERROR: i32 = 255;
+
+// Recursion in an interpreted program is recursion in the interpreter, which uses far more stack
+// for each call than compiled code does.
+const INTERP_STACK_SIZE: usize = 512 * 1024 * 1024;
 
 fn main() {
-    // foo();
-    let programs: Vec<String> = env::args().collect();
-    // let programs = vec!["(format 3i)"];
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let options = match cli::parse_args(&args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("error: {}\n\nFor more information, try `--help`.", e);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    if options.help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+
+    let mut sources = Vec::new();
+
+    for name in &options.inputs {
+        match read_source(name) {
+            Ok(contents) => sources.push(Source {
+                name: if name == "-" { "<stdin>".to_string() } else { name.clone() },
+                contents,
+            }),
+            Err(e) => {
+                eprintln!("error: couldn't read `{}`: {}", name, e);
+                process::exit(EXIT_IO);
+            }
+        }
+    }
+
+    if options.command == Command::Fmt {
+        process::exit(format_sources(&sources, &options));
+    }
+
+    let compiler = match Compiler::new(options.compile_options()) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(EXIT_IO);
+        }
+    };
+
+    if options.command == Command::Repl {
+        process::exit(start_repl(&sources, &options, compiler.symbol_table().clone()));
+    }
+
+    if options.command == Command::Lsp {
+        process::exit(lsp::serve(io::stdin().lock(), io::stdout(), compiler.symbol_table().clone()));
+    }
+
+    if options.command == Command::Interp {
+        match compiler.analyze(&sources) {
+            Ok(program) => {
+                let names = sources.iter().map(|s| s.name.clone()).collect();
+                process::exit(interpret(program, names));
+            }
+            Err(compilation) => report(&sources, &compilation),
+        }
+    }
+
+    let compilation = compiler.compile(&sources);
+    report_passes(&compilation);
+    report(&sources, &compilation);
+
+    if options.check {
+        return;
+    }
+
+    let output = compilation.output.unwrap_or_default();
+
+    if let (Some(path), Some(map)) = (&options.source_map, compilation.source_map) {
+        if let Err(e) = fs::write(path, map + "\n") {
+            eprintln!("error: couldn't write `{}`: {}", path, e);
+            process::exit(EXIT_IO);
+        }
+    }
+
+    if options.command != Command::Compile {
+        process::exit(build(&output, &options, &sources));
+    }
+
+    let written = match &options.output {
+        None => writeln!(io::stdout(), "{}", output),
+        Some(path) => fs::write(path, output + "\n"),
+    };
+
+    match written {
+        // The reader of a pipe, like `head`, is allowed to stop early.
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            let path = options.output.as_deref().unwrap_or("stdout");
+            eprintln!("error: couldn't write `{}`: {}", path, e);
+            process::exit(EXIT_IO);
+        }
+        _ => (),
+    }
+}
+
+/// Shows every error found while compiling, exiting if there are any.
+fn report(sources: &[Source], compilation: &Compilation) {
+    if !compilation.is_ok() {
+        let errors = &compilation.diagnostics;
 
-    for program in &programs[1..] {
-        let contents = fs::read_to_string(program).expect("Something went wrong reading the file");
-        println!("{}", run(contents.as_str()));
+        for d in errors {
+            let source = &sources[d.span.source as usize];
+            eprintln!("{}", d.clone().with_file(&source.name).render(&source.contents));
+        }
+
+        if compilation.limited {
+            eprintln!(
+                "error: aborting after reaching the limit of {} error(s); use `--error-limit` to report more",
+                errors.len()
+            );
+        } else if errors.len() > 1 {
+            eprintln!("error: aborting due to {} previous errors", errors.len());
+        }
+
+        process::exit(EXIT_COMPILE_ERROR);
+    }
+}
+
+/// Shows the dumps and timings asked for by `--dump-after` and `--time-passes`.
+fn report_passes(compilation: &Compilation) {
+    for (pass, dump) in &compilation.dumps {
+        eprintln!("=== AST after {} ===\n{}", pass, dump);
+    }
+
+    for (stage, time) in &compilation.timings {
+        eprintln!("time: {:>10.3}ms  {}", time.as_secs_f64() * 1000.0, stage);
     }
 }
 
-fn examine(program: &str) -> Result<(), (u32, String)> {
-    println!("########## Initial Program ##########\n{}", program);
+/// Builds the generated C into an executable, and runs it for `lispc run`. Returns the status to
+/// exit with.
+fn build(c: &str, options: &Options, sources: &[Source]) -> i32 {
+    let toolchain = Toolchain::from_env();
+
+    // The generated C, and the executable for `run`, are kept in a directory that's removed
+    // afterwards.
+    let dir = env::temp_dir().join(format!("lispc-{}", process::id()));
 
-    // let tokens = lex::start("(format (* 1 2 3))  (format 17i) (format 1.28) (format (+ 6 7 (* 2 7)))").unwrap();
-    let tokens = lex::start(program).unwrap();
-    let parse_tree = parse::parse(&tokens).unwrap();
+    let exe = match (&options.output, options.command) {
+        (Some(path), _) => Path::new(path).to_path_buf(),
+        (None, Command::Run) => dir.join("main"),
+        (None, _) => match Path::new(&options.inputs[0]).file_stem() {
+            Some(stem) if options.inputs[0] != "-" => Path::new(stem).to_path_buf(),
+            _ => Path::new("a.out").to_path_buf(),
+        },
+    };
 
-    let mut output = String::new();
+    // The C file is named after the executable, so that the C compiler's messages make sense.
+    let c_file = dir.join(exe.file_name().unwrap_or_default()).with_extension("c");
 
-    for tree in parse_tree.as_slice() {
-        output.push_str(format!("{:?}", tree).as_str());
-        output.push('\n');
-        output.push('\n');
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("error: couldn't create `{}`: {}", dir.display(), e);
+        return EXIT_IO;
     }
 
-    println!("\n########## Parse Tree ##########\n{}", output);
+    let status = match toolchain.build(c, &c_file, &exe) {
+        Ok(()) if options.command == Command::Run => match build::run(&exe) {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("error: couldn't run `{}`: {}", exe.display(), e);
+                EXIT_IO
+            }
+        },
+        Ok(()) => 0,
+        Err(BuildError::Io(e)) => {
+            eprintln!("error: {}", e);
+            EXIT_TOOLCHAIN
+        }
+        Err(BuildError::Rejected(errors)) => {
+            let c_name = c_file.display().to_string();
 
-    let cnde = ConditionUnroll;
-    let fne = FunctionUnfurl;
-    let sv = SymbolValidation;
-    let mut sym_table = SymbolTable::dummy();
-    let ast = ast::construct_ast(&parse_tree)?;
+            for d in errors {
+                eprintln!("{}", render_c_error(d, &c_name, c, sources));
+            }
 
-    println!("\n########## Initial AST ##########\n{:#?}", ast);
+            EXIT_COMPILE_ERROR
+        }
+    };
 
-    let mut preliminary_pass = Vec::new();
+    let _ = fs::remove_dir_all(&dir);
 
-    for line in ast {
-        preliminary_pass.append(&mut cnde.try_visit(&line, &mut sym_table)?);
+    status
+}
+
+/// Loads the sources into a new REPL, then reads the rest from stdin. Returns the status to exit
+/// with.
+#[cfg(unix)]
+fn start_repl(sources: &[Source], options: &Options, sym_table: SymbolTable) -> i32 {
+    let mut repl = match repl::Repl::new(sym_table, options.trampoline, options.error_limit) {
+        Ok(repl) => repl,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return EXIT_TOOLCHAIN;
+        }
+    };
+
+    for source in sources {
+        if !repl.eval(&source.name, &source.contents) {
+            return EXIT_COMPILE_ERROR;
+        }
     }
 
-    println!(
-        "\n########## AST After Conditional Unroll ##########\n{:#?}",
-        preliminary_pass
-    );
+    repl.start();
+    0
+}
+
+/// Formats each source file in place, or writes it to stdout if it was read from stdin. With
+/// `--check`, nothing is written, and files that aren't formatted are listed instead. Returns the
+/// status to exit with.
+fn format_sources(sources: &[Source], options: &Options) -> i32 {
+    let mut status = 0;
+
+    for (source, path) in sources.iter().zip(&options.inputs) {
+        let mut diagnostics = Diagnostics::new(options.error_limit);
+        let formatted = pretty::format_source(&source.contents, &mut diagnostics);
+
+        if !diagnostics.is_empty() {
+            for d in diagnostics.into_vec() {
+                eprintln!("{}", d.with_file(&source.name).render(&source.contents));
+            }
 
-    let mut secondary_pass = Vec::new();
+            status = EXIT_COMPILE_ERROR;
+            continue;
+        }
 
-    for line in preliminary_pass {
-        secondary_pass.append(&mut fne.try_visit(&line, &mut sym_table)?);
+        let written = if options.check {
+            if formatted != source.contents {
+                println!("{}", source.name);
+                status = EXIT_COMPILE_ERROR;
+            }
+
+            Ok(())
+        } else if path == "-" {
+            write!(io::stdout(), "{}", formatted)
+        } else if formatted != source.contents {
+            fs::write(path, formatted)
+        } else {
+            Ok(())
+        };
+
+        if let Err(e) = written {
+            eprintln!("error: couldn't write `{}`: {}", source.name, e);
+            return EXIT_IO;
+        }
+    }
+
+    status
+}
+
+/// Runs the program with the interpreter, on a thread with room for deep recursion. Returns the
+/// status to exit with, which is the same as the compiled program's.
+fn interpret(program: Program, sources: Vec<String>) -> i32 {
+    let interpreter = thread::Builder::new()
+        .stack_size(INTERP_STACK_SIZE)
+        .spawn(move || {
+            let stdout = io::stdout();
+            let mut out = io::BufWriter::new(stdout.lock());
+
+            let mut interpreter = interp::Interpreter::new(program.sym_table, &mut out);
+            interpreter.set_sources(sources);
+
+            match interpreter.run(&program.ast) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    EXIT_RUNTIME_ERROR
+                }
+            }
+        });
+
+    match interpreter.map(|t| t.join()) {
+        Ok(Ok(status)) => status,
+        // The panic has already been reported.
+        Ok(Err(_)) => EXIT_RUNTIME_ERROR,
+        Err(e) => {
+            eprintln!("error: couldn't start the interpreter: {}", e);
+            EXIT_IO
+        }
     }
+}
 
-    println!(
-        "\n########## AST After Function Unrolling ##########\n{:#?}",
-        secondary_pass
-    );
+#[cfg(not(unix))]
+fn start_repl(_sources: &[Source], _options: &Options, _sym_table: SymbolTable) -> i32 {
+    eprintln!("error: the REPL is only supported on Unix");
+    EXIT_TOOLCHAIN
+}
 
-    let mut tertiary_pass = Vec::new();
+/// Shows an error from the C compiler against the LISP source that it points to, if there is one,
+/// and otherwise against the generated C.
+fn render_c_error(mut d: Diagnostic, c_name: &str, c: &str, sources: &[Source]) -> String {
+    let file = d.file.clone().unwrap_or_default();
 
-    for line in secondary_pass {
-        tertiary_pass.push(sv.try_visit(&line, &mut sym_table)?);
+    // A `#line` directive only gives the line, so the column is in the C, and means nothing here.
+    if let Some(source) = sources.iter().find(|s| s.name == file) {
+        d.span.column = 0;
+
+        return d
+            .with_note("the generated C is invalid, which is a bug in lispc")
+            .render(&source.contents);
     }
 
-    println!("\n########## Final AST ##########\n{:#?}", tertiary_pass);
+    if file == c_name {
+        return d
+            .with_note("the generated C is invalid, which is a bug in lispc")
+            .render(c);
+    }
 
-    Ok(())
+    d.render("")
 }
 
-fn run(program: &str) -> String {
-    let mut sym_table = SymbolTable::load(None);
-    let sv = SymbolValidation;
-    let fne = FunctionUnfurl;
-    let ce = ConditionUnroll;
-
-    let tokens = lex::start(program).unwrap();
-    let parse_tree = parse::parse(&tokens).unwrap();
-    let ast = ast::construct_ast(&parse_tree).unwrap();
-
-    let ast: Vec<ASTNode> = ast
-        .iter()
-        .map(|n| ce.visit(n, &mut sym_table))
-        .flatten()
-        .collect();
-    let ast: Vec<ASTNode> = ast
-        .iter()
-        .map(|n| fne.visit(n, &mut sym_table))
-        .flatten()
-        .collect();
-    let ast = ast.iter().map(|n| sv.visit(n, &mut sym_table)).collect();
-    let mut transpiler = transpile::Transpiler::new(sym_table);
-    transpiler.translate(&ast)
+fn read_source(name: &str) -> io::Result<String> {
+    if name == "-" {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        Ok(contents)
+    } else {
+        fs::read_to_string(name)
+    }
 }
@@ -1,77 +1,212 @@
+use crate::diagnostic::{Diagnostic, Diagnostics, Span, MALFORMED_LIST, UNBALANCED_LIST};
 use crate::lex::{Token, TokenValue};
 use crate::parse::ParseTree::Branch;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ParseTree {
     Leaf(Token),
-    Branch(Vec<ParseTree>, u32, u32, Option<Box<ParseTree>>),
+    // elements, opening span, closing span, tail following `.`
+    Branch(Vec<ParseTree>, Span, Span, Option<Box<ParseTree>>),
 }
 
-pub fn parse(tokens: &Vec<Token>) -> Result<Vec<ParseTree>, (u32, String)> {
+impl ParseTree {
+    /// The position of the first character of the tree.
+    pub fn span(&self) -> Span {
+        match self {
+            ParseTree::Leaf(t) => t.span(),
+            ParseTree::Branch(_, start, _, _) => *start,
+        }
+    }
+
+    /// A copy of the tree without any positions, for comparing trees read from different text.
+    pub fn without_spans(&self) -> ParseTree {
+        match self {
+            ParseTree::Leaf(t) => ParseTree::Leaf(Token::from(t.value())),
+            ParseTree::Branch(elems, _, _, tail) => ParseTree::Branch(
+                elems.iter().map(ParseTree::without_spans).collect(),
+                Span::default(),
+                Span::default(),
+                tail.as_ref().map(|t| Box::new(t.without_spans())),
+            ),
+        }
+    }
+}
+
+/// Writes the tree as source code. Line information is not preserved.
+impl std::fmt::Display for ParseTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseTree::Leaf(t) => write!(f, "{}", t.value()),
+            ParseTree::Branch(elems, _, _, tail) => {
+                let mut parts: Vec<String> = elems.iter().map(|e| e.to_string()).collect();
+
+                if let Some(t) = tail {
+                    parts.push(format!(". {}", t));
+                }
+
+                write!(f, "({})", parts.join(" "))
+            }
+        }
+    }
+}
+
+pub fn parse(tokens: &[Token]) -> Result<Vec<ParseTree>, Diagnostic> {
+    let mut diagnostics = Diagnostics::new(1);
+    let statements = parse_all(tokens, &mut diagnostics);
+
+    diagnostics.into_result(statements)
+}
+
+/// Parses every top level form. After an error, parsing resumes at the next `(` found at the start
+/// of a line, since it most likely begins a new top level form.
+pub fn parse_all(tokens: &[Token], diagnostics: &mut Diagnostics) -> Vec<ParseTree> {
     let mut statements: Vec<ParseTree> = Vec::new();
-    let mut t = &tokens[..];
+    let mut t = tokens;
 
-    while !t.is_empty() {
-        let r = statement(t)?;
-        t = r.1;
-        statements.push(r.0);
+    while !t.is_empty() && !diagnostics.is_full() {
+        match statement(t) {
+            Ok((tree, rest)) => {
+                t = rest;
+                statements.push(tree);
+            }
+            Err(d) => {
+                diagnostics.report(d);
+
+                let resume = t[1..]
+                    .iter()
+                    .position(|x| x.value == TokenValue::Open && x.span.column == 1)
+                    .map_or(t.len(), |i| i + 1);
+
+                t = &t[resume..];
+            }
+        }
     }
 
-    Ok(statements)
+    statements
 }
 
 // The following auxiliary functions expect at least one token to be present.
-fn statement(tokens: &[Token]) -> Result<(ParseTree, &[Token]), (u32, String)> {
+fn statement(tokens: &[Token]) -> Result<(ParseTree, &[Token]), Diagnostic> {
     let rest = &tokens[1..];
 
     match tokens[0].value() {
-        TokenValue::Open => list(rest, tokens[0].line()),
-        TokenValue::Close => Err((tokens[0].line(), "Unexpected end of list.".to_string())),
+        TokenValue::Open => list(rest, tokens[0].span()),
+        TokenValue::Close => Err(Diagnostic::error(
+            UNBALANCED_LIST,
+            tokens[0].span(),
+            "Unexpected end of list.".to_string(),
+        )),
+        // A `.` inside of a list is handled by `list`, so anywhere else it's out of place.
+        TokenValue::Cons => Err(Diagnostic::error(
+            MALFORMED_LIST,
+            tokens[0].span(),
+            "Expected a value, found `.`.".to_string(),
+        )),
+        TokenValue::Quote => quoted("quote", rest, tokens[0].span()),
+        TokenValue::Quasiquote => quoted("quasiquote", rest, tokens[0].span()),
+        TokenValue::Unquote => quoted("unquote", rest, tokens[0].span()),
+        TokenValue::UnquoteSplicing => quoted("unquote-splicing", rest, tokens[0].span()),
         _ => Ok((ParseTree::Leaf(tokens[0].clone()), rest)),
     }
 }
 
-fn list(tokens: &[Token], start_line: u32) -> Result<(ParseTree, &[Token]), (u32, String)> {
+/// Expand reader shorthands like `'x` into their full form, `(quote x)`.
+fn quoted<'a>(
+    form: &str,
+    tokens: &'a [Token],
+    span: Span,
+) -> Result<(ParseTree, &'a [Token]), Diagnostic> {
+    if tokens.is_empty() {
+        return Err(Diagnostic::error(
+            MALFORMED_LIST,
+            span,
+            format!("Expected a value following `{}`.", form),
+        ));
+    }
+
+    let (tree, rest) = statement(tokens)?;
+
+    let stop = match &tree {
+        ParseTree::Leaf(t) => t.span(),
+        ParseTree::Branch(_, _, stop, _) => *stop,
+    };
+
+    let symbol = ParseTree::Leaf(Token {
+        span,
+        value: TokenValue::Symbol(form.to_string()),
+    });
+
+    Ok((Branch(vec![symbol, tree], span, stop, None), rest))
+}
+
+fn list(tokens: &[Token], start: Span) -> Result<(ParseTree, &[Token]), Diagnostic> {
     let mut vals: Vec<ParseTree> = Vec::new();
-    let mut t = &tokens[..];
+    let mut t = tokens;
 
     while !t.is_empty() {
         if t[0].value() == TokenValue::Close {
             return Ok((
-                ParseTree::Branch(vals, start_line, t[0].line(), None),
+                ParseTree::Branch(vals, start, t[0].span(), None),
                 &t[1..],
             ));
         } else if t[0].value() == TokenValue::Cons {
-            // Handle it.
+            if t.len() == 1 {
+                break;
+            }
+
             let (consed, rest) = statement(&t[1..])?;
             if rest.is_empty() {
-                return Err((t[0].line(), String::from("Expected EOF.")));
+                return Err(Diagnostic::error(
+                    UNBALANCED_LIST,
+                    start,
+                    String::from("Expected EOF."),
+                )
+                .with_note("the list is never closed"));
             } else if rest[0].value() != TokenValue::Close {
-                return Err((
-                    t[0].line(),
+                return Err(Diagnostic::error(
+                    MALFORMED_LIST,
+                    t[0].span(),
                     String::from("Expected end of list following cons."),
                 ));
             }
             return Ok((
-                Branch(vals, start_line, rest[0].line(), Some(Box::new(consed))),
+                Branch(vals, start, rest[0].span(), Some(Box::new(consed))),
                 &rest[1..],
             ));
         }
 
-        let r = statement(t)?;
+        // A nested list can only be unbalanced by running out of tokens, in which case the
+        // outermost list is the one reported, since that's where parsing resumes.
+        let r = statement(t).map_err(|mut d| {
+            if d.code == UNBALANCED_LIST {
+                d.span = start;
+            }
+            d
+        })?;
 
         t = r.1;
         vals.push(r.0);
     }
 
-    Err((0, "Unexpected EOF at end of list.".to_string()))
+    Err(Diagnostic::error(
+        UNBALANCED_LIST,
+        start,
+        "Unexpected EOF at end of list.".to_string(),
+    )
+    .with_note("the list is never closed"))
 }
 
 #[cfg(test)]
 mod test {
+    use crate::diagnostic::{Diagnostics, Span, MALFORMED_LIST, UNBALANCED_LIST};
     use crate::lex::{start, Token, TokenValue::*};
     use crate::parse::ParseTree::*;
-    use crate::parse::parse;
+    use crate::parse::{parse, parse_all};
+
+    /// The span of a single character, like a parenthesis.
+    fn at(line: u32, column: u32) -> Span {
+        Span::new(line, column, 1)
+    }
 
     #[test]
     fn single_terminal() {
@@ -90,7 +225,7 @@ mod test {
         let x = parse(&tokens).unwrap();
 
         assert_eq!(x.len(), 1);
-        assert_eq!(x[0], Branch(Vec::new(), 0, 0, None));
+        assert_eq!(x[0], Branch(Vec::new(), Span::default(), Span::default(), None));
     }
 
     #[test]
@@ -108,13 +243,13 @@ mod test {
         assert_eq!(x.len(), 1);
 
         match &x[0] {
-            Branch(x, 0, 0, None) => {
+            Branch(x, start, stop, None) if *start == Span::default() && *stop == Span::default() => {
                 assert_eq!(x.len(), 3);
                 assert_eq!(x[0], Leaf(Token::from(Symbol("+".to_string()))));
                 assert_eq!(x[1], Leaf(Token::from(Int(16))));
                 assert_eq!(x[2], Leaf(Token::from(Int(4))));
             }
-            _ => assert!(false),
+            _ => panic!(),
         }
     }
 
@@ -135,16 +270,18 @@ mod test {
 
         assert_eq!(x.len(), 1);
 
+        let none = Span::default();
+
         match &x[0] {
-            Branch(x, 0, 0, None) => {
+            Branch(x, start, stop, None) if *start == none && *stop == none => {
                 assert_eq!(x.len(), 2);
-                assert_eq!(x[0], Branch(Vec::new(), 0, 0, None));
+                assert_eq!(x[0], Branch(Vec::new(), none, none, None));
                 assert_eq!(
                     x[1],
-                    Branch(vec!(Branch(Vec::new(), 0, 0, None)), 0, 0, None)
+                    Branch(vec!(Branch(Vec::new(), none, none, None)), none, none, None)
                 );
             }
-            _ => assert!(false),
+            _ => panic!(),
         }
     }
 
@@ -158,14 +295,14 @@ mod test {
         assert_eq!(x[1], Leaf(tokens[1].clone()));
         assert_eq!(x[2], Leaf(tokens[2].clone()));
         match &x[3] {
-            Branch(x, 1, 1, None) => {
+            Branch(x, start, stop, None) if *start == at(1, 10) && *stop == at(1, 26) => {
                 assert_eq!(x.len(), 4);
                 assert_eq!(x[0], Leaf(tokens[4].clone()));
                 assert_eq!(x[1], Leaf(tokens[5].clone()));
                 assert_eq!(x[2], Leaf(tokens[6].clone()));
 
                 match &x[3] {
-                    Branch(x, 1, 1, None) => {
+                    Branch(x, start, stop, None) if *start == at(1, 19) && *stop == at(1, 25) => {
                         assert_eq!(x.len(), 3);
 
                         assert_eq!(x[0], Leaf(tokens[8].clone()));
@@ -179,7 +316,7 @@ mod test {
         }
 
         match &x[4] {
-            Branch(x, 1, 1, None) => {
+            Branch(x, start, stop, None) if *start == at(1, 28) && *stop == at(1, 29) => {
                 assert_eq!(x.len(), 0);
             }
             _ => panic!(),
@@ -189,7 +326,7 @@ mod test {
     #[test]
     #[should_panic]
     fn fails_unbalanced_parens() {
-        parse(&vec![
+        parse(&[
             Token::from(Open),
             Token::from(Open),
             Token::from(Close),
@@ -206,10 +343,10 @@ mod test {
         assert_eq!(
             Branch(
                 Vec::new(),
-                1,
-                1,
+                at(1, 1),
+                at(1, 6),
                 Some(Box::new(Leaf(Token {
-                    line: 1,
+                    span: Span::new(1, 4, 2),
                     value: Symbol("zs".to_string())
                 })))
             ),
@@ -223,19 +360,20 @@ mod test {
         let x = parse(&tokens).unwrap();
         assert_eq!(1, x.len());
 
-        if let Branch(lambda_expr, 1, 1, None) = &x[0] {
+        if let Branch(lambda_expr, start, stop, None) = &x[0] {
+            assert_eq!((at(1, 1), at(1, 15)), (*start, *stop));
             assert_eq!(3, lambda_expr.len());
             assert_eq!(
                 &Leaf(Token {
-                    line: 1,
+                    span: Span::new(1, 2, 6),
                     value: Symbol("lambda".to_string())
                 }),
                 &lambda_expr[0]
             );
-            assert_eq!(&Branch(Vec::new(), 1, 1, None), &lambda_expr[1]);
+            assert_eq!(&Branch(Vec::new(), at(1, 9), at(1, 10), None), &lambda_expr[1]);
             assert_eq!(
                 &Leaf(Token {
-                    line: 1,
+                    span: Span::new(1, 12, 3),
                     value: Nil
                 }),
                 &lambda_expr[2]
@@ -252,7 +390,8 @@ mod test {
 
         assert_eq!(1, x.len());
 
-        if let Branch(args, 1, 1, Some(t)) = &x[0] {
+        if let Branch(args, start, stop, Some(t)) = &x[0] {
+            assert_eq!((at(1, 1), at(1, 13)), (*start, *stop));
             assert_eq!(4, args.len());
             match t.as_ref() {
                 Leaf(t) if t.value() == Symbol("e".to_string()) => (),
@@ -284,6 +423,100 @@ mod test {
         parse(&tokens).unwrap();
     }
 
+    #[test]
+    fn quote_shorthand() {
+        let tokens = start("'(a ,b ,@c)").unwrap();
+        let x = parse(&tokens).unwrap();
+
+        assert_eq!(1, x.len());
+
+        // The expanded symbols take the position of the shorthand.
+        let symbol = |s: &str, column: u32, length: u32| {
+            Leaf(Token {
+                span: Span::new(1, column, length),
+                value: Symbol(s.to_string()),
+            })
+        };
+
+        assert_eq!(
+            Branch(
+                vec![
+                    symbol("quote", 1, 1),
+                    Branch(
+                        vec![
+                            symbol("a", 3, 1),
+                            Branch(vec![symbol("unquote", 5, 1), symbol("b", 6, 1)], at(1, 5), at(1, 6), None),
+                            Branch(
+                                vec![symbol("unquote-splicing", 8, 2), symbol("c", 10, 1)],
+                                Span::new(1, 8, 2),
+                                at(1, 10),
+                                None
+                            ),
+                        ],
+                        at(1, 2),
+                        at(1, 11),
+                        None
+                    ),
+                ],
+                at(1, 1),
+                at(1, 11),
+                None
+            ),
+            x[0]
+        );
+    }
+
+    #[test]
+    fn display() {
+        let tokens = start("(define x '(1 \"two\" (:three) . four))").unwrap();
+        let x = parse(&tokens).unwrap();
+
+        assert_eq!(
+            "(define x (quote (1 \"two\" (:three) . four)))",
+            x[0].to_string()
+        );
+    }
+
+    #[test]
+    fn unclosed_list_reports_its_start() {
+        let error = parse(&start("(a)\n  (b (c)\n").unwrap()).unwrap_err();
+
+        assert_eq!(UNBALANCED_LIST, error.code);
+        assert_eq!(at(2, 3), error.span);
+    }
+
+    #[test]
+    fn resume_after_errors() {
+        let tokens = start("(a))\n(b (c)\n(d) (e\n(f)").unwrap();
+        let mut diagnostics = Diagnostics::new(0);
+        let trees = parse_all(&tokens, &mut diagnostics);
+
+        // The stray `)` skips the rest of its line, and the unclosed list ends at the next line
+        // starting with `(`.
+        let names: Vec<String> = trees.iter().map(|t| t.to_string()).collect();
+        assert_eq!(vec!["(a)", "(d)", "(f)"], names);
+
+        let errors = diagnostics.into_vec();
+        assert_eq!(3, errors.len());
+        assert_eq!(at(1, 4), errors[0].span);
+        assert_eq!(at(2, 1), errors[1].span);
+        assert_eq!(at(3, 5), errors[2].span);
+    }
+
+    #[test]
+    fn dangling_quote() {
+        assert!(parse(&start("(a ')").unwrap()).is_err());
+        assert!(parse(&start("`").unwrap()).is_err());
+    }
+
+    #[test]
+    fn misplaced_cons() {
+        assert_eq!(MALFORMED_LIST, parse(&start(".").unwrap()).unwrap_err().code);
+        assert_eq!(MALFORMED_LIST, parse(&start("(a ,. b)").unwrap()).unwrap_err().code);
+        assert_eq!(MALFORMED_LIST, parse(&start("(a . . b)").unwrap()).unwrap_err().code);
+        assert_eq!(UNBALANCED_LIST, parse(&start("(a .").unwrap()).unwrap_err().code);
+    }
+
     #[test]
     #[should_panic]
     fn non_continued_vararg_list() {
@@ -0,0 +1,478 @@
+use crate::ast::{
+    ASTNode, ASTVisitor, ConditionUnroll, FunctionUnfurl, SymbolTable, SymbolValidation, TailCallMarking,
+};
+use crate::dce::DeadCodeElimination;
+use crate::diagnostic::Diagnostics;
+use crate::fold::ConstantFolding;
+use std::time::{Duration, Instant};
+
+/// A property of the AST that one pass establishes, and that later passes may rely on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    // Every condition is an `ExpandedCondition`, whose branches are lists of nodes.
+    ConditionsUnrolled,
+    // No call, or `recur`, has a call as an argument. Each nested call is moved into a
+    // `function_unwrap` temporary that's defined right before the call that uses it.
+    FunctionsUnfurled,
+    // Every variable refers to a definition that's in scope.
+    SymbolsValidated,
+    // Every function that's called is defined somewhere in the program.
+    CallsValidated,
+    // Calls in tail position are `TailCall`s, and `recur` is only used in tail position.
+    TailCallsMarked,
+}
+
+/// The properties that `Transpiler::translate` relies on.
+pub const TRANSLATABLE: &[Property] = &[
+    Property::ConditionsUnrolled,
+    Property::FunctionsUnfurled,
+    Property::SymbolsValidated,
+    Property::CallsValidated,
+    Property::TailCallsMarked,
+];
+
+/// A transformation of the whole program, between the construction of the AST and its translation.
+pub trait Pass {
+    /// The name that `--dump-after` and `--time-passes` know the pass by.
+    fn name(&self) -> &str;
+
+    /// The properties that must hold before the pass runs.
+    fn requires(&self) -> &[Property] {
+        &[]
+    }
+
+    /// The properties that hold once the pass has run, in addition to the ones that held before.
+    fn provides(&self) -> &[Property] {
+        &[]
+    }
+
+    /// The properties that no longer hold once the pass has run.
+    fn invalidates(&self) -> &[Property] {
+        &[]
+    }
+
+    /// Runs the pass over every top level node. Errors are reported, and the forms that cause them
+    /// are left out of the result.
+    fn run(&self, ast: Vec<ASTNode>, sym_table: &mut SymbolTable, diagnostics: &mut Diagnostics) -> Vec<ASTNode>;
+}
+
+/// Visits every top level node, reporting the errors, and keeping the results of the nodes that
+/// don't have any.
+fn visit_all<T>(
+    visitor: &impl ASTVisitor<T>,
+    ast: &[ASTNode],
+    sym_table: &mut SymbolTable,
+    diagnostics: &mut Diagnostics,
+) -> Vec<T> {
+    let mut visited = Vec::new();
+
+    for n in ast {
+        match visitor.try_visit(n, sym_table) {
+            Ok(result) => visited.push(result),
+            Err(d) => diagnostics.report(d),
+        }
+    }
+
+    visited
+}
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &str {
+        "constant-folding"
+    }
+
+    fn run(&self, ast: Vec<ASTNode>, sym_table: &mut SymbolTable, diagnostics: &mut Diagnostics) -> Vec<ASTNode> {
+        // Earlier errors leave stand-ins behind, like the `nil` in place of a token that couldn't
+        // be read, which would only be reported again as calls that always fail. Folding is an
+        // optimization, so it waits until they're fixed.
+        if !diagnostics.is_empty() {
+            return ast;
+        }
+
+        visit_all(self, &ast, sym_table, diagnostics)
+    }
+}
+
+impl Pass for ConditionUnroll {
+    fn name(&self) -> &str {
+        "condition-unroll"
+    }
+
+    fn provides(&self) -> &[Property] {
+        &[Property::ConditionsUnrolled]
+    }
+
+    fn run(&self, ast: Vec<ASTNode>, sym_table: &mut SymbolTable, diagnostics: &mut Diagnostics) -> Vec<ASTNode> {
+        visit_all(self, &ast, sym_table, diagnostics).into_iter().flatten().collect()
+    }
+}
+
+impl Pass for FunctionUnfurl {
+    fn name(&self) -> &str {
+        "function-unfurl"
+    }
+
+    fn requires(&self) -> &[Property] {
+        &[Property::ConditionsUnrolled]
+    }
+
+    fn provides(&self) -> &[Property] {
+        &[Property::FunctionsUnfurled]
+    }
+
+    fn run(&self, ast: Vec<ASTNode>, sym_table: &mut SymbolTable, diagnostics: &mut Diagnostics) -> Vec<ASTNode> {
+        visit_all(self, &ast, sym_table, diagnostics).into_iter().flatten().collect()
+    }
+}
+
+impl Pass for SymbolValidation {
+    fn name(&self) -> &str {
+        "symbol-validation"
+    }
+
+    fn requires(&self) -> &[Property] {
+        &[Property::FunctionsUnfurled]
+    }
+
+    fn provides(&self) -> &[Property] {
+        &[Property::SymbolsValidated]
+    }
+
+    fn run(&self, ast: Vec<ASTNode>, sym_table: &mut SymbolTable, diagnostics: &mut Diagnostics) -> Vec<ASTNode> {
+        let mut validated = Vec::new();
+
+        for n in &ast {
+            match self.validate(n, sym_table) {
+                Ok(node) => validated.push(node),
+                Err(errors) => errors.into_iter().for_each(|d| diagnostics.report(d)),
+            }
+        }
+
+        validated
+    }
+}
+
+/// Reports calls to functions that aren't defined anywhere in the program. This is separate from
+/// `SymbolValidation`, since it can only run once every definition has been registered.
+pub struct CallValidation;
+
+impl Pass for CallValidation {
+    fn name(&self) -> &str {
+        "call-validation"
+    }
+
+    fn requires(&self) -> &[Property] {
+        &[Property::SymbolsValidated]
+    }
+
+    fn provides(&self) -> &[Property] {
+        &[Property::CallsValidated]
+    }
+
+    fn run(&self, ast: Vec<ASTNode>, sym_table: &mut SymbolTable, diagnostics: &mut Diagnostics) -> Vec<ASTNode> {
+        let mut called = Vec::new();
+
+        for n in ast {
+            let errors = SymbolValidation.undefined_calls(&n, sym_table);
+
+            if errors.is_empty() {
+                called.push(n);
+            } else {
+                errors.into_iter().for_each(|d| diagnostics.report(d));
+            }
+        }
+
+        called
+    }
+}
+
+impl Pass for TailCallMarking {
+    fn name(&self) -> &str {
+        "tail-call-marking"
+    }
+
+    fn requires(&self) -> &[Property] {
+        &[Property::ConditionsUnrolled, Property::FunctionsUnfurled]
+    }
+
+    fn provides(&self) -> &[Property] {
+        &[Property::TailCallsMarked]
+    }
+
+    fn run(&self, ast: Vec<ASTNode>, sym_table: &mut SymbolTable, diagnostics: &mut Diagnostics) -> Vec<ASTNode> {
+        visit_all(self, &ast, sym_table, diagnostics)
+    }
+}
+
+impl Pass for DeadCodeElimination {
+    fn name(&self) -> &str {
+        "dead-code-elimination"
+    }
+
+    fn requires(&self) -> &[Property] {
+        &[Property::ConditionsUnrolled, Property::FunctionsUnfurled, Property::SymbolsValidated]
+    }
+
+    fn run(&self, ast: Vec<ASTNode>, _: &mut SymbolTable, _: &mut Diagnostics) -> Vec<ASTNode> {
+        self.eliminate(ast)
+    }
+}
+
+/// What was recorded while the passes ran, besides the AST.
+#[derive(Clone, Debug, Default)]
+pub struct PassRecord {
+    /// The AST after each pass named by `PassManager::set_dump_after`, by the name of the pass.
+    pub dumps: Vec<(String, String)>,
+    /// How long each pass took, in the order they ran, if `PassManager::set_time_passes` is on.
+    pub timings: Vec<(String, Duration)>,
+}
+
+/// Runs a list of passes in order, making sure that each one runs after the passes it relies on.
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    // The properties that must hold after the last pass.
+    finally: Vec<Property>,
+    dump_after: Vec<String>,
+    time_passes: bool,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PassManager {
+    /// Creates a manager with no passes, which can be added in any order that satisfies them.
+    pub fn new() -> Self {
+        PassManager { passes: Vec::new(), finally: Vec::new(), dump_after: Vec::new(), time_passes: false }
+    }
+
+    /// Creates a manager with the passes that every program goes through before it's translated.
+    /// Passes that are inserted must leave the AST translatable.
+    pub fn standard() -> Self {
+        let mut manager = Self::new();
+        manager.passes.push(Box::new(ConstantFolding));
+        manager.passes.push(Box::new(ConditionUnroll));
+        manager.passes.push(Box::new(FunctionUnfurl));
+        manager.passes.push(Box::new(SymbolValidation));
+        manager.passes.push(Box::new(CallValidation));
+        manager.passes.push(Box::new(TailCallMarking));
+        manager.finally = TRANSLATABLE.to_vec();
+        manager
+    }
+
+    /// Creates a manager with the standard passes, followed by the removal of dead code. Programs
+    /// that are compiled in pieces, like in the REPL, can't use it, since a later piece may use
+    /// a definition that looks dead.
+    pub fn optimized() -> Self {
+        let mut manager = Self::standard();
+        manager.passes.push(Box::new(DeadCodeElimination));
+        manager
+    }
+
+    /// The names of the passes, in the order they run.
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    /// Adds a pass to run after every other.
+    pub fn add(&mut self, pass: Box<dyn Pass>) -> Result<(), String> {
+        self.insert(self.passes.len(), pass)
+    }
+
+    /// Adds a pass to run right before the one named.
+    pub fn insert_before(&mut self, name: &str, pass: Box<dyn Pass>) -> Result<(), String> {
+        let i = self.position(name)?;
+        self.insert(i, pass)
+    }
+
+    /// Adds a pass to run right after the one named.
+    pub fn insert_after(&mut self, name: &str, pass: Box<dyn Pass>) -> Result<(), String> {
+        let i = self.position(name)?;
+        self.insert(i + 1, pass)
+    }
+
+    /// Writes out the AST after each of the passes named.
+    pub fn set_dump_after(&mut self, names: Vec<String>) -> Result<(), String> {
+        for name in &names {
+            self.position(name)?;
+        }
+
+        self.dump_after = names;
+        Ok(())
+    }
+
+    /// Measures how long each pass takes.
+    pub fn set_time_passes(&mut self, time_passes: bool) {
+        self.time_passes = time_passes;
+    }
+
+    /// Runs every pass over the program.
+    pub fn run(&self, ast: &[ASTNode], sym_table: &mut SymbolTable, diagnostics: &mut Diagnostics) -> Vec<ASTNode> {
+        self.run_recorded(ast, sym_table, diagnostics, &mut PassRecord::default())
+    }
+
+    /// Runs every pass over the program, adding the dumps and timings asked for to `record`.
+    pub fn run_recorded(
+        &self,
+        ast: &[ASTNode],
+        sym_table: &mut SymbolTable,
+        diagnostics: &mut Diagnostics,
+        record: &mut PassRecord,
+    ) -> Vec<ASTNode> {
+        let mut ast = ast.to_vec();
+
+        for pass in &self.passes {
+            let start = Instant::now();
+            ast = pass.run(ast, sym_table, diagnostics);
+
+            if self.time_passes {
+                record.timings.push((pass.name().to_string(), start.elapsed()));
+            }
+
+            if self.dump_after.iter().any(|n| n == pass.name()) {
+                let dump = ast.iter().map(|n| format!("{:#?}", n)).collect::<Vec<String>>().join("\n");
+                record.dumps.push((pass.name().to_string(), dump));
+            }
+        }
+
+        ast
+    }
+
+    fn position(&self, name: &str) -> Result<usize, String> {
+        self.passes
+            .iter()
+            .position(|p| p.name() == name)
+            .ok_or_else(|| format!("unknown pass `{}`, expected one of {}", name, self.names().join(", ")))
+    }
+
+    fn insert(&mut self, i: usize, pass: Box<dyn Pass>) -> Result<(), String> {
+        if self.passes.iter().any(|p| p.name() == pass.name()) {
+            return Err(format!("there's already a pass named `{}`", pass.name()));
+        }
+
+        self.passes.insert(i, pass);
+
+        match self.check() {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.passes.remove(i);
+                Err(e)
+            }
+        }
+    }
+
+    /// Checks that each pass only runs once the properties it requires hold, and that the
+    /// properties required at the end still hold.
+    fn check(&self) -> Result<(), String> {
+        let mut holding = Vec::new();
+
+        for pass in &self.passes {
+            if let Some(p) = pass.requires().iter().find(|p| !holding.contains(*p)) {
+                return Err(format!("`{}` requires {:?}, which doesn't hold before it", pass.name(), p));
+            }
+
+            holding.retain(|p| !pass.invalidates().contains(p));
+            for p in pass.provides() {
+                if !holding.contains(p) {
+                    holding.push(*p);
+                }
+            }
+        }
+
+        match self.finally.iter().find(|p| !holding.contains(*p)) {
+            Some(p) => Err(format!("{:?} doesn't hold after the last pass", p)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::test_utils::force_from;
+    use crate::passes::*;
+
+    /// Does nothing, but claims whatever it's told to.
+    struct Noop {
+        name: &'static str,
+        requires: &'static [Property],
+        invalidates: &'static [Property],
+    }
+
+    impl Pass for Noop {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn requires(&self) -> &[Property] {
+            self.requires
+        }
+
+        fn invalidates(&self) -> &[Property] {
+            self.invalidates
+        }
+
+        fn run(&self, ast: Vec<ASTNode>, _: &mut SymbolTable, _: &mut Diagnostics) -> Vec<ASTNode> {
+            ast
+        }
+    }
+
+    fn noop(name: &'static str, requires: &'static [Property], invalidates: &'static [Property]) -> Box<dyn Pass> {
+        Box::new(Noop { name, requires, invalidates })
+    }
+
+    #[test]
+    fn standard_order() {
+        let manager = PassManager::standard();
+
+        assert_eq!(
+            vec![
+                "constant-folding",
+                "condition-unroll",
+                "function-unfurl",
+                "symbol-validation",
+                "call-validation",
+                "tail-call-marking",
+            ],
+            manager.names()
+        );
+        assert_eq!(Ok(()), manager.check());
+    }
+
+    #[test]
+    fn preconditions() {
+        let mut manager = PassManager::standard();
+
+        assert!(manager.insert_before("condition-unroll", noop("a", &[Property::ConditionsUnrolled], &[])).is_err());
+        assert!(manager.insert_after("condition-unroll", noop("a", &[Property::ConditionsUnrolled], &[])).is_ok());
+        assert!(manager.add(noop("a", &[], &[])).is_err());
+        assert!(manager.insert_after("missing", noop("b", &[], &[])).is_err());
+
+        // The transpiler can't handle calls nested in the arguments of other calls.
+        assert!(manager.add(noop("b", &[], &[Property::FunctionsUnfurled])).is_err());
+        assert_eq!(7, manager.names().len());
+
+        let mut manager = PassManager::new();
+        assert!(manager.add(Box::new(FunctionUnfurl)).is_err());
+        assert!(manager.add(Box::new(ConditionUnroll)).is_ok());
+        assert!(manager.add(Box::new(FunctionUnfurl)).is_ok());
+    }
+
+    #[test]
+    fn dumps_and_timings() {
+        let mut manager = PassManager::standard();
+        manager.set_time_passes(true);
+        assert!(manager.set_dump_after(vec!["eval".to_string()]).is_err());
+        manager.set_dump_after(vec!["function-unfurl".to_string()]).unwrap();
+
+        let mut record = PassRecord::default();
+        let ast = force_from("(define f (lambda (x) x))");
+        manager.run_recorded(&ast, &mut SymbolTable::dummy(), &mut Diagnostics::new(0), &mut record);
+
+        assert_eq!(6, record.timings.len());
+        assert_eq!(1, record.dumps.len());
+        assert_eq!("function-unfurl", record.dumps[0].0);
+        assert!(record.dumps[0].1.contains("Lambda"));
+    }
+}
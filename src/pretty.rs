@@ -0,0 +1,520 @@
+use crate::diagnostic::{Diagnostic, Diagnostics, Span, INTERNAL};
+use crate::lex::{self, Comment, Token, TokenValue};
+use crate::parse::{self, ParseTree};
+
+/// Lines are only broken to fit in this many characters, so longer atoms may still go past it.
+pub const WIDTH: usize = 100;
+
+/// Special forms, and how many of their arguments go on the first line. The rest are a body,
+/// indented by two spaces.
+const BODY_FORMS: [(&str, usize); 11] = [
+    ("define", 1),
+    ("defun", 2),
+    ("defmacro", 2),
+    ("define-syntax", 1),
+    ("syntax-rules", 1),
+    ("lambda", 1),
+    ("let", 1),
+    ("let*", 1),
+    ("letrec", 1),
+    ("begin", 0),
+    ("progn", 0),
+];
+
+// A line and column, which orders anything in the source.
+type Position = (u32, u32);
+
+/// How the elements of a list are indented when it's split across lines.
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    // A special form with this many arguments before its body.
+    Body(usize),
+    // A call, with its arguments lined up after the first.
+    Call,
+    // Anything else, like the bindings of a `let`, with every element lined up.
+    Data,
+}
+
+/// Something written inside of a list, or at the top level.
+#[derive(Clone, Copy)]
+enum Item<'a> {
+    Tree(&'a ParseTree),
+    // The tree following a `.`.
+    Tail(&'a ParseTree),
+    Comment(&'a Comment),
+}
+
+impl Item<'_> {
+    fn start(&self) -> Position {
+        match self {
+            Item::Tree(t) | Item::Tail(t) => start(t),
+            Item::Comment(c) => (c.span.line, c.span.column),
+        }
+    }
+
+    fn end(&self) -> Position {
+        match self {
+            Item::Tree(t) | Item::Tail(t) => end(t),
+            Item::Comment(c) => (c.span.line, c.span.column),
+        }
+    }
+}
+
+fn start(tree: &ParseTree) -> Position {
+    let span = tree.span();
+    (span.line, span.column)
+}
+
+/// Whether a list in the tree has an element on a later line than the one before it ends, so
+/// that the tree is written on several lines even if it would fit on one.
+fn broken(tree: &ParseTree) -> bool {
+    if let Some(quoted) = shorthand(tree) {
+        return broken(quoted);
+    }
+
+    match tree {
+        ParseTree::Leaf(_) => false,
+        ParseTree::Branch(elems, open, _, tail) => {
+            let mut previous = (open.line, open.column);
+
+            for elem in elems.iter().chain(tail.as_deref()) {
+                if start(elem).0 > previous.0 || broken(elem) {
+                    return true;
+                }
+
+                previous = end(elem);
+            }
+
+            false
+        }
+    }
+}
+
+fn end(tree: &ParseTree) -> Position {
+    match tree {
+        ParseTree::Leaf(t) => (t.span.line, t.span.column + t.span.length.saturating_sub(1)),
+        ParseTree::Branch(_, _, stop, _) => (stop.line, stop.column),
+    }
+}
+
+/// Reader shorthands, like `'x`, are parsed into lists whose head has the position of the
+/// shorthand itself, rather than following a `(`.
+fn shorthand(tree: &ParseTree) -> Option<&ParseTree> {
+    match tree {
+        ParseTree::Branch(elems, start, _, None) if elems.len() == 2 => match &elems[0] {
+            ParseTree::Leaf(Token {
+                span,
+                value: TokenValue::Symbol(s),
+            }) if span == start
+                && ["quote", "quasiquote", "unquote", "unquote-splicing"].contains(&s.as_str()) =>
+            {
+                Some(&elems[1])
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether a shorthand has to be kept apart from what it quotes, as in `, @x`, which would
+/// otherwise be read back as `,@x`.
+fn spaced(prefix: &str, quoted: &ParseTree) -> bool {
+    matches!(quoted, ParseTree::Leaf(Token { value: TokenValue::Symbol(s), .. }) if prefix == "," && s.starts_with('@'))
+}
+
+fn style(elems: &[ParseTree]) -> Style {
+    match elems.first() {
+        Some(ParseTree::Leaf(Token {
+            value: TokenValue::Symbol(s),
+            ..
+        })) => BODY_FORMS
+            .iter()
+            .find(|(name, _)| name == s)
+            .map_or(Style::Call, |(_, n)| Style::Body(*n)),
+        _ => Style::Data,
+    }
+}
+
+/// Writes parse trees back out as source code, taking the text of each token, and the comments,
+/// from the original source. Lists that were written on one line stay that way if they fit, and
+/// the elements of other lists keep to their own lines, but are indented by the usual rules.
+struct Formatter<'a> {
+    // The characters of each line of the source.
+    lines: Vec<Vec<char>>,
+    comments: &'a [Comment],
+    out: String,
+    column: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &str, comments: &'a [Comment]) -> Self {
+        Formatter {
+            lines: source.split('\n').map(|l| l.chars().collect()).collect(),
+            comments,
+            out: String::new(),
+            column: 0,
+        }
+    }
+
+    fn write(&mut self, s: &str) {
+        self.out.push_str(s);
+        self.column += s.chars().count();
+    }
+
+    fn newline(&mut self, indent: usize, blank: bool) {
+        if blank {
+            self.out.push('\n');
+        }
+
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+        self.column = indent;
+    }
+
+    fn text(&self, token: &Token) -> String {
+        let span = token.span;
+
+        self.lines[span.line as usize - 1]
+            .iter()
+            .skip(span.column as usize - 1)
+            .take(span.length as usize)
+            .collect()
+    }
+
+    /// Comments between two positions.
+    fn comments_between(&self, after: Position, before: Position) -> impl Iterator<Item = &'a Comment> {
+        self.comments.iter().filter(move |c| {
+            let at = (c.span.line, c.span.column);
+            after < at && at < before
+        })
+    }
+
+    /// The tree on a single line, if it was written that way and has no comments. Only the `)`s
+    /// that close its lists may be on later lines.
+    fn flat(&self, tree: &ParseTree) -> Option<String> {
+        if broken(tree) || self.comments_between(start(tree), end(tree)).next().is_some() {
+            return None;
+        }
+
+        Some(self.flat_unchecked(tree))
+    }
+
+    fn flat_unchecked(&self, tree: &ParseTree) -> String {
+        if let (Some(quoted), ParseTree::Branch(elems, ..)) = (shorthand(tree), tree) {
+            let prefix = match &elems[0] {
+                ParseTree::Leaf(t) => self.text(t),
+                _ => unreachable!(),
+            };
+
+            let space = if spaced(&prefix, quoted) { " " } else { "" };
+            return prefix + space + &self.flat_unchecked(quoted);
+        }
+
+        match tree {
+            ParseTree::Leaf(t) => self.text(t),
+            ParseTree::Branch(elems, _, _, tail) => {
+                let mut parts: Vec<String> = elems.iter().map(|e| self.flat_unchecked(e)).collect();
+
+                if let Some(t) = tail {
+                    parts.push(format!(". {}", self.flat_unchecked(t)));
+                }
+
+                format!("({})", parts.join(" "))
+            }
+        }
+    }
+
+    /// Whether the tree can be written on one line, starting at a column.
+    fn fits(&self, tree: &ParseTree, column: usize) -> bool {
+        self.flat(tree).is_some_and(|s| column + s.chars().count() <= WIDTH)
+    }
+
+    fn program(mut self, trees: &[ParseTree]) -> String {
+        // Comments inside of a tree are written along with it.
+        let mut items: Vec<Item> = trees.iter().map(Item::Tree).collect();
+        items.extend(
+            self.comments
+                .iter()
+                .filter(|c| {
+                    let at = (c.span.line, c.span.column);
+                    !trees.iter().any(|t| start(t) < at && at < end(t))
+                })
+                .map(Item::Comment),
+        );
+        items.sort_by_key(Item::start);
+
+        let mut previous: Option<Position> = None;
+
+        for item in items {
+            match (item, previous) {
+                // A comment on the same line as the end of a form stays there.
+                (Item::Comment(c), Some(end)) if end.0 == c.span.line => {
+                    self.write(" ");
+                    self.write(&c.text);
+                }
+                (_, Some(end)) => {
+                    self.newline(0, item.start().0 > end.0 + 1);
+                    self.item(item);
+                }
+                (_, None) => self.item(item),
+            }
+
+            previous = Some(item.end());
+        }
+
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+
+        self.out
+    }
+
+    fn item(&mut self, item: Item) {
+        match item {
+            Item::Tree(t) => self.tree(t),
+            Item::Tail(t) => {
+                self.write(". ");
+                self.tree(t);
+            }
+            Item::Comment(c) => self.write(&c.text),
+        }
+    }
+
+    fn tree(&mut self, tree: &ParseTree) {
+        if let Some(s) = self.flat(tree) {
+            if self.column + s.chars().count() <= WIDTH {
+                self.write(&s);
+                return;
+            }
+        }
+
+        if let (Some(quoted), ParseTree::Branch(elems, ..)) = (shorthand(tree), tree) {
+            let prefix = self.flat_unchecked(&elems[0]);
+            self.write(&prefix);
+
+            if spaced(&prefix, quoted) {
+                self.write(" ");
+            }
+
+            // Comments may come between the shorthand and what it quotes.
+            let column = self.column;
+            for comment in self.comments_between(start(tree), start(quoted)) {
+                self.write(&comment.text);
+                self.newline(column, false);
+            }
+
+            self.tree(quoted);
+            return;
+        }
+
+        let one_line = self.flat(tree).is_some();
+
+        match tree {
+            ParseTree::Leaf(t) => {
+                let text = self.text(t);
+                self.write(&text);
+            }
+            ParseTree::Branch(elems, open, close, tail) => {
+                self.list(elems, tail.as_deref(), (*open, *close), one_line)
+            }
+        }
+    }
+
+    /// Writes a list across several lines. `one_line` is whether it was written on one line, and
+    /// so is only being split because it's too long.
+    fn list(&mut self, elems: &[ParseTree], tail: Option<&ParseTree>, (open, close): (Span, Span), one_line: bool) {
+        let column = self.column;
+        let style = style(elems);
+
+        let mut items: Vec<Item> = elems.iter().map(Item::Tree).collect();
+        items.extend(tail.map(Item::Tail));
+        items.extend(
+            self.comments_between((open.line, open.column), (close.line, close.column))
+                .filter(|c| {
+                    let at = (c.span.line, c.span.column);
+                    !items.iter().any(|i| i.start() < at && at < i.end())
+                })
+                .map(Item::Comment)
+                .collect::<Vec<_>>(),
+        );
+        items.sort_by_key(Item::start);
+
+        self.write("(");
+
+        // Where the arguments of a call line up, which is after the head unless the first
+        // argument is on its own line.
+        let mut align = column + 1;
+        let mut previous = (open.line, open.column);
+        let mut after_comment = false;
+        let mut index = 0;
+
+        for item in items {
+            let indent = match (style, index) {
+                (_, 0) => column + 1,
+                (Style::Body(n), i) if i <= n => column + 4,
+                (Style::Body(_), _) => column + 2,
+                (Style::Call, 1) => column + 1,
+                _ => align,
+            };
+
+            let blank = item.start().0 > previous.0 + 1;
+
+            match item {
+                Item::Comment(c) if c.span.line == previous.0 => {
+                    if !self.out.ends_with('(') {
+                        self.write(" ");
+                    }
+
+                    self.write(&c.text);
+                }
+                Item::Comment(c) => {
+                    self.newline(indent, blank);
+                    self.write(&c.text);
+                }
+                Item::Tree(t) | Item::Tail(t) => {
+                    // A body written on the same line as its form only moves when the form is too
+                    // long, in which case each part of it gets its own line.
+                    let body = one_line && matches!(style, Style::Body(n) if index > n);
+                    let moved = !self.fits(t, self.column + 1) && self.fits(t, indent);
+
+                    if after_comment || (index > 0 && (item.start().0 > previous.0 || body || moved)) {
+                        self.newline(indent, blank && index > 0);
+                    } else if index > 0 {
+                        self.write(" ");
+                    }
+
+                    if style == Style::Call && index == 1 {
+                        align = self.column;
+                    }
+
+                    self.item(item);
+                    index += 1;
+                }
+            }
+
+            after_comment = matches!(item, Item::Comment(_));
+            previous = item.end();
+        }
+
+        if after_comment {
+            self.newline(column, false);
+        }
+
+        self.write(")");
+    }
+}
+
+/// Formats a whole file. The result is only valid if no errors were reported. The formatted
+/// source is read back before it's returned, to make sure that it parses into the same trees,
+/// with the same comments.
+pub fn format_source(source: &str, diagnostics: &mut Diagnostics) -> String {
+    let (tokens, comments) = lex::tokenize_lossless(source, diagnostics);
+    let trees = parse::parse_all(&tokens, diagnostics);
+
+    if !diagnostics.is_empty() {
+        return String::new();
+    }
+
+    let formatted = Formatter::new(source, &comments).program(&trees);
+
+    let mut check = Diagnostics::new(1);
+    let (new_tokens, new_comments) = lex::tokenize_lossless(&formatted, &mut check);
+    let new_trees = parse::parse_all(&new_tokens, &mut check);
+
+    let same_trees = trees.iter().map(ParseTree::without_spans).eq(new_trees.iter().map(ParseTree::without_spans));
+    let same_comments = comments.iter().map(|c| &c.text).eq(new_comments.iter().map(|c| &c.text));
+
+    if !check.is_empty() || !same_trees || !same_comments {
+        diagnostics.report(
+            Diagnostic::error(
+                INTERNAL,
+                Span::default(),
+                "Formatting would change the program, so it was left as it is.".to_string(),
+            )
+            .with_note("this is a bug in lispc"),
+        );
+
+        return String::new();
+    }
+
+    formatted
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostic::{Diagnostics, UNBALANCED_LIST};
+    use crate::pretty::*;
+
+    fn format(source: &str) -> String {
+        let mut diagnostics = Diagnostics::new(1);
+        let formatted = format_source(source, &mut diagnostics);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics.into_vec());
+        formatted
+    }
+
+    #[test]
+    fn spacing() {
+        assert_eq!("(define x (+ 1 2))\n", format("  ( define   x\t(+ 1 2 ) )  "));
+        assert_eq!("'(a b . c)\n", format("' ( a b .  c )"));
+        assert_eq!("`(a ,b ,@c)\n", format("`(a , b ,@ c)"));
+        assert_eq!("`(a , @b)\n", format("`(a ,\n@b)"));
+        assert_eq!("", format(""));
+    }
+
+    #[test]
+    fn tokens_keep_their_text() {
+        assert_eq!("(format 1.50 +3 \"a\\tb\" nil)\n", format("(format 1.50 +3 \"a\\tb\" nil)"));
+    }
+
+    #[test]
+    fn indentation() {
+        let source = "(defun fact (n)\n(if (<= n 1)\n1\n(* n (fact (- n 1)))))";
+        let expected = "(defun fact (n)\n  (if (<= n 1)\n      1\n      (* n (fact (- n 1)))))\n";
+        assert_eq!(expected, format(source));
+
+        let source = "(let ((a 1)\n(b 2))\n(+ a b))";
+        let expected = "(let ((a 1)\n      (b 2))\n  (+ a b))\n";
+        assert_eq!(expected, format(source));
+
+        let source = "(f\na\n   b)";
+        assert_eq!("(f\n a\n b)\n", format(source));
+    }
+
+    #[test]
+    fn long_lines_are_broken() {
+        let args = ["argument"; 12].join(" ");
+        let source = format!("(defun f () (g {}) (h))", args);
+        let formatted = format(&source);
+
+        assert!(formatted.lines().all(|l| l.len() <= WIDTH));
+        assert!(formatted.starts_with("(defun f ()\n  (g argument"));
+        assert!(formatted.ends_with("\n  (h))\n"));
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let source = ";; About f.\n(defun f (x) ; the argument\n  ;; Returns it.\n  x)\n\n\n(f 1) ; done\n";
+        let expected = ";; About f.\n(defun f (x) ; the argument\n  ;; Returns it.\n  x)\n\n(f 1) ; done\n";
+        assert_eq!(expected, format(source));
+
+        // A comment before the end of a list leaves the `)` on its own line.
+        assert_eq!("(f a ; last\n)\n", format("(f a ; last\n)"));
+
+        // Or between a shorthand and what it quotes.
+        assert_eq!("',; why\n  x\n", format("',; why\nx"));
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        let source = "(define map-aux (lambda (rec f alist out)\n  (if (eqv (length alist) 0)\n      (reverse out)\n      (funcall rec \n\t       rec\n\t       f))))";
+        let once = format(source);
+        assert_eq!(once, format(&once));
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let mut diagnostics = Diagnostics::new(1);
+        format_source("(define x", &mut diagnostics);
+        assert_eq!(UNBALANCED_LIST, diagnostics.into_vec()[0].code);
+    }
+}
@@ -0,0 +1,385 @@
+use crate::ast::{self, ASTNode, SymbolTable};
+use crate::build::{BuildError, Toolchain};
+use crate::diagnostic::{Diagnostics, UNBALANCED_LIST};
+use crate::expand::MacroExpander;
+use crate::lex::{self, TokenValue};
+use crate::parse;
+use crate::transpile::{Linkage, Transpiler};
+use std::ffi::{CStr, CString};
+use std::io::{self, BufRead, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+const HELP: &str = "\
+Enter forms to run them. Forms may span several lines, and names defined by earlier inputs stay
+defined until the session is reset.
+
+    :ast <FORMS>    Show the AST of the forms, after every pass, without running them
+    :c <FORMS>      Show the C generated for the forms, without running them
+    :reset          Forget every definition
+    :help           Print this message
+    :quit           Leave the REPL, as does the end of input
+";
+
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+    fn dlerror() -> *mut c_char;
+}
+
+const RTLD_NOW: c_int = 2;
+#[cfg(target_os = "macos")]
+const RTLD_GLOBAL: c_int = 0x8;
+#[cfg(not(target_os = "macos"))]
+const RTLD_GLOBAL: c_int = 0x100;
+
+/// A shared object loaded with `dlopen`. Symbols are loaded globally, so that later inputs can
+/// refer to the globals and functions defined by earlier ones.
+struct Library(*mut c_void);
+
+impl Library {
+    fn open(path: &Path) -> Result<Self, String> {
+        let name = CString::new(path.to_string_lossy().as_bytes()).unwrap();
+        let handle = unsafe { dlopen(name.as_ptr(), RTLD_NOW | RTLD_GLOBAL) };
+
+        if handle.is_null() {
+            Err(Self::error())
+        } else {
+            Ok(Library(handle))
+        }
+    }
+
+    /// Finds an entry function made by `Transpiler::translate_entry`.
+    fn entry(&self, name: &str) -> Result<extern "C" fn() -> c_int, String> {
+        let name = CString::new(name).unwrap();
+        let symbol = unsafe { dlsym(self.0, name.as_ptr()) };
+
+        if symbol.is_null() {
+            Err(Self::error())
+        } else {
+            Ok(unsafe { std::mem::transmute::<*mut c_void, extern "C" fn() -> c_int>(symbol) })
+        }
+    }
+
+    fn error() -> String {
+        let message = unsafe { dlerror() };
+
+        if message.is_null() {
+            "unknown error".to_string()
+        } else {
+            unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            dlclose(self.0);
+        }
+    }
+}
+
+/// Everything that carries over from one input to the next. It's only updated once an input has
+/// compiled, so that a mistake doesn't leave anything half defined.
+#[derive(Clone)]
+struct Session {
+    sym_table: SymbolTable,
+    expander: MacroExpander,
+    scope_count: usize,
+    linkage: Linkage,
+}
+
+pub struct Repl {
+    toolchain: Toolchain,
+    // Where each input is built.
+    dir: PathBuf,
+    initial: Session,
+    session: Session,
+    // Inputs are unloaded in reverse, since later inputs depend on earlier ones.
+    inputs: Vec<Library>,
+    // Counts every input built, so that no two share a name, even after a reset.
+    built: usize,
+    trampoline: bool,
+    error_limit: usize,
+    // Loaded last, so that it's closed after every input.
+    _runtime: Library,
+}
+
+impl Repl {
+    pub fn new(sym_table: SymbolTable, trampoline: bool, error_limit: usize) -> Result<Self, String> {
+        let toolchain = Toolchain::from_env();
+
+        let runtime = match toolchain.runtime_library() {
+            Ok(path) => Library::open(&path)?,
+            Err(BuildError::Io(e)) => return Err(e),
+            Err(BuildError::Rejected(_)) => return Err("couldn't build the runtime".to_string()),
+        };
+
+        let dir = env::temp_dir().join(format!("lispc-repl-{}", process::id()));
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("couldn't create `{}`: {}", dir.display(), e))?;
+
+        let session = Session {
+            sym_table,
+            expander: MacroExpander::new(),
+            scope_count: 0,
+            linkage: Linkage::default(),
+        };
+
+        Ok(Repl {
+            toolchain,
+            dir,
+            initial: session.clone(),
+            session,
+            inputs: Vec::new(),
+            built: 0,
+            trampoline,
+            error_limit,
+            _runtime: runtime,
+        })
+    }
+
+    /// Reads inputs from stdin until it ends, or `:quit` is entered.
+    pub fn start(&mut self) {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        println!("lispc REPL. Enter :help for a list of commands.");
+
+        loop {
+            let input = match read_input(&mut lines) {
+                Some(input) => input,
+                None => {
+                    // Leaves the terminal on a new line.
+                    println!();
+                    break;
+                }
+            };
+
+            let trimmed = input.trim();
+
+            if !trimmed.starts_with(':') {
+                self.eval("<repl>", &input);
+                continue;
+            }
+
+            let (command, forms) = match trimmed.find(char::is_whitespace) {
+                Some(i) => (&trimmed[..i], &trimmed[i..]),
+                None => (trimmed, ""),
+            };
+
+            match command {
+                ":ast" => {
+                    if let Some(ast) = self.compile("<repl>", forms, &mut self.session.clone()) {
+                        ast.iter().for_each(|n| println!("{:#?}", n));
+                    }
+                }
+                ":c" => {
+                    let mut session = self.session.clone();
+
+                    if let Some(ast) = self.compile("<repl>", forms, &mut session) {
+                        let mut transpiler = Transpiler::new(session.sym_table);
+                        transpiler.set_trampoline(self.trampoline);
+                        println!("{}", transpiler.translate_entry(&ast, "input", &mut session.linkage));
+                    }
+                }
+                ":reset" => self.reset(),
+                ":help" => print!("{}", HELP),
+                ":quit" => break,
+                _ => eprintln!("error: unknown command `{}`, enter :help for a list of commands", command),
+            }
+        }
+    }
+
+    /// Compiles and runs an input, returning whether it succeeded.
+    pub fn eval(&mut self, name: &str, input: &str) -> bool {
+        let mut session = self.session.clone();
+
+        let ast = match self.compile(name, input, &mut session) {
+            Some(ast) => ast,
+            None => return false,
+        };
+
+        self.built += 1;
+        let entry = format!("lispc_input{}", self.built);
+        let c_file = self.dir.join(format!("input{}.c", self.built));
+        let object = self.dir.join(format!("input{}.so", self.built));
+
+        let mut transpiler = Transpiler::new(session.sym_table);
+        transpiler.set_trampoline(self.trampoline);
+        let c = transpiler.translate_entry(&ast, &entry, &mut session.linkage);
+        session.sym_table = transpiler.into_symbol_table();
+
+        match self.toolchain.build_shared(&c, &c_file, &object) {
+            Ok(()) => (),
+            Err(BuildError::Io(e)) => {
+                eprintln!("error: {}", e);
+                return false;
+            }
+            Err(BuildError::Rejected(errors)) => {
+                let c_name = c_file.display().to_string();
+
+                for d in errors {
+                    let source = if d.file.as_deref() == Some(&c_name) { c.as_str() } else { "" };
+                    eprintln!("{}", d.with_note("the generated C is invalid, which is a bug in lispc").render(source));
+                }
+
+                return false;
+            }
+        }
+
+        let library = Library::open(&object);
+        let _ = fs::remove_file(&object);
+
+        let status = match library.and_then(|l| l.entry(&entry).map(|f| (l, f))) {
+            Ok((library, f)) => {
+                // Once loaded, the input's definitions exist, even if running it fails part way.
+                self.inputs.push(library);
+                self.session = session;
+                f()
+            }
+            Err(e) => {
+                eprintln!("error: couldn't load the input: {}", e);
+                return false;
+            }
+        };
+
+        status == 0
+    }
+
+    /// Runs an input through every stage before translation, reporting any errors.
+    fn compile(&self, name: &str, input: &str, session: &mut Session) -> Option<Vec<ASTNode>> {
+        let mut diagnostics = Diagnostics::new(self.error_limit);
+
+        let tokens = lex::tokenize(input, &mut diagnostics);
+        let trees = parse::parse_all(&tokens, &mut diagnostics);
+        let trees = session.expander.expand_all(&trees, &mut session.sym_table, &mut diagnostics);
+        let ast = ast::construct_ast_after(&trees, &mut session.scope_count, &mut diagnostics);
+        let ast = ast::apply_visitors(&ast, &mut session.sym_table, &mut diagnostics);
+
+        if diagnostics.is_empty() {
+            return Some(ast);
+        }
+
+        for d in diagnostics.into_vec() {
+            eprintln!("{}", d.with_file(name).render(input));
+        }
+
+        None
+    }
+
+    fn reset(&mut self) {
+        while let Some(library) = self.inputs.pop() {
+            drop(library);
+        }
+
+        self.session = self.initial.clone();
+    }
+}
+
+impl Drop for Repl {
+    fn drop(&mut self) {
+        self.reset();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Reads lines until they hold complete forms, or a command. Returns `None` at the end of input.
+fn read_input(lines: &mut impl Iterator<Item = io::Result<String>>) -> Option<String> {
+    let mut input = String::new();
+
+    loop {
+        print!("{}", if input.is_empty() { "lispc> " } else { "   ...> " });
+        io::stdout().flush().ok()?;
+
+        match lines.next() {
+            Some(Ok(line)) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            // Whatever has been read is still run, even if it's incomplete.
+            _ if !input.trim().is_empty() => return Some(input),
+            _ => return None,
+        }
+
+        let forms = match input.trim_start().strip_prefix(':') {
+            Some(command) => command.trim_start_matches(|c: char| !c.is_whitespace()),
+            None => input.as_str(),
+        };
+
+        if !input.trim().is_empty() && !needs_more_input(forms) {
+            return Some(input);
+        }
+    }
+}
+
+/// Checks if the input ends inside of a list, in which case the REPL waits for the rest of it.
+/// Any other error is left to be reported when the input is compiled.
+fn needs_more_input(input: &str) -> bool {
+    let tokens = match lex::start(input) {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+
+    match parse::parse(&tokens) {
+        // An unclosed list is reported where it starts, while an extra `)` is reported where it is.
+        Err(d) if d.code == UNBALANCED_LIST => tokens
+            .iter()
+            .any(|t| t.span == d.span && t.value == TokenValue::Open),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repl::*;
+
+    #[test]
+    fn incomplete_input() {
+        assert!(needs_more_input("(define x"));
+        assert!(needs_more_input("(defun f (x)\n  (let ((y 1))\n"));
+        assert!(!needs_more_input("(define x 1)"));
+        assert!(!needs_more_input("(define x 1))"));
+        assert!(!needs_more_input("x"));
+        assert!(!needs_more_input("(format \"(\")"));
+    }
+
+    #[test]
+    fn read_multi_line_input() {
+        let mut lines = vec!["(define x", "  1)", ":ast (f", "x)"]
+            .into_iter()
+            .map(|l| Ok(l.to_string()));
+
+        assert_eq!(Some("(define x\n  1)\n".to_string()), read_input(&mut lines));
+        assert_eq!(Some(":ast (f\nx)\n".to_string()), read_input(&mut lines));
+        assert_eq!(None, read_input(&mut lines));
+    }
+
+    #[test]
+    fn definitions_persist() {
+        // Without a C compiler, there's nothing to test.
+        let mut repl = match Repl::new(SymbolTable::load(None).unwrap(), false, 20) {
+            Ok(repl) => repl,
+            Err(_) => return,
+        };
+
+        assert!(repl.eval("<test>", "(define x 41)"));
+        assert!(repl.eval("<test>", "(defun f (y) (+ x y))"));
+        assert!(repl.eval("<test>", "(define z (f 1))"));
+
+        // A failed input leaves the session as it was.
+        assert!(!repl.eval("<test>", "(define w 1) (f undefined)"));
+        assert!(!repl.eval("<test>", "w"));
+
+        // Runtime errors only abandon the current input.
+        assert!(!repl.eval("<test>", "(car 1)"));
+        assert!(repl.eval("<test>", "(+ z 1)"));
+
+        repl.reset();
+        assert!(!repl.eval("<test>", "x"));
+        assert!(repl.eval("<test>", "(define x 1)"));
+    }
+}
@@ -1,16 +1,76 @@
-use crate::ast::{ASTNode, Statement::*, SymbolTable, Value::*};
+use crate::ast::{ASTNode, LetKind, Quoted, Statement::*, SymbolTable, Value, Value::*};
+use crate::diagnostic::Span;
+use crate::interp;
 use crate::lex::{TokenValue, TokenValue::Symbol};
+use json::{object, JsonValue};
 
-type LambdaDefinition = (Vec<String>, Option<String>, Vec<ASTNode>, usize);
+// required args, vararg, body, scope ID, enclosing scope IDs, name it's defined as, span
+type LambdaDefinition = (Vec<String>, Option<String>, Vec<ASTNode>, usize, Vec<usize>, Option<String>, Span);
+
+// name, required args, vararg, body, scope ID, span of the name
+type DefunDefinition = (String, Vec<String>, Option<String>, Vec<ASTNode>, usize, Span);
+
+// name of the function if made by `defun`, C names of the required args, C name of the vararg
+type RecurTarget = (Option<String>, Vec<String>, Option<String>);
+
+/// How the generated program manages memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collector {
+    // Data are freed as soon as nothing refers to them.
+    RefCount,
+    // A tracing collector frees whatever can't be reached from the roots the program registers.
+    Tracing,
+    // The tracing collector, collecting before every allocation.
+    Stress,
+}
+
+// The variables declared in a C block.
+#[derive(Default)]
+struct Block {
+    // Variables holding references, released when the block ends.
+    locals: Vec<String>,
+    // The number of roots registered for the tracing collector, popped when the block ends.
+    roots: u32,
+}
+
+/// The globals and functions defined by inputs to the REPL that have already been loaded. Later
+/// inputs refer to these, rather than defining them again.
+#[derive(Clone, Debug, Default)]
+pub struct Linkage {
+    pub globals: Vec<String>,
+    pub functions: Vec<String>,
+}
 
 pub struct Transpiler {
     sym_table: SymbolTable,
     functions: Vec<LambdaDefinition>,
+    globals: Vec<String>,
+    constants: Vec<(String, String)>,
+    // The constant pool, holding the name of the constant for each literal, along with the
+    // definition it was made from. See `pool_literal`.
+    literals: Vec<(String, String)>,
+    // The literals that are allocated statically, with the definition of each.
+    static_literals: Vec<(String, String)>,
+    trampoline: bool,
+    leak_check: bool,
+    collector: Collector,
+    // The variables declared in each C block of the function currently being translated,
+    // innermost last.
+    blocks: Vec<Block>,
+    // The function currently being translated, which `recur` restarts.
+    recur_target: Option<RecurTarget>,
+    // The names of the input files, by the index in each span. Positions are only written to the C
+    // when these are known.
+    sources: Vec<String>,
+    // The position of each marker left in the C by `mark`.
+    positions: Vec<Span>,
+    // The first and last lines of the C that come from each position, once markers are resolved.
+    source_map: Vec<(usize, usize, Span)>,
 }
 
 impl Transpiler {
     fn imports() -> &'static str {
-        "#include \"lisp.h\"\n"
+        "#include <stdlib.h>\n#include \"lisp.h\"\n"
     }
 
     fn main_definition() -> &'static str {
@@ -21,133 +81,223 @@ impl Transpiler {
         "\nreturn 0;\n}"
     }
 
-    fn update_capture_vec(captures: &mut Vec<String>, new_captures: &Vec<String>) {
-        for capture in new_captures {
-            if !captures.contains(&capture) {
-                captures.push(capture.clone());
-            }
-        }
+    // Runtime errors jump back to the start of the entry function, which then returns 1.
+    fn entry_definition(name: &str) -> String {
+        format!(
+            "int {}(void) {{jmp_buf recovery;\nif (setjmp(recovery)) {{fflush(stdout); return 1;}}\n\
+             set_error_recovery(&recovery); set_global_error_behavior(LogAndRecover);\n",
+            name
+        )
+    }
+
+    fn entry_postfix() -> &'static str {
+        "\nfflush(stdout);\nreturn 0;\n}"
     }
 
     /// Find captured variables inside the body of a lambda expression. Assumes all variables within
-    /// the body are already valid. Returns a list of captured Lisp symbol names.
-    fn find_captures(
-        args: &Vec<String>,
+    /// the body are already valid. Returns a list of captured Lisp symbol names. Natives used as
+    /// values aren't captured, since they're constants.
+    pub fn find_captures(
+        args: &[String],
         vararg: &Option<String>,
-        body: &Vec<ASTNode>,
-        scope_id: usize,
+        body: &[ASTNode],
+        sym_table: &SymbolTable,
     ) -> Vec<String> {
+        let mut bound = args.to_vec();
+        bound.extend(vararg.iter().cloned());
+
         let mut captures = Vec::new();
+        Self::find_free_symbols(body, &bound, &mut captures);
+        captures.retain(|c| !sym_table.is_native(c));
+
+        captures
+    }
+
+    /// Collect every symbol used in `body` that isn't one of the `bound` names, including those
+    /// used inside of nested lambdas and let forms.
+    fn find_free_symbols(body: &[ASTNode], bound: &[String], captures: &mut Vec<String>) {
+        // Names defined earlier in the same body, such as those generated by the visitors in the
+        // ast module, are local to the generated C function.
+        let mut bound = bound.to_vec();
+        let bound = &mut bound;
 
         for line in body {
             match line {
                 ASTNode::Statement(ExpandedCondition(c, t, f)) => {
-                    Self::update_capture_vec(
-                        &mut captures,
-                        &Self::find_captures(
-                            args,
-                            vararg,
-                            &vec![ASTNode::Value(c.clone())],
-                            scope_id,
-                        ),
-                    );
-                    Self::update_capture_vec(
-                        &mut captures,
-                        &mut Self::find_captures(args, vararg, t, scope_id),
-                    );
-                    Self::update_capture_vec(
-                        &mut captures,
-                        &mut Self::find_captures(args, vararg, f, scope_id),
-                    );
-                }
-                ASTNode::Value(Call(_, params)) => params.iter().for_each(|v| {
-                    Self::update_capture_vec(
-                        &mut captures,
-                        &mut Self::find_captures(
-                            args,
-                            vararg,
-                            &vec![ASTNode::Value(v.clone())],
-                            scope_id,
-                        ),
-                    )
-                }),
-                ASTNode::Value(Lambda(a, v, b, s)) => {
-                    Self::update_capture_vec(&mut captures, &mut Self::find_captures(a, v, b, *s))
+                    Self::find_free_symbols(&[ASTNode::Value(c.clone())], bound, captures);
+                    Self::find_free_symbols(t, bound, captures);
+                    Self::find_free_symbols(f, bound, captures);
+                }
+                ASTNode::Value(Call(_, params, _))
+                | ASTNode::Value(TailCall(_, params, _))
+                | ASTNode::Value(Recur(params, _)) => {
+                    for param in params {
+                        Self::find_free_symbols(&[ASTNode::Value(param.clone())], bound, captures);
+                    }
+                }
+                ASTNode::Value(Lambda(a, v, b, _, _)) => {
+                    let mut inner = bound.to_vec();
+                    inner.extend(a.iter().cloned());
+                    inner.extend(v.iter().cloned());
+
+                    Self::find_free_symbols(b, &inner, captures);
                 }
+                ASTNode::Value(Let(kind, bindings, b, _)) => {
+                    let mut inner = bound.to_vec();
+                    inner.extend(bindings.iter().map(|(name, _)| name.clone()));
+
+                    for (_, init) in bindings {
+                        match kind {
+                            LetKind::Let => Self::find_free_symbols(init, bound, captures),
+                            LetKind::LetRec => Self::find_free_symbols(init, &inner, captures),
+                        }
+                    }
+
+                    Self::find_free_symbols(b, &inner, captures);
+                }
+                ASTNode::Value(Begin(b)) => Self::find_free_symbols(b, bound, captures),
                 ASTNode::Value(Literal(t)) => {
                     if let Symbol(s) = t.value() {
-                        if !(args.contains(&s)
-                            || match vararg {
-                                Some(y) => y.eq(&s),
-                                None => false,
-                            })
-                        {
-                            // This is a hotfix to avoid capturing generated symbols.
-                            if !s.starts_with("gensym") {
-                                captures.push(s.clone());
-                            }
+                        if !bound.contains(&s) && !captures.contains(&s) {
+                            captures.push(s);
                         }
                     }
                 }
-                ASTNode::Statement(Definition(_, v)) | ASTNode::Statement(Redefinition(_, v)) => {
-                    Self::update_capture_vec(
-                        &mut captures,
-                        &mut Self::find_captures(
-                            args,
-                            vararg,
-                            &vec![ASTNode::Value(v.clone())],
-                            scope_id,
-                        ),
-                    )
+                ASTNode::Statement(Definition(name, v, _)) => {
+                    Self::find_free_symbols(&[ASTNode::Value(v.clone())], bound, captures);
+                    bound.push(name.clone());
+                }
+                ASTNode::Statement(Redefinition(_, v)) => {
+                    Self::find_free_symbols(&[ASTNode::Value(v.clone())], bound, captures)
                 }
+                ASTNode::Statement(Declaration(name)) => bound.push(name.clone()),
                 ASTNode::Value(Condition(..)) => panic!("Contact the developer."),
-                ASTNode::Statement(Declaration(..)) => (),
+                // Function definitions only exist at the top level, so they are never captured from.
+                ASTNode::Statement(FunctionDefinition(..)) | ASTNode::Value(Quotation(..)) => (),
             }
         }
-
-        captures
     }
 
-    fn extract_lambda_definitions(ast: &Vec<ASTNode>) -> Vec<LambdaDefinition> {
+    /// Collect every lambda in the AST along with the scopes that enclose it, which are needed to
+    /// resolve the names of its captures.
+    pub fn extract_lambda_definitions(
+        ast: &[ASTNode],
+        scope_ids: &mut Vec<usize>,
+    ) -> Vec<LambdaDefinition> {
         let mut output = Vec::new();
 
         for line in ast {
             match line {
                 ASTNode::Value(Condition(..)) => panic!(),
-                ASTNode::Value(Call(_, args)) => {
+                ASTNode::Value(Call(_, args, _))
+                | ASTNode::Value(TailCall(_, args, _))
+                | ASTNode::Value(Recur(args, _)) => {
                     for arg in args {
-                        output.append(&mut Self::extract_lambda_definitions(&vec![
-                            ASTNode::Value(arg.clone()),
-                        ]));
+                        output.append(&mut Self::extract_lambda_definitions(
+                            &[ASTNode::Value(arg.clone())],
+                            scope_ids,
+                        ));
                     }
                 }
-                ASTNode::Value(Lambda(args, vararg, body, scope_id)) => {
-                    output.push((args.clone(), vararg.clone(), body.clone(), *scope_id));
+                ASTNode::Value(Lambda(args, vararg, body, scope_id, span)) => {
+                    output.push((
+                        args.clone(),
+                        vararg.clone(),
+                        body.clone(),
+                        *scope_id,
+                        scope_ids.clone(),
+                        None,
+                        *span,
+                    ));
 
-                    output.append(&mut Self::extract_lambda_definitions(body));
+                    scope_ids.push(*scope_id);
+                    output.append(&mut Self::extract_lambda_definitions(body, scope_ids));
+                    scope_ids.pop();
                 }
-                ASTNode::Statement(Definition(_, value))
-                | ASTNode::Statement(Redefinition(_, value)) => {
-                    output.append(&mut Self::extract_lambda_definitions(&vec![
-                        ASTNode::Value(value.clone()),
-                    ]))
+                ASTNode::Value(Let(kind, bindings, body, scope_id)) => {
+                    if *kind == LetKind::LetRec {
+                        scope_ids.push(*scope_id);
+                    }
+
+                    for (name, init) in bindings {
+                        let mut lambdas = Self::extract_lambda_definitions(init, scope_ids);
+
+                        if let Some(ASTNode::Value(value)) = init.last() {
+                            Self::name_lambda(&mut lambdas, value, name);
+                        }
+
+                        output.append(&mut lambdas);
+                    }
+
+                    if *kind == LetKind::Let {
+                        scope_ids.push(*scope_id);
+                    }
+
+                    output.append(&mut Self::extract_lambda_definitions(body, scope_ids));
+                    scope_ids.pop();
+                }
+                ASTNode::Value(Begin(body)) => {
+                    output.append(&mut Self::extract_lambda_definitions(body, scope_ids));
+                }
+                ASTNode::Statement(FunctionDefinition(_, _, _, body, scope_id, _)) => {
+                    scope_ids.push(*scope_id);
+                    output.append(&mut Self::extract_lambda_definitions(body, scope_ids));
+                    scope_ids.pop();
+                }
+                ASTNode::Statement(Definition(name, value, _))
+                | ASTNode::Statement(Redefinition(name, value)) => {
+                    let mut lambdas =
+                        Self::extract_lambda_definitions(&[ASTNode::Value(value.clone())], scope_ids);
+                    Self::name_lambda(&mut lambdas, value, name);
+                    output.append(&mut lambdas);
                 }
                 ASTNode::Statement(ExpandedCondition(c, t, f)) => {
-                    output.append(&mut Self::extract_lambda_definitions(&vec![
-                        ASTNode::Value(c.clone()),
-                    ]));
-                    output.append(&mut Self::extract_lambda_definitions(t));
-                    output.append(&mut Self::extract_lambda_definitions(f));
+                    output.append(&mut Self::extract_lambda_definitions(
+                        &[ASTNode::Value(c.clone())],
+                        scope_ids,
+                    ));
+                    output.append(&mut Self::extract_lambda_definitions(t, scope_ids));
+                    output.append(&mut Self::extract_lambda_definitions(f, scope_ids));
                 }
-                ASTNode::Statement(Declaration(..)) | ASTNode::Value(Literal(..)) => (),
+                ASTNode::Statement(Declaration(..))
+                | ASTNode::Value(Literal(..))
+                | ASTNode::Value(Quotation(..)) => (),
             }
         }
 
         output
     }
 
+    /// Names a lambda after the variable it's defined as, unless the variable was made by the
+    /// compiler, such as the result of an `if`.
+    fn name_lambda(lambdas: &mut [LambdaDefinition], value: &Value, name: &str) {
+        if let Lambda(_, _, _, scope_id, _) = value {
+            if let Some(lambda) = lambdas.iter_mut().find(|l| l.3 == *scope_id) {
+                if !SymbolTable::is_generated(name) {
+                    lambda.5 = Some(name.to_string());
+                }
+            }
+        }
+    }
+
+    /// The name a lambda is known by at runtime, in backtraces and when it's displayed: the
+    /// variable it's defined as, or else where it's written, like `<lambda main.lisp:3>`.
+    pub fn lambda_label(name: Option<&str>, span: Span, sources: &[String]) -> String {
+        match (name, sources.get(span.source as usize)) {
+            (Some(name), _) => name.to_string(),
+            (None, Some(file)) if span.line > 0 => format!("<lambda {}:{}>", file, span.line),
+            (None, _) => String::from("<lambda>"),
+        }
+    }
+
+    /// The label of an extracted lambda.
+    fn label(&self, scope_id: usize) -> String {
+        let lambda = self.functions.iter().find(|f| f.3 == scope_id).unwrap();
+        Self::lambda_label(lambda.5.as_deref(), lambda.6, &self.sources)
+    }
+
     fn lambda_name(&mut self, scope_id: usize) -> String {
-        if scope_id > self.functions.len() {
+        if !self.functions.iter().any(|f| f.3 == scope_id) {
             panic!("Attempt to generate a name for a lambda that has not been extracted.");
         }
 
@@ -158,14 +308,88 @@ impl Transpiler {
     /// (captures, lambda params, varargs).
     fn translate_lambda(&mut self, scope_id: usize) -> String {
         let fn_name = self.lambda_name(scope_id);
+
+        let (args, vararg, body, _, mut scope_ids, name, span) = self
+            .functions
+            .iter()
+            .find(|f| f.3 == scope_id)
+            .unwrap()
+            .clone();
+        let captures = Self::find_captures(&args, &vararg, &body, &self.sym_table);
+        let label = self.label(scope_id);
+        let description = match name {
+            Some(name) => format!("lambda `{}`", name),
+            None => label.clone(),
+        };
+
+        // Captures are named the same as they were in the enclosing scopes.
+        scope_ids.push(scope_id);
+
+        let marker = self.mark(&ASTNode::Value(Lambda(args.clone(), vararg.clone(), body.clone(), scope_id, span)));
+
+        marker + &self.translate_function(
+            &fn_name,
+            &description,
+            &label,
+            &captures,
+            &args,
+            &vararg,
+            &body,
+            &scope_ids,
+            None,
+        )
+    }
+
+    /// Creates the top level C function for a function defined using `defun`. These never have
+    /// captures, as any free variables in the body must be globals.
+    fn translate_defun(
+        &mut self,
+        name: &str,
+        args: &[String],
+        vararg: &Option<String>,
+        body: &[ASTNode],
+        scope_id: usize,
+        span: Span,
+    ) -> String {
+        let fn_name = self.sym_table.get_fn(name).unwrap().clone();
+        let marker = self.mark_span(span);
+
+        marker + &self.translate_function(
+            &fn_name,
+            &format!("function `{}`", name),
+            name,
+            &[],
+            args,
+            vararg,
+            body,
+            &[scope_id],
+            Some(name),
+        )
+    }
+
+    /// Creates a C function with the `LispFunction` signature. Arguments are given in the order
+    /// (captures, params, varargs). The description is only used in the argument count error, and
+    /// the label names the function's frame in backtraces. If the body restarts itself with
+    /// `recur` or a tail call to `self_name`, it's wrapped in a loop.
+    #[allow(clippy::too_many_arguments)]
+    fn translate_function(
+        &mut self,
+        fn_name: &str,
+        description: &str,
+        label: &str,
+        captures: &[String],
+        args: &[String],
+        vararg: &Option<String>,
+        body: &[ASTNode],
+        scope_ids: &[usize],
+        self_name: Option<&str>,
+    ) -> String {
+        let scope_ids = scope_ids.to_vec();
         let mut output = format!(
             "struct LispDatum* {}(struct LispDatum** _args, uint32_t _nargs){{",
             fn_name
         );
 
-        let (args, vararg, body, id) = self.functions[scope_id - 1].clone();
-        let captures = Self::find_captures(&args, &vararg, &body, id);
-
         let n_captures = captures.len();
         let n_named_args = args.len();
 
@@ -176,26 +400,29 @@ impl Transpiler {
             ("!=", "exactly ")
         };
         output.push_str(
-            format!("if (_nargs {} {} + {}) {{ return raise_err(Argument, \"Expected {}{} argument(s) to lambda expression {}.\"); }}",
+            format!("if (_nargs {} {} + {}) {{ return raise_err(Argument, \"Expected {}{} argument(s) to {}.\"); }}",
                 cmp,
                 args.len(),
                 n_captures,
                 qual,
                 args.len(),
-                scope_id,
+                description,
             ).as_str());
 
+        // The frame is popped right before the function returns, which it only does at the end.
+        output.push_str(format!("struct CallFrame _frame; push_frame(&_frame, {});", c_string(label)).as_str());
+
+        // Roots registered during the call are all dropped along with its frame.
+        if self.tracing() {
+            output.push_str("struct GcFrame _gc_frame; gc_push_frame(&_gc_frame);");
+        }
+
         for (i, capture) in captures.iter().enumerate() {
-            // TODO(matthew-c21): At some point, every individual symbol will need to have an
-            //  associated scope. However, since only lambdas have a scope and all other variables
-            //  must be global, we can rough out the idea here pretty easily. The alternative is
-            //  going through things like this and just renaming all captured variables before
-            //  reaching the translation stage.
             output.push_str(
                 format!(
                     "struct LispDatum* {} = _args[{}];",
                     self.sym_table
-                        .get(capture.as_str(), Some(&vec![id]))
+                        .get(capture.as_str(), Some(&scope_ids))
                         .unwrap(),
                     i
                 )
@@ -208,7 +435,7 @@ impl Transpiler {
                 format!(
                     "struct LispDatum* {} = _args[{}];",
                     self.sym_table
-                        .get(arg.as_str(), Some(&vec![scope_id]))
+                        .get(arg.as_str(), Some(&scope_ids))
                         .unwrap(),
                     n_captures + i
                 )
@@ -216,27 +443,87 @@ impl Transpiler {
             )
         }
 
-        if vararg.is_some() {
+        if let Some(vararg) = vararg {
             output.push_str(
                 format!(
-                    "struct LispDatum* {} = {}(_args + {} + {}, _nargs - {} - {});",
+                    "struct LispDatum* {} = {};",
                     self.sym_table
-                        .get(vararg.as_ref().unwrap().as_str(), Some(&vec![scope_id]))
+                        .get(vararg.as_str(), Some(&scope_ids))
                         .unwrap(),
-                    self.sym_table.get("list", None).unwrap(),
-                    n_captures, n_named_args,
-                    n_captures, n_named_args
+                    self.call_native(
+                        self.sym_table.get("list", None).unwrap(),
+                        &format!("_args + {} + {}", n_captures, n_named_args),
+                        &format!("_nargs - {} - {}", n_captures, n_named_args),
+                    ),
                 )
                 .as_str(),
             )
         }
 
-        let mut lines: Vec<String> = body
+        if self.tracing() {
+            for name in captures.iter().chain(args).chain(vararg) {
+                output.push_str(format!("gc_root(&{});", self.sym_table.get(name.as_str(), Some(&scope_ids)).unwrap()).as_str());
+            }
+        }
+
+        let c_args: Vec<String> = args
             .iter()
-            .map(|n| self.translate_node(n, &mut vec![scope_id]))
-            .flatten()
+            .map(|a| self.sym_table.get(a.as_str(), Some(&scope_ids)).unwrap().clone())
             .collect();
-        let ret_value = lines.pop().unwrap();
+        let c_vararg = vararg
+            .as_ref()
+            .map(|v| self.sym_table.get(v.as_str(), Some(&scope_ids)).unwrap().clone());
+        let looped = Self::restarts(body, self_name);
+
+        // The arguments are borrowed from the caller, unless `recur` replaces them, in which case
+        // the function holds references of its own.
+        let mut owned_args: Vec<String> = if looped { c_args.clone() } else { Vec::new() };
+
+        for c_arg in &owned_args {
+            output.push_str(format!("retain({});", c_arg).as_str());
+        }
+
+        owned_args.extend(c_vararg.iter().cloned());
+
+        self.recur_target = Some((self_name.map(String::from), c_args, c_vararg));
+
+        if looped {
+            output.push_str("for (;;) {");
+        }
+
+        let outer_blocks = std::mem::take(&mut self.blocks);
+        self.open_block();
+
+        let mut lines: Vec<String> = Vec::new();
+
+        for (i, node) in body.iter().enumerate() {
+            let marker = self.mark(node);
+            let mut node_lines = self.translate_node(node, &mut scope_ids.clone());
+
+            if i + 1 < body.len() {
+                Self::discard(node, &mut node_lines);
+            }
+
+            if let Some(first) = node_lines.first_mut() {
+                first.insert_str(0, &marker);
+            }
+
+            lines.append(&mut node_lines);
+        }
+
+        let ret_value = match body.last() {
+            Some(ASTNode::Value(v)) => Self::owned(v, lines.pop().unwrap()),
+            _ => lines.pop().unwrap(),
+        };
+
+        lines.push(format!("struct LispDatum* _result = {}", ret_value));
+        self.close_block(&mut lines);
+        self.blocks = outer_blocks;
+        self.recur_target = None;
+
+        for arg in owned_args.iter().rev() {
+            lines.push(format!("release({})", arg));
+        }
 
         for line in &lines {
             output.push_str(line.as_str());
@@ -246,7 +533,126 @@ impl Transpiler {
             }
         }
 
-        output.push_str(format!("return {};}}", ret_value).as_str());
+        if self.tracing() {
+            output.push_str("gc_pop_frame(&_gc_frame);");
+        }
+
+        output.push_str("pop_frame(&_frame); return _result;}");
+
+        if looped {
+            output.push('}');
+        }
+
+        output
+    }
+
+    /// Check if a function body uses `recur`, or makes a tail call to itself. Nested lambdas are
+    /// separate functions, so they aren't searched.
+    fn restarts(body: &[ASTNode], self_name: Option<&str>) -> bool {
+        body.iter().any(|node| match node {
+            ASTNode::Value(Recur(..)) => true,
+            ASTNode::Value(TailCall(callee, ..)) => Some(callee.as_str()) == self_name,
+            ASTNode::Value(Let(_, _, b, _)) | ASTNode::Value(Begin(b)) => Self::restarts(b, self_name),
+            ASTNode::Statement(Redefinition(_, v)) => {
+                Self::restarts(&[ASTNode::Value(v.clone())], self_name)
+            }
+            ASTNode::Statement(ExpandedCondition(_, t, f)) => {
+                Self::restarts(t, self_name) || Self::restarts(f, self_name)
+            }
+            _ => false,
+        })
+    }
+
+    fn declare_global(&mut self, c_name: &str) {
+        if !self.globals.iter().any(|g| g == c_name) {
+            self.globals.push(c_name.to_string());
+        }
+    }
+
+    /// Check if the C expression for a value holds a reference of its own, rather than one borrowed
+    /// from a variable or a constant.
+    fn owns(value: &Value) -> bool {
+        match value {
+            Literal(t) => !matches!(t.value(), Symbol(_)) && !Self::pooled(&t.value()),
+            Quotation(_) => false,
+            Begin(body) => match body.last() {
+                Some(ASTNode::Value(v)) => Self::owns(v),
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+
+    /// The C expression for a value, as a reference owned by whatever it's given to.
+    fn owned(value: &Value, expr: String) -> String {
+        if Self::owns(value) {
+            expr
+        } else {
+            format!("retain({})", expr)
+        }
+    }
+
+    /// Drop the value of a node that's only evaluated for its side effects.
+    fn discard(node: &ASTNode, lines: &mut Vec<String>) {
+        if let ASTNode::Value(v) = node {
+            if let Some(value) = lines.pop() {
+                if Self::owns(v) {
+                    lines.push(format!("release({})", value));
+                }
+            }
+        }
+    }
+
+    /// Translate a sequence of nodes, where only the value of the last is used. The last line is
+    /// an expression for that value, which the caller owns.
+    fn translate_body(&mut self, body: &[ASTNode], scope_ids: &mut Vec<usize>) -> Vec<String> {
+        let mut output = Vec::new();
+
+        for (i, node) in body.iter().enumerate() {
+            let mut lines = self.translate_node(node, scope_ids);
+
+            if i + 1 < body.len() {
+                Self::discard(node, &mut lines);
+            } else if let ASTNode::Value(v) = node {
+                let value = lines.pop().unwrap();
+                lines.push(Self::owned(v, value));
+            }
+
+            output.append(&mut lines);
+        }
+
+        output
+    }
+
+    /// Record a variable that holds a reference until the end of the current C block. It's a root
+    /// until then when tracing.
+    fn declare_local(&mut self, c_name: &str, output: &mut Vec<String>) {
+        let tracing = self.tracing();
+        let block = self.blocks.last_mut().unwrap();
+        block.locals.push(c_name.to_string());
+
+        if tracing {
+            output.push(format!("gc_root(&{})", c_name));
+            block.roots += 1;
+        }
+    }
+
+    fn open_block(&mut self) {
+        self.blocks.push(Block::default());
+    }
+
+    /// Release the variables of the innermost C block, before it ends.
+    fn close_block(&mut self, output: &mut Vec<String>) {
+        let block = self.blocks.pop().unwrap();
+        output.append(&mut Self::release_block(&block));
+    }
+
+    fn release_block(block: &Block) -> Vec<String> {
+        let mut output: Vec<String> = block.locals.iter().rev().map(|l| format!("release({})", l)).collect();
+
+        if block.roots > 0 {
+            output.push(format!("gc_pop_roots({})", block.roots));
+        }
 
         output
     }
@@ -259,39 +665,611 @@ impl Transpiler {
         Self {
             sym_table,
             functions: Vec::new(),
+            globals: Vec::new(),
+            constants: Vec::new(),
+            literals: Vec::new(),
+            static_literals: Vec::new(),
+            trampoline: false,
+            leak_check: false,
+            collector: Collector::RefCount,
+            blocks: Vec::new(),
+            recur_target: None,
+            sources: Vec::new(),
+            positions: Vec::new(),
+            source_map: Vec::new(),
+        }
+    }
+
+    /// Gives back the symbol table, which holds every name defined by the translated program.
+    pub fn into_symbol_table(self) -> SymbolTable {
+        self.sym_table
+    }
+
+    /// When enabled, calls in tail position to functions made by `defun`, `funcall`, or `apply`
+    /// return to a trampoline in the runtime rather than being made directly, so that mutually
+    /// recursive functions run in constant stack space.
+    pub fn set_trampoline(&mut self, trampoline: bool) {
+        self.trampoline = trampoline;
+    }
+
+    /// When enabled, `main` releases every global and constant before it returns, then reports any
+    /// data that are still live, which have leaked.
+    pub fn set_leak_check(&mut self, leak_check: bool) {
+        self.leak_check = leak_check;
+    }
+
+    /// Chooses how the program manages memory. With a tracing collector, every variable holding
+    /// data is registered as a root while it's in scope. Only whole programs support tracing, not
+    /// inputs to the REPL.
+    pub fn set_collector(&mut self, collector: Collector) {
+        self.collector = collector;
+    }
+
+    fn tracing(&self) -> bool {
+        self.collector != Collector::RefCount
+    }
+
+    /// The C expression that calls a native function. Natives don't register roots, so the data
+    /// they allocate are pinned until they return when tracing.
+    fn call_native(&self, c_name: &str, args: &str, nargs: &str) -> String {
+        if self.tracing() {
+            format!("call_native({}, {}, {})", c_name, args, nargs)
+        } else {
+            format!("{}({}, {})", c_name, args, nargs)
         }
     }
 
+    /// Names the input files, so that the C is written with `#line` directives pointing back to
+    /// the LISP source. The C compiler and debuggers then report those lines instead.
+    pub fn set_sources(&mut self, sources: Vec<String>) {
+        self.sources = sources;
+    }
+
+    /// Describes the C lines that come from each position in the source, for the last program
+    /// translated, as JSON. Lines after a position that aren't part of any statement, like the
+    /// start of `main`, are counted with it, as the C compiler would.
+    pub fn source_map(&self, c_file: Option<&str>) -> String {
+        let mappings: Vec<JsonValue> = self
+            .source_map
+            .iter()
+            .map(|(first, last, span)| {
+                object! {
+                    c_lines: [*first, *last],
+                    source: span.source,
+                    line: span.line,
+                    column: span.column,
+                    length: span.length,
+                }
+            })
+            .collect();
+
+        let map = object! {
+            version: 1,
+            file: c_file,
+            sources: self.sources.clone(),
+            mappings: mappings,
+        };
+
+        map.pretty(2)
+    }
+
+    /// Finds the position of every token in a node.
+    fn find_spans(node: &ASTNode, spans: &mut Vec<Span>) {
+        match node {
+            ASTNode::Value(v)
+            | ASTNode::Statement(Definition(_, v, _))
+            | ASTNode::Statement(Redefinition(_, v)) => Self::find_value_spans(v, spans),
+            ASTNode::Statement(FunctionDefinition(_, _, _, body, _, span)) => {
+                spans.push(*span);
+                body.iter().for_each(|n| Self::find_spans(n, spans));
+            }
+            ASTNode::Statement(ExpandedCondition(c, t, f)) => {
+                Self::find_value_spans(c, spans);
+                t.iter().chain(f).for_each(|n| Self::find_spans(n, spans));
+            }
+            ASTNode::Statement(Declaration(_)) => (),
+        }
+    }
+
+    fn find_value_spans(value: &Value, spans: &mut Vec<Span>) {
+        match value {
+            Literal(t) => spans.push(t.span),
+            Quotation(q) => Self::find_quoted_spans(q, spans),
+            Call(_, args, _) | TailCall(_, args, _) => args.iter().for_each(|a| Self::find_value_spans(a, spans)),
+            Recur(args, span) => {
+                spans.push(*span);
+                args.iter().for_each(|a| Self::find_value_spans(a, spans));
+            }
+            Condition(c, t, f) => [c, t, f].iter().for_each(|v| Self::find_value_spans(v, spans)),
+            Lambda(_, _, body, _, span) => {
+                spans.push(*span);
+                body.iter().for_each(|n| Self::find_spans(n, spans));
+            }
+            Begin(body) => body.iter().for_each(|n| Self::find_spans(n, spans)),
+            Let(_, bindings, body, _) => bindings
+                .iter()
+                .flat_map(|(_, init)| init)
+                .chain(body)
+                .for_each(|n| Self::find_spans(n, spans)),
+        }
+    }
+
+    fn find_quoted_spans(quoted: &Quoted, spans: &mut Vec<Span>) {
+        match quoted {
+            Quoted::Atom(t) => spans.push(t.span),
+            Quoted::List(items, tail) => items
+                .iter()
+                .chain(tail.as_deref())
+                .for_each(|q| Self::find_quoted_spans(q, spans)),
+        }
+    }
+
+    /// Marks the C that follows as coming from the first line of a node, if it's known. Nodes made
+    /// by the compiler have no position, but may hold tokens from the source, and the tokens of a
+    /// macro's template may be in another file, so the file of the first token found is used.
+    fn mark(&mut self, node: &ASTNode) -> String {
+        let mut spans = Vec::new();
+        Self::find_spans(node, &mut spans);
+        spans.retain(|s| s.line > 0 && (s.source as usize) < self.sources.len());
+
+        let first = match spans.first() {
+            Some(s) => *s,
+            None => return String::new(),
+        };
+
+        let span = spans
+            .into_iter()
+            .filter(|s| s.source == first.source)
+            .min_by_key(|s| (s.line, s.column))
+            .unwrap();
+
+        self.mark_span(span)
+    }
+
+    fn mark_span(&mut self, span: Span) -> String {
+        if span.line == 0 || span.source as usize >= self.sources.len() {
+            return String::new();
+        }
+
+        self.positions.push(span);
+
+        // Functions are generated in a different order than they're written out, so the line
+        // directive can't be written until the whole program is.
+        format!("\n#line {}\n", self.positions.len() - 1)
+    }
+
+    /// Replaces each marker left by `mark` with a `#line` directive, and records which lines of
+    /// the C come from each position. The C for a position is joined onto one line, since the C
+    /// compiler counts lines up from the directive. Only the `#include`s at the top need lines of
+    /// their own.
+    fn resolve_marks(&mut self, c: &str) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        let mut current: Option<(usize, Span)> = None;
+
+        self.source_map.clear();
+
+        for line in c.split('\n') {
+            let marker = line.strip_prefix("#line ").and_then(|m| m.parse::<usize>().ok());
+
+            match marker {
+                Some(i) => {
+                    let span = self.positions[i];
+
+                    if let Some((first, previous)) = current.take() {
+                        if first <= lines.len() {
+                            self.source_map.push((first, lines.len(), previous));
+                        }
+                    }
+
+                    lines.push(format!("#line {} {}", span.line, c_string(&self.sources[span.source as usize])));
+                    current = Some((lines.len() + 1, span));
+                }
+                None if current.is_some() && lines.len() >= current.unwrap().0 => {
+                    let last = lines.last_mut().unwrap();
+                    last.push(' ');
+                    last.push_str(line);
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+
+        if let Some((first, span)) = current {
+            if first <= lines.len() {
+                self.source_map.push((first, lines.len(), span));
+            }
+        }
+
+        self.positions.clear();
+        lines.join("\n")
+    }
+
     /// Convert a modified AST to a String. If the AST is not in a reduced form as a result of
     /// visitors in the ast module, this program may crash. Specifically, it assumes that all
     /// functions, conditions, and lambdas have been unrolled.
-    pub fn translate(&mut self, ast: &Vec<ASTNode>) -> String {
+    pub fn translate(&mut self, ast: &[ASTNode]) -> String {
+        self.translate_program(ast, None)
+    }
+
+    /// Translates one input to the REPL into a function named `entry`, rather than `main`, to be
+    /// built as a shared object. The function returns 1 if a runtime error occurs, and prints the
+    /// value of each top level expression. Names defined by the input are added to `linkage`.
+    pub fn translate_entry(&mut self, ast: &[ASTNode], entry: &str, linkage: &mut Linkage) -> String {
+        self.translate_program(ast, Some((entry, linkage)))
+    }
+
+    fn translate_program(&mut self, ast: &[ASTNode], mut entry: Option<(&str, &mut Linkage)>) -> String {
         let mut output = String::from(Self::imports());
+
+        if entry.is_some() {
+            output.push_str("#include <stdio.h>\n");
+        }
+
         let mut scope_ids = Vec::new();
 
         self.functions.clear();
         self.functions
-            .append(&mut Self::extract_lambda_definitions(ast));
+            .append(&mut Self::extract_lambda_definitions(ast, &mut Vec::new()));
+        self.globals.clear();
+        self.constants.clear();
+        self.literals.clear();
+        self.static_literals.clear();
 
-        for (_, _, _, scope_id) in self.functions.clone() {
-            output.push_str(self.translate_lambda(scope_id).as_str())
-        }
+        // The body of main is generated first, as it determines which globals exist.
+        let mut main = String::new();
 
-        output.push_str(Self::main_definition());
+        self.blocks.clear();
+        self.open_block();
 
         for node in ast {
-            for line in self.translate_node(node, &mut scope_ids) {
-                output.push_str(line.as_str());
+            let mut lines = self.translate_node(node, &mut scope_ids);
+
+            // The REPL shows the value of every expression that it's given.
+            match (&entry, node) {
+                (Some(_), ASTNode::Value(v)) => {
+                    let value = lines.pop().unwrap();
+                    lines.push(format!("display({})", value));
+                    lines.push(String::from("printf(\"\\n\")"));
+
+                    if Self::owns(v) {
+                        lines.push(format!("release({})", value));
+                    }
+                }
+                _ => Self::discard(node, &mut lines),
+            }
+
+            if !lines.is_empty() {
+                main.push_str(&self.mark(node));
+            }
+
+            for line in lines {
+                main.push_str(line.as_str());
 
                 // This will make extraneous semicolons for forms that generate braces, such as
                 // conditionals. However, this is essentially harmless, and can safely be ignored.
-                if !(output.ends_with(';') || output.ends_with('}')) {
-                    output.push(';');
+                if !(main.ends_with(';') || main.ends_with('}')) {
+                    main.push(';');
                 }
             }
         }
 
-        output.push_str(Self::postfix());
+        let mut cleanup = Vec::new();
+        self.close_block(&mut cleanup);
+
+        for line in cleanup {
+            main.push_str(line.as_str());
+            main.push(';');
+        }
+
+        let defuns: Vec<DefunDefinition> = ast
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::Statement(FunctionDefinition(name, args, vararg, body, scope_id, span)) => {
+                    Some((name.clone(), args.clone(), vararg.clone(), body.clone(), *scope_id, *span))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut definitions = String::new();
+
+        for (_, _, _, scope_id, ..) in self.functions.clone() {
+            definitions.push_str(self.translate_lambda(scope_id).as_str())
+        }
+
+        for (name, args, vararg, body, scope_id, span) in &defuns {
+            definitions.push_str(self.translate_defun(name, args, vararg, body, *scope_id, *span).as_str())
+        }
+
+        let (previous_globals, previous_functions) = match &entry {
+            Some((_, linkage)) => (linkage.globals.clone(), linkage.functions.clone()),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        for global in &previous_globals {
+            output.push_str(format!("extern struct LispDatum* {};\n", global).as_str());
+        }
+
+        // Globals live outside of main so that functions created by `defun` can refer to them.
+        for global in &self.globals {
+            if !previous_globals.contains(global) {
+                output.push_str(format!("struct LispDatum* {};\n", global).as_str());
+            }
+        }
+
+        for (literal, datum) in &self.static_literals {
+            output.push_str(
+                format!("static struct LispDatum* const {} = &(struct LispDatum){};\n", literal, datum).as_str(),
+            );
+        }
+
+        for (constant, _) in &self.constants {
+            output.push_str(format!("struct LispDatum* {};\n", constant).as_str());
+        }
+
+        let defun_names: Vec<String> = defuns
+            .iter()
+            .map(|(name, ..)| self.sym_table.get_fn(name).unwrap().clone())
+            .collect();
+
+        // Functions may refer to each other in any order, so every definition is declared up front.
+        for c_name in previous_functions.iter().chain(defun_names.iter()) {
+            output.push_str(
+                format!("struct LispDatum* {}(struct LispDatum** _args, uint32_t _nargs);\n", c_name)
+                    .as_str(),
+            )
+        }
+
+        for (_, _, _, scope_id, ..) in self.functions.clone() {
+            output.push_str(
+                format!(
+                    "struct LispDatum* {}(struct LispDatum** _args, uint32_t _nargs);\n",
+                    self.lambda_name(scope_id)
+                )
+                .as_str(),
+            )
+        }
+
+        output.push_str(definitions.as_str());
+
+        match &entry {
+            Some((name, _)) => output.push_str(&Self::entry_definition(name)),
+            None => output.push_str(Self::main_definition()),
+        }
+
+        if self.tracing() {
+            output.push_str(
+                format!(
+                    "gc_enable({}); struct GcFrame _gc_frame; gc_push_frame(&_gc_frame);",
+                    (self.collector == Collector::Stress) as u8
+                )
+                .as_str(),
+            );
+
+            for global in self.globals.iter().chain(self.constants.iter().map(|(c, _)| c)) {
+                output.push_str(format!("gc_root(&{});", global).as_str());
+            }
+
+            // Quoted data are built in a single expression each, which isn't rooted until it's done.
+            output.push_str("size_t _pin = gc_pin();");
+        }
+
+        // Constant data is built once, before anything that might use it.
+        for (constant, value) in &self.constants {
+            output.push_str(format!("{} = {};", constant, value).as_str());
+        }
+
+        if self.tracing() {
+            output.push_str("gc_unpin(_pin);");
+        }
+
+        output.push_str(main.as_str());
+
+        // Everything the program holds is dropped at the end, so that only leaked data are left.
+        if self.leak_check && entry.is_none() {
+            if self.tracing() {
+                output.push_str("gc_pop_frame(&_gc_frame); gc_collect();");
+            } else {
+                for global in self.globals.iter().chain(self.constants.iter().map(|(c, _)| c)) {
+                    output.push_str(format!("release({});", global).as_str());
+                }
+            }
+
+            output.push_str("report_leaks();");
+        }
+
+        match entry.as_mut() {
+            Some((_, linkage)) => {
+                output.push_str(Self::entry_postfix());
+
+                for global in &self.globals {
+                    if !linkage.globals.contains(global) {
+                        linkage.globals.push(global.clone());
+                    }
+                }
+
+                linkage.functions.extend(defun_names);
+            }
+            None => output.push_str(Self::postfix()),
+        }
+
+        self.resolve_marks(&output)
+    }
+
+    /// Check if a literal is kept in the constant pool, rather than built each time it's evaluated.
+    /// A rational with a denominator of 0 fails when it's built, so it's left where it's used.
+    fn pooled(value: &TokenValue) -> bool {
+        match value {
+            TokenValue::Int(_)
+            | TokenValue::Float(_)
+            | TokenValue::Complex(..)
+            | TokenValue::Str(_)
+            | TokenValue::Keyword(_) => true,
+            TokenValue::Rational(_, y) => *y != 0,
+            _ => false,
+        }
+    }
+
+    /// Find the constant holding a literal, adding it to the pool the first time it's used, so that
+    /// every use of the same literal refers to the same datum. Numbers and strings are allocated
+    /// statically, while keywords are interned by liblisp when the program starts. Constants have
+    /// no references, so they're never freed.
+    fn pool_literal(&mut self, value: &TokenValue) -> String {
+        let definition = match value {
+            TokenValue::Int(x) => format!("{{.type = Integer, .int_val = {}}}", x),
+            TokenValue::Float(x) => format!("{{.type = Real, .float_val = {}}}", c_double(*x)),
+            TokenValue::Complex(x, y) => {
+                format!("{{.type = Complex, .real = {}, .im = {}}}", c_double(*x), c_double(*y))
+            }
+            TokenValue::Rational(x, y) => {
+                let (x, y) = interp::reduce_rational(*x, *y).unwrap();
+                format!("{{.type = Rational, .num = {}, .den = {}}}", x, y)
+            }
+            // Like `new_string`, the length stops at the first null character.
+            TokenValue::Str(x) => format!(
+                "{{.type = String, .content = (char*) {}, .length = {}}}",
+                c_string(x),
+                x.find('\0').unwrap_or(x.len())
+            ),
+            TokenValue::Keyword(x) => format!("{}({})", self.sym_table.get_factory("keyword"), c_string(x)),
+            _ => panic!("Encountered a literal that can't be pooled. Contact the developer."),
+        };
+
+        self.pool(definition, !matches!(value, TokenValue::Keyword(_)))
+    }
+
+    /// Find the constant holding a native function wrapped in a lambda, so that it can be used as a
+    /// value. Unlike literals, the lambda is released when the program ends.
+    fn pool_native(&mut self, name: &str) -> String {
+        let c_name = self.sym_table.get_fn(name).unwrap();
+        let definition = format!("{}({}, NULL, 0, {})", self.sym_table.get_factory("lambda"), c_name, c_string(name));
+        self.pool(definition, false)
+    }
+
+    /// Find the constant made from a definition, adding it to the pool if it's new. Constants that
+    /// aren't allocated statically are built when the program starts.
+    fn pool(&mut self, definition: String, allocated_statically: bool) -> String {
+        if let Some((name, _)) = self.literals.iter().find(|(_, d)| *d == definition) {
+            return name.clone();
+        }
+
+        let name = self.sym_table.generate("literal");
+
+        if allocated_statically {
+            self.static_literals.push((name.clone(), definition.clone()));
+        } else {
+            self.constants.push((name.clone(), definition.clone()));
+        }
+
+        self.literals.push((name.clone(), definition));
+        name
+    }
+
+    /// Produce the C expression for a literal token that doesn't need to be looked up.
+    fn translate_literal(&mut self, value: &TokenValue) -> String {
+        match value {
+            v if Self::pooled(v) => self.pool_literal(v),
+            TokenValue::Rational(x, y) => {
+                format!("{}({},{})", self.sym_table.get_factory("rational"), x, y)
+            }
+            TokenValue::True => format!("{}()", self.sym_table.get_factory("true")),
+            TokenValue::False => format!("{}()", self.sym_table.get_factory("false")),
+            TokenValue::Nil => format!("{}()", self.sym_table.get_factory("nil")),
+            _ => panic!("Encountered invalid token literal in AST. Contact the developer."),
+        }
+    }
+
+    /// Produce the C expression that builds a piece of quoted data.
+    fn translate_quoted(&mut self, quoted: &Quoted) -> String {
+        match quoted {
+            Quoted::Atom(t) => match t.value() {
+                Symbol(s) => format!("{}({})", self.sym_table.get_factory("symbol"), c_string(&s)),
+                v => self.translate_literal(&v),
+            },
+            // The runtime represents an empty list as a cons cell with no car.
+            Quoted::List(items, None) if items.is_empty() => {
+                format!("{}(NULL, NULL)", self.sym_table.get_factory("cons"))
+            }
+            Quoted::List(items, tail) => {
+                let mut output = match tail {
+                    Some(t) => self.translate_quoted(t),
+                    None => format!("{}()", self.sym_table.get_factory("nil")),
+                };
+
+                for item in items.iter().rev() {
+                    let item = self.translate_quoted(item);
+                    output = format!("{}({}, {})", self.sym_table.get_factory("cons"), item, output);
+                }
+
+                output
+            }
+        }
+    }
+
+    /// Evaluate each argument into a new array, returning its name. The array holds a reference to
+    /// each argument, which is released once the call is made.
+    fn translate_arglist(&mut self, args: &[Value], scope_ids: &mut Vec<usize>, output: &mut Vec<String>) -> String {
+        let arglist = self.sym_table.generate("arglist");
+
+        // The array is a root until the end of the block, so it starts out empty.
+        if self.tracing() && !args.is_empty() {
+            output.push(format!("struct LispDatum* {}[{}] = {{NULL}};\n", arglist, args.len()));
+            output.push(format!("gc_root_array({}, {})", arglist, args.len()));
+            self.blocks.last_mut().unwrap().roots += 1;
+        } else {
+            output.push(format!("struct LispDatum* {}[{}];\n", arglist, args.len()));
+        }
+
+        for (i, arg) in args.iter().enumerate() {
+            let mut prefix = self.translate_node(&ASTNode::Value(arg.clone()), scope_ids);
+            let line = format!("{}[{}] = {};\n", arglist, i, Self::owned(arg, prefix.pop().unwrap()));
+            output.append(&mut prefix);
+            output.push(line);
+        }
+
+        arglist
+    }
+
+    /// Rebind the arguments of the current function and jump back to the start of its loop. Every
+    /// argument is evaluated before any are reassigned, and the variables of the current iteration
+    /// are released.
+    fn translate_recur(&mut self, args: &[Value], scope_ids: &mut Vec<usize>) -> Vec<String> {
+        let mut output = Vec::new();
+        let arglist = self.translate_arglist(args, scope_ids, &mut output);
+        let (_, c_args, c_vararg) = self.recur_target.clone().unwrap();
+
+        // The new arguments take over the references in the arglist.
+        for (i, c_arg) in c_args.iter().enumerate() {
+            output.push(format!("reassign(&{}, {}[{}])", c_arg, arglist, i));
+        }
+
+        if let Some(c_vararg) = c_vararg {
+            output.push(format!(
+                "reassign(&{}, {})",
+                c_vararg,
+                self.call_native(
+                    self.sym_table.get("list", None).unwrap(),
+                    &format!("{} + {}", arglist, c_args.len()),
+                    &(args.len() - c_args.len()).to_string(),
+                ),
+            ));
+            output.push(format!(
+                "release_all({} + {}, {})",
+                arglist,
+                c_args.len(),
+                args.len() - c_args.len()
+            ));
+        }
+
+        // The arglist is a root until the vararg is built from it, so the blocks are only left
+        // once the arguments are in place.
+        for block in self.blocks.iter().rev() {
+            output.append(&mut Self::release_block(block));
+        }
+
+        output.push(String::from("continue"));
+
+        // The loop restarts before this value could be used.
+        output.push(String::from("NULL"));
 
         output
     }
@@ -302,60 +1280,69 @@ impl Transpiler {
         match node {
             ASTNode::Value(Literal(t)) => {
                 match t.value() {
-                    TokenValue::Int(x) => {
-                        output.push(format!("{}({})", self.sym_table.get_factory("int"), x))
-                    }
-                    TokenValue::Float(x) => {
-                        output.push(format!("{}({})", self.sym_table.get_factory("float"), x))
-                    }
-                    TokenValue::Complex(x, y) => {
-                        output.push(format!("{}({},{})", self.sym_table.get_factory("complex"), x, y))
-                    }
-                    TokenValue::Rational(x, y) => {
-                        output.push(format!("{}({},{})", self.sym_table.get_factory("rational"), x, y))
-                    }
-                    TokenValue::Str(x) => {
-                        output.push(format!("{}({:?})", self.sym_table.get_factory("string"), x))
-                    }
-                    TokenValue::Keyword(x) => {
-                        output.push(format!("{}(\"{}\")", self.sym_table.get_factory("keyword"), x))
-                    }
-                    TokenValue::True => {
-                        output.push(format!("{}()", self.sym_table.get_factory("true")))
-                    }
-                    TokenValue::False => {
-                        output.push(format!("{}()", self.sym_table.get_factory("false")))
-                    }
+                    // Natives are C functions, rather than data, so they're wrapped in a lambda.
+                    TokenValue::Symbol(s) if self.sym_table.is_native(&s) => output.push(self.pool_native(&s)),
                     TokenValue::Symbol(s) => {
                         output.push(self.sym_table.get(s.as_str(), Some(scope_ids)).unwrap().clone())
                     }
-                    TokenValue::Nil => {
-                        output.push(format!("{}()", self.sym_table.get_factory("nil")))
-                    }
-                    _ => panic!("Encountered invalid token literal in AST. Contact the developer.")
+                    v => output.push(self.translate_literal(&v)),
                 }
             }
-            ASTNode::Value(Call(callee, args)) => {
-                let arglist = self.sym_table.generate("arglist");
-
-                output.push(format!("struct LispDatum* {}[{}];\n", arglist, args.len()));
+            ASTNode::Value(Quotation(q)) => {
+                let constant = self.sym_table.generate("quoted");
+                let value = self.translate_quoted(q);
+                self.constants.push((constant.clone(), value));
+                output.push(constant);
+            }
+            ASTNode::Value(Call(callee, args, _)) => {
+                let arglist = self.translate_arglist(args, scope_ids, &mut output);
+                let c_name = self.sym_table.get_fn(callee.as_str()).unwrap();
+                let mut call = format!("{}({}, {})", c_name, arglist, args.len());
 
-                for (i, arg) in args.iter().enumerate() {
-                    let mut prefix = self.translate_node(&ASTNode::Value(arg.clone()), scope_ids);
-                    let line = format!("{}[{}] = {};\n", arglist, i, prefix.pop().unwrap());
-                    output.append(&mut prefix);
-                    output.push(line);
+                // Functions made by `defun` may defer a tail call when trampolining.
+                if self.sym_table.is_defun(callee) {
+                    if self.trampoline {
+                        call = format!("resolve_tail_calls({})", call);
+                    }
+                } else {
+                    call = self.call_native(c_name, &arglist, &args.len().to_string());
                 }
 
-                output.push(format!("{}({}, {})", self.sym_table.get(callee.as_str(), Some(scope_ids)).unwrap(), arglist, args.len()))
+                // The arguments are only borrowed by the call, so they're released once it returns.
+                let value = self.sym_table.generate("call_value");
+                output.push(format!("struct LispDatum* {} = {}", value, call));
+                output.push(format!("release_all({}, {})", arglist, args.len()));
+                output.push(value);
             }
+            ASTNode::Value(TailCall(callee, args, span)) => {
+                let restarts = match &self.recur_target {
+                    Some((Some(name), c_args, c_vararg)) if name == callee => {
+                        args.len() == c_args.len() || (c_vararg.is_some() && args.len() > c_args.len())
+                    }
+                    _ => false,
+                };
+
+                if restarts {
+                    output.append(&mut self.translate_recur(args, scope_ids));
+                } else if self.trampoline && (callee == "funcall" || callee == "apply") {
+                    // Deferred calls take over the references in the arglist.
+                    let arglist = self.translate_arglist(args, scope_ids, &mut output);
+                    output.push(format!("tail_{}({}, {})", self.sym_table.get_fn(callee.as_str()).unwrap(), arglist, args.len()))
+                } else if self.trampoline && self.sym_table.is_defun(callee) {
+                    let arglist = self.translate_arglist(args, scope_ids, &mut output);
+                    output.push(format!("tail_call({}, {}, {})", self.sym_table.get_fn(callee.as_str()).unwrap(), arglist, args.len()))
+                } else {
+                    output.append(&mut self.translate_node(&ASTNode::Value(Call(callee.clone(), args.clone(), *span)), scope_ids));
+                }
+            }
+            ASTNode::Value(Recur(args, _)) => output.append(&mut self.translate_recur(args, scope_ids)),
             ASTNode::Value(Condition(..)) => {
                 panic!("Conditions should have been upgraded to expanded conditions before this step. Contact the developer.")
             }
-            ASTNode::Value(Lambda(args, vararg, body, scope_id)) => {
+            ASTNode::Value(Lambda(args, vararg, body, scope_id, _)) => {
                 let lambda_fn_name = self.lambda_name(*scope_id);
                 let capture_vec_name = self.sym_table.generate("lambda_captures");
-                let captures = Self::find_captures(args, vararg, body, *scope_id);
+                let captures = Self::find_captures(args, vararg, body, &self.sym_table);
 
                 let capture_vec_name = if !captures.is_empty() {
                     capture_vec_name
@@ -366,51 +1353,156 @@ impl Transpiler {
                 scope_ids.push(*scope_id);
 
                 if !captures.is_empty() {
-                    output.push(format!("struct LispDatum* {}[{}];", capture_vec_name, captures.len()));
+                    // The lambda may outlive the block it was created in, so captures live on the heap.
+                    output.push(format!("struct LispDatum** {} = malloc(sizeof(struct LispDatum*) * {});", capture_vec_name, captures.len()));
 
                     for (i, capture) in captures.iter().enumerate() {
-                        output.push(format!("{}[{}] = {};", capture_vec_name, i, self.sym_table.get(capture, Some(scope_ids)).unwrap()))
+                        output.push(format!("{}[{}] = retain({});", capture_vec_name, i, self.sym_table.get(capture, Some(scope_ids)).unwrap()))
                     }
                 }
 
                 assert_eq!(*scope_id, scope_ids.pop().unwrap());
 
-                // NOTE(matthew-c21): NULL is used for all non-native lambdas.
-                output.push(format!("{}({}, {}, {}, NULL)", self.sym_table.get_factory("lambda"), lambda_fn_name, capture_vec_name, captures.len()));
+                output.push(format!("{}({}, {}, {}, {})", self.sym_table.get_factory("lambda"), lambda_fn_name, capture_vec_name, captures.len(), c_string(&self.label(*scope_id))));
+            }
+            ASTNode::Value(Let(kind, bindings, body, scope_id)) => {
+                // The bindings only exist within a C block, so the result is stored outside of it.
+                let let_value = self.sym_table.generate("let_value");
+                output.push(format!("struct LispDatum* {}", let_value));
+                output.push(String::from("{"));
+                self.open_block();
+
+                // Recursive bindings all need to exist before any initializer is evaluated.
+                if *kind == LetKind::LetRec {
+                    scope_ids.push(*scope_id);
+
+                    for (name, _) in bindings {
+                        let c_name = self.sym_table.get(name.as_str(), Some(scope_ids)).unwrap().clone();
+                        output.push(format!("struct LispDatum* {} = {}()", c_name, self.sym_table.get_factory("nil")));
+                        self.declare_local(&c_name, &mut output);
+                    }
+                }
+
+                for (name, init) in bindings {
+                    let mut lines = self.translate_body(init, scope_ids);
+                    let v = lines.pop().unwrap();
+                    output.append(&mut lines);
+
+                    let c_name = self.sym_table.get(name.as_str(), Some(&vec![*scope_id])).unwrap().clone();
+
+                    match kind {
+                        LetKind::Let => {
+                            output.push(format!("struct LispDatum* {} = {}", c_name, v));
+                            self.declare_local(&c_name, &mut output);
+                        }
+                        LetKind::LetRec => output.push(format!("reassign(&{}, {})", c_name, v)),
+                    }
+                }
+
+                if *kind == LetKind::LetRec {
+                    // Lambdas capture by value, so any that captured a binding before it was
+                    // initialized are patched to refer to the final value.
+                    for (name, init) in bindings {
+                        if let [ASTNode::Value(Lambda(a, v, b, _, _))] = init.as_slice() {
+                            let c_name = self.sym_table.get(name.as_str(), Some(scope_ids)).unwrap().clone();
+
+                            for (i, capture) in Self::find_captures(a, v, b, &self.sym_table).iter().enumerate() {
+                                if bindings.iter().any(|(n, _)| n == capture) {
+                                    output.push(format!("reassign(&{}->captures[{}], retain({}))", c_name, i, self.sym_table.get(capture.as_str(), Some(scope_ids)).unwrap()))
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    scope_ids.push(*scope_id);
+                }
+
+                let mut lines = self.translate_body(body, scope_ids);
+                let v = lines.pop().unwrap();
+                output.append(&mut lines);
+                output.push(format!("{} = {}", let_value, v));
+
+                assert_eq!(*scope_id, scope_ids.pop().unwrap());
+
+                self.close_block(&mut output);
+                output.push(String::from("}"));
+                output.push(let_value);
+            }
+            ASTNode::Value(Begin(body)) => {
+                // Every form but the last is evaluated only for its side effects, so its value is
+                // dropped.
+                for (i, n) in body.iter().enumerate() {
+                    let mut lines = self.translate_node(n, scope_ids);
+
+                    if i + 1 < body.len() {
+                        Self::discard(n, &mut lines);
+                    }
+
+                    output.append(&mut lines);
+                }
             }
             ASTNode::Statement(Declaration(name)) => {
-                output.push(format!("struct LispDatum* {}", self.sym_table.get(name.as_str(), Some(scope_ids)).unwrap()))
+                let c_name = self.sym_table.get(name.as_str(), Some(scope_ids)).unwrap().clone();
+                output.push(format!("struct LispDatum* {} = NULL", c_name));
+                self.declare_local(&c_name, &mut output);
             }
-            ASTNode::Statement(Definition(name, value)) => {
-                let mut value = self.translate_node(&ASTNode::Value(value.clone()), scope_ids);
+            ASTNode::Statement(Definition(name, value, _)) => {
+                let mut lines = self.translate_node(&ASTNode::Value(value.clone()), scope_ids);
+
+                let v = Self::owned(value, lines.pop().unwrap());
+                output.append(&mut lines);
+
+                let c_name = self.sym_table.get(name.as_str(), Some(scope_ids)).unwrap().clone();
 
-                let v = value.pop().unwrap();
-                output.append(&mut value);
+                if scope_ids.is_empty() {
+                    self.declare_global(&c_name);
+                    output.push(format!("reassign(&{}, {})", c_name, v))
+                } else {
+                    output.push(format!("struct LispDatum* {} = {}", c_name, v));
+                    self.declare_local(&c_name, &mut output);
+                }
+            }
+            ASTNode::Statement(FunctionDefinition(name, ..)) => {
+                // The function itself is generated separately, but its name is also usable as a value.
+                let c_name = self.sym_table.get(name.as_str(), None).unwrap().clone();
+                self.declare_global(&c_name);
 
-                output.push(format!("struct LispDatum* {} = {}", self.sym_table.get(name.as_str(), Some(scope_ids)).unwrap(), v))
+                output.push(format!("reassign(&{}, {}({}, NULL, 0, {}))", c_name, self.sym_table.get_factory("lambda"), self.sym_table.get_fn(name).unwrap(), c_string(name)))
             }
             ASTNode::Statement(Redefinition(name, value)) => {
-                let mut value = self.translate_node(&ASTNode::Value(value.clone()), scope_ids);
+                let mut lines = self.translate_node(&ASTNode::Value(value.clone()), scope_ids);
 
-                let v = value.pop().unwrap();
-                output.append(&mut value);
+                let v = Self::owned(value, lines.pop().unwrap());
+                output.append(&mut lines);
 
-                output.push(format!("{} = {}", self.sym_table.get(name.as_str(), Some(scope_ids)).unwrap(), v))
+                // The variable's old value is released.
+                output.push(format!("reassign(&{}, {})", self.sym_table.get(name.as_str(), Some(scope_ids)).unwrap(), v))
             }
             ASTNode::Statement(ExpandedCondition(c, t, f)) => {
-                let mut c = self.translate_node(&ASTNode::Value(c.clone()), scope_ids);
-                let cond = c.pop().unwrap();
-                output.append(&mut c);
-                output.push(format!("if (truthy({})) {{", cond));
+                let mut lines = self.translate_node(&ASTNode::Value(c.clone()), scope_ids);
+                let cond = lines.pop().unwrap();
+                output.append(&mut lines);
 
-                for v in t {
-                    output.append(&mut self.translate_node(v, scope_ids));
+                if Self::owns(c) {
+                    output.push(format!("if (consume_truthy({})) {{", cond));
+                } else {
+                    output.push(format!("if (truthy({})) {{", cond));
                 }
 
-                output.push(String::from("} else {"));
+                for (i, branch) in [t, f].iter().enumerate() {
+                    self.open_block();
+
+                    for v in branch.iter() {
+                        let mut lines = self.translate_node(v, scope_ids);
+                        Self::discard(v, &mut lines);
+                        output.append(&mut lines);
+                    }
+
+                    self.close_block(&mut output);
 
-                for v in f {
-                    output.append(&mut self.translate_node(v, scope_ids));
+                    if i == 0 {
+                        output.push(String::from("} else {"));
+                    }
                 }
 
                 output.push(String::from("}"));
@@ -420,3 +1512,215 @@ impl Transpiler {
         output
     }
 }
+
+/// Writes a number as a C double literal. Debug formatting always has a decimal point or an
+/// exponent, so C doesn't read it as an integer, keeps the sign of zero, and round trips exactly.
+fn c_double(x: f64) -> String {
+    format!("{:?}", x)
+}
+
+/// Writes text as a C string literal. Bytes that aren't printable ASCII are written as octal
+/// escapes, which always end after three digits, unlike hex escapes. `?` is escaped so that it
+/// can't start a trigraph.
+fn c_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+
+    for b in text.bytes() {
+        match b {
+            b'"' | b'\\' | b'?' => {
+                literal.push('\\');
+                literal.push(b as char);
+            }
+            b' '..=b'~' => literal.push(b as char),
+            _ => literal.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::SymbolTable;
+    use crate::compiler::{CompileOptions, Compiler, Source};
+    use crate::transpile::*;
+
+    /// Runs every pass but `DeadCodeElimination` over a program, so that each form it has is
+    /// translated.
+    fn analyze(program: &str) -> (Vec<ASTNode>, SymbolTable) {
+        let options = CompileOptions { keep_dead_code: true, ..CompileOptions::default() };
+        let program = Compiler::new(options).unwrap().analyze(&[Source::new("<string>", program)]).ok().unwrap();
+
+        (program.ast, program.sym_table)
+    }
+
+    fn translate(program: &str, sources: &[&str]) -> (String, Transpiler) {
+        let (ast, sym_table) = analyze(program);
+        let mut transpiler = Transpiler::new(sym_table);
+        transpiler.set_sources(sources.iter().map(|s| s.to_string()).collect());
+        (transpiler.translate(&ast), transpiler)
+    }
+
+    const PROGRAM: &str = "(defun twice (x)\n  (* x 2))\n\n(define f (lambda (y)\n  (twice y)))\n(format (funcall f 3))\n";
+
+    #[test]
+    fn line_directives() {
+        let (c, _) = translate(PROGRAM, &["a \"b\".lisp"]);
+        let directives: Vec<&str> = c.lines().filter(|l| l.starts_with("#line")).collect();
+
+        assert!(directives.iter().all(|d| d.ends_with(" \"a \\\"b\\\".lisp\"")), "{:?}", directives);
+
+        for line in &["#line 1 ", "#line 2 ", "#line 5 ", "#line 6 "] {
+            assert!(directives.iter().any(|d| d.starts_with(line)), "no `{}` in {:?}", line, directives);
+        }
+
+        // The C for each position is on the line after its directive.
+        let lines: Vec<&str> = c.lines().collect();
+        let first = lines.iter().position(|l| l.starts_with("#line")).unwrap();
+        assert!(lines[first..].iter().step_by(2).all(|l| l.starts_with("#line")));
+
+        // Without the names of the sources, there are no directives.
+        assert!(!translate(PROGRAM, &[]).0.contains("#line"));
+    }
+
+    #[test]
+    fn source_map() {
+        let (c, transpiler) = translate(PROGRAM, &["a.lisp"]);
+        let map = json::parse(&transpiler.source_map(Some("a.c"))).unwrap();
+        let lines: Vec<&str> = c.lines().collect();
+
+        assert_eq!("a.c", map["file"]);
+        assert_eq!("a.lisp", map["sources"][0]);
+        assert_eq!(lines.iter().filter(|l| l.starts_with("#line")).count(), map["mappings"].len());
+
+        for mapping in map["mappings"].members() {
+            let first = mapping["c_lines"][0].as_usize().unwrap();
+            let last = mapping["c_lines"][1].as_usize().unwrap();
+
+            assert!(first <= last);
+            assert_eq!(format!("#line {} \"a.lisp\"", mapping["line"]), lines[first - 2]);
+        }
+
+        // Callees have no position, so the call to `twice` is found by its argument. The start of
+        // the lambda comes from the same position.
+        let calls: Vec<&JsonValue> = map["mappings"].members().filter(|m| m["line"] == 5).collect();
+        assert!(calls.iter().all(|m| m["column"] == 10));
+        assert!(calls.iter().any(|m| lines[m["c_lines"][0].as_usize().unwrap() - 1].contains("twice")));
+    }
+
+    #[test]
+    fn lambda_labels() {
+        let (c, _) = translate(PROGRAM, &["a.lisp"]);
+        assert!(c.contains("push_frame(&_frame, \"twice\");"));
+        assert!(c.contains("push_frame(&_frame, \"f\");"));
+        assert!(c.contains(", 0, \"f\")"));
+        assert!(c.contains("argument(s) to lambda `f`."));
+
+        let (c, _) = translate("(format\n  (lambda (x) x))", &["a.lisp"]);
+        assert!(c.contains("push_frame(&_frame, \"<lambda a.lisp:2>\");"));
+        assert!(c.contains(", 0, \"<lambda a.lisp:2>\")"));
+        assert!(c.contains("argument(s) to <lambda a.lisp:2>."));
+    }
+
+    #[test]
+    fn reference_counts() {
+        let (c, _) = translate("(define x (list 1 2))\n(define x (car x))\n(format x)\n", &[]);
+
+        // Globals release their old values, and take a reference to any they borrow.
+        assert!(c.contains("reassign(&"));
+        assert!(c.contains("= retain("));
+
+        // Arguments are released after each call, and so is a result that isn't used.
+        assert!(c.contains("release_all(gensym"));
+        assert!(c.contains("release(gensym"));
+        assert!(!c.contains("report_leaks();"));
+
+        let (c, _) = translate("(defun id (y) y)\n(define f (lambda (z) (lambda () z)))\n", &[]);
+        assert!(c.contains("struct LispDatum* _result = retain("));
+        assert!(c.contains("_lambda_captures[0] = retain("));
+
+        let (ast, sym_table) = analyze("(define x (list 1 2))\n");
+        let mut transpiler = Transpiler::new(sym_table);
+        transpiler.set_leak_check(true);
+        let c = transpiler.translate(&ast);
+        assert!(c.contains("report_leaks();"));
+        assert!(c.find("report_leaks();") > c.rfind("release("));
+    }
+
+    #[test]
+    fn tracing_roots() {
+        let program = "(define x '(1 2))\n(defun f (y . zs) (let ((n (length zs))) (cons y n)))\n(format (f x 1 2))\n";
+        let (ast, sym_table) = analyze(program);
+        let mut transpiler = Transpiler::new(sym_table);
+        transpiler.set_collector(Collector::Stress);
+        transpiler.set_leak_check(true);
+        let c = transpiler.translate(&ast);
+
+        // Globals and constants are roots for the whole program, and quoted data are pinned while
+        // they're built.
+        assert!(c.contains("gc_enable(1);"));
+        assert!(c.contains("gc_root(&gensym"));
+        assert!(c.contains("gc_pin();"));
+
+        // Functions register their arguments and locals, and natives are called through the
+        // runtime, which pins what they allocate.
+        assert!(c.contains("gc_push_frame(&_gc_frame);"));
+        assert!(c.contains("gc_pop_frame(&_gc_frame);"));
+        assert!(c.contains("gc_root_array(gensym"));
+        assert!(c.contains("gc_pop_roots("));
+        assert!(c.contains("call_native(length, "));
+        assert!(c.contains("gc_collect();"));
+
+        // Reference counting doesn't register anything.
+        let (c, _) = translate(program, &[]);
+        assert!(!c.contains("gc_"));
+        assert!(!c.contains("call_native"));
+    }
+
+    #[test]
+    fn constant_pool() {
+        let program = "(defun f (x) (list x :a \"s\" 2/4))\n(format (f 1) (f 1) :a \"s\" 1/2 1/0 '(:a 1))\n";
+        let (c, _) = translate(program, &[]);
+        let definitions = |pattern: &str| c.lines().filter(|l| l.contains(pattern)).count();
+
+        // Each literal is defined once, however often it's used, and rationals are reduced first.
+        assert_eq!(1, definitions("{.type = Integer, .int_val = 1}"));
+        assert_eq!(1, definitions("{.type = String, .content = (char*) \"s\", .length = 1}"));
+        assert_eq!(1, definitions("{.type = Rational, .num = 1, .den = 2}"));
+        assert!(c.contains("static struct LispDatum* const gensym"));
+        assert_eq!(1, c.matches("keyword(\"a\")").count());
+        assert!(!c.contains("new_integer") && !c.contains("new_string"));
+
+        // A rational that can't be made fails when it's evaluated, as before.
+        assert!(c.contains("new_rational(1,0)"));
+
+        // Floats are written as doubles, even when they're whole or too big for any integer.
+        let (c, _) = translate("(format 1e20 -0.0 2.0 1.5e-7)\n", &[]);
+        assert!(c.contains(".float_val = 1e20}"));
+        assert!(c.contains(".float_val = -0.0}"));
+        assert!(c.contains(".float_val = 2.0}"));
+        assert!(c.contains(".float_val = 1.5e-7}"));
+    }
+
+    #[test]
+    fn c_strings() {
+        assert_eq!("\"a \\\"b\\\" \\\\ c\\?\"", c_string("a \"b\" \\ c?"));
+        assert_eq!("\"\\001\\0121\"", c_string("\u{1}\n1"));
+        assert_eq!("\"\\316\\273 \\342\\200\\213\"", c_string("λ \u{200b}"));
+
+        let (c, _) = translate("(format \"x\u{200b}y\")\n(defun λ (x) x)\n", &[]);
+        assert!(c.contains("(char*) \"x\\342\\200\\213y\""));
+        assert!(c.contains("push_frame(&_frame, \"\\316\\273\")"));
+    }
+
+    #[test]
+    fn native_values() {
+        let (c, _) = translate("(define f (lambda (xs) (apply + xs)))\n(format (apply + (list 1 2)))\n", &[]);
+
+        // Natives are wrapped in a single lambda, which isn't captured, since it's a constant.
+        assert_eq!(1, c.matches("new_lambda(add, NULL, 0, \"+\")").count());
+        assert!(!c.contains("retain(add)"));
+        assert!(!c.contains("_lambda_captures"));
+    }
+}
@@ -0,0 +1,253 @@
+//! Compiles every sample in `lisp/` against liblisp, runs it, and compares what happened with the
+//! sample's `.expected` file, which sits next to it.
+//!
+//! An expected file starts with the exit status of the program, or `compile error` if lispc
+//! should reject the sample, followed by the program's stdout and stderr, or lispc's errors:
+//!
+//! ```text
+//! status: 255
+//! --- stdout
+//! (1 2)
+//! --- stderr
+//! Type Mismatch Exception: ...
+//! ```
+//!
+//! Samples are built with `--leak-check`, so any data they leak are reported on stderr. Empty
+//! sections are left out. Run with `LISPC_BLESS=1` to write the expected files from the
+//! current results, rather than checking against them.
+//!
+//! Every sample is also built with the tracing collector, which should leak nothing, but
+//! otherwise behave the same. The samples in `lisp/gc/` are small enough to run with the
+//! collector collecting before every allocation. Finally, every sample is run with `lispc interp`,
+//! whose output should be identical to the compiled program's.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::{env, fs};
+
+// The status lispc exits with when the program has errors.
+const EXIT_COMPILE_ERROR: i32 = 1;
+// The status lispc exits with when the C compiler can't be run.
+const EXIT_TOOLCHAIN: i32 = 4;
+
+/// What happened when a sample was compiled and run.
+enum Outcome {
+    // lispc rejected the sample, with these errors.
+    CompileError(String),
+    // The program ran, and exited with this status, or was killed if there's none.
+    Ran(Option<i32>, String, String),
+}
+
+impl Outcome {
+    fn render(&self) -> String {
+        let (header, stdout, stderr) = match self {
+            Outcome::CompileError(stderr) => ("compile error".to_string(), "", stderr.as_str()),
+            Outcome::Ran(status, stdout, stderr) => (
+                status.map_or("killed by a signal".to_string(), |s| format!("status: {}", s)),
+                stdout.as_str(),
+                stderr.as_str(),
+            ),
+        };
+
+        let mut rendered = header + "\n";
+
+        for (name, contents) in &[("stdout", stdout), ("stderr", stderr)] {
+            if !contents.is_empty() {
+                rendered += &format!("--- {}\n{}", name, contents);
+
+                if !contents.ends_with('\n') {
+                    rendered.push('\n');
+                }
+            }
+        }
+
+        rendered
+    }
+}
+
+/// Finds every sample under `dir`, in a stable order.
+fn samples(dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            found.append(&mut samples(&path));
+        } else if path.extension().is_some_and(|e| e == "lisp") {
+            found.push(path);
+        }
+    }
+
+    found.sort();
+    found
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Compiles a sample with `lispc build --leak-check`, and any other flags given, then runs it.
+/// A missing C compiler is a failure, rather than a reason to skip the sample.
+fn run_sample(root: &Path, sample: &Path, work: &Path, flags: &[&str]) -> Outcome {
+    let exe = work.join(sample.file_stem().unwrap());
+
+    let Output { status, stderr, .. } = Command::new(env!("CARGO_BIN_EXE_lispc"))
+        .args(["build", "--leak-check"])
+        .args(flags)
+        .arg("-o")
+        .arg(&exe)
+        .arg(sample)
+        .current_dir(root)
+        .env("LISPC_CACHE", work.join("cache"))
+        .output()
+        .unwrap();
+
+    match status.code() {
+        Some(0) => (),
+        Some(EXIT_COMPILE_ERROR) => return Outcome::CompileError(text(&stderr)),
+        Some(EXIT_TOOLCHAIN) => panic!(
+            "Couldn't build `{}`, since the C compiler couldn't be run. The samples need one \
+             to be installed.\n{}",
+            sample.display(),
+            text(&stderr)
+        ),
+        _ => panic!("Couldn't build `{}`:\n{}", sample.display(), text(&stderr)),
+    }
+
+    let output = Command::new(&exe).output().unwrap();
+    fs::remove_file(&exe).unwrap();
+
+    Outcome::Ran(output.status.code(), text(&output.stdout), text(&output.stderr))
+}
+
+/// Runs a sample with `lispc interp`, which should behave exactly as the compiled program does.
+fn interpret_sample(root: &Path, sample: &Path) -> Outcome {
+    let Output { status, stdout, stderr } = Command::new(env!("CARGO_BIN_EXE_lispc"))
+        .arg("interp")
+        .arg(sample)
+        .current_dir(root)
+        .output()
+        .unwrap();
+
+    match status.code() {
+        Some(EXIT_COMPILE_ERROR) => Outcome::CompileError(text(&stderr)),
+        code => Outcome::Ran(code, text(&stdout), text(&stderr)),
+    }
+}
+
+#[test]
+fn samples_match_expected() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work = Path::new(env!("CARGO_TARGET_TMPDIR")).join("samples");
+    fs::create_dir_all(&work).unwrap();
+
+    let bless = env::var("LISPC_BLESS").is_ok_and(|v| !v.is_empty() && v != "0");
+    let mut failures = Vec::new();
+
+    for sample in samples(&root.join("lisp")) {
+        // Paths are shown relative to the crate, so that the errors in expected files don't
+        // depend on where it's checked out.
+        let relative = sample.strip_prefix(root).unwrap();
+        let expected_path = sample.with_extension("expected");
+
+        let actual = run_sample(root, relative, &work, &[]).render();
+
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => (),
+            Ok(expected) => failures.push(format!(
+                "`{}` didn't match `{}`.\n\nExpected:\n{}\nFound:\n{}",
+                relative.display(),
+                expected_path.strip_prefix(root).unwrap().display(),
+                expected,
+                actual
+            )),
+            Err(_) => failures.push(format!(
+                "`{}` has no expected file. Run with `LISPC_BLESS=1` to make one.\n\nFound:\n{}",
+                relative.display(),
+                actual
+            )),
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{} sample(s) failed.\n\n{}", failures.len(), failures.join("\n"));
+    }
+}
+
+/// The expected outcome of a sample without any leaks reported.
+fn without_leaks(expected: &str) -> String {
+    let kept: Vec<&str> = expected.lines().filter(|l| !l.starts_with("Leak check: ")).collect();
+    let mut expected = kept.join("\n") + "\n";
+
+    if expected.ends_with("--- stderr\n") {
+        expected.truncate(expected.len() - "--- stderr\n".len());
+    }
+
+    expected
+}
+
+#[test]
+fn samples_match_expected_with_tracing_gc() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work = Path::new(env!("CARGO_TARGET_TMPDIR")).join("samples-gc");
+    fs::create_dir_all(&work).unwrap();
+
+    let mut failures = Vec::new();
+
+    for sample in samples(&root.join("lisp")) {
+        let relative = sample.strip_prefix(root).unwrap();
+        let gc = if relative.starts_with("lisp/gc") { "--gc=stress" } else { "--gc=tracing" };
+
+        let actual = run_sample(root, relative, &work, &[gc]).render();
+
+        // Missing expected files are reported by `samples_match_expected`.
+        if let Ok(expected) = fs::read_to_string(sample.with_extension("expected")) {
+            if without_leaks(&expected) != actual {
+                failures.push(format!(
+                    "`{}` with `{}` didn't match.\n\nExpected:\n{}\nFound:\n{}",
+                    relative.display(),
+                    gc,
+                    without_leaks(&expected),
+                    actual
+                ));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{} sample(s) failed.\n\n{}", failures.len(), failures.join("\n"));
+    }
+}
+
+#[test]
+fn samples_match_expected_when_interpreted() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut failures = Vec::new();
+
+    for sample in samples(&root.join("lisp")) {
+        let relative = sample.strip_prefix(root).unwrap();
+        let actual = interpret_sample(root, relative).render();
+
+        // The interpreter has nothing to leak.
+        if let Ok(expected) = fs::read_to_string(sample.with_extension("expected")) {
+            if without_leaks(&expected) != actual {
+                failures.push(format!(
+                    "`{}` didn't match when interpreted.\n\nExpected:\n{}\nFound:\n{}",
+                    relative.display(),
+                    without_leaks(&expected),
+                    actual
+                ));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{} sample(s) failed.\n\n{}", failures.len(), failures.join("\n"));
+    }
+}